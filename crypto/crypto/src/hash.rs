@@ -540,6 +540,17 @@ define_hasher! {
     )
 }
 
+define_hasher! {
+    /// The hasher used to compute the hash of an internal node in the Sparse Merkle Tree when the
+    /// number of leaves under each child is committed into the hash.
+    (
+        SparseMerkleCountedInternalHasher,
+        SPARSE_MERKLE_COUNTED_INTERNAL_HASHER,
+        SPARSE_MERKLE_COUNTED_INTERNAL_SEED,
+        b"SparseMerkleCountedInternal"
+    )
+}
+
 define_hasher! {
     /// The hasher used only for testing. It doesn't have a salt.
     (TestOnlyHasher, TEST_ONLY_HASHER, TEST_ONLY_SEED, b"")
@@ -575,6 +586,121 @@ pub static GENESIS_BLOCK_ID: Lazy<HashValue> = Lazy::new(|| {
     ])
 });
 
+/// The kinds of values a [`HashScheme`] hashes for the Sparse Merkle Tree. Each domain has its own
+/// seed, the same way each type has its own [`CryptoHasher`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum HashDomain {
+    /// Internal nodes of the Sparse Merkle Tree.
+    SparseMerkleInternal,
+    /// Internal nodes of the Sparse Merkle Tree that commit to the leaf counts of their children.
+    SparseMerkleCountedInternal,
+    /// Leaf nodes of the Sparse Merkle Tree.
+    SparseMerkleLeafNode,
+    /// Account state blobs.
+    AccountStateBlob,
+}
+
+impl HashDomain {
+    /// All the domains, in the order of their discriminants.
+    pub const ALL: [HashDomain; 4] = [
+        HashDomain::SparseMerkleInternal,
+        HashDomain::SparseMerkleCountedInternal,
+        HashDomain::SparseMerkleLeafNode,
+        HashDomain::AccountStateBlob,
+    ];
+
+    /// Returns the salt of the domain, which is the same as the salt of the [`CryptoHasher`] of the
+    /// corresponding type.
+    pub fn salt(self) -> &'static [u8] {
+        match self {
+            HashDomain::SparseMerkleInternal => b"SparseMerkleInternal",
+            HashDomain::SparseMerkleCountedInternal => b"SparseMerkleCountedInternal",
+            HashDomain::SparseMerkleLeafNode => b"SparseMerkleLeafNode",
+            HashDomain::AccountStateBlob => b"AccountStateBlob",
+        }
+    }
+}
+
+/// A hash function the Sparse Merkle Tree and its proofs can be computed with. Unlike the
+/// [`CryptoHasher`]s, which are fixed for the build, a scheme is picked for each tree, so trees
+/// compatible with other implementations can be built next to the default ones.
+///
+/// The value of some domain is hashed as the seed of the domain followed by the bytes of the value,
+/// where the seed is the hash of [`LIBRA_HASH_PREFIX`] followed by the salt of the domain. So
+/// [`Blake3`] gives the same hashes as the [`CryptoHash`] implementations.
+pub trait HashScheme: Clone + fmt::Debug + Send + Sync {
+    /// Hashes the concatenation of `buffers`.
+    fn hash_of(&self, buffers: &[&[u8]]) -> HashValue;
+
+    /// Returns the seed hashed before the values of `domain`.
+    fn seed(&self, domain: HashDomain) -> &[u8; HashValue::LENGTH];
+
+    /// Returns the hash of an empty subtree.
+    fn placeholder_hash(&self) -> HashValue {
+        *SPARSE_MERKLE_PLACEHOLDER_HASH
+    }
+}
+
+macro_rules! define_hash_scheme {
+    (
+        $(#[$attr:meta])*
+        ($scheme_type: ident, $seeds_name: ident, $hash_of: expr)
+    ) => {
+        $(#[$attr])*
+        #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+        pub struct $scheme_type;
+
+        static $seeds_name: Lazy<[[u8; HashValue::LENGTH]; 4]> = Lazy::new(|| {
+            let mut seeds = [[0; HashValue::LENGTH]; 4];
+            for (seed, domain) in seeds.iter_mut().zip(HashDomain::ALL.iter()) {
+                *seed = $hash_of(&[LIBRA_HASH_PREFIX, domain.salt()]).hash;
+            }
+            seeds
+        });
+
+        impl HashScheme for $scheme_type {
+            fn hash_of(&self, buffers: &[&[u8]]) -> HashValue {
+                $hash_of(buffers)
+            }
+
+            fn seed(&self, domain: HashDomain) -> &[u8; HashValue::LENGTH] {
+                &$seeds_name[domain as usize]
+            }
+        }
+    };
+}
+
+fn blake3_of(buffers: &[&[u8]]) -> HashValue {
+    let mut state = blake3::Hasher::new();
+    for buffer in buffers {
+        state.update(buffer);
+    }
+    HashValue::from_blake3(state)
+}
+
+fn digest_of<D: digest::Digest>(buffers: &[&[u8]]) -> HashValue {
+    let mut state = D::new();
+    for buffer in buffers {
+        state.input(buffer);
+    }
+    HashValue::from_slice(state.result().as_slice()).expect("Digest must have 32 bytes.")
+}
+
+define_hash_scheme! {
+    /// BLAKE3, the hash function of the [`CryptoHasher`]s and the default for trees.
+    (Blake3, BLAKE3_SEEDS, blake3_of)
+}
+
+define_hash_scheme! {
+    /// SHA3-256, the hash function of upstream Libra.
+    (Sha3_256, SHA3_256_SEEDS, digest_of::<sha3::Sha3_256>)
+}
+
+define_hash_scheme! {
+    /// SHA-256.
+    (Sha2_256, SHA2_256_SEEDS, digest_of::<sha2::Sha256>)
+}
+
 /// Provides a test_only_hash() method that can be used in tests on types that implement
 /// `serde::Serialize`.
 ///
@@ -273,3 +273,35 @@ proptest! {
         prop_assert!(HashValue::from_bit_iter(bits2.into_iter()).is_err());
     }
 }
+
+#[test]
+fn test_hash_schemes() {
+    let message: &[&[u8]] = &[b"a", b"bc"];
+    assert_eq!(
+        format!("{:x}", Blake3.hash_of(message)),
+        "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85",
+    );
+    assert_eq!(
+        format!("{:x}", Sha3_256.hash_of(message)),
+        "3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532",
+    );
+    assert_eq!(
+        format!("{:x}", Sha2_256.hash_of(message)),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+    );
+
+    // The seeds of `Blake3` are the seeds of the corresponding hashers.
+    assert_eq!(
+        Blake3.seed(HashDomain::SparseMerkleInternal),
+        SparseMerkleInternalHasher::seed(),
+    );
+    assert_eq!(
+        Blake3.seed(HashDomain::SparseMerkleCountedInternal),
+        SparseMerkleCountedInternalHasher::seed(),
+    );
+    assert_ne!(
+        Sha3_256.seed(HashDomain::SparseMerkleInternal),
+        Sha3_256.seed(HashDomain::SparseMerkleLeafNode),
+    );
+    assert_eq!(Sha2_256.placeholder_hash(), *SPARSE_MERKLE_PLACEHOLDER_HASH);
+}
@@ -0,0 +1,142 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    builder::{build_tree, JellyfishMerkleBuilder},
+    mock_tree_store::MockTreeStore,
+    node_type::{Node, NodeKey},
+    test_helper::init_mock_db_with_options,
+    JellyfishMerkleTree, NodeBatch, TreeReader, TreeWriter,
+};
+use anyhow::Result;
+use libra_crypto::{
+    hash::{Sha3_256, SPARSE_MERKLE_PLACEHOLDER_HASH},
+    HashValue,
+};
+use libra_types::account_state_blob::AccountStateBlob;
+use proptest::{collection::btree_map, prelude::*};
+use std::{cell::Cell, collections::BTreeMap};
+
+/// Records the size of the largest batch written to the underlying store.
+#[derive(Default)]
+struct BatchSizeRecorder {
+    store: MockTreeStore,
+    max_batch_size: Cell<usize>,
+}
+
+impl TreeWriter for BatchSizeRecorder {
+    fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()> {
+        self.max_batch_size
+            .set(self.max_batch_size.get().max(node_batch.len()));
+        self.store.write_node_batch(node_batch)
+    }
+}
+
+fn expected_root_hash(btree: &BTreeMap<HashValue, AccountStateBlob>) -> HashValue {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::new(&db);
+    let (root_hash, batch) = tree
+        .put_blob_set(
+            btree.iter().map(|(k, v)| (*k, v.clone())).collect(),
+            0, /* version */
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    root_hash
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(10))]
+
+    #[test]
+    fn test_build_tree(
+        btree in btree_map(any::<HashValue>(), any::<AccountStateBlob>(), 1..1000),
+        max_batch_size in 1..100usize,
+    ) {
+        let writer = BatchSizeRecorder::default();
+        let mut builder = JellyfishMerkleBuilder::new_with_batch_size(&writer, 1, max_batch_size);
+        for (key, value) in &btree {
+            builder.add(*key, value.clone()).unwrap();
+        }
+        let root_hash = builder.finish().unwrap();
+        prop_assert_eq!(root_hash, expected_root_hash(&btree));
+
+        // Adding a key freezes at most one leaf and one internal node per level.
+        prop_assert!(writer.max_batch_size.get() < max_batch_size + 65);
+
+        let tree = JellyfishMerkleTree::new(&writer.store);
+        prop_assert_eq!(tree.get_root_hash(1).unwrap(), root_hash);
+        for (key, value) in &btree {
+            prop_assert_eq!(&tree.get(*key, 1).unwrap().unwrap(), value);
+            let (blob, proof) = tree.get_with_proof(*key, 1).unwrap();
+            proof.verify(root_hash, *key, blob.as_ref()).unwrap();
+        }
+    }
+}
+
+#[test]
+fn test_build_empty_tree() {
+    let db = MockTreeStore::default();
+    assert_eq!(
+        build_tree(&db, 0 /* version */, vec![]).unwrap(),
+        *SPARSE_MERKLE_PLACEHOLDER_HASH
+    );
+    assert_eq!(
+        db.get_node(&NodeKey::new_empty_path(0)).unwrap(),
+        Node::new_null()
+    );
+}
+
+#[test]
+fn test_build_single_leaf() {
+    let key = HashValue::random();
+    let value = AccountStateBlob::from(vec![1u8, 2, 3]);
+    let db = MockTreeStore::default();
+    let root_hash = build_tree(&db, 0 /* version */, vec![(key, value.clone())]).unwrap();
+
+    let btree: BTreeMap<_, _> = vec![(key, value.clone())].into_iter().collect();
+    assert_eq!(root_hash, expected_root_hash(&btree));
+    assert_eq!(
+        db.get_node(&NodeKey::new_empty_path(0)).unwrap(),
+        Node::new_leaf(key, value)
+    );
+}
+
+#[test]
+fn test_build_unsorted() {
+    let key1 = HashValue::new([0x01; HashValue::LENGTH]);
+    let key2 = HashValue::new([0x02; HashValue::LENGTH]);
+    let value = AccountStateBlob::from(vec![1u8]);
+
+    let db = MockTreeStore::default();
+    let mut builder = JellyfishMerkleBuilder::new(&db, 0 /* version */);
+    builder.add(key2, value.clone()).unwrap();
+    assert!(builder.add(key1, value.clone()).is_err());
+    assert!(builder.add(key2, value).is_err());
+}
+
+#[test]
+fn test_build_tree_with_scheme() {
+    let blob_sets = vec![(0..100u8)
+        .map(|i| (HashValue::random(), AccountStateBlob::from(vec![i])))
+        .collect::<BTreeMap<_, _>>()
+        .into_iter()
+        .collect::<Vec<_>>()];
+    let db = MockTreeStore::default();
+    let mut builder = JellyfishMerkleBuilder::new_with_scheme(&db, 0, 10, Sha3_256);
+    for (key, value) in &blob_sets[0] {
+        builder.add(*key, value.clone()).unwrap();
+    }
+    let root_hash = builder.finish().unwrap();
+
+    let (_db, root_hashes) = init_mock_db_with_options(&blob_sets, None, Sha3_256);
+    assert_eq!(root_hash, root_hashes[0]);
+    let tree = JellyfishMerkleTree::new_with_scheme(&db, None, Sha3_256);
+    for (key, value) in &blob_sets[0] {
+        let (blob, proof) = tree.get_with_proof(*key, 0).unwrap();
+        assert_eq!(blob.as_ref(), Some(value));
+        proof
+            .verify_with_scheme(&Sha3_256, root_hash, *key, blob.as_ref())
+            .unwrap();
+    }
+}
@@ -0,0 +1,592 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module implements the functionality to build a `JellyfishMerkleTree` from accounts sorted
+//! by key. Nodes are frozen as soon as no later account can change them, so they can be written
+//! to storage while the rest of the accounts stream in and the memory used stays bounded.
+//!
+//! [`JellyfishMerkleBuilder`] loads trusted accounts in bulk, for example at genesis or in
+//! migrations, without verifying anything. Restoring a tree from untrusted chunks builds the
+//! tree the same way, see [`restore`](../restore/index.html).
+//!
+//! [`JellyfishMerkleBuilder`]: struct.JellyfishMerkleBuilder.html
+
+#[cfg(test)]
+mod builder_test;
+
+use crate::{
+    iterator::path_to_key,
+    nibble_path::{NibbleIterator, NibblePath},
+    node_type::{Child, Children, InternalNode, LeafNode, Node, NodeKey},
+    NodeBatch, TreeWriter, ROOT_NIBBLE_HEIGHT,
+};
+use anyhow::{ensure, format_err, Result};
+use libra_crypto::{
+    hash::{Blake3, HashScheme},
+    HashValue,
+};
+use libra_types::{account_state_blob::AccountStateBlob, transaction::Version};
+use mirai_annotations::*;
+
+/// The default maximum number of frozen nodes [`JellyfishMerkleBuilder`] keeps in memory before
+/// writing them.
+///
+/// [`JellyfishMerkleBuilder`]: struct.JellyfishMerkleBuilder.html
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 10_000;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum ChildInfo {
+    /// This child is an internal node. The hash of the internal node is stored here if it is
+    /// known, otherwise it is `None`. In the process of building a tree, we will only know the hash
+    /// of an internal node after we see all the keys that share the same prefix.
+    Internal { hash: Option<HashValue> },
+
+    /// This child is a leaf node.
+    Leaf { node: LeafNode },
+}
+
+impl ChildInfo {
+    /// Converts `self` to a child, assuming the hash is known if it's an internal node.
+    fn into_child<S: HashScheme>(self, version: Version, hash_scheme: &S) -> Child {
+        match self {
+            Self::Internal { hash } => {
+                Child::new(
+                    hash.expect("Must have been initialized."),
+                    version,
+                    false, /* is_leaf */
+                )
+            }
+            Self::Leaf { node } => {
+                Child::new(
+                    node.hash_with_scheme(hash_scheme),
+                    version,
+                    true, /* is_leaf */
+                )
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct InternalInfo {
+    /// The node key of this internal node.
+    pub(crate) node_key: NodeKey,
+
+    /// The existing children. Every time a child appears, the corresponding position will be set
+    /// to `Some`.
+    pub(crate) children: [Option<ChildInfo>; 16],
+}
+
+impl InternalInfo {
+    /// Creates an empty internal node with no children.
+    pub(crate) fn new_empty(node_key: NodeKey) -> Self {
+        Self {
+            node_key,
+            children: Default::default(),
+        }
+    }
+
+    pub(crate) fn set_child(&mut self, index: usize, child_info: ChildInfo) {
+        precondition!(index < 16);
+        self.children[index] = Some(child_info);
+    }
+
+    /// Converts `self` to an internal node, assuming all of its children are already known and
+    /// fully initialized.
+    fn into_internal_node<S: HashScheme>(
+        mut self,
+        version: Version,
+        hash_scheme: &S,
+    ) -> (NodeKey, InternalNode) {
+        let mut children = Children::new();
+
+        // Calling `into_iter` on an array is equivalent to calling `iter`:
+        // https://github.com/rust-lang/rust/issues/25725. So we use `iter_mut` and `take`.
+        for (index, child_info_option) in self.children.iter_mut().enumerate() {
+            if let Some(child_info) = child_info_option.take() {
+                children.insert(
+                    (index as u8).into(),
+                    child_info.into_child(version, hash_scheme),
+                );
+            }
+        }
+
+        (self.node_key, InternalNode::new(children))
+    }
+}
+
+/// The tree being built from accounts sorted by key, made of the nodes that may still change and
+/// the frozen ones that have not been taken out yet.
+pub(crate) struct PartialTree<S> {
+    /// The version of the tree we are building.
+    version: Version,
+
+    /// The node key of the root of what we are building. It has an empty nibble path unless we
+    /// are building a shard, in which case it is the prefix of all keys in the shard.
+    root_node_key: NodeKey,
+
+    /// The nodes we have partially built. Each `partial_nodes[i-1]` is the parent of
+    /// `partial_nodes[i]`. If a node `partial_nodes[i-1]` has multiple children, only the
+    /// rightmost known child will appear here as `partial_nodes[i]`, because any other children on
+    /// the left would have been frozen.
+    ///
+    /// At any point in time, the structure looks like the following:
+    ///
+    /// ```text
+    /// +----+----+----+----+----+----+----+----+
+    /// |    |    |    |    |    |    |    | C  |  partial_nodes[0]
+    /// +----+----+----+----+----+----+----+----+
+    ///   |         |              |
+    ///   |         |              |
+    ///   |         |              |
+    ///   v         v              v
+    /// Frozen    Frozen     +----+----+----+----+----+----+----+----+
+    ///                      |    |    |    | B  |    |    | A  |    |  partial_nodes[1]
+    ///                      +----+----+----+----+----+----+----+----+
+    ///                             |         |
+    ///                             |         |
+    ///                             |         |
+    ///                             v         v
+    ///                            Frozen    Previously inserted account
+    /// ```
+    ///
+    /// We insert the accounts from left to right. So if the next account appears at position `A`,
+    /// it will cause the leaf at position `B` to be frozen. If it appears at position `B`, it
+    /// might cause a few internal nodes to be created additionally. If it appears at position `C`,
+    /// it will also cause `partial_nodes[1]` to be added to `frozen_nodes` as an internal node and
+    /// be removed from `partial_nodes`.
+    pub(crate) partial_nodes: Vec<InternalInfo>,
+
+    /// The nodes that have been fully built and are ready to be written to storage.
+    pub(crate) frozen_nodes: NodeBatch,
+
+    /// The most recently added leaf. This is used to ensure the keys come in increasing order.
+    pub(crate) previous_leaf: Option<LeafNode>,
+
+    /// The largest key whose leaf has been frozen.
+    last_frozen_key: Option<HashValue>,
+
+    /// The number of leaves that have been frozen.
+    num_frozen_leaves: u64,
+
+    /// The hash scheme the tree is computed with.
+    hash_scheme: S,
+}
+
+impl<S: HashScheme> PartialTree<S> {
+    /// Creates an empty tree with the root at `root_node_key`.
+    pub(crate) fn new(root_node_key: NodeKey, hash_scheme: S) -> Self {
+        Self {
+            version: root_node_key.version(),
+            root_node_key: root_node_key.clone(),
+            partial_nodes: vec![InternalInfo::new_empty(root_node_key)],
+            frozen_nodes: NodeBatch::new(),
+            previous_leaf: None,
+            last_frozen_key: None,
+            num_frozen_leaves: 0,
+            hash_scheme,
+        }
+    }
+
+    /// Creates a tree that continues from `partial_nodes`, where `previous_leaf` is the last of
+    /// the `num_frozen_leaves` leaves that have already been frozen.
+    pub(crate) fn new_resumed(
+        root_node_key: NodeKey,
+        partial_nodes: Vec<InternalInfo>,
+        previous_leaf: LeafNode,
+        num_frozen_leaves: u64,
+        hash_scheme: S,
+    ) -> Self {
+        Self {
+            version: root_node_key.version(),
+            root_node_key,
+            partial_nodes,
+            frozen_nodes: NodeBatch::new(),
+            last_frozen_key: Some(previous_leaf.account_key()),
+            previous_leaf: Some(previous_leaf),
+            num_frozen_leaves,
+            hash_scheme,
+        }
+    }
+
+    pub(crate) fn version(&self) -> Version {
+        self.version
+    }
+
+    pub(crate) fn root_node_key(&self) -> &NodeKey {
+        &self.root_node_key
+    }
+
+    /// Returns the number of nibbles shared by all the keys in the tree.
+    pub(crate) fn num_prefix_nibbles(&self) -> usize {
+        self.root_node_key.nibble_path().num_nibbles()
+    }
+
+    pub(crate) fn last_frozen_key(&self) -> Option<HashValue> {
+        self.last_frozen_key
+    }
+
+    pub(crate) fn num_frozen_leaves(&self) -> u64 {
+        self.num_frozen_leaves
+    }
+
+    pub(crate) fn hash_scheme(&self) -> &S {
+        &self.hash_scheme
+    }
+
+    /// Adds an account, whose key must be larger than all the keys added so far.
+    pub(crate) fn add(&mut self, key: HashValue, value: AccountStateBlob) -> Result<()> {
+        ensure!(
+            key.common_prefix_nibbles_len(path_to_key(self.root_node_key.nibble_path()))
+                >= self.num_prefix_nibbles(),
+            "Account key {:x} is not in the shard {:?}.",
+            key,
+            self.root_node_key.nibble_path(),
+        );
+        if let Some(ref prev_leaf) = self.previous_leaf {
+            ensure!(
+                key > prev_leaf.account_key(),
+                "Account keys must come in increasing order.",
+            )
+        }
+        let leaf_node = LeafNode::new_with_scheme(key, value, &self.hash_scheme);
+        self.add_one(leaf_node.clone());
+        self.previous_leaf.replace(leaf_node);
+        Ok(())
+    }
+
+    /// Adds the leaf of one account.
+    fn add_one(&mut self, new_leaf: LeafNode) {
+        let new_key = new_leaf.account_key();
+        let nibble_path = NibblePath::new(new_key.to_vec());
+        let mut nibbles = nibble_path.nibbles();
+        // The nibbles of the prefix lead to `partial_nodes[0]`.
+        let num_prefix_nibbles = self.num_prefix_nibbles();
+        for _ in 0..num_prefix_nibbles {
+            nibbles.next();
+        }
+
+        for i in 0..ROOT_NIBBLE_HEIGHT - num_prefix_nibbles {
+            let child_index = u8::from(nibbles.next().expect("This nibble must exist.")) as usize;
+
+            match self.partial_nodes[i].children[child_index] {
+                Some(ref child_info) => {
+                    // If there exists an internal node at this position, we just continue the loop
+                    // with the next nibble. Here we deal with the leaf case.
+                    if let ChildInfo::Leaf { node } = child_info {
+                        assert_eq!(
+                            i,
+                            self.partial_nodes.len() - 1,
+                            "If we see a leaf, there will be no more partial internal nodes on \
+                             lower level, since they would have been frozen.",
+                        );
+
+                        let existing_leaf = node.clone();
+                        self.insert_at_leaf(child_index, existing_leaf, new_leaf, nibbles);
+                        break;
+                    }
+                }
+                None => {
+                    // This means that we are going to put a leaf in this position. For all the
+                    // descendants on the left, they are now frozen.
+                    self.freeze(i + 1);
+
+                    // Mark this position as a leaf child.
+                    self.partial_nodes[i]
+                        .set_child(child_index, ChildInfo::Leaf { node: new_leaf });
+
+                    // We do not add this leaf node to self.frozen_nodes because we don't know its
+                    // node key yet. We will know its node key when the next account comes.
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Inserts a new account at the position of the existing leaf node. We may need to create
+    /// multiple internal nodes depending on the length of the common prefix of the existing key
+    /// and the new key.
+    fn insert_at_leaf<'b>(
+        &mut self,
+        child_index: usize,
+        existing_leaf: LeafNode,
+        new_leaf: LeafNode,
+        mut remaining_nibbles: NibbleIterator<'b>,
+    ) {
+        let new_key = new_leaf.account_key();
+        let num_existing_partial_nodes = self.partial_nodes.len();
+
+        // The node at this position becomes an internal node. Since we may insert more nodes at
+        // this position in the future, we do not know its hash yet.
+        self.partial_nodes[num_existing_partial_nodes - 1]
+            .set_child(child_index, ChildInfo::Internal { hash: None });
+
+        // Next we build the new internal nodes from top to bottom. All these internal node except
+        // the bottom one will now have a single internal node child.
+        let common_prefix_len = existing_leaf
+            .account_key()
+            .common_prefix_nibbles_len(new_key);
+        for _ in self.num_prefix_nibbles() + num_existing_partial_nodes..common_prefix_len {
+            let visited_nibbles = remaining_nibbles.visited_nibbles().collect();
+            let next_nibble = remaining_nibbles.next().expect("This nibble must exist.");
+            let new_node_key = NodeKey::new(self.version, visited_nibbles);
+
+            let mut internal_info = InternalInfo::new_empty(new_node_key);
+            internal_info.set_child(
+                u8::from(next_nibble) as usize,
+                ChildInfo::Internal { hash: None },
+            );
+            self.partial_nodes.push(internal_info);
+        }
+
+        // The last internal node will have two leaf node children.
+        let visited_nibbles = remaining_nibbles.visited_nibbles().collect();
+        let new_node_key = NodeKey::new(self.version, visited_nibbles);
+        let mut internal_info = InternalInfo::new_empty(new_node_key);
+
+        // Next we put the existing leaf as a child of this internal node.
+        let existing_child_index = existing_leaf.account_key().get_nibble(common_prefix_len);
+        internal_info.set_child(
+            u8::from(existing_child_index) as usize,
+            ChildInfo::Leaf {
+                node: existing_leaf,
+            },
+        );
+
+        // Do not set the new child for now. We always call `freeze` first, then set the new child
+        // later, because this way it's easier in `freeze` to find the correct leaf to freeze --
+        // it's always the rightmost leaf on the lowest level.
+        self.partial_nodes.push(internal_info);
+        self.freeze(self.partial_nodes.len());
+
+        // Now we set the new child.
+        let new_child_index = new_key.get_nibble(common_prefix_len);
+        assert!(
+            new_child_index > existing_child_index,
+            "New leaf must be on the right.",
+        );
+        self.partial_nodes
+            .last_mut()
+            .expect("This node must exist.")
+            .set_child(
+                u8::from(new_child_index) as usize,
+                ChildInfo::Leaf { node: new_leaf },
+            );
+    }
+
+    /// Puts the nodes that will not be changed later in `self.frozen_nodes`.
+    fn freeze(&mut self, num_remaining_partial_nodes: usize) {
+        self.freeze_previous_leaf();
+        self.freeze_internal_nodes(num_remaining_partial_nodes);
+    }
+
+    /// Freezes the previously added leaf node. It should always be the rightmost leaf node on the
+    /// lowest level, inserted in the previous `add_one` call.
+    fn freeze_previous_leaf(&mut self) {
+        // If this is the very first key, there is no previous leaf to freeze. If we have just
+        // resumed, the previous leaf has been frozen before the interruption.
+        let previous_key = match &self.previous_leaf {
+            Some(leaf_node) => leaf_node.account_key(),
+            None => return,
+        };
+        if self.last_frozen_key == Some(previous_key) {
+            return;
+        }
+        self.last_frozen_key = Some(previous_key);
+        self.num_frozen_leaves += 1;
+
+        let last_node = self
+            .partial_nodes
+            .last()
+            .expect("Must have at least one partial node.");
+        let rightmost_child_index = last_node
+            .children
+            .iter()
+            .rposition(|x| x.is_some())
+            .expect("Must have at least one child.");
+
+        match last_node.children[rightmost_child_index] {
+            Some(ChildInfo::Leaf { ref node }) => {
+                let child_node_key = last_node
+                    .node_key
+                    .gen_child_node_key(self.version, (rightmost_child_index as u8).into());
+                self.frozen_nodes
+                    .insert(child_node_key, node.clone().into());
+            }
+            _ => panic!("Must have at least one child and must not have further internal nodes."),
+        }
+    }
+
+    /// Freeze extra internal nodes. Only `num_remaining_nodes` partial internal nodes will be kept
+    /// and the ones on the lower level will be frozen.
+    fn freeze_internal_nodes(&mut self, num_remaining_nodes: usize) {
+        while self.partial_nodes.len() > num_remaining_nodes {
+            let last_node = self.partial_nodes.pop().expect("This node must exist.");
+            let (node_key, internal_node) =
+                last_node.into_internal_node(self.version, &self.hash_scheme);
+            // Keep the hash of this node before moving it into `frozen_nodes`, so we can update
+            // its parent later.
+            let node_hash = internal_node.hash_with_scheme(&self.hash_scheme);
+            self.frozen_nodes.insert(node_key, internal_node.into());
+
+            // Now that we have computed the hash of the internal node above, we will also update
+            // its parent unless it is root node.
+            if let Some(parent_node) = self.partial_nodes.last_mut() {
+                // This internal node must be the rightmost child of its parent at the moment.
+                let rightmost_child_index = parent_node
+                    .children
+                    .iter()
+                    .rposition(|x| x.is_some())
+                    .expect("Must have at least one child.");
+
+                match parent_node.children[rightmost_child_index] {
+                    Some(ChildInfo::Internal { ref mut hash }) => {
+                        assert_eq!(hash.replace(node_hash), None);
+                    }
+                    _ => panic!(
+                        "Must have at least one child and the rightmost child must not be a leaf."
+                    ),
+                }
+            }
+        }
+    }
+
+    /// Freezes all the remaining nodes, since there is no more account, and returns the root hash.
+    /// If there is a single leaf, it is placed at the first `single_leaf_num_nibbles` nibbles of
+    /// its key rather than under the root.
+    pub(crate) fn finish(&mut self, single_leaf_num_nibbles: usize) -> Result<HashValue> {
+        if self.previous_leaf.is_none() {
+            // Nothing has been added. An empty tree has a null root node, while an empty shard
+            // has no nodes at all.
+            if self.num_prefix_nibbles() == 0 {
+                self.frozen_nodes
+                    .insert(self.root_node_key.clone(), Node::new_null());
+            }
+            return Ok(self.hash_scheme.placeholder_hash());
+        }
+
+        // Deal with the special case when there is a single leaf.
+        if let Some(leaf_node) = self.single_leaf() {
+            let node_key = NodeKey::new(
+                self.version,
+                NibblePath::new(leaf_node.account_key().to_vec())
+                    .nibbles()
+                    .take(single_leaf_num_nibbles)
+                    .collect(),
+            );
+            let leaf_hash = leaf_node.hash_with_scheme(&self.hash_scheme);
+            assert!(self.frozen_nodes.is_empty());
+            self.frozen_nodes.insert(node_key, leaf_node.into());
+            return Ok(leaf_hash);
+        }
+
+        self.freeze(0);
+        Ok(self
+            .frozen_nodes
+            .get(&self.root_node_key)
+            .ok_or_else(|| format_err!("Root node is not built."))?
+            .hash_with_scheme(&self.hash_scheme))
+    }
+
+    /// Returns the leaf if it is the only one that has been added.
+    fn single_leaf(&self) -> Option<LeafNode> {
+        if self.partial_nodes.len() != 1 {
+            return None;
+        }
+        let mut children = self.partial_nodes[0].children.iter().flatten();
+        match (children.next(), children.next()) {
+            (Some(ChildInfo::Leaf { node }), None) => Some(node.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Builds the tree at some version from accounts sorted by key and writes it through a
+/// [`TreeWriter`](../trait.TreeWriter.html). Nothing is verified, so it is meant for trusted
+/// accounts only. The root is written last, but an interrupted build can not be resumed.
+pub struct JellyfishMerkleBuilder<'a, W, S = Blake3> {
+    /// The underlying storage.
+    writer: &'a W,
+
+    /// The tree being built.
+    tree: PartialTree<S>,
+
+    /// The frozen nodes are written once there are this many of them.
+    max_batch_size: usize,
+}
+
+impl<'a, W> JellyfishMerkleBuilder<'a, W>
+where
+    W: 'a + TreeWriter,
+{
+    /// Starts building the tree at `version`.
+    pub fn new(writer: &'a W, version: Version) -> Self {
+        Self::new_with_batch_size(writer, version, DEFAULT_MAX_BATCH_SIZE)
+    }
+
+    /// Starts building the tree at `version`, keeping at most about `max_batch_size` frozen
+    /// nodes in memory.
+    pub fn new_with_batch_size(writer: &'a W, version: Version, max_batch_size: usize) -> Self {
+        Self::new_with_scheme(writer, version, max_batch_size, Blake3)
+    }
+}
+
+impl<'a, W, S> JellyfishMerkleBuilder<'a, W, S>
+where
+    W: 'a + TreeWriter,
+    S: HashScheme,
+{
+    /// Same as
+    /// [`new_with_batch_size`](struct.JellyfishMerkleBuilder.html#method.new_with_batch_size), for
+    /// a tree computed with `hash_scheme`.
+    pub fn new_with_scheme(
+        writer: &'a W,
+        version: Version,
+        max_batch_size: usize,
+        hash_scheme: S,
+    ) -> Self {
+        Self {
+            writer,
+            tree: PartialTree::new(NodeKey::new_empty_path(version), hash_scheme),
+            max_batch_size,
+        }
+    }
+
+    /// Adds an account. Its key must be larger than all the keys added so far.
+    pub fn add(&mut self, key: HashValue, value: AccountStateBlob) -> Result<()> {
+        self.tree.add(key, value)?;
+        if self.tree.frozen_nodes.len() >= self.max_batch_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Finishes building the tree. Writes the remaining nodes and returns the root hash.
+    pub fn finish(mut self) -> Result<HashValue> {
+        let root_hash = self.tree.finish(0 /* single_leaf_num_nibbles */)?;
+        self.flush()?;
+        Ok(root_hash)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if !self.tree.frozen_nodes.is_empty() {
+            self.writer.write_node_batch(&self.tree.frozen_nodes)?;
+            self.tree.frozen_nodes.clear();
+        }
+        Ok(())
+    }
+}
+
+/// Builds the tree at `version` from `kvs`, which must be sorted by key, and writes it to
+/// `writer`. Returns the root hash.
+pub fn build_tree<W: TreeWriter>(
+    writer: &W,
+    version: Version,
+    kvs: impl IntoIterator<Item = (HashValue, AccountStateBlob)>,
+) -> Result<HashValue> {
+    let mut builder = JellyfishMerkleBuilder::new(writer, version);
+    for (key, value) in kvs {
+        builder.add(key, value)?;
+    }
+    builder.finish()
+}
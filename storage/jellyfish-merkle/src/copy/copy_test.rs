@@ -0,0 +1,229 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    copy::{copy_tree, copy_tree_with_batch_size, copy_tree_with_scheme},
+    mock_tree_store::MockTreeStore,
+    node_type::{LeafCountMode, Node, NodeKey},
+    test_helper::init_mock_db_with_options,
+    JellyfishMerkleTree, TreeReader,
+};
+use libra_crypto::{
+    hash::{Blake3, CryptoHash, Sha3_256},
+    HashValue,
+};
+use libra_types::{account_state_blob::AccountStateBlob, transaction::Version};
+use proptest::{collection::vec, prelude::*};
+use std::collections::{BTreeMap, BTreeSet};
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(10))]
+
+    #[test]
+    fn test_copy_tree(
+        batches in vec(
+            vec((any::<HashValue>(), any::<AccountStateBlob>()), 1..50),
+            1..5,
+        ),
+        leaf_count_mode in prop_oneof![
+            Just(None),
+            Just(Some(LeafCountMode::Stored)),
+            Just(Some(LeafCountMode::Committed)),
+        ],
+        new_version in proptest::option::of(0..100 as Version),
+        max_batch_size in 1..20usize,
+        other_keys in vec(any::<HashValue>(), 10),
+    ) {
+        let (db, _root_hashes) = init_mock_db_with_options(&batches, leaf_count_mode, Blake3);
+        let version = batches.len() as Version - 1;
+        let target_version = new_version.unwrap_or(version);
+        let target = MockTreeStore::default();
+        let root_hash =
+            copy_tree_with_batch_size(&db, &target, version, new_version, max_batch_size).unwrap();
+
+        let db_tree = JellyfishMerkleTree::new(&db);
+        let target_tree = JellyfishMerkleTree::new(&target);
+        prop_assert_eq!(db_tree.get_root_hash(version).unwrap(), root_hash);
+        prop_assert_eq!(target_tree.get_root_hash(target_version).unwrap(), root_hash);
+        if leaf_count_mode.is_some() {
+            prop_assert_eq!(
+                target_tree.get_leaf_count(target_version).unwrap(),
+                db_tree.get_leaf_count(version).unwrap()
+            );
+        }
+        for key in batches.iter().flatten().map(|(k, _v)| k).chain(other_keys.iter()) {
+            prop_assert_eq!(
+                target_tree.get_with_proof(*key, target_version).unwrap(),
+                db_tree.get_with_proof(*key, version).unwrap()
+            );
+        }
+
+        // Only the nodes of the copied version are in the target, the same as in the source once
+        // every earlier version is purged.
+        db.purge_stale_nodes(version).unwrap();
+        prop_assert_eq!(target.num_nodes(), db.num_nodes());
+    }
+
+    #[test]
+    fn test_copy_hash_only_tree(
+        batches in vec(
+            vec((any::<HashValue>(), any::<AccountStateBlob>()), 1..50),
+            1..5,
+        ),
+        max_batch_size in 1..20usize,
+    ) {
+        let db = MockTreeStore::default();
+        let (_root_hashes, batch) = JellyfishMerkleTree::new(&db).with_hash_only_leaves()
+            .put_blob_sets(batches.clone(), 0 /* first_version */)
+            .unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+        let version = batches.len() as Version - 1;
+        let target = MockTreeStore::default();
+        copy_tree_with_batch_size(&db, &target, version, Some(7), max_batch_size).unwrap();
+
+        // Only the blobs of the leaves in the copied version are carried over.
+        let kvs: BTreeMap<_, _> = batches.iter().flatten().cloned().collect();
+        let blob_hashes: BTreeSet<_> = kvs.values().map(|blob| blob.hash()).collect();
+        prop_assert_eq!(target.num_values(), blob_hashes.len());
+        let db_tree = JellyfishMerkleTree::new(&db);
+        let target_tree = JellyfishMerkleTree::new(&target);
+        for key in kvs.keys() {
+            prop_assert_eq!(
+                target_tree.get_with_proof(*key, 7).unwrap(),
+                db_tree.get_with_proof(*key, version).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_copy_tree_then_update(
+        batch0 in vec((any::<HashValue>(), any::<AccountStateBlob>()), 1..50),
+        batch1 in vec((any::<HashValue>(), any::<AccountStateBlob>()), 1..50),
+        batch2 in vec((any::<HashValue>(), any::<AccountStateBlob>()), 1..50),
+    ) {
+        // Version 1 of the source is copied to version 10 of the target, and both are updated
+        // with the same accounts.
+        let (db, _root_hashes) = init_mock_db_with_options(&[batch0, batch1], None, Blake3);
+        let target = MockTreeStore::default();
+        copy_tree(&db, &target, 1 /* version */, Some(10)).unwrap();
+
+        let (db_root_hash, db_batch) = JellyfishMerkleTree::new(&db)
+            .put_blob_set(batch2.clone(), 2 /* version */)
+            .unwrap();
+        db.write_tree_update_batch(db_batch).unwrap();
+        let (target_root_hash, target_batch) = JellyfishMerkleTree::new(&target)
+            .put_blob_set(batch2, 11 /* version */)
+            .unwrap();
+        target.write_tree_update_batch(target_batch).unwrap();
+        prop_assert_eq!(target_root_hash, db_root_hash);
+    }
+}
+
+#[test]
+fn test_copy_renumbers_node_keys() {
+    let key1 = HashValue::new([0x00; HashValue::LENGTH]);
+    let key2 = HashValue::new([0xff; HashValue::LENGTH]);
+    let (db, _root_hashes) = init_mock_db_with_options(
+        &[
+            vec![
+                (key1, AccountStateBlob::from(vec![1u8])),
+                (key2, AccountStateBlob::from(vec![2u8])),
+            ],
+            vec![(key2, AccountStateBlob::from(vec![3u8]))],
+        ],
+        None, /* leaf_count_mode */
+        Blake3,
+    );
+
+    // Without renumbering, the leaf of `key1` stays at version 0.
+    let target = MockTreeStore::default();
+    copy_tree(&db, &target, 1 /* version */, None).unwrap();
+    let root_node_key = NodeKey::new_empty_path(1);
+    assert!(target.get_node_option(&root_node_key).unwrap().is_some());
+    assert!(target
+        .get_node_option(&root_node_key.gen_child_node_key(0, 0.into()))
+        .unwrap()
+        .is_some());
+    assert!(target
+        .get_node_option(&NodeKey::new_empty_path(0))
+        .unwrap()
+        .is_none());
+
+    // With renumbering, every node is at the new version.
+    let target = MockTreeStore::default();
+    copy_tree(&db, &target, 1 /* version */, Some(5)).unwrap();
+    let root_node_key = NodeKey::new_empty_path(5);
+    let root_node = target.get_node(&root_node_key).unwrap();
+    match &root_node {
+        Node::Internal(internal_node) => {
+            assert_eq!(internal_node.child(0.into()).unwrap().version, 5);
+            assert_eq!(internal_node.child(15.into()).unwrap().version, 5);
+        }
+        _ => panic!("Root must be an internal node."),
+    }
+    assert_eq!(
+        target
+            .get_node(&root_node_key.gen_child_node_key(5, 0.into()))
+            .unwrap(),
+        Node::new_leaf(key1, AccountStateBlob::from(vec![1u8]))
+    );
+    assert_eq!(target.num_nodes(), 3);
+}
+
+#[test]
+fn test_copy_hash_only_leaf_root() {
+    let key = HashValue::random();
+    let blob = AccountStateBlob::from(vec![1u8, 2, 3]);
+    let db = MockTreeStore::default();
+    let (_root_hash, batch) = JellyfishMerkleTree::new(&db)
+        .with_hash_only_leaves()
+        .put_blob_set(vec![(key, blob.clone())], 0 /* version */)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let target = MockTreeStore::default();
+    copy_tree(&db, &target, 0 /* version */, Some(2)).unwrap();
+    assert_eq!(target.num_values(), 1);
+    assert_eq!(
+        JellyfishMerkleTree::new(&target)
+            .get_with_proof(key, 2)
+            .unwrap()
+            .0,
+        Some(blob)
+    );
+}
+
+#[test]
+fn test_copy_tree_with_scheme() {
+    let blob_sets = vec![(0..10u8)
+        .map(|i| (HashValue::random(), AccountStateBlob::from(vec![i])))
+        .collect::<Vec<_>>()];
+    let (db, root_hashes) = init_mock_db_with_options(&blob_sets, None, Sha3_256);
+    let target = MockTreeStore::default();
+    let root_hash = copy_tree_with_scheme(&db, &target, 0, Some(4), 3, &Sha3_256).unwrap();
+    assert_eq!(root_hash, root_hashes[0]);
+
+    let target_tree = JellyfishMerkleTree::new_with_scheme(&target, None, Sha3_256);
+    assert_eq!(target_tree.get_root_hash(4).unwrap(), root_hash);
+    for (key, blob) in &blob_sets[0] {
+        let (value, proof) = target_tree.get_with_proof(*key, 4).unwrap();
+        assert_eq!(value.as_ref(), Some(blob));
+        proof
+            .verify_with_scheme(&Sha3_256, root_hash, *key, value.as_ref())
+            .unwrap();
+    }
+}
+
+#[test]
+fn test_copy_empty_tree() {
+    let db = MockTreeStore::default();
+    db.put_node(NodeKey::new_empty_path(0), Node::new_null())
+        .unwrap();
+    let target = MockTreeStore::default();
+    copy_tree(&db, &target, 0 /* version */, Some(3)).unwrap();
+    assert_eq!(
+        target.get_node(&NodeKey::new_empty_path(3)).unwrap(),
+        Node::new_null()
+    );
+    assert!(copy_tree(&db, &target, 1 /* version */, None).is_err());
+}
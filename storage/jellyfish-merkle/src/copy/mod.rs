@@ -0,0 +1,168 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module implements copying the tree at a single version from one store into another, so a
+//! new store can start from that version without replaying the history before it. Only the nodes
+//! reachable from the root of the version are copied, along with the blobs of hash-only leaves,
+//! so the target has no other versions and no stale node indices.
+//!
+//! The tree can optionally be renumbered to a new base version, in which case every node is
+//! written under a node key of the new version. The root hash stays the same since versions are
+//! not part of node hashes.
+
+#[cfg(test)]
+mod copy_test;
+
+use crate::{
+    builder::DEFAULT_MAX_BATCH_SIZE,
+    node_type::{Children, InternalNode, Node, NodeKey},
+    NodeBatch, TreeReader, TreeWriter, ValueBatch,
+};
+use anyhow::{ensure, Result};
+use libra_crypto::{
+    hash::{Blake3, HashScheme},
+    HashValue,
+};
+use libra_nibble::Nibble;
+use libra_types::transaction::Version;
+
+/// Copies the tree at `version` from `reader` into `writer`. If `new_version` is given, the tree
+/// is written at that version instead. Returns the root hash of the tree.
+pub fn copy_tree<R: TreeReader, W: TreeWriter>(
+    reader: &R,
+    writer: &W,
+    version: Version,
+    new_version: Option<Version>,
+) -> Result<HashValue> {
+    copy_tree_with_batch_size(reader, writer, version, new_version, DEFAULT_MAX_BATCH_SIZE)
+}
+
+/// Same as [`copy_tree`](fn.copy_tree.html), but keeps at most `max_batch_size` nodes in memory
+/// before writing them. The blobs of the hash-only leaves in a batch are written right before it.
+///
+/// Nodes are visited depth first, so apart from the batch only the keys of the nodes waiting to
+/// be visited are kept in memory, which is at most 15 per level of the tree. The root is written
+/// last, so an interrupted copy leaves no root in the target.
+pub fn copy_tree_with_batch_size<R: TreeReader, W: TreeWriter>(
+    reader: &R,
+    writer: &W,
+    version: Version,
+    new_version: Option<Version>,
+    max_batch_size: usize,
+) -> Result<HashValue> {
+    copy_tree_with_scheme(
+        reader,
+        writer,
+        version,
+        new_version,
+        max_batch_size,
+        &Blake3,
+    )
+}
+
+/// Same as [`copy_tree_with_batch_size`](fn.copy_tree_with_batch_size.html), for a tree computed
+/// with `hash_scheme`, which is only used for the returned root hash.
+pub fn copy_tree_with_scheme<R: TreeReader, W: TreeWriter, S: HashScheme>(
+    reader: &R,
+    writer: &W,
+    version: Version,
+    new_version: Option<Version>,
+    max_batch_size: usize,
+    hash_scheme: &S,
+) -> Result<HashValue> {
+    let target_version = new_version.unwrap_or(version);
+    let root_node_key = NodeKey::new_empty_path(version);
+    let root_node = reader.get_node(&root_node_key)?;
+    let root_hash = root_node.hash_with_scheme(hash_scheme);
+
+    let mut batch = NodeBatch::new();
+    let mut value_batch = ValueBatch::new();
+    let mut stack = vec![];
+    push_children(&root_node_key, &root_node, &mut stack);
+    let root_node = renumber(root_node, new_version);
+
+    while let Some(node_key) = stack.pop() {
+        let node = reader.get_node(&node_key)?;
+        ensure!(
+            !matches!(node, Node::Null),
+            "Unexpected null node {:?} under the root.",
+            node_key
+        );
+        push_children(&node_key, &node, &mut stack);
+        add_value(reader, &node, &mut value_batch)?;
+        let mut target_node_key = node_key;
+        if let Some(new_version) = new_version {
+            target_node_key.set_version(new_version);
+        }
+        batch.insert(target_node_key, renumber(node, new_version));
+        if batch.len() >= max_batch_size {
+            write_batches(writer, &mut batch, &mut value_batch)?;
+        }
+    }
+
+    add_value(reader, &root_node, &mut value_batch)?;
+    batch.insert(NodeKey::new_empty_path(target_version), root_node);
+    write_batches(writer, &mut batch, &mut value_batch)?;
+    Ok(root_hash)
+}
+
+/// Adds the blob of `node` to `value_batch` if it is a hash-only leaf.
+fn add_value<R: TreeReader>(reader: &R, node: &Node, value_batch: &mut ValueBatch) -> Result<()> {
+    if let Node::Leaf(leaf_node) = node {
+        if leaf_node.is_hash_only() {
+            let blob_hash = leaf_node.blob_hash();
+            value_batch.insert(blob_hash, reader.get_value(&blob_hash)?);
+        }
+    }
+    Ok(())
+}
+
+/// Writes and clears `value_batch` and then `batch`, so no leaf is written before its blob.
+fn write_batches<W: TreeWriter>(
+    writer: &W,
+    batch: &mut NodeBatch,
+    value_batch: &mut ValueBatch,
+) -> Result<()> {
+    if !value_batch.is_empty() {
+        writer.write_value_batch(value_batch)?;
+        value_batch.clear();
+    }
+    writer.write_node_batch(batch)?;
+    batch.clear();
+    Ok(())
+}
+
+/// Pushes the keys of the children of `node` onto `stack`, in reverse order so they are visited
+/// in order of nibble paths.
+fn push_children(node_key: &NodeKey, node: &Node, stack: &mut Vec<NodeKey>) {
+    if let Node::Internal(internal_node) = node {
+        for i in (0..16u8).rev() {
+            let n = Nibble::from(i);
+            if let Some(child) = internal_node.child(n) {
+                stack.push(node_key.gen_child_node_key(child.version, n));
+            }
+        }
+    }
+}
+
+/// Rewrites the versions of the children of `node` to `new_version`, if it is given.
+fn renumber(node: Node, new_version: Option<Version>) -> Node {
+    let (internal_node, new_version) = match (node, new_version) {
+        (Node::Internal(internal_node), Some(new_version)) => (internal_node, new_version),
+        (node, _) => return node,
+    };
+    let children: Children = (0..16u8)
+        .map(Nibble::from)
+        .filter_map(|n| {
+            internal_node.child(n).map(|child| {
+                let mut child = child.clone();
+                child.version = new_version;
+                (n, child)
+            })
+        })
+        .collect();
+    match internal_node.leaf_count_mode() {
+        Some(mode) => InternalNode::new_with_leaf_count(children, mode).into(),
+        None => InternalNode::new(children).into(),
+    }
+}
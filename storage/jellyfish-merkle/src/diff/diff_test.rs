@@ -0,0 +1,253 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    diff::{StateDiff, StateDiffIterator},
+    mock_tree_store::MockTreeStore,
+    node_type::{Node, NodeKey},
+    test_helper::init_mock_db_with_options,
+    JellyfishMerkleTree, TreeReader,
+};
+use anyhow::Result;
+use libra_crypto::{
+    hash::{Blake3, Sha3_256},
+    HashValue,
+};
+use libra_nibble::Nibble;
+use libra_types::account_state_blob::AccountStateBlob;
+use proptest::{collection::btree_map, prelude::*};
+use std::{cell::RefCell, collections::BTreeMap};
+
+type Update = Option<Option<AccountStateBlob>>;
+
+/// Returns the blob sets that put `old_kvs` at version 0 and apply `updates` at version 1.
+fn blob_sets(
+    old_kvs: &BTreeMap<HashValue, AccountStateBlob>,
+    updates: &BTreeMap<HashValue, Option<AccountStateBlob>>,
+) -> Vec<Vec<(HashValue, Option<AccountStateBlob>)>> {
+    vec![
+        old_kvs.iter().map(|(k, v)| (*k, Some(v.clone()))).collect(),
+        updates.clone().into_iter().collect(),
+    ]
+}
+
+fn expected_diff(
+    old_kvs: &BTreeMap<HashValue, AccountStateBlob>,
+    new_kvs: &BTreeMap<HashValue, AccountStateBlob>,
+) -> Vec<(HashValue, StateDiff)> {
+    let mut keys: Vec<_> = old_kvs.keys().chain(new_kvs.keys()).cloned().collect();
+    keys.sort();
+    keys.dedup();
+    keys.into_iter()
+        .filter_map(|key| {
+            let diff = match (old_kvs.get(&key), new_kvs.get(&key)) {
+                (Some(old), Some(new)) if old == new => return None,
+                (Some(old), Some(new)) => StateDiff::Modified {
+                    old: old.clone(),
+                    new: new.clone(),
+                },
+                (Some(old), None) => StateDiff::Removed(old.clone()),
+                (None, Some(new)) => StateDiff::Added(new.clone()),
+                (None, None) => unreachable!(),
+            };
+            Some((key, diff))
+        })
+        .collect()
+}
+
+fn arb_old_kvs_and_updates() -> impl Strategy<
+    Value = (
+        BTreeMap<HashValue, AccountStateBlob>,
+        BTreeMap<HashValue, Option<AccountStateBlob>>,
+    ),
+> {
+    btree_map(
+        any::<HashValue>(),
+        (any::<Option<AccountStateBlob>>(), any::<Update>()),
+        1..500,
+    )
+    .prop_map(|kvs| {
+        let mut old_kvs = BTreeMap::new();
+        let mut updates = BTreeMap::new();
+        for (key, (old, update)) in kvs {
+            match (old, update) {
+                // Deleting a key that does not exist is not allowed.
+                (None, Some(None)) | (None, None) => (),
+                (None, Some(Some(new))) => {
+                    updates.insert(key, Some(new));
+                }
+                (Some(old), update) => {
+                    if let Some(update) = update {
+                        updates.insert(key, update);
+                    }
+                    old_kvs.insert(key, old);
+                }
+            }
+        }
+        (old_kvs, updates)
+    })
+    .prop_filter(
+        "Both versions need a non-empty write set.",
+        |(old_kvs, updates)| !old_kvs.is_empty() && !updates.is_empty(),
+    )
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(20))]
+
+    #[test]
+    fn test_diff((old_kvs, updates) in arb_old_kvs_and_updates()) {
+        let (db, _root_hashes) =
+            init_mock_db_with_options(&blob_sets(&old_kvs, &updates), None, Blake3);
+        let mut new_kvs = old_kvs.clone();
+        for (key, update) in &updates {
+            match update {
+                Some(blob) => new_kvs.insert(*key, blob.clone()),
+                None => new_kvs.remove(key),
+            };
+        }
+
+        let diff = StateDiffIterator::new(&db, 0, 1)
+            .unwrap()
+            .map(|res| res.map(|entry| (entry.key, entry.diff)))
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        prop_assert_eq!(diff, expected_diff(&old_kvs, &new_kvs));
+
+        let reverse_diff = StateDiffIterator::new(&db, 1, 0)
+            .unwrap()
+            .map(|res| res.map(|entry| (entry.key, entry.diff)))
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        prop_assert_eq!(reverse_diff, expected_diff(&new_kvs, &old_kvs));
+
+        prop_assert_eq!(StateDiffIterator::new(&db, 1, 1).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_diff_with_proofs((old_kvs, updates) in arb_old_kvs_and_updates()) {
+        let (db, _root_hashes) =
+            init_mock_db_with_options(&blob_sets(&old_kvs, &updates), None, Blake3);
+        let tree = JellyfishMerkleTree::new(&db);
+        let old_root_hash = tree.get_root_hash(0).unwrap();
+        let new_root_hash = tree.get_root_hash(1).unwrap();
+
+        for entry in StateDiffIterator::new(&db, 0, 1).unwrap().with_proofs() {
+            let entry = entry.unwrap();
+            let (old, new) = match &entry.diff {
+                StateDiff::Added(new) => (None, Some(new)),
+                StateDiff::Removed(old) => (Some(old), None),
+                StateDiff::Modified { old, new } => (Some(old), Some(new)),
+            };
+            let (old_proof, new_proof) = entry.proofs.unwrap();
+            old_proof.verify(old_root_hash, entry.key, old).unwrap();
+            new_proof.verify(new_root_hash, entry.key, new).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_diff_with_scheme((old_kvs, updates) in arb_old_kvs_and_updates()) {
+        let (db, root_hashes) =
+            init_mock_db_with_options(&blob_sets(&old_kvs, &updates), None, Sha3_256);
+        let mut new_kvs = old_kvs.clone();
+        for (key, update) in &updates {
+            match update {
+                Some(blob) => new_kvs.insert(*key, blob.clone()),
+                None => new_kvs.remove(key),
+            };
+        }
+
+        let mut diff = vec![];
+        for entry in StateDiffIterator::new_with_scheme(&db, 0, 1, Sha3_256)
+            .unwrap()
+            .with_proofs()
+        {
+            let entry = entry.unwrap();
+            let (old, new) = match &entry.diff {
+                StateDiff::Added(new) => (None, Some(new)),
+                StateDiff::Removed(old) => (Some(old), None),
+                StateDiff::Modified { old, new } => (Some(old), Some(new)),
+            };
+            let (old_proof, new_proof) = entry.proofs.as_ref().unwrap();
+            old_proof
+                .verify_with_scheme(&Sha3_256, root_hashes[0], entry.key, old)
+                .unwrap();
+            new_proof
+                .verify_with_scheme(&Sha3_256, root_hashes[1], entry.key, new)
+                .unwrap();
+            diff.push((entry.key, entry.diff));
+        }
+        prop_assert_eq!(diff, expected_diff(&old_kvs, &new_kvs));
+    }
+}
+
+/// A reader that records the keys of all nodes read through it.
+struct RecordingReader<'a> {
+    db: &'a MockTreeStore,
+    reads: RefCell<Vec<NodeKey>>,
+}
+
+impl<'a> TreeReader for RecordingReader<'a> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        self.reads.borrow_mut().push(node_key.clone());
+        self.db.get_node_option(node_key)
+    }
+}
+
+#[test]
+fn test_diff_skips_shared_subtrees() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::new(&db);
+    let key1 = HashValue::new([0x00; HashValue::LENGTH]);
+    let key2 = HashValue::new([0xff; HashValue::LENGTH]);
+    let (_root_hash, batch) = tree
+        .put_blob_set(
+            vec![
+                (key1, AccountStateBlob::from(vec![1u8])),
+                (key2, AccountStateBlob::from(vec![2u8])),
+            ],
+            0, /* version */
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let (_root_hash, batch) = tree
+        .put_blob_set(
+            vec![(key2, AccountStateBlob::from(vec![3u8]))],
+            1, /* version */
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let reader = RecordingReader {
+        db: &db,
+        reads: RefCell::new(vec![]),
+    };
+    let diff = StateDiffIterator::new(&reader, 0, 1)
+        .unwrap()
+        .map(|res| res.map(|entry| (entry.key, entry.diff)))
+        .collect::<Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(
+        diff,
+        vec![(
+            key2,
+            StateDiff::Modified {
+                old: AccountStateBlob::from(vec![2u8]),
+                new: AccountStateBlob::from(vec![3u8]),
+            }
+        )]
+    );
+
+    // The leaf of `key1` is shared between both versions, so it is never read.
+    let root0 = NodeKey::new_empty_path(0);
+    let root1 = NodeKey::new_empty_path(1);
+    assert_eq!(
+        reader.reads.into_inner(),
+        vec![
+            root0.clone(),
+            root1.clone(),
+            root0.gen_child_node_key(0, Nibble::from(15)),
+            root1.gen_child_node_key(1, Nibble::from(15)),
+        ]
+    );
+}
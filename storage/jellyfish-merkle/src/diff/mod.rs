@@ -0,0 +1,307 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module implements `StateDiffIterator`, which yields the keys whose values differ between
+//! two versions of the tree, in ascending order of keys. Both trees are walked at the same time
+//! and subtrees that are shared between them are skipped without being read: a subtree is shared
+//! if both sides refer to the same node key, or if the hashes stored in the parents are the same.
+
+#[cfg(test)]
+mod diff_test;
+
+use crate::{
+    get_leaf_blob,
+    node_type::{InternalNode, LeafNode, Node, NodeKey},
+    JellyfishMerkleTree, TreeReader,
+};
+use anyhow::{bail, Result};
+use libra_crypto::{
+    hash::{Blake3, HashScheme},
+    HashValue,
+};
+use libra_nibble::Nibble;
+use libra_types::{
+    account_state_blob::AccountStateBlob, proof::SparseMerkleProof, transaction::Version,
+};
+use std::collections::VecDeque;
+
+/// How the value of a key changed between the old and the new version.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum StateDiff {
+    /// The key does not exist in the old version.
+    Added(AccountStateBlob),
+    /// The key does not exist in the new version.
+    Removed(AccountStateBlob),
+    /// The key exists in both versions with different values.
+    Modified {
+        old: AccountStateBlob,
+        new: AccountStateBlob,
+    },
+}
+
+/// A key whose value differs between the old and the new version.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StateDiffEntry {
+    pub key: HashValue,
+    pub diff: StateDiff,
+    /// The proofs of the key in the old and the new version, respectively. Only present if the
+    /// iterator is created [`with_proofs`](struct.StateDiffIterator.html#method.with_proofs).
+    pub proofs: Option<(SparseMerkleProof, SparseMerkleProof)>,
+}
+
+/// One side of a pair of subtrees being compared.
+#[derive(Debug)]
+enum Subtree {
+    /// A node that has not been read yet, along with its hash recorded in the parent.
+    Unloaded { node_key: NodeKey, hash: HashValue },
+    /// An internal node that has been read.
+    Internal {
+        node_key: NodeKey,
+        node: InternalNode,
+    },
+    /// A leaf, either read from storage or pushed down from a higher level because the other side
+    /// has an internal node there.
+    Leaf(LeafNode),
+}
+
+impl Subtree {
+    fn node_key(&self) -> Option<&NodeKey> {
+        match self {
+            Subtree::Unloaded { node_key, .. } | Subtree::Internal { node_key, .. } => {
+                Some(node_key)
+            }
+            Subtree::Leaf(_) => None,
+        }
+    }
+
+    fn hash<S: HashScheme>(&self, hash_scheme: &S) -> Option<HashValue> {
+        match self {
+            Subtree::Unloaded { hash, .. } => Some(*hash),
+            Subtree::Internal { .. } => None,
+            Subtree::Leaf(leaf_node) => Some(leaf_node.hash_with_scheme(hash_scheme)),
+        }
+    }
+
+    /// Returns the part of this subtree under child `n`, given that this subtree sits at `depth`
+    /// nibbles.
+    fn child(&self, n: Nibble, depth: usize) -> Option<Subtree> {
+        match self {
+            Subtree::Internal { node_key, node } => node.child(n).map(|child| Subtree::Unloaded {
+                node_key: node_key.gen_child_node_key(child.version, n),
+                hash: child.hash,
+            }),
+            Subtree::Leaf(leaf_node) => {
+                if leaf_node.account_key().get_nibble(depth) == n {
+                    Some(Subtree::Leaf(leaf_node.clone()))
+                } else {
+                    None
+                }
+            }
+            Subtree::Unloaded { .. } => unreachable!("Subtree must be loaded before splitting."),
+        }
+    }
+}
+
+/// The `StateDiffIterator` implementation.
+pub struct StateDiffIterator<'a, R, S = Blake3> {
+    /// The storage engine from which we can read nodes using node keys.
+    reader: &'a R,
+
+    /// The version compared from.
+    old_version: Version,
+
+    /// The version compared to.
+    new_version: Version,
+
+    /// Whether to attach proofs to the yielded entries.
+    with_proofs: bool,
+
+    /// The pairs of subtrees that remain to be compared. The top of the stack always covers the
+    /// smallest keys.
+    stack: Vec<(Option<Subtree>, Option<Subtree>)>,
+
+    /// Entries that have been found but not yielded yet.
+    pending: VecDeque<(HashValue, StateDiff)>,
+
+    /// The hash scheme the tree is computed with.
+    hash_scheme: S,
+}
+
+impl<'a, R> StateDiffIterator<'a, R>
+where
+    R: TreeReader,
+{
+    /// Constructs a new iterator over the differences from `old_version` to `new_version`.
+    pub fn new(reader: &'a R, old_version: Version, new_version: Version) -> Result<Self> {
+        Self::new_with_scheme(reader, old_version, new_version, Blake3)
+    }
+}
+
+impl<'a, R, S> StateDiffIterator<'a, R, S>
+where
+    R: TreeReader,
+    S: HashScheme,
+{
+    /// Same as [`new`](struct.StateDiffIterator.html#method.new), for a tree computed with
+    /// `hash_scheme`.
+    pub fn new_with_scheme(
+        reader: &'a R,
+        old_version: Version,
+        new_version: Version,
+        hash_scheme: S,
+    ) -> Result<Self> {
+        let old_root = Self::load_root(reader, old_version)?;
+        let new_root = Self::load_root(reader, new_version)?;
+        Ok(Self {
+            reader,
+            old_version,
+            new_version,
+            with_proofs: false,
+            stack: vec![(old_root, new_root)],
+            pending: VecDeque::new(),
+            hash_scheme,
+        })
+    }
+
+    /// Makes the iterator attach the proofs of each key in both versions to the yielded entries.
+    pub fn with_proofs(mut self) -> Self {
+        self.with_proofs = true;
+        self
+    }
+
+    fn load_root(reader: &R, version: Version) -> Result<Option<Subtree>> {
+        let node_key = NodeKey::new_empty_path(version);
+        Ok(match reader.get_node(&node_key)? {
+            Node::Internal(node) => Some(Subtree::Internal { node_key, node }),
+            Node::Leaf(leaf_node) => Some(Subtree::Leaf(leaf_node)),
+            Node::Null => None,
+        })
+    }
+
+    fn load(&self, subtree: Option<Subtree>) -> Result<Option<Subtree>> {
+        Ok(match subtree {
+            Some(Subtree::Unloaded { node_key, .. }) => match self.reader.get_node(&node_key)? {
+                Node::Internal(node) => Some(Subtree::Internal { node_key, node }),
+                Node::Leaf(leaf_node) => Some(Subtree::Leaf(leaf_node)),
+                Node::Null => bail!("Non-root null node exists with node key {:?}", node_key),
+            },
+            subtree => subtree,
+        })
+    }
+
+    /// Compares the pair of subtrees on top of the stack. Either finds the entries that differ,
+    /// or pushes the pairs of children to compare next.
+    fn step(&mut self) -> Result<()> {
+        let (old, new) = self.stack.pop().expect("The stack must not be empty.");
+        if let (Some(old), Some(new)) = (&old, &new) {
+            let same_node_key = old.node_key().is_some() && old.node_key() == new.node_key();
+            let old_hash = old.hash(&self.hash_scheme);
+            let same_hash = old_hash.is_some() && old_hash == new.hash(&self.hash_scheme);
+            if same_node_key || same_hash {
+                return Ok(());
+            }
+        }
+
+        let old = self.load(old)?;
+        let new = self.load(new)?;
+        match (old, new) {
+            (None, None) => (),
+            (Some(Subtree::Leaf(old)), None) => {
+                let removed = StateDiff::Removed(get_leaf_blob(self.reader, old.clone())?);
+                self.pending.push_back((old.account_key(), removed));
+            }
+            (None, Some(Subtree::Leaf(new))) => {
+                let added = StateDiff::Added(get_leaf_blob(self.reader, new.clone())?);
+                self.pending.push_back((new.account_key(), added));
+            }
+            (Some(Subtree::Leaf(old)), Some(Subtree::Leaf(new))) => {
+                let old_key = old.account_key();
+                let new_key = new.account_key();
+                if old_key == new_key {
+                    if old.blob_hash() != new.blob_hash() {
+                        self.pending.push_back((
+                            old_key,
+                            StateDiff::Modified {
+                                old: get_leaf_blob(self.reader, old)?,
+                                new: get_leaf_blob(self.reader, new)?,
+                            },
+                        ));
+                    }
+                } else {
+                    let removed = (
+                        old_key,
+                        StateDiff::Removed(get_leaf_blob(self.reader, old)?),
+                    );
+                    let added = (new_key, StateDiff::Added(get_leaf_blob(self.reader, new)?));
+                    if old_key < new_key {
+                        self.pending.push_back(removed);
+                        self.pending.push_back(added);
+                    } else {
+                        self.pending.push_back(added);
+                        self.pending.push_back(removed);
+                    }
+                }
+            }
+            (old, new) => {
+                // At least one side is an internal node, which determines where we are.
+                let depth = match (&old, &new) {
+                    (Some(Subtree::Internal { node_key, .. }), _)
+                    | (_, Some(Subtree::Internal { node_key, .. })) => {
+                        node_key.nibble_path().num_nibbles()
+                    }
+                    _ => unreachable!("All other cases have been handled."),
+                };
+                // Push the children in reverse order so the smallest keys are compared first.
+                for i in (0..16u8).rev() {
+                    let n = Nibble::from(i);
+                    let old_child = old.as_ref().and_then(|subtree| subtree.child(n, depth));
+                    let new_child = new.as_ref().and_then(|subtree| subtree.child(n, depth));
+                    if old_child.is_some() || new_child.is_some() {
+                        self.stack.push((old_child, new_child));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn make_entry(&self, key: HashValue, diff: StateDiff) -> Result<StateDiffEntry> {
+        let proofs = if self.with_proofs {
+            let tree = JellyfishMerkleTree::new_with_scheme(
+                self.reader,
+                None, /* leaf_count_mode */
+                self.hash_scheme.clone(),
+            );
+            let (_, old_proof) = tree.get_with_proof(key, self.old_version)?;
+            let (_, new_proof) = tree.get_with_proof(key, self.new_version)?;
+            Some((old_proof, new_proof))
+        } else {
+            None
+        };
+        Ok(StateDiffEntry { key, diff, proofs })
+    }
+}
+
+impl<'a, R, S> Iterator for StateDiffIterator<'a, R, S>
+where
+    R: TreeReader,
+    S: HashScheme,
+{
+    type Item = Result<StateDiffEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((key, diff)) = self.pending.pop_front() {
+                return Some(self.make_entry(key, diff));
+            }
+            if self.stack.is_empty() {
+                return None;
+            }
+            if let Err(err) = self.step() {
+                // Do not yield anything after an error.
+                self.stack.clear();
+                return Some(Err(err));
+            }
+        }
+    }
+}
@@ -0,0 +1,252 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    file_store::{FileStore, SEGMENT_FILE_EXTENSION},
+    mock_tree_store::MockTreeStore,
+    node_type::LeafCountMode,
+    JellyfishMerkleTree, RootVersionReader,
+};
+use libra_crypto::HashValue;
+use libra_temppath::TempPath;
+use libra_types::{account_state_blob::AccountStateBlob, transaction::Version};
+use proptest::{collection::vec, prelude::*};
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Returns the paths of the segment files in `dir`, in order.
+fn segment_paths(dir: &Path) -> Vec<PathBuf> {
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension().and_then(|ext| ext.to_str()) == Some(SEGMENT_FILE_EXTENSION)
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// Puts `blob_set` at `version` into the tree in `store`. Returns the root hash.
+fn put(
+    store: &FileStore,
+    blob_set: Vec<(HashValue, AccountStateBlob)>,
+    version: Version,
+) -> HashValue {
+    let (root_hash, batch) = JellyfishMerkleTree::new(store)
+        .put_blob_set(blob_set, version)
+        .unwrap();
+    store.write_tree_update_batch(&batch).unwrap();
+    root_hash
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(10))]
+
+    #[test]
+    fn test_file_store(
+        batches in vec(
+            vec((any::<HashValue>(), any::<AccountStateBlob>()), 1..50),
+            1..5,
+        ),
+        leaf_count_mode in proptest::option::of(any::<LeafCountMode>()),
+        max_segment_size in 1..10_000u64,
+        least_readable_version in any::<prop::sample::Index>(),
+    ) {
+        let tmp_dir = TempPath::new();
+        let db = MockTreeStore::default();
+        let store = FileStore::open_with_segment_size(tmp_dir.path(), max_segment_size).unwrap();
+        for (version, blob_set) in batches.iter().enumerate() {
+            let tree = match leaf_count_mode {
+                Some(mode) => JellyfishMerkleTree::new_with_leaf_count(&db, mode),
+                None => JellyfishMerkleTree::new(&db),
+            };
+            let (_root_hash, batch) = tree
+                .put_blob_set(blob_set.clone(), version as Version)
+                .unwrap();
+            store.write_tree_update_batch(&batch).unwrap();
+            db.write_tree_update_batch(batch).unwrap();
+        }
+        let least_readable_version = least_readable_version.index(batches.len()) as Version;
+        db.purge_stale_nodes(least_readable_version).unwrap();
+        store.purge_stale_nodes(least_readable_version).unwrap();
+
+        // The store has the same nodes as the mock store, before and after compaction, and after
+        // being reopened each time.
+        for compact in &[false, true] {
+            let store = FileStore::open_with_segment_size(tmp_dir.path(), max_segment_size)
+                .unwrap();
+            if *compact {
+                store.compact().unwrap();
+            }
+            prop_assert_eq!(store.num_nodes(), db.num_nodes());
+            prop_assert_eq!(store.get_root_versions().unwrap(), db.get_root_versions().unwrap());
+            let db_tree = JellyfishMerkleTree::new(&db);
+            let store_tree = JellyfishMerkleTree::new(&store);
+            for version in least_readable_version..batches.len() as Version {
+                prop_assert_eq!(
+                    store_tree.get_root_hash(version).unwrap(),
+                    db_tree.get_root_hash(version).unwrap()
+                );
+                for (key, _blob) in batches.iter().flatten() {
+                    prop_assert_eq!(
+                        store_tree.get_with_proof(*key, version).unwrap(),
+                        db_tree.get_with_proof(*key, version).unwrap()
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_file_store_torn_tail() {
+    let tmp_dir = TempPath::new();
+    let key1 = HashValue::new([0x00; HashValue::LENGTH]);
+    let key2 = HashValue::new([0xff; HashValue::LENGTH]);
+    let (root_hash0, root_hash1) = {
+        let store = FileStore::open(tmp_dir.path()).unwrap();
+        let root_hash0 = put(&store, vec![(key1, AccountStateBlob::from(vec![1u8]))], 0);
+        let root_hash1 = put(&store, vec![(key2, AccountStateBlob::from(vec![2u8]))], 1);
+        (root_hash0, root_hash1)
+    };
+    let segment_path = segment_paths(tmp_dir.path()).pop().unwrap();
+    let segment = fs::read(&segment_path).unwrap();
+
+    // Both writes are in the only segment.
+    let store = FileStore::open(tmp_dir.path()).unwrap();
+    assert_eq!(
+        JellyfishMerkleTree::new(&store).get_root_hash(1).unwrap(),
+        root_hash1
+    );
+    drop(store);
+
+    // Cutting the segment anywhere drops the second write, and the first one too if it is cut.
+    let mut num_cuts_to_version_0 = 0;
+    for cut_len in 1..segment.len() {
+        fs::write(&segment_path, &segment[..cut_len]).unwrap();
+        let store = FileStore::open(tmp_dir.path()).unwrap();
+        let tree = JellyfishMerkleTree::new(&store);
+        // A torn write is dropped as a whole.
+        assert!(tree.get_root_hash(1).is_err());
+        if let Ok(root_hash) = tree.get_root_hash(0) {
+            assert_eq!(root_hash, root_hash0);
+            num_cuts_to_version_0 += 1;
+
+            // The torn tail is truncated, so the store can be written to again.
+            let root_hash = put(&store, vec![(key2, AccountStateBlob::from(vec![2u8]))], 1);
+            assert_eq!(root_hash, root_hash1);
+            drop(store);
+            let store = FileStore::open(tmp_dir.path()).unwrap();
+            assert_eq!(
+                JellyfishMerkleTree::new(&store).get_root_hash(1).unwrap(),
+                root_hash1
+            );
+        }
+    }
+    assert!(num_cuts_to_version_0 > 0);
+
+    // Garbage after the last commit is dropped as well.
+    fs::write(&segment_path, &segment).unwrap();
+    OpenOptions::new()
+        .append(true)
+        .open(&segment_path)
+        .unwrap()
+        .write_all(&[0xab; 100])
+        .unwrap();
+    let store = FileStore::open(tmp_dir.path()).unwrap();
+    assert_eq!(
+        JellyfishMerkleTree::new(&store).get_root_hash(1).unwrap(),
+        root_hash1
+    );
+    assert_eq!(fs::read(&segment_path).unwrap(), segment);
+}
+
+#[test]
+fn test_file_store_corrupted_segment() {
+    let tmp_dir = TempPath::new();
+    {
+        let store = FileStore::open_with_segment_size(tmp_dir.path(), 1).unwrap();
+        for version in 0..3 {
+            put(
+                &store,
+                vec![(HashValue::random(), AccountStateBlob::from(vec![1u8]))],
+                version,
+            );
+        }
+        assert_eq!(store.num_segments(), 3);
+    }
+
+    // Only the last segment may have a torn tail.
+    let first_segment_path = &segment_paths(tmp_dir.path())[0];
+    let mut segment = fs::read(first_segment_path).unwrap();
+    segment.pop();
+    fs::write(first_segment_path, &segment).unwrap();
+    assert!(FileStore::open(tmp_dir.path()).is_err());
+}
+
+#[test]
+fn test_file_store_compact() {
+    let tmp_dir = TempPath::new();
+    let key = HashValue::random();
+    let store = FileStore::open_with_segment_size(tmp_dir.path(), 1).unwrap();
+    let root_hashes: Vec<_> = (0..10)
+        .map(|version| {
+            put(
+                &store,
+                vec![(key, AccountStateBlob::from(vec![version as u8]))],
+                version,
+            )
+        })
+        .collect();
+    assert_eq!(store.num_segments(), 10);
+    assert_eq!(store.num_nodes(), 10);
+
+    store.purge_stale_nodes(9).unwrap();
+    assert_eq!(store.num_nodes(), 1);
+    store.compact().unwrap();
+    assert_eq!(store.num_segments(), 1);
+    assert_eq!(segment_paths(tmp_dir.path()).len(), 1);
+    drop(store);
+
+    let store = FileStore::open(tmp_dir.path()).unwrap();
+    assert_eq!(store.num_nodes(), 1);
+    let tree = JellyfishMerkleTree::new(&store);
+    assert_eq!(tree.get_root_hash(9).unwrap(), root_hashes[9]);
+    assert!(tree.get_root_hash(8).is_err());
+}
+
+#[test]
+fn test_file_store_hash_only_leaves() {
+    let tmp_dir = TempPath::new();
+    let store = FileStore::open_with_segment_size(tmp_dir.path(), 1).unwrap();
+    let kvs: Vec<_> = (0..3u8)
+        .map(|i| (HashValue::random(), AccountStateBlob::from(vec![i; 100])))
+        .collect();
+    for (version, kv) in kvs.iter().enumerate() {
+        let (_root_hash, batch) = JellyfishMerkleTree::new(&store)
+            .with_hash_only_leaves()
+            .put_blob_set(vec![kv.clone()], version as Version)
+            .unwrap();
+        store.write_tree_update_batch(&batch).unwrap();
+    }
+    assert_eq!(store.num_values(), 3);
+
+    let check = |store: &FileStore| {
+        let tree = JellyfishMerkleTree::new(store);
+        for (key, blob) in &kvs {
+            assert_eq!(tree.get_with_proof(*key, 2).unwrap().0.as_ref(), Some(blob));
+        }
+    };
+    check(&store);
+    store.compact().unwrap();
+    check(&store);
+    drop(store);
+
+    let store = FileStore::open(tmp_dir.path()).unwrap();
+    assert_eq!(store.num_values(), 3);
+    check(&store);
+}
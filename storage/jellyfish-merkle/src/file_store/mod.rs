@@ -0,0 +1,665 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module implements [`FileStore`], a log-structured store of the tree in plain files, for
+//! deployments that do not want to depend on RocksDB.
+//!
+//! Everything written is appended as records to segment files in one directory. A record is the
+//! length and checksum of its body followed by the body, which is a tag and an encoded node, an
+//! encoded [`StaleNodeIndex`], a purge of the stale nodes up to some version, a commit, or the
+//! blob of a hash-only leaf. Every write ends with a commit record, and only committed records
+//! count, so a write is atomic.
+//!
+//! Only the location of each node and blob in the segments is kept in memory, along with the stale
+//! node indices. They are rebuilt by replaying the segments when the store is opened. If the last
+//! segment ends with a torn or uncommitted write, e.g. after a crash, it is truncated to the last
+//! commit. Purging stale nodes only appends a purge record, and [`compact`] rewrites the live
+//! nodes, blobs and stale node indices into new segments and deletes the old ones to reclaim the
+//! space. Blobs are never purged.
+//!
+//! [`FileStore`]: struct.FileStore.html
+//! [`StaleNodeIndex`]: ../struct.StaleNodeIndex.html
+//! [`compact`]: struct.FileStore.html#method.compact
+
+#[cfg(test)]
+mod file_store_test;
+
+use crate::{
+    node_type::{Node, NodeKey},
+    NodeBatch, RootVersionReader, StaleNodeIndex, StaleNodeIndexBatch, StaleNodeIndexWriter,
+    TreeReader, TreeUpdateBatch, TreeWriter, ValueBatch,
+};
+use anyhow::{bail, ensure, format_err, Result};
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
+use bytes::Bytes;
+use libra_crypto::HashValue;
+use libra_types::{account_state_blob::AccountStateBlob, transaction::Version};
+use num_derive::{FromPrimitive, ToPrimitive};
+use num_traits::cast::FromPrimitive;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs::{self, File, OpenOptions},
+    io::{BufReader, Cursor, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// A new segment is started once the current one has grown beyond this many bytes.
+pub const DEFAULT_MAX_SEGMENT_SIZE: u64 = 64 << 20;
+
+const SEGMENT_FILE_EXTENSION: &str = "seg";
+
+/// The size of the length and the checksum in front of the body of a record.
+const RECORD_HEADER_SIZE: u64 = 8;
+
+#[repr(u8)]
+#[derive(FromPrimitive, ToPrimitive)]
+enum RecordTag {
+    Node = 0,
+    StaleNodeIndex = 1,
+    Purge = 2,
+    Commit = 3,
+    Value = 4,
+}
+
+/// Where the encoded node with some key, or the blob with some hash, is in the segments.
+#[derive(Clone, Copy, Debug)]
+struct NodeLocation {
+    segment_id: u64,
+    offset: u64,
+    len: u32,
+}
+
+/// The records of one write, encoded.
+#[derive(Default)]
+struct RecordBatch {
+    bytes: Vec<u8>,
+    /// The nodes in the batch, with their offsets in `bytes` and their lengths.
+    nodes: Vec<(NodeKey, u64, u32)>,
+    /// The blobs in the batch, with their offsets in `bytes` and their lengths.
+    values: Vec<(HashValue, u64, u32)>,
+}
+
+impl RecordBatch {
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn len(&self) -> u64 {
+        self.bytes.len() as u64
+    }
+
+    /// Appends a record and returns the offset of its payload, which follows the tag.
+    fn add_record(&mut self, tag: RecordTag, payload: &[u8]) -> u64 {
+        let mut body = Vec::with_capacity(payload.len() + 1);
+        body.push(tag as u8);
+        body.extend_from_slice(payload);
+        self.bytes
+            .write_u32::<LittleEndian>(body.len() as u32)
+            .expect("Writing to a vector never fails.");
+        self.bytes
+            .write_u32::<LittleEndian>(checksum(&body))
+            .expect("Writing to a vector never fails.");
+        self.bytes.extend(body);
+        self.len() - payload.len() as u64
+    }
+
+    fn add_node(&mut self, node_key: &NodeKey, node: &Node) -> Result<()> {
+        let encoded_key = node_key.encode()?;
+        let encoded_node = node.encode()?;
+        let mut payload = Vec::with_capacity(1 + encoded_key.len() + encoded_node.len());
+        payload.push(encoded_key.len() as u8);
+        payload.extend(encoded_key);
+        payload.extend_from_slice(&encoded_node);
+        let payload_offset = self.add_record(RecordTag::Node, &payload);
+        let node_offset = payload_offset + payload.len() as u64 - encoded_node.len() as u64;
+        self.nodes
+            .push((node_key.clone(), node_offset, encoded_node.len() as u32));
+        Ok(())
+    }
+
+    fn add_value(&mut self, blob_hash: &HashValue, blob: &[u8]) {
+        let mut payload = Vec::with_capacity(HashValue::LENGTH + blob.len());
+        payload.extend_from_slice(blob_hash.as_ref());
+        payload.extend_from_slice(blob);
+        let payload_offset = self.add_record(RecordTag::Value, &payload);
+        self.values.push((
+            *blob_hash,
+            payload_offset + HashValue::LENGTH as u64,
+            blob.len() as u32,
+        ));
+    }
+
+    fn add_stale_node_index(&mut self, stale_node_index: &StaleNodeIndex) -> Result<()> {
+        let payload = encode_stale_node_index(stale_node_index)?;
+        self.add_record(RecordTag::StaleNodeIndex, &payload);
+        Ok(())
+    }
+
+    fn add_purge(&mut self, least_readable_version: Version) -> Result<()> {
+        let mut payload = vec![];
+        payload.write_u64::<BigEndian>(least_readable_version)?;
+        self.add_record(RecordTag::Purge, &payload);
+        Ok(())
+    }
+
+    fn add_commit(&mut self) {
+        self.add_record(RecordTag::Commit, &[]);
+    }
+}
+
+/// Returns the checksum of the body of a record, the first four bytes of its hash.
+fn checksum(body: &[u8]) -> u32 {
+    let hash = HashValue::sha3_256_of(body);
+    Cursor::new(hash.as_ref())
+        .read_u32::<LittleEndian>()
+        .expect("Hash is longer than four bytes.")
+}
+
+fn encode_stale_node_index(stale_node_index: &StaleNodeIndex) -> Result<Vec<u8>> {
+    let mut out = vec![];
+    out.write_u64::<BigEndian>(stale_node_index.stale_since_version)?;
+    out.extend(stale_node_index.node_key.encode()?);
+    Ok(out)
+}
+
+fn decode_stale_node_index(val: &[u8]) -> Result<StaleNodeIndex> {
+    let stale_since_version = Cursor::new(val).read_u64::<BigEndian>()?;
+    let node_key = NodeKey::decode(&val[8..])?;
+    Ok(StaleNodeIndex {
+        stale_since_version,
+        node_key,
+    })
+}
+
+/// A committed record, as replayed when the store is opened.
+enum Record {
+    Node(NodeKey, NodeLocation),
+    Value(HashValue, NodeLocation),
+    StaleNodeIndex(StaleNodeIndex),
+    Purge(Version),
+}
+
+/// The state of a [`FileStore`](struct.FileStore.html), guarded by its lock.
+struct Inner {
+    /// Every segment, by id. The last one is the one being appended to.
+    segments: BTreeMap<u64, File>,
+    /// The length of the last segment.
+    active_segment_len: u64,
+    nodes: HashMap<NodeKey, NodeLocation>,
+    values: HashMap<HashValue, NodeLocation>,
+    stale_node_indices: BTreeSet<StaleNodeIndex>,
+}
+
+impl Inner {
+    fn active_segment_id(&self) -> u64 {
+        *self
+            .segments
+            .keys()
+            .next_back()
+            .expect("There is always a segment.")
+    }
+
+    fn read_node(&self, location: NodeLocation) -> Result<Node> {
+        Node::decode(&self.read_bytes(location)?)
+    }
+
+    fn read_bytes(&self, location: NodeLocation) -> Result<Vec<u8>> {
+        let mut file = self
+            .segments
+            .get(&location.segment_id)
+            .ok_or_else(|| format_err!("Missing segment {}.", location.segment_id))?;
+        file.seek(SeekFrom::Start(location.offset))?;
+        let mut bytes = vec![0; location.len as usize];
+        file.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn apply(&mut self, record: Record) -> Result<()> {
+        match record {
+            Record::Node(node_key, location) => {
+                self.nodes.insert(node_key, location);
+            }
+            Record::Value(blob_hash, location) => {
+                self.values.insert(blob_hash, location);
+            }
+            Record::StaleNodeIndex(stale_node_index) => {
+                self.stale_node_indices.insert(stale_node_index);
+            }
+            Record::Purge(least_readable_version) => {
+                // Only records retired before or at `least_readable_version` can be purged in
+                // order to keep that version still readable.
+                let to_prune: Vec<_> = self
+                    .stale_node_indices
+                    .iter()
+                    .take_while(|index| index.stale_since_version <= least_readable_version)
+                    .cloned()
+                    .collect();
+                for index in to_prune {
+                    let removed = self.nodes.remove(&index.node_key).is_some();
+                    ensure!(removed, "Stale node index refers to non-existent node.");
+                    self.stale_node_indices.remove(&index);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts a new segment to append to.
+    fn roll_over(&mut self, dir: &Path) -> Result<()> {
+        let segment_id = self.active_segment_id() + 1;
+        self.segments
+            .insert(segment_id, open_segment(dir, segment_id)?);
+        // Makes sure the new segment is still in the directory after a crash.
+        sync_dir(dir)?;
+        self.active_segment_len = 0;
+        Ok(())
+    }
+
+    /// Appends `batch`, which must end with a commit, to the active segment, starting a new
+    /// segment first if the active one would grow beyond `max_segment_size`.
+    fn append(&mut self, dir: &Path, max_segment_size: u64, batch: RecordBatch) -> Result<()> {
+        if self.active_segment_len > 0 && self.active_segment_len + batch.len() > max_segment_size {
+            self.roll_over(dir)?;
+        }
+        let segment_id = self.active_segment_id();
+        let mut file = &self.segments[&segment_id];
+        file.seek(SeekFrom::Start(self.active_segment_len))?;
+        file.write_all(&batch.bytes)?;
+        file.sync_data()?;
+
+        let segment_offset = self.active_segment_len;
+        self.active_segment_len += batch.len();
+        for (node_key, offset, len) in batch.nodes {
+            self.nodes.insert(
+                node_key,
+                NodeLocation {
+                    segment_id,
+                    offset: segment_offset + offset,
+                    len,
+                },
+            );
+        }
+        for (blob_hash, offset, len) in batch.values {
+            self.values.insert(
+                blob_hash,
+                NodeLocation {
+                    segment_id,
+                    offset: segment_offset + offset,
+                    len,
+                },
+            );
+        }
+        Ok(())
+    }
+}
+
+/// A log-structured store of the tree in the segment files of a directory. See the
+/// [module documentation](index.html) for the layout.
+pub struct FileStore {
+    dir: PathBuf,
+    max_segment_size: u64,
+    inner: Mutex<Inner>,
+}
+
+impl FileStore {
+    /// Opens the store in `dir`, creating it if it does not exist.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        Self::open_with_segment_size(dir, DEFAULT_MAX_SEGMENT_SIZE)
+    }
+
+    /// Same as [`open`](struct.FileStore.html#method.open), but starts a new segment once the
+    /// current one has grown beyond `max_segment_size` bytes.
+    pub fn open_with_segment_size<P: AsRef<Path>>(dir: P, max_segment_size: u64) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let mut segment_ids = vec![];
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(SEGMENT_FILE_EXTENSION) {
+                continue;
+            }
+            let segment_id = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<u64>().ok())
+                .ok_or_else(|| format_err!("Unexpected segment file {:?}.", path))?;
+            segment_ids.push(segment_id);
+        }
+        segment_ids.sort();
+        let is_new = segment_ids.is_empty();
+        if is_new {
+            segment_ids.push(0);
+        }
+
+        let mut inner = Inner {
+            segments: BTreeMap::new(),
+            active_segment_len: 0,
+            nodes: HashMap::new(),
+            values: HashMap::new(),
+            stale_node_indices: BTreeSet::new(),
+        };
+        let last_segment_id = *segment_ids.last().expect("Must exist.");
+        for segment_id in segment_ids {
+            let file = open_segment(&dir, segment_id)?;
+            let file_len = file.metadata()?.len();
+            let committed_len = replay_segment(&file, segment_id, file_len, &mut inner)?;
+            if committed_len < file_len {
+                ensure!(
+                    segment_id == last_segment_id,
+                    "Segment {} is corrupted at offset {}.",
+                    segment_id,
+                    committed_len
+                );
+                // Drops the torn or uncommitted tail of the last write.
+                file.set_len(committed_len)?;
+                file.sync_all()?;
+            }
+            inner.segments.insert(segment_id, file);
+            inner.active_segment_len = committed_len;
+        }
+        if is_new {
+            sync_dir(&dir)?;
+        }
+
+        Ok(Self {
+            dir,
+            max_segment_size,
+            inner: Mutex::new(inner),
+        })
+    }
+
+    /// Writes the nodes, stale node indices and values in `batch` atomically.
+    pub fn write_tree_update_batch(&self, batch: &TreeUpdateBatch) -> Result<()> {
+        self.write(
+            &batch.node_batch,
+            &batch.stale_node_index_batch,
+            &batch.value_batch,
+        )
+    }
+
+    /// Purges the stale nodes that are no longer needed to read `least_readable_version` or
+    /// later versions. The space is only reclaimed by [`compact`](#method.compact).
+    pub fn purge_stale_nodes(&self, least_readable_version: Version) -> Result<()> {
+        let mut batch = RecordBatch::new();
+        batch.add_purge(least_readable_version)?;
+        batch.add_commit();
+        let mut inner = self.inner.lock().unwrap();
+        inner.append(&self.dir, self.max_segment_size, batch)?;
+        inner.apply(Record::Purge(least_readable_version))
+    }
+
+    /// Rewrites the live nodes, values and stale node indices into new segments and deletes the old
+    /// segments.
+    ///
+    /// The old segments are deleted newest first, so if this is interrupted, the segments left
+    /// are replayed to a state that was committed before, plus some of the live records again.
+    pub fn compact(&self) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let old_segment_ids: Vec<_> = inner.segments.keys().cloned().collect();
+        inner.roll_over(&self.dir)?;
+
+        let mut node_keys: Vec<_> = inner.nodes.keys().cloned().collect();
+        node_keys.sort();
+        let mut batch = RecordBatch::new();
+        for node_key in node_keys {
+            if batch.len() >= self.max_segment_size {
+                batch.add_commit();
+                inner.append(&self.dir, self.max_segment_size, batch)?;
+                batch = RecordBatch::new();
+            }
+            let node = inner.read_node(inner.nodes[&node_key])?;
+            batch.add_node(&node_key, &node)?;
+        }
+        let mut blob_hashes: Vec<_> = inner.values.keys().cloned().collect();
+        blob_hashes.sort();
+        for blob_hash in blob_hashes {
+            if batch.len() >= self.max_segment_size {
+                batch.add_commit();
+                inner.append(&self.dir, self.max_segment_size, batch)?;
+                batch = RecordBatch::new();
+            }
+            let blob = inner.read_bytes(inner.values[&blob_hash])?;
+            batch.add_value(&blob_hash, &blob);
+        }
+        for stale_node_index in &inner.stale_node_indices {
+            batch.add_stale_node_index(stale_node_index)?;
+        }
+        batch.add_commit();
+        inner.append(&self.dir, self.max_segment_size, batch)?;
+        // The new segments have to survive a crash before the old ones can go.
+        sync_dir(&self.dir)?;
+
+        for segment_id in old_segment_ids.into_iter().rev() {
+            inner.segments.remove(&segment_id);
+            fs::remove_file(segment_path(&self.dir, segment_id))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of nodes in the store.
+    pub fn num_nodes(&self) -> usize {
+        self.inner.lock().unwrap().nodes.len()
+    }
+
+    /// Returns the number of values in the store.
+    pub fn num_values(&self) -> usize {
+        self.inner.lock().unwrap().values.len()
+    }
+
+    /// Returns the number of segment files of the store.
+    pub fn num_segments(&self) -> usize {
+        self.inner.lock().unwrap().segments.len()
+    }
+
+    fn write(
+        &self,
+        node_batch: &NodeBatch,
+        stale_node_index_batch: &StaleNodeIndexBatch,
+        value_batch: &ValueBatch,
+    ) -> Result<()> {
+        let mut batch = RecordBatch::new();
+        for (node_key, node) in node_batch {
+            batch.add_node(node_key, node)?;
+        }
+        for (blob_hash, blob) in value_batch {
+            batch.add_value(blob_hash, blob.as_ref());
+        }
+        for stale_node_index in stale_node_index_batch {
+            batch.add_stale_node_index(stale_node_index)?;
+        }
+        batch.add_commit();
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.append(&self.dir, self.max_segment_size, batch)?;
+        inner
+            .stale_node_indices
+            .extend(stale_node_index_batch.iter().cloned());
+        Ok(())
+    }
+}
+
+impl TreeReader for FileStore {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        let inner = self.inner.lock().unwrap();
+        match inner.nodes.get(node_key) {
+            Some(location) => Ok(Some(inner.read_node(*location)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn get_encoded_node_option(&self, node_key: &NodeKey) -> Result<Option<Bytes>> {
+        let inner = self.inner.lock().unwrap();
+        match inner.nodes.get(node_key) {
+            Some(location) => Ok(Some(Bytes::from(inner.read_bytes(*location)?))),
+            None => Ok(None),
+        }
+    }
+
+    fn has_encoded_nodes(&self) -> bool {
+        true
+    }
+
+    fn get_value_option(&self, blob_hash: &HashValue) -> Result<Option<AccountStateBlob>> {
+        let inner = self.inner.lock().unwrap();
+        match inner.values.get(blob_hash) {
+            Some(location) => Ok(Some(AccountStateBlob::from(inner.read_bytes(*location)?))),
+            None => Ok(None),
+        }
+    }
+}
+
+impl RootVersionReader for FileStore {
+    fn get_root_versions(&self) -> Result<Vec<Version>> {
+        let mut versions: Vec<_> = self
+            .inner
+            .lock()
+            .unwrap()
+            .nodes
+            .keys()
+            .filter(|node_key| node_key.nibble_path().num_nibbles() == 0)
+            .map(|node_key| node_key.version())
+            .collect();
+        versions.sort();
+        Ok(versions)
+    }
+}
+
+impl TreeWriter for FileStore {
+    fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()> {
+        self.write(node_batch, &StaleNodeIndexBatch::new(), &ValueBatch::new())
+    }
+
+    fn write_value_batch(&self, value_batch: &ValueBatch) -> Result<()> {
+        self.write(&NodeBatch::new(), &StaleNodeIndexBatch::new(), value_batch)
+    }
+}
+
+impl StaleNodeIndexWriter for FileStore {
+    fn write_stale_node_index_batch(
+        &self,
+        stale_node_index_batch: &StaleNodeIndexBatch,
+    ) -> Result<()> {
+        self.write(
+            &NodeBatch::new(),
+            stale_node_index_batch,
+            &ValueBatch::new(),
+        )
+    }
+}
+
+fn segment_path(dir: &Path, segment_id: u64) -> PathBuf {
+    dir.join(format!("{:020}.{}", segment_id, SEGMENT_FILE_EXTENSION))
+}
+
+fn sync_dir(dir: &Path) -> Result<()> {
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+fn open_segment(dir: &Path, segment_id: u64) -> Result<File> {
+    Ok(OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(segment_path(dir, segment_id))?)
+}
+
+/// Applies the committed records of a segment to `inner` and returns the length of the segment up
+/// to the last commit. Reading stops at the first record that is torn or does not match its
+/// checksum.
+fn replay_segment(file: &File, segment_id: u64, file_len: u64, inner: &mut Inner) -> Result<u64> {
+    let mut reader = BufReader::new(file);
+    let mut offset = 0;
+    let mut committed_len = 0;
+    let mut pending = vec![];
+    while let Some(body) = read_record_body(&mut reader, offset, file_len)? {
+        let payload_offset = offset + RECORD_HEADER_SIZE + 1;
+        offset += RECORD_HEADER_SIZE + body.len() as u64;
+        let record = match decode_record(&body, segment_id, payload_offset) {
+            Ok(Some(record)) => record,
+            Ok(None) => {
+                for record in pending.drain(..) {
+                    inner.apply(record)?;
+                }
+                committed_len = offset;
+                continue;
+            }
+            Err(_) => break,
+        };
+        pending.push(record);
+    }
+    Ok(committed_len)
+}
+
+/// Reads the body of the record at `offset`, or returns `None` if the rest of the segment is not a
+/// whole record with a matching checksum.
+fn read_record_body<R: Read>(
+    reader: &mut R,
+    offset: u64,
+    file_len: u64,
+) -> Result<Option<Vec<u8>>> {
+    if offset + RECORD_HEADER_SIZE > file_len {
+        return Ok(None);
+    }
+    let len = reader.read_u32::<LittleEndian>()?;
+    let expected_checksum = reader.read_u32::<LittleEndian>()?;
+    if len == 0 || offset + RECORD_HEADER_SIZE + u64::from(len) > file_len {
+        return Ok(None);
+    }
+    let mut body = vec![0; len as usize];
+    match reader.read_exact(&mut body) {
+        Ok(()) => (),
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+    if checksum(&body) != expected_checksum {
+        return Ok(None);
+    }
+    Ok(Some(body))
+}
+
+/// Decodes the body of a record, whose payload is at `payload_offset` in the segment. Returns
+/// `None` for a commit.
+fn decode_record(body: &[u8], segment_id: u64, payload_offset: u64) -> Result<Option<Record>> {
+    let payload = &body[1..];
+    Ok(Some(match RecordTag::from_u8(body[0]) {
+        Some(RecordTag::Node) => {
+            ensure!(!payload.is_empty(), "Empty node record.");
+            let key_len = payload[0] as usize;
+            ensure!(payload.len() > 1 + key_len, "Node record too short.");
+            let node_key = NodeKey::decode(&payload[1..=key_len])?;
+            let node_offset = 1 + key_len as u64;
+            Record::Node(
+                node_key,
+                NodeLocation {
+                    segment_id,
+                    offset: payload_offset + node_offset,
+                    len: (payload.len() as u64 - node_offset) as u32,
+                },
+            )
+        }
+        Some(RecordTag::StaleNodeIndex) => {
+            Record::StaleNodeIndex(decode_stale_node_index(payload)?)
+        }
+        Some(RecordTag::Purge) => Record::Purge(Cursor::new(payload).read_u64::<BigEndian>()?),
+        Some(RecordTag::Commit) => return Ok(None),
+        Some(RecordTag::Value) => {
+            ensure!(
+                payload.len() >= HashValue::LENGTH,
+                "Value record too short."
+            );
+            let blob_hash = HashValue::from_slice(&payload[..HashValue::LENGTH])?;
+            Record::Value(
+                blob_hash,
+                NodeLocation {
+                    segment_id,
+                    offset: payload_offset + HashValue::LENGTH as u64,
+                    len: (payload.len() - HashValue::LENGTH) as u32,
+                },
+            )
+        }
+        None => bail!("Unknown record tag {}.", body[0]),
+    }))
+}
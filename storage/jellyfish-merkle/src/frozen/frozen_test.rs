@@ -0,0 +1,208 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    frozen::{write_frozen_tree, write_frozen_tree_with_scheme, FrozenTree},
+    iterator::JellyfishMerkleIterator,
+    mock_tree_store::MockTreeStore,
+    node_type::{Node, NodeKey},
+    test_helper::init_mock_db_with_options,
+    JellyfishMerkleTree, TreeReader,
+};
+use anyhow::Result;
+use libra_crypto::{
+    hash::{Blake3, CryptoHash, Sha3_256},
+    HashValue,
+};
+use libra_temppath::TempPath;
+use libra_types::{account_state_blob::AccountStateBlob, transaction::Version};
+use proptest::{collection::vec, prelude::*};
+use std::{collections::BTreeMap, fs};
+
+fn iterate<R: TreeReader>(reader: &R, version: Version) -> Vec<(HashValue, AccountStateBlob)> {
+    JellyfishMerkleIterator::new_borrowed(reader, version, HashValue::zero())
+        .unwrap()
+        .collect::<Result<Vec<_>>>()
+        .unwrap()
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(10))]
+
+    #[test]
+    fn test_frozen_tree(
+        batches in vec(
+            vec((any::<HashValue>(), any::<AccountStateBlob>()), 1..50),
+            1..5,
+        ),
+        other_keys in vec(any::<HashValue>(), 10),
+    ) {
+        let (db, _root_hashes) = init_mock_db_with_options(&batches, None, Blake3);
+        let kvs: BTreeMap<_, _> = batches.iter().flatten().cloned().collect();
+        let version = batches.len() as Version - 1;
+        let path = TempPath::new();
+        let root_hash = write_frozen_tree(&db, version, path.path()).unwrap();
+
+        let frozen = FrozenTree::open(path.path()).unwrap();
+        prop_assert_eq!(frozen.version(), version);
+        prop_assert_eq!(frozen.root_hash().unwrap(), root_hash);
+        prop_assert_eq!(
+            JellyfishMerkleTree::new(&db).get_root_hash(version).unwrap(),
+            root_hash
+        );
+
+        let db_tree = JellyfishMerkleTree::new(&db);
+        let frozen_tree = JellyfishMerkleTree::new(&frozen);
+        for key in kvs.keys().chain(other_keys.iter()) {
+            prop_assert_eq!(
+                frozen_tree.get_with_proof(*key, version).unwrap(),
+                db_tree.get_with_proof(*key, version).unwrap()
+            );
+        }
+        for key in kvs.keys() {
+            prop_assert_eq!(
+                frozen_tree.get_range_proof(*key, version).unwrap(),
+                db_tree.get_range_proof(*key, version).unwrap()
+            );
+        }
+        prop_assert_eq!(
+            iterate(&frozen, version),
+            kvs.iter().map(|(k, v)| (*k, v.clone())).collect::<Vec<_>>()
+        );
+
+    }
+}
+
+#[test]
+fn test_frozen_hash_only_tree() {
+    let kvs: BTreeMap<_, _> = (0..20u8)
+        .map(|i| {
+            (
+                HashValue::random(),
+                AccountStateBlob::from(vec![i; 1 + i as usize]),
+            )
+        })
+        .collect();
+    let db = MockTreeStore::default();
+    let (root_hash, batch) = JellyfishMerkleTree::new(&db)
+        .with_hash_only_leaves()
+        .put_blob_set(kvs.clone().into_iter().collect(), 0 /* version */)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let path = TempPath::new();
+    assert_eq!(write_frozen_tree(&db, 0, path.path()).unwrap(), root_hash);
+
+    let frozen = FrozenTree::open(path.path()).unwrap();
+    let frozen_tree = JellyfishMerkleTree::new(&frozen);
+    for (key, blob) in &kvs {
+        assert_eq!(
+            frozen_tree.get_with_proof(*key, 0).unwrap(),
+            JellyfishMerkleTree::new(&db)
+                .get_with_proof(*key, 0)
+                .unwrap()
+        );
+        assert_eq!(
+            frozen.get_value_option(&blob.hash()).unwrap().as_ref(),
+            Some(blob)
+        );
+    }
+    assert!(frozen
+        .get_value_option(&HashValue::random())
+        .unwrap()
+        .is_none());
+    assert_eq!(iterate(&frozen, 0), kvs.into_iter().collect::<Vec<_>>());
+}
+
+#[test]
+fn test_frozen_tree_with_scheme() {
+    let blob_sets = vec![(0..10u8)
+        .map(|i| (HashValue::random(), AccountStateBlob::from(vec![i])))
+        .collect::<Vec<_>>()];
+    let (db, root_hashes) = init_mock_db_with_options(&blob_sets, None, Sha3_256);
+    let path = TempPath::new();
+    let root_hash = write_frozen_tree_with_scheme(&db, 0, path.path(), &Sha3_256).unwrap();
+    assert_eq!(root_hash, root_hashes[0]);
+
+    let frozen = FrozenTree::open(path.path()).unwrap();
+    assert_eq!(frozen.root_hash_with_scheme(&Sha3_256).unwrap(), root_hash);
+    assert_ne!(frozen.root_hash().unwrap(), root_hash);
+    let frozen_tree = JellyfishMerkleTree::new_with_scheme(&frozen, None, Sha3_256);
+    for (key, blob) in &blob_sets[0] {
+        let (value, proof) = frozen_tree.get_with_proof(*key, 0).unwrap();
+        assert_eq!(value.as_ref(), Some(blob));
+        proof
+            .verify_with_scheme(&Sha3_256, root_hash, *key, value.as_ref())
+            .unwrap();
+    }
+}
+
+#[test]
+fn test_frozen_tree_other_versions() {
+    let key1 = HashValue::new([0x00; HashValue::LENGTH]);
+    let key2 = HashValue::new([0xff; HashValue::LENGTH]);
+    let (db, _root_hashes) = init_mock_db_with_options(
+        &[
+            vec![
+                (key1, AccountStateBlob::from(vec![1u8])),
+                (key2, AccountStateBlob::from(vec![2u8])),
+            ],
+            vec![(key2, AccountStateBlob::from(vec![3u8]))],
+        ],
+        None, /* leaf_count_mode */
+        Blake3,
+    );
+    let path = TempPath::new();
+    write_frozen_tree(&db, 1 /* version */, path.path()).unwrap();
+    let frozen = FrozenTree::open(path.path()).unwrap();
+
+    // Only the nodes reachable from the root at version 1 are in the file, under their own node
+    // keys. The leaf of `key1` was written at version 0.
+    assert!(frozen
+        .get_node_option(&NodeKey::new_empty_path(0))
+        .unwrap()
+        .is_none());
+    let root_node_key = NodeKey::new_empty_path(1);
+    assert!(frozen.get_node_option(&root_node_key).unwrap().is_some());
+    let leaf1_node_key = root_node_key.gen_child_node_key(0, 0.into());
+    assert_eq!(
+        frozen.get_node(&leaf1_node_key).unwrap(),
+        Node::new_leaf(key1, AccountStateBlob::from(vec![1u8]))
+    );
+    assert!(frozen
+        .get_node_option(&root_node_key.gen_child_node_key(1, 0.into()))
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn test_frozen_empty_tree() {
+    let db = MockTreeStore::default();
+    db.put_node(NodeKey::new_empty_path(0), Node::new_null())
+        .unwrap();
+    let path = TempPath::new();
+    write_frozen_tree(&db, 0 /* version */, path.path()).unwrap();
+    let frozen = FrozenTree::open(path.path()).unwrap();
+    assert!(iterate(&frozen, 0).is_empty());
+}
+
+#[test]
+fn test_frozen_tree_corrupted() {
+    let key = HashValue::new([0x00; HashValue::LENGTH]);
+    let (db, _root_hashes) = init_mock_db_with_options(
+        &[vec![(key, AccountStateBlob::from(vec![1u8]))]],
+        None, /* leaf_count_mode */
+        Blake3,
+    );
+    let path = TempPath::new();
+    write_frozen_tree(&db, 0 /* version */, path.path()).unwrap();
+    let bytes = fs::read(path.path()).unwrap();
+
+    let mut bad_magic = bytes.clone();
+    bad_magic[0] ^= 1;
+    assert!(FrozenTree::new(bad_magic).is_err());
+
+    let truncated = bytes[..bytes.len() - 1].to_vec();
+    assert!(FrozenTree::new(truncated).is_err());
+
+    assert!(FrozenTree::new(bytes).is_ok());
+}
@@ -0,0 +1,418 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module implements a compact read-only file holding all the nodes of the tree at a single
+//! version, along with the blobs of its hash-only leaves, so that a checkpoint can be published as
+//! one immutable file and queried in place.
+//! [`FrozenTree`] implements [`TreeReader`] over the bytes of such a file, which may come from
+//! reading the file or from memory mapping it, so `get_with_proof`, range proofs and iteration
+//! all work against the file without importing it into a database.
+//!
+//! The layout of the file is as follows, with all integers in little endian:
+//!
+//! ```text
+//! +--------+---------+-----------+--------------+------------+--------------------+
+//! | magic  | version | num_nodes | index_offset | num_values | value_index_offset |
+//! | 8 byte | u64     | u64       | u64          | u64        | u64                |
+//! +--------+---------+-----------+--------------+------------+--------------------+
+//! +-------+-----+-------+-----+-------+-------------+
+//! | node  | ... | value | ... | index | value index |
+//! | bytes |     | bytes |     |       |             |
+//! +-------+-----+-------+-----+-------+-------------+
+//! ```
+//!
+//! Nodes are encoded by [`Node::encode`] and stored one after another, followed by the blobs of
+//! the hash-only leaves. The index at `index_offset` has `num_nodes` fixed-size entries sorted by
+//! nibble path, so a node can be found by binary search. Each entry is:
+//!
+//! ```text
+//! +-----------------------------+-------------+--------------+--------+--------+
+//! | nibble path, zero padded    | num_nibbles | node version | offset | length |
+//! | 32 byte                     | u8          | u64          | u64    | u32    |
+//! +-----------------------------+-------------+--------------+--------+--------+
+//! ```
+//!
+//! Every node reachable from the root of a version has a distinct nibble path, so the node
+//! version is only stored to tell apart node keys that do not belong to the frozen version.
+//!
+//! The value index at `value_index_offset` has `num_values` fixed-size entries sorted by blob
+//! hash, each of which is:
+//!
+//! ```text
+//! +-----------+--------+--------+
+//! | blob hash | offset | length |
+//! | 32 byte   | u64    | u32    |
+//! +-----------+--------+--------+
+//! ```
+//!
+//! [`FrozenTree`]: struct.FrozenTree.html
+//! [`TreeReader`]: ../trait.TreeReader.html
+//! [`Node::encode`]: ../node_type/enum.Node.html#method.encode
+
+#[cfg(test)]
+mod frozen_test;
+
+use crate::{
+    nibble_path::NibblePath,
+    node_type::{Node, NodeKey},
+    TreeReader,
+};
+use anyhow::{ensure, format_err, Context, Result};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use libra_crypto::{
+    hash::{Blake3, HashScheme},
+    HashValue,
+};
+use libra_nibble::Nibble;
+use libra_types::{account_state_blob::AccountStateBlob, transaction::Version};
+use std::{
+    cmp::Ordering,
+    collections::BTreeSet,
+    fs::{self, File},
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+/// The magic bytes at the start of a frozen tree file. The last byte is the format version.
+const MAGIC: &[u8; 8] = b"JMTFROZ\x02";
+
+/// The size of the header: magic, version, number of nodes, offset of the index, number of values
+/// and offset of the value index.
+const HEADER_SIZE: usize = 8 + 8 + 8 + 8 + 8 + 8;
+
+/// The size of each index entry: padded nibble path, number of nibbles, node version, offset
+/// and length.
+const INDEX_ENTRY_SIZE: usize = HashValue::LENGTH + 1 + 8 + 8 + 4;
+
+/// The size of each value index entry: blob hash, offset and length.
+const VALUE_INDEX_ENTRY_SIZE: usize = HashValue::LENGTH + 8 + 4;
+
+/// Writes all the nodes of the tree at `version`, along with the blobs of its hash-only leaves,
+/// into a frozen tree file at `path`. Returns the root hash of the tree.
+pub fn write_frozen_tree<R: TreeReader>(
+    reader: &R,
+    version: Version,
+    path: &Path,
+) -> Result<HashValue> {
+    write_frozen_tree_with_scheme(reader, version, path, &Blake3)
+}
+
+/// Same as [`write_frozen_tree`](fn.write_frozen_tree.html), for a tree computed with
+/// `hash_scheme`, which is only used for the returned root hash.
+pub fn write_frozen_tree_with_scheme<R: TreeReader, S: HashScheme>(
+    reader: &R,
+    version: Version,
+    path: &Path,
+    hash_scheme: &S,
+) -> Result<HashValue> {
+    let file = File::create(path).with_context(|| format!("Failed to create {:?}.", path))?;
+    let mut writer = BufWriter::new(file);
+    // The header is written last, once the numbers of entries and the offsets of the indices are
+    // known.
+    writer.write_all(&[0u8; HEADER_SIZE])?;
+
+    let root_node_key = NodeKey::new_empty_path(version);
+    let root_hash = reader
+        .get_node(&root_node_key)?
+        .hash_with_scheme(hash_scheme);
+    let mut offset = HEADER_SIZE as u64;
+    let mut index = vec![];
+    let mut blob_hashes = BTreeSet::new();
+    let mut stack = vec![root_node_key];
+    while let Some(node_key) = stack.pop() {
+        let node = reader.get_node(&node_key)?;
+        match &node {
+            Node::Internal(internal_node) => {
+                // Push the children in reverse order so the nodes are written in order of nibble
+                // paths.
+                for i in (0..16u8).rev() {
+                    let n = Nibble::from(i);
+                    if let Some(child) = internal_node.child(n) {
+                        stack.push(node_key.gen_child_node_key(child.version, n));
+                    }
+                }
+            }
+            Node::Leaf(leaf_node) if leaf_node.is_hash_only() => {
+                blob_hashes.insert(leaf_node.blob_hash());
+            }
+            _ => (),
+        }
+        let node_bytes = node.encode()?;
+        writer.write_all(&node_bytes)?;
+        index.push(IndexEntry {
+            path: IndexEntry::pad_path(node_key.nibble_path()),
+            num_nibbles: node_key.nibble_path().num_nibbles() as u8,
+            version: node_key.version(),
+            offset,
+            len: node_bytes.len() as u32,
+        });
+        offset += node_bytes.len() as u64;
+    }
+
+    // The blobs are written in order of their hashes, so the value index is already sorted.
+    let mut value_index = vec![];
+    for blob_hash in blob_hashes {
+        let blob = reader.get_value(&blob_hash)?;
+        writer.write_all(blob.as_ref())?;
+        value_index.push(ValueIndexEntry {
+            blob_hash,
+            offset,
+            len: blob.as_ref().len() as u32,
+        });
+        offset += blob.as_ref().len() as u64;
+    }
+
+    let index_offset = offset;
+    index.sort_by(|a, b| a.cmp_path(&b.path, b.num_nibbles));
+    for entry in &index {
+        entry.serialize(&mut writer)?;
+    }
+    let value_index_offset = index_offset + (index.len() * INDEX_ENTRY_SIZE) as u64;
+    for entry in &value_index {
+        entry.serialize(&mut writer)?;
+    }
+
+    writer.seek(SeekFrom::Start(0))?;
+    writer.write_all(MAGIC)?;
+    writer.write_u64::<LittleEndian>(version)?;
+    writer.write_u64::<LittleEndian>(index.len() as u64)?;
+    writer.write_u64::<LittleEndian>(index_offset)?;
+    writer.write_u64::<LittleEndian>(value_index.len() as u64)?;
+    writer.write_u64::<LittleEndian>(value_index_offset)?;
+    writer.flush()?;
+    Ok(root_hash)
+}
+
+/// An entry of the index of a frozen tree file.
+struct IndexEntry {
+    path: [u8; HashValue::LENGTH],
+    num_nibbles: u8,
+    version: Version,
+    offset: u64,
+    len: u32,
+}
+
+impl IndexEntry {
+    fn pad_path(nibble_path: &NibblePath) -> [u8; HashValue::LENGTH] {
+        let mut path = [0u8; HashValue::LENGTH];
+        path[..nibble_path.bytes().len()].copy_from_slice(nibble_path.bytes());
+        path
+    }
+
+    fn cmp_path(&self, path: &[u8; HashValue::LENGTH], num_nibbles: u8) -> Ordering {
+        (&self.path, self.num_nibbles).cmp(&(path, num_nibbles))
+    }
+
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&self.path)?;
+        writer.write_u8(self.num_nibbles)?;
+        writer.write_u64::<LittleEndian>(self.version)?;
+        writer.write_u64::<LittleEndian>(self.offset)?;
+        writer.write_u32::<LittleEndian>(self.len)?;
+        Ok(())
+    }
+
+    fn deserialize(mut data: &[u8]) -> Result<Self> {
+        let mut path = [0u8; HashValue::LENGTH];
+        path.copy_from_slice(&data[..HashValue::LENGTH]);
+        data = &data[HashValue::LENGTH..];
+        Ok(Self {
+            path,
+            num_nibbles: data.read_u8()?,
+            version: data.read_u64::<LittleEndian>()?,
+            offset: data.read_u64::<LittleEndian>()?,
+            len: data.read_u32::<LittleEndian>()?,
+        })
+    }
+}
+
+/// An entry of the value index of a frozen tree file.
+struct ValueIndexEntry {
+    blob_hash: HashValue,
+    offset: u64,
+    len: u32,
+}
+
+impl ValueIndexEntry {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(self.blob_hash.as_ref())?;
+        writer.write_u64::<LittleEndian>(self.offset)?;
+        writer.write_u32::<LittleEndian>(self.len)?;
+        Ok(())
+    }
+
+    fn deserialize(mut data: &[u8]) -> Result<Self> {
+        let blob_hash = HashValue::from_slice(&data[..HashValue::LENGTH])?;
+        data = &data[HashValue::LENGTH..];
+        Ok(Self {
+            blob_hash,
+            offset: data.read_u64::<LittleEndian>()?,
+            len: data.read_u32::<LittleEndian>()?,
+        })
+    }
+}
+
+/// A read-only view of the tree at a single version, backed by the bytes of a frozen tree file.
+pub struct FrozenTree<B> {
+    /// The bytes of the whole file.
+    bytes: B,
+
+    /// The version of the tree in the file.
+    version: Version,
+
+    /// The number of nodes in the file.
+    num_nodes: usize,
+
+    /// The offset of the index in the file.
+    index_offset: usize,
+
+    /// The number of blobs of hash-only leaves in the file.
+    num_values: usize,
+
+    /// The offset of the value index in the file.
+    value_index_offset: usize,
+}
+
+impl FrozenTree<Vec<u8>> {
+    /// Reads the frozen tree file at `path` into memory.
+    pub fn open(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path).with_context(|| format!("Failed to read {:?}.", path))?;
+        Self::new(bytes)
+    }
+}
+
+impl<B: AsRef<[u8]>> FrozenTree<B> {
+    /// Creates a `FrozenTree` over the bytes of a frozen tree file, for example a memory map of
+    /// the file.
+    pub fn new(bytes: B) -> Result<Self> {
+        let mut header = bytes.as_ref();
+        ensure!(
+            header.len() >= HEADER_SIZE,
+            "Frozen tree file is too short."
+        );
+        ensure!(
+            &header[..MAGIC.len()] == MAGIC,
+            "Not a frozen tree file, or unsupported format version."
+        );
+        header = &header[MAGIC.len()..];
+        let version = header.read_u64::<LittleEndian>()?;
+        let num_nodes = header.read_u64::<LittleEndian>()? as usize;
+        let index_offset = header.read_u64::<LittleEndian>()? as usize;
+        let num_values = header.read_u64::<LittleEndian>()? as usize;
+        let value_index_offset = header.read_u64::<LittleEndian>()? as usize;
+        ensure!(
+            index_offset >= HEADER_SIZE
+                && num_nodes
+                    .checked_mul(INDEX_ENTRY_SIZE)
+                    .and_then(|index_size| index_size.checked_add(index_offset))
+                    == Some(value_index_offset)
+                && num_values
+                    .checked_mul(VALUE_INDEX_ENTRY_SIZE)
+                    .and_then(|index_size| index_size.checked_add(value_index_offset))
+                    == Some(bytes.as_ref().len()),
+            "Frozen tree file has corrupted header.",
+        );
+        Ok(Self {
+            bytes,
+            version,
+            num_nodes,
+            index_offset,
+            num_values,
+            value_index_offset,
+        })
+    }
+
+    /// Returns the version of the tree in this file.
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// Returns the root hash of the tree in this file.
+    pub fn root_hash(&self) -> Result<HashValue> {
+        self.root_hash_with_scheme(&Blake3)
+    }
+
+    /// Same as [`root_hash`](struct.FrozenTree.html#method.root_hash), for a tree computed with
+    /// `hash_scheme`.
+    pub fn root_hash_with_scheme<S: HashScheme>(&self, hash_scheme: &S) -> Result<HashValue> {
+        Ok(self
+            .get_node(&NodeKey::new_empty_path(self.version))?
+            .hash_with_scheme(hash_scheme))
+    }
+
+    fn index_entry(&self, i: usize) -> Result<IndexEntry> {
+        let start = self.index_offset + i * INDEX_ENTRY_SIZE;
+        IndexEntry::deserialize(&self.bytes.as_ref()[start..start + INDEX_ENTRY_SIZE])
+    }
+
+    /// Finds the index entry of the node at `nibble_path` by binary search.
+    fn find(&self, nibble_path: &NibblePath) -> Result<Option<IndexEntry>> {
+        let path = IndexEntry::pad_path(nibble_path);
+        let num_nibbles = nibble_path.num_nibbles() as u8;
+        let (mut lo, mut hi) = (0, self.num_nodes);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let entry = self.index_entry(mid)?;
+            match entry.cmp_path(&path, num_nibbles) {
+                Ordering::Less => lo = mid + 1,
+                Ordering::Greater => hi = mid,
+                Ordering::Equal => return Ok(Some(entry)),
+            }
+        }
+        Ok(None)
+    }
+
+    fn value_index_entry(&self, i: usize) -> Result<ValueIndexEntry> {
+        let start = self.value_index_offset + i * VALUE_INDEX_ENTRY_SIZE;
+        ValueIndexEntry::deserialize(&self.bytes.as_ref()[start..start + VALUE_INDEX_ENTRY_SIZE])
+    }
+
+    /// Finds the value index entry of the blob with `blob_hash` by binary search.
+    fn find_value(&self, blob_hash: &HashValue) -> Result<Option<ValueIndexEntry>> {
+        let (mut lo, mut hi) = (0, self.num_values);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let entry = self.value_index_entry(mid)?;
+            match entry.blob_hash.cmp(blob_hash) {
+                Ordering::Less => lo = mid + 1,
+                Ordering::Greater => hi = mid,
+                Ordering::Equal => return Ok(Some(entry)),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the bytes at `offset` with length `len`, which must be between the header and the
+    /// index.
+    fn data(&self, offset: u64, len: u32) -> Option<&[u8]> {
+        let start = offset as usize;
+        let end = start
+            .checked_add(len as usize)
+            .filter(|end| start >= HEADER_SIZE && *end <= self.index_offset)?;
+        Some(&self.bytes.as_ref()[start..end])
+    }
+}
+
+impl<B: AsRef<[u8]>> TreeReader for FrozenTree<B> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        let entry = match self.find(node_key.nibble_path())? {
+            Some(entry) if entry.version == node_key.version() => entry,
+            _ => return Ok(None),
+        };
+        let node_bytes = self
+            .data(entry.offset, entry.len)
+            .ok_or_else(|| format_err!("Node {:?} is out of bounds.", node_key))?;
+        Ok(Some(Node::decode(node_bytes)?))
+    }
+
+    fn get_value_option(&self, blob_hash: &HashValue) -> Result<Option<AccountStateBlob>> {
+        let entry = match self.find_value(blob_hash)? {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let blob = self
+            .data(entry.offset, entry.len)
+            .ok_or_else(|| format_err!("Value with hash {:x} is out of bounds.", blob_hash))?;
+        Ok(Some(AccountStateBlob::from(blob.to_vec())))
+    }
+}
@@ -2,14 +2,20 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
-    iterator::JellyfishMerkleIterator, mock_tree_store::MockTreeStore, test_helper::plus_one,
+    iterator::{IterDirection, JellyfishMerkleIterator},
+    mock_tree_store::MockTreeStore,
+    test_helper::plus_one,
     JellyfishMerkleTree,
 };
 use anyhow::Result;
 use libra_crypto::HashValue;
 use libra_types::{account_state_blob::AccountStateBlob, transaction::Version};
 use rand::{rngs::StdRng, SeedableRng};
-use std::{collections::BTreeMap, sync::Arc};
+use std::{
+    collections::BTreeMap,
+    ops::Bound::{Excluded, Included},
+    sync::Arc,
+};
 
 #[test]
 fn test_iterator_same_version() {
@@ -106,4 +112,154 @@ fn run_tests(
         .unwrap();
         assert_eq!(iter.collect::<Result<Vec<_>>>().unwrap(), vec![]);
     }
+
+    run_reverse_tests(&db, btree, version);
+    run_bounded_tests(&db, btree, version);
+    run_seek_tests(&db, btree, version);
+}
+
+fn run_reverse_tests(
+    db: &MockTreeStore,
+    btree: &BTreeMap<HashValue, AccountStateBlob>,
+    version: Version,
+) {
+    let reverse_iter = |starting_key| {
+        JellyfishMerkleIterator::new_with_direction(
+            db,
+            version,
+            starting_key,
+            IterDirection::Reverse,
+        )
+        .unwrap()
+        .collect::<Result<Vec<_>>>()
+        .unwrap()
+    };
+
+    assert_eq!(
+        reverse_iter(HashValue::new([0xFF; HashValue::LENGTH])),
+        btree.clone().into_iter().rev().collect::<Vec<_>>(),
+    );
+
+    for i in 0..btree.len() {
+        let ith_key = *btree.keys().nth(i).unwrap();
+        assert_eq!(
+            reverse_iter(ith_key),
+            btree
+                .clone()
+                .into_iter()
+                .take(i + 1)
+                .rev()
+                .collect::<Vec<_>>(),
+        );
+        if ith_key != HashValue::zero() {
+            assert_eq!(
+                reverse_iter(minus_one(ith_key)),
+                btree.clone().into_iter().take(i).rev().collect::<Vec<_>>(),
+            );
+        }
+    }
+
+    if !btree.contains_key(&HashValue::zero()) {
+        assert_eq!(reverse_iter(HashValue::zero()), vec![]);
+    }
+}
+
+fn run_bounded_tests(
+    db: &MockTreeStore,
+    btree: &BTreeMap<HashValue, AccountStateBlob>,
+    version: Version,
+) {
+    let keys: Vec<_> = btree.keys().cloned().collect();
+    for i in 0..keys.len() {
+        for j in i..keys.len() {
+            let iter = JellyfishMerkleIterator::new_borrowed(db, version, keys[i])
+                .unwrap()
+                .with_end_key(keys[j]);
+            assert_eq!(
+                iter.collect::<Result<Vec<_>>>().unwrap(),
+                btree
+                    .range(keys[i]..keys[j])
+                    .map(|(k, v)| (*k, v.clone()))
+                    .collect::<Vec<_>>(),
+            );
+
+            let iter = JellyfishMerkleIterator::new_with_direction(
+                db,
+                version,
+                keys[j],
+                IterDirection::Reverse,
+            )
+            .unwrap()
+            .with_end_key(keys[i]);
+            assert_eq!(
+                iter.collect::<Result<Vec<_>>>().unwrap(),
+                btree
+                    .range((Excluded(keys[i]), Included(keys[j])))
+                    .rev()
+                    .map(|(k, v)| (*k, v.clone()))
+                    .collect::<Vec<_>>(),
+            );
+        }
+    }
+}
+
+fn run_seek_tests(
+    db: &MockTreeStore,
+    btree: &BTreeMap<HashValue, AccountStateBlob>,
+    version: Version,
+) {
+    let keys: Vec<_> = btree.keys().cloned().collect();
+    let mut iter = JellyfishMerkleIterator::new_borrowed(db, version, HashValue::zero()).unwrap();
+    let mut reverse_iter = JellyfishMerkleIterator::new_with_direction(
+        db,
+        version,
+        HashValue::zero(),
+        IterDirection::Reverse,
+    )
+    .unwrap();
+    assert_eq!(reverse_iter.direction(), IterDirection::Reverse);
+
+    // Seek back and forth, partially consuming the iterators in between.
+    for i in (0..keys.len()).rev().chain(0..keys.len()) {
+        iter.seek(keys[i]).unwrap();
+        assert_eq!(iter.next().map(|res| res.unwrap().0), Some(keys[i]));
+        assert_eq!(
+            iter.next().map(|res| res.unwrap().0),
+            keys.get(i + 1).cloned()
+        );
+
+        iter.seek(plus_one(keys[i])).unwrap();
+        assert_eq!(
+            iter.next().map(|res| res.unwrap().0),
+            keys.get(i + 1).cloned()
+        );
+
+        reverse_iter.seek(keys[i]).unwrap();
+        assert_eq!(reverse_iter.next().map(|res| res.unwrap().0), Some(keys[i]));
+        assert_eq!(
+            reverse_iter.next().map(|res| res.unwrap().0),
+            i.checked_sub(1).map(|j| keys[j]),
+        );
+    }
+
+    // Seeking after exhaustion restarts the iteration.
+    assert!(iter.by_ref().all(|res| res.is_ok()));
+    iter.seek(HashValue::zero()).unwrap();
+    assert_eq!(
+        iter.collect::<Result<Vec<_>>>().unwrap(),
+        btree.clone().into_iter().collect::<Vec<_>>(),
+    );
+}
+
+fn minus_one(key: HashValue) -> HashValue {
+    let mut buf = key.to_vec();
+    for i in (0..HashValue::LENGTH).rev() {
+        if buf[i] == 0 {
+            buf[i] = 255;
+        } else {
+            buf[i] -= 1;
+            break;
+        }
+    }
+    HashValue::from_slice(&buf).unwrap()
 }
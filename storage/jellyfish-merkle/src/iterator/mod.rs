@@ -4,21 +4,32 @@
 //! This module implements `JellyfishMerkleIterator`. Initialized with a version and a key, the
 //! iterator generates all the key-value pairs in this version of the tree, starting from the
 //! smallest key that is greater or equal to the given key, by performing a depth first traversal
-//! on the tree.
+//! on the tree. The iterator can also run in reverse, starting from the largest key that is less
+//! or equal to the given key, stop at an exclusive end key, and be repositioned with `seek`.
 
 #[cfg(test)]
 mod iterator_test;
 
 use crate::{
+    get_leaf_blob,
     nibble_path::NibblePath,
-    node_type::{InternalNode, Node, NodeKey},
+    node_type::{InternalNode, LeafNode, Node, NodeKey},
     TreeReader,
 };
 use anyhow::{format_err, Result};
 use libra_crypto::HashValue;
 use libra_nibble::Nibble;
 use libra_types::{account_state_blob::AccountStateBlob, transaction::Version};
-use std::sync::Arc;
+use std::{marker::PhantomData, ops::Deref, sync::Arc};
+
+/// The order in which a [`JellyfishMerkleIterator`] yields keys.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IterDirection {
+    /// From smaller keys to bigger keys.
+    Forward,
+    /// From bigger keys to smaller keys.
+    Reverse,
+}
 
 /// `NodeVisitInfo` keeps track of the status of an internal node during the iteration process. It
 /// indicates which ones of its children have been visited.
@@ -35,76 +46,115 @@ struct NodeVisitInfo {
     children_bitmap: u16,
 
     /// This integer always has exactly one 1-bit. The position of the 1-bit (from LSB) indicates
-    /// the next child to visit in the iteration process. All the ones on the left have already
-    /// been visited. All the chilren on the right (including this one) have not been visited yet.
+    /// the next child to visit in the iteration process. When iterating forward, all the ones on
+    /// the left have already been visited and all the children on the right (including this one)
+    /// have not been visited yet. When iterating in reverse it is the other way around.
     next_child_to_visit: u16,
 }
 
 impl NodeVisitInfo {
     /// Constructs a new `NodeVisitInfo` with given node key and node. `next_child_to_visit` will
-    /// be set to the leftmost child.
-    fn new(node_key: NodeKey, node: InternalNode) -> Self {
+    /// be set to the first child in `direction`, i.e., the leftmost child when iterating forward
+    /// and the rightmost child when iterating in reverse.
+    fn new(node_key: NodeKey, node: InternalNode, direction: IterDirection) -> Self {
         let (children_bitmap, _) = node.generate_bitmaps();
+        let next_child_to_visit = match direction {
+            IterDirection::Forward => 1 << children_bitmap.trailing_zeros(),
+            IterDirection::Reverse => 1 << (15 - children_bitmap.leading_zeros()),
+        };
         Self {
             node_key,
             node,
             children_bitmap,
-            next_child_to_visit: 1 << children_bitmap.trailing_zeros(),
+            next_child_to_visit,
         }
     }
 
     /// Same as `new` but points `next_child_to_visit` to a specific location. If the child
-    /// corresponding to `next_child_to_visit` does not exist, set it to the next one on the
-    /// right.
+    /// corresponding to `next_child_to_visit` does not exist, set it to the next one in
+    /// `direction`. Returns `None` if there is no such child.
     fn new_next_child_to_visit(
         node_key: NodeKey,
         node: InternalNode,
         next_child_to_visit: Nibble,
-    ) -> Self {
+        direction: IterDirection,
+    ) -> Option<Self> {
         let (children_bitmap, _) = node.generate_bitmaps();
-        let mut next_child_to_visit = 1 << u8::from(next_child_to_visit);
-        while next_child_to_visit & children_bitmap == 0 {
-            next_child_to_visit <<= 1;
+        // Use a wider integer so shifting past the leftmost child does not overflow.
+        let mut next_child_to_visit = 1u32 << u8::from(next_child_to_visit);
+        while next_child_to_visit != 0
+            && next_child_to_visit <= 0x8000
+            && next_child_to_visit & u32::from(children_bitmap) == 0
+        {
+            match direction {
+                IterDirection::Forward => next_child_to_visit <<= 1,
+                IterDirection::Reverse => next_child_to_visit >>= 1,
+            }
         }
-        Self {
+        if next_child_to_visit == 0 || next_child_to_visit > 0x8000 {
+            return None;
+        }
+        Some(Self {
             node_key,
             node,
             children_bitmap,
-            next_child_to_visit,
-        }
+            next_child_to_visit: next_child_to_visit as u16,
+        })
     }
 
-    /// Whether the next child to visit is the rightmost one.
-    fn is_rightmost(&self) -> bool {
-        assert!(self.next_child_to_visit.leading_zeros() >= self.children_bitmap.leading_zeros());
-        self.next_child_to_visit.leading_zeros() == self.children_bitmap.leading_zeros()
+    /// Whether the next child to visit is the last one in `direction`.
+    fn is_last(&self, direction: IterDirection) -> bool {
+        assert_ne!(self.next_child_to_visit & self.children_bitmap, 0);
+        match direction {
+            IterDirection::Forward => {
+                self.next_child_to_visit.leading_zeros() == self.children_bitmap.leading_zeros()
+            }
+            IterDirection::Reverse => {
+                self.next_child_to_visit.trailing_zeros() == self.children_bitmap.trailing_zeros()
+            }
+        }
     }
 
-    /// Advances `next_child_to_visit` to the next child on the right.
-    fn advance(&mut self) {
-        assert!(!self.is_rightmost(), "Advancing past rightmost child.");
-        self.next_child_to_visit <<= 1;
-        while self.next_child_to_visit & self.children_bitmap == 0 {
-            self.next_child_to_visit <<= 1;
+    /// Advances `next_child_to_visit` to the next child in `direction`.
+    fn advance(&mut self, direction: IterDirection) {
+        assert!(!self.is_last(direction), "Advancing past the last child.");
+        loop {
+            match direction {
+                IterDirection::Forward => self.next_child_to_visit <<= 1,
+                IterDirection::Reverse => self.next_child_to_visit >>= 1,
+            }
+            if self.next_child_to_visit & self.children_bitmap != 0 {
+                break;
+            }
         }
     }
 }
 
-/// The `JellyfishMerkleIterator` implementation.
-pub struct JellyfishMerkleIterator<R: TreeReader> {
+/// The `JellyfishMerkleIterator` implementation. `D` is the handle through which the iterator
+/// reaches the [`TreeReader`], either an `Arc<R>` (see [`new`](JellyfishMerkleIterator::new)) or
+/// a plain reference (see [`new_borrowed`](JellyfishMerkleIterator::new_borrowed)).
+pub struct JellyfishMerkleIterator<R, D = Arc<R>> {
     /// The storage engine from which we can read nodes using node keys.
-    reader: Arc<R>,
+    reader: D,
 
     /// The version of the tree this iterator is running on.
     version: Version,
 
+    /// The order in which keys are yielded.
+    direction: IterDirection,
+
+    /// If set, the iteration stops before reaching this key.
+    end_key: Option<HashValue>,
+
     /// The stack used for depth first traversal.
     parent_stack: Vec<NodeVisitInfo>,
 
     /// Whether the iteration has finished. Usually this can be determined by checking whether
-    /// `self.parent_stack` is empty. But in case of a tree with a single leaf, we need this
-    /// additional bit.
+    /// `self.parent_stack` is empty. But in case of a tree with a single leaf, or when the end key
+    /// has been reached, we need this additional bit.
     done: bool,
+
+    phantom: PhantomData<R>,
 }
 
 impl<R> JellyfishMerkleIterator<R>
@@ -115,87 +165,204 @@ where
     /// following `next` call will yield the smallest key that is greater or equal to
     /// `starting_key`.
     pub fn new(reader: Arc<R>, version: Version, starting_key: HashValue) -> Result<Self> {
-        let mut parent_stack = vec![];
-        let mut done = false;
-
-        let mut current_node_key = NodeKey::new_empty_path(version);
-        let nibble_path = NibblePath::new(starting_key.to_vec());
-        let mut nibble_iter = nibble_path.nibbles();
-
-        while let Node::Internal(internal_node) = reader.get_node(&current_node_key)? {
-            let child_index = nibble_iter.next().expect("Should have enough nibbles.");
-            match internal_node.child(child_index) {
-                Some(child) => {
-                    // If this child exists, we just push the node onto stack and repeat.
-                    parent_stack.push(NodeVisitInfo::new_next_child_to_visit(
-                        current_node_key.clone(),
-                        internal_node.clone(),
-                        child_index,
-                    ));
-                    current_node_key =
-                        current_node_key.gen_child_node_key(child.version, child_index);
-                }
-                None => {
-                    let (bitmap, _) = internal_node.generate_bitmaps();
-                    if u32::from(u8::from(child_index)) < 15 - bitmap.leading_zeros() {
-                        // If this child does not exist and there's another child on the right, we
-                        // set the child on the right to be the next one to visit.
-                        parent_stack.push(NodeVisitInfo::new_next_child_to_visit(
-                            current_node_key,
-                            internal_node,
-                            child_index,
-                        ));
-                    } else {
-                        // Otherwise we have done visiting this node. Go backward and clean up the
-                        // stack.
-                        Self::cleanup_stack(&mut parent_stack);
-                    }
-                    return Ok(Self {
-                        reader,
-                        version,
-                        parent_stack,
-                        done,
-                    });
-                }
+        Self::new_with_direction(reader, version, starting_key, IterDirection::Forward)
+    }
+}
+
+impl<'a, R> JellyfishMerkleIterator<R, &'a R>
+where
+    R: TreeReader,
+{
+    /// Same as [`new`](JellyfishMerkleIterator::new), but borrows the reader instead of sharing
+    /// the ownership of it.
+    pub fn new_borrowed(reader: &'a R, version: Version, starting_key: HashValue) -> Result<Self> {
+        Self::new_with_direction(reader, version, starting_key, IterDirection::Forward)
+    }
+}
+
+impl<R, D> JellyfishMerkleIterator<R, D>
+where
+    R: TreeReader,
+    D: Deref<Target = R>,
+{
+    /// Constructs a new iterator running in `direction`. The following `next` call will yield the
+    /// smallest key that is greater or equal to `starting_key` if `direction` is
+    /// [`Forward`](IterDirection::Forward), or the biggest key that is less or equal to
+    /// `starting_key` if `direction` is [`Reverse`](IterDirection::Reverse).
+    pub fn new_with_direction(
+        reader: D,
+        version: Version,
+        starting_key: HashValue,
+        direction: IterDirection,
+    ) -> Result<Self> {
+        let mut iter = Self {
+            reader,
+            version,
+            direction,
+            end_key: None,
+            parent_stack: vec![],
+            done: false,
+            phantom: PhantomData,
+        };
+        iter.seek(starting_key)?;
+        Ok(iter)
+    }
+
+    /// Makes the iterator stop before `end_key`. The bound is exclusive: when iterating forward
+    /// only keys less than `end_key` are yielded, and when iterating in reverse only keys greater
+    /// than `end_key` are yielded.
+    pub fn with_end_key(mut self, end_key: HashValue) -> Self {
+        self.end_key = Some(end_key);
+        self
+    }
+
+    /// Returns the direction of this iterator.
+    pub fn direction(&self) -> IterDirection {
+        self.direction
+    }
+
+    /// Repositions the iterator, so the following `next` call will yield the first key at or
+    /// after `key` in the direction of this iterator. Internal nodes on the current path that
+    /// are shared with the path to `key` are reused instead of being read again.
+    pub fn seek(&mut self, key: HashValue) -> Result<()> {
+        self.done = false;
+
+        // Only the nodes whose nibble paths are prefixes of `key` can be reused. Those are always
+        // at the bottom of the stack.
+        let num_shared_nodes = self
+            .parent_stack
+            .iter()
+            .take_while(|info| {
+                let nibble_path = info.node_key.nibble_path();
+                key.common_prefix_nibbles_len(path_to_key(nibble_path)) >= nibble_path.num_nibbles()
+            })
+            .count();
+        self.parent_stack.truncate(num_shared_nodes);
+
+        // Resume the descent from the deepest shared node, or from the root if there is none.
+        let (mut current_node_key, mut current_node) = match self.parent_stack.pop() {
+            Some(info) => (info.node_key, Node::Internal(info.node)),
+            None => {
+                let root_node_key = NodeKey::new_empty_path(self.version);
+                let root_node = self.reader.get_node(&root_node_key)?;
+                (root_node_key, root_node)
             }
-        }
+        };
+        let nibble_path = NibblePath::new(key.to_vec());
+        let mut nibble_iter = nibble_path
+            .nibbles()
+            .skip(current_node_key.nibble_path().num_nibbles());
 
-        match reader.get_node(&current_node_key)? {
-            Node::Internal(_) => unreachable!("Should have reached the bottom of the tree."),
-            Node::Leaf(leaf_node) => {
-                if leaf_node.account_key() < starting_key {
-                    Self::cleanup_stack(&mut parent_stack);
-                    if parent_stack.is_empty() {
-                        done = true;
+        loop {
+            match current_node {
+                Node::Internal(internal_node) => {
+                    let child_index = nibble_iter.next().expect("Should have enough nibbles.");
+                    match internal_node.child(child_index) {
+                        Some(child) => {
+                            // If this child exists, we just push the node onto stack and repeat.
+                            let child_node_key =
+                                current_node_key.gen_child_node_key(child.version, child_index);
+                            let inline_leaf = child.inline_leaf.clone();
+                            self.parent_stack.push(
+                                NodeVisitInfo::new_next_child_to_visit(
+                                    current_node_key,
+                                    internal_node,
+                                    child_index,
+                                    self.direction,
+                                )
+                                .expect("The child exists."),
+                            );
+                            current_node = match inline_leaf {
+                                Some(leaf_node) => Node::Leaf(leaf_node),
+                                None => self.reader.get_node(&child_node_key)?,
+                            };
+                            current_node_key = child_node_key;
+                        }
+                        None => {
+                            match NodeVisitInfo::new_next_child_to_visit(
+                                current_node_key,
+                                internal_node,
+                                child_index,
+                                self.direction,
+                            ) {
+                                // If this child does not exist and there's another child in
+                                // `direction`, we set that child to be the next one to visit.
+                                Some(info) => self.parent_stack.push(info),
+                                // Otherwise we have done visiting this node. Go backward and
+                                // clean up the stack.
+                                None => Self::cleanup_stack(&mut self.parent_stack, self.direction),
+                            }
+                            return Ok(());
+                        }
+                    }
+                }
+                Node::Leaf(leaf_node) => {
+                    let passed = match self.direction {
+                        IterDirection::Forward => leaf_node.account_key() < key,
+                        IterDirection::Reverse => leaf_node.account_key() > key,
+                    };
+                    if passed {
+                        if self.parent_stack.is_empty() {
+                            // The entire tree has a single leaf node and it is out of range.
+                            self.done = true;
+                        } else {
+                            Self::cleanup_stack(&mut self.parent_stack, self.direction);
+                        }
                     }
+                    return Ok(());
+                }
+                Node::Null => {
+                    self.done = true;
+                    return Ok(());
                 }
             }
-            Node::Null => done = true,
         }
-
-        Ok(Self {
-            reader,
-            version,
-            parent_stack,
-            done,
-        })
     }
 
-    fn cleanup_stack(parent_stack: &mut Vec<NodeVisitInfo>) {
+    fn cleanup_stack(parent_stack: &mut Vec<NodeVisitInfo>, direction: IterDirection) {
         while let Some(info) = parent_stack.last_mut() {
-            if info.is_rightmost() {
+            if info.is_last(direction) {
                 parent_stack.pop();
             } else {
-                info.advance();
+                info.advance(direction);
                 break;
             }
         }
     }
+
+    /// Returns whether `key` comes before `self.end_key`.
+    fn is_before_end(&self, key: HashValue) -> bool {
+        match (self.direction, self.end_key) {
+            (_, None) => true,
+            (IterDirection::Forward, Some(end_key)) => key < end_key,
+            (IterDirection::Reverse, Some(end_key)) => key > end_key,
+        }
+    }
+
+    /// Returns the key and blob of `leaf_node` if it comes before the end key, otherwise marks the
+    /// iteration as finished. The blob of a hash-only leaf is only read in the former case.
+    fn yield_leaf(&mut self, leaf_node: LeafNode) -> Option<Result<(HashValue, AccountStateBlob)>> {
+        let key = leaf_node.account_key();
+        if self.is_before_end(key) {
+            Some(get_leaf_blob(&*self.reader, leaf_node).map(|blob| (key, blob)))
+        } else {
+            self.done = true;
+            None
+        }
+    }
+}
+
+/// Pads `nibble_path` with zeros to a full key.
+pub(crate) fn path_to_key(nibble_path: &NibblePath) -> HashValue {
+    let mut bytes = nibble_path.bytes().to_vec();
+    bytes.resize(HashValue::LENGTH, 0);
+    HashValue::from_slice(&bytes).expect("Must have the right length.")
 }
 
-impl<R> Iterator for JellyfishMerkleIterator<R>
+impl<R, D> Iterator for JellyfishMerkleIterator<R, D>
 where
     R: TreeReader,
+    D: Deref<Target = R>,
 {
     type Item = Result<(HashValue, AccountStateBlob)>;
 
@@ -209,18 +376,18 @@ where
             match self.reader.get_node(&root_node_key) {
                 Ok(Node::Leaf(leaf_node)) => {
                     // This means the entire tree has a single leaf node. The key of this leaf node
-                    // is greater or equal to `starting_key` (otherwise we would have set `done` to
-                    // true in `new`). Return the node and mark `self.done` so next time we return
-                    // None.
+                    // is in range (otherwise we would have set `done` to true in `seek`). Return
+                    // the node and mark `self.done` so next time we return None.
+                    let ret = self.yield_leaf(leaf_node);
                     self.done = true;
-                    return Some(Ok((leaf_node.account_key(), leaf_node.blob().clone())));
+                    return ret;
                 }
                 Ok(Node::Internal(_)) => {
-                    // This means `starting_key` is bigger than every key in this tree, or we have
+                    // This means `starting_key` is beyond every key in this tree, or we have
                     // iterated past the last key.
                     return None;
                 }
-                Ok(Node::Null) => unreachable!("We would have set done to true in new."),
+                Ok(Node::Null) => unreachable!("We would have set done to true in seek."),
                 Err(err) => return Some(Err(err)),
             }
         }
@@ -232,23 +399,29 @@ where
                 .expect("We have checked that self.parent_stack is not empty.");
             let child_index =
                 Nibble::from(last_visited_node_info.next_child_to_visit.trailing_zeros() as u8);
-            let node_key = last_visited_node_info.node_key.gen_child_node_key(
-                last_visited_node_info
-                    .node
-                    .child(child_index)
-                    .expect("Child should exist.")
-                    .version,
-                child_index,
-            );
-            match self.reader.get_node(&node_key) {
+            let child = last_visited_node_info
+                .node
+                .child(child_index)
+                .expect("Child should exist.");
+            let node_key = last_visited_node_info
+                .node_key
+                .gen_child_node_key(child.version, child_index);
+            // A leaf inlined into its parent does not have to be read.
+            let node = match &child.inline_leaf {
+                Some(leaf_node) => Ok(Node::Leaf(leaf_node.clone())),
+                None => self.reader.get_node(&node_key),
+            };
+            match node {
                 Ok(Node::Internal(internal_node)) => {
-                    let visit_info = NodeVisitInfo::new(node_key, internal_node);
+                    let visit_info = NodeVisitInfo::new(node_key, internal_node, self.direction);
                     self.parent_stack.push(visit_info);
                 }
                 Ok(Node::Leaf(leaf_node)) => {
-                    let ret = (leaf_node.account_key(), leaf_node.blob().clone());
-                    Self::cleanup_stack(&mut self.parent_stack);
-                    return Some(Ok(ret));
+                    let ret = self.yield_leaf(leaf_node);
+                    if ret.is_some() {
+                        Self::cleanup_stack(&mut self.parent_stack, self.direction);
+                    }
+                    return ret;
                 }
                 Ok(Node::Null) => return Some(Err(format_err!("Should not reach a null node."))),
                 Err(err) => return Some(Err(err)),
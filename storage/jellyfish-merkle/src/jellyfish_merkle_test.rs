@@ -14,7 +14,8 @@ use proptest::{
     prelude::*,
 };
 use rand::{rngs::StdRng, Rng, SeedableRng};
-use std::{collections::HashMap, ops::Bound};
+use restore::JellyfishMerkleRestore;
+use std::{cell::RefCell, collections::HashMap, ops::Bound};
 use test_helper::{init_mock_db, plus_one};
 
 fn update_nibble(original_key: &HashValue, n: usize, nibble: u8) -> HashValue {
@@ -689,6 +690,772 @@ proptest! {
             proof,
         );
     }
+
+    #[test]
+    fn test_get_chunk_with_proof(
+        btree in btree_map(any::<HashValue>(), any::<AccountStateBlob>(), 1..1000),
+        max_items in 1usize..100,
+        max_bytes in 1usize..10000,
+    ) {
+        let (db, version) = init_mock_db(&btree.clone().into_iter().collect());
+        let tree = JellyfishMerkleTree::new(&db);
+        let expected_root_hash = tree.get_root_hash(version).unwrap();
+
+        // Restore the tree from the chunks, which verifies every proof along the way.
+        let restore_db = MockTreeStore::default();
+        let mut restore =
+            JellyfishMerkleRestore::new(&restore_db, version, expected_root_hash).unwrap();
+        let mut all_blobs = vec![];
+        let mut start_key = Some(HashValue::zero());
+        while let Some(key) = start_key {
+            let chunk = tree
+                .get_chunk_with_proof(version, key, max_items, max_bytes)
+                .unwrap();
+            prop_assert!(!chunk.blobs.is_empty());
+            prop_assert!(chunk.blobs.len() <= max_items);
+            let num_bytes: usize = chunk
+                .blobs
+                .iter()
+                .map(|(_key, blob)| HashValue::LENGTH + blob.as_ref().len())
+                .sum();
+            prop_assert!(chunk.blobs.len() == 1 || num_bytes <= max_bytes);
+
+            all_blobs.extend(chunk.blobs.clone());
+            restore.add_chunk(chunk.blobs, chunk.proof).unwrap();
+            start_key = chunk.next_start_key;
+        }
+        restore.finish().unwrap();
+
+        prop_assert_eq!(all_blobs, btree.into_iter().collect::<Vec<_>>());
+        prop_assert_eq!(
+            JellyfishMerkleTree::new(&restore_db).get_root_hash(version).unwrap(),
+            expected_root_hash
+        );
+    }
+}
+
+#[test]
+fn test_get_chunk_with_proof_beyond_last_key() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::new(&db);
+
+    let key = HashValue::new([0x11; HashValue::LENGTH]);
+    let (_root_hash, batch) = tree
+        .put_blob_set(
+            vec![(key, AccountStateBlob::from(vec![1u8]))],
+            0, /* version */
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let chunk = tree
+        .get_chunk_with_proof(0, HashValue::zero(), 10, 0)
+        .unwrap();
+    assert_eq!(chunk.blobs, vec![(key, AccountStateBlob::from(vec![1u8]))]);
+    assert_eq!(chunk.next_start_key, None);
+    assert!(tree
+        .get_chunk_with_proof(0, plus_one(key), 10, 1000)
+        .is_err());
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(10))]
+
+    #[test]
+    fn test_leaf_count(
+        kvs in btree_map(any::<HashValue>(), any::<AccountStateBlob>(), 1..500),
+        to_delete in vec(any::<prop::sample::Index>(), 1..100),
+        nonexistent_keys in vec(any::<HashValue>(), 10),
+        mode in any::<LeafCountMode>(),
+    ) {
+        let all_keys: Vec<_> = kvs.keys().cloned().collect();
+        let keys_to_delete: BTreeSet<_> =
+            to_delete.iter().map(|index| *index.get(&all_keys)).collect();
+        let blob_sets = vec![
+            kvs.iter().map(|(k, v)| (*k, Some(v.clone()))).collect::<Vec<_>>(),
+            keys_to_delete.iter().map(|k| (*k, None)).collect(),
+        ];
+
+        let db = MockTreeStore::default();
+        let tree = JellyfishMerkleTree::new_with_leaf_count(&db, mode);
+        let (root_hashes, batch) = tree.put_blob_sets2(blob_sets.clone(), 0).unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+
+        let uncounted_db = MockTreeStore::default();
+        let (uncounted_root_hashes, _batch) = JellyfishMerkleTree::new(&uncounted_db)
+            .put_blob_sets2(blob_sets, 0)
+            .unwrap();
+        match mode {
+            LeafCountMode::Stored => prop_assert_eq!(&root_hashes, &uncounted_root_hashes),
+            LeafCountMode::Committed => prop_assert_ne!(&root_hashes, &uncounted_root_hashes),
+        }
+
+        let mut btree = kvs;
+        for (version, root_hash) in root_hashes.into_iter().enumerate() {
+            let version = version as Version;
+            if version == 1 {
+                for key in &keys_to_delete {
+                    btree.remove(key);
+                }
+            }
+            prop_assert_eq!(tree.get_leaf_count(version).unwrap(), btree.len() as u64);
+
+            let keys: Vec<_> = btree.keys().cloned().collect();
+            for (i, key) in keys.iter().enumerate() {
+                prop_assert_eq!(tree.get_key_at_index(i as u64, version).unwrap(), Some(*key));
+                prop_assert_eq!(tree.get_rank(*key, version).unwrap(), i as u64);
+            }
+            prop_assert_eq!(tree.get_key_at_index(keys.len() as u64, version).unwrap(), None);
+
+            for key in keys.iter().chain(nonexistent_keys.iter()) {
+                let expected_rank = btree.range(..key).count() as u64;
+                prop_assert_eq!(tree.get_rank(*key, version).unwrap(), expected_rank);
+                if mode == LeafCountMode::Committed {
+                    let (blob, proof) = tree.get_with_counted_proof(*key, version).unwrap();
+                    prop_assert_eq!(blob.as_ref(), btree.get(key));
+                    prop_assert_eq!(
+                        proof.verify(root_hash, *key, blob.as_ref()).unwrap(),
+                        (expected_rank, btree.len() as u64)
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_leaf_count_requires_counted_tree() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::new(&db);
+    // The root has an internal node at nibble 0 and a leaf at nibble f.
+    let key1 = HashValue::new([0x00; HashValue::LENGTH]);
+    let key2 = update_nibble(&key1, 1, 1);
+    let key3 = HashValue::new([0xff; HashValue::LENGTH]);
+    let (_root_hash, batch) = tree
+        .put_blob_set(
+            vec![
+                (key1, AccountStateBlob::from(vec![1u8])),
+                (key2, AccountStateBlob::from(vec![2u8])),
+                (key3, AccountStateBlob::from(vec![3u8])),
+            ],
+            0, /* version */
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    assert!(tree.get_leaf_count(0).is_err());
+    assert!(tree.get_rank(key3, 0).is_err());
+    assert!(tree.get_key_at_index(0, 0).is_err());
+    assert!(tree.get_with_counted_proof(key1, 0).is_err());
+
+    // Counting cannot be turned on for an existing tree, since the internal node at nibble 0 does
+    // not know its leaf count.
+    let counted_tree = JellyfishMerkleTree::new_with_leaf_count(&db, LeafCountMode::Stored);
+    let key4 = update_nibble(&key3, 1, 0);
+    assert!(counted_tree
+        .put_blob_set(vec![(key4, AccountStateBlob::from(vec![4u8]))], 1)
+        .is_err());
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(10))]
+
+    #[test]
+    fn test_update_proof(
+        kvs in btree_map(any::<HashValue>(), any::<AccountStateBlob>(), 0..200),
+        writes in vec(
+            (
+                any::<prop::sample::Index>(),
+                proptest::option::of(any::<HashValue>()),
+                any::<AccountStateBlob>(),
+            ),
+            1..50,
+        ),
+        other_blob in any::<AccountStateBlob>(),
+    ) {
+        let db = MockTreeStore::default();
+        let tree = JellyfishMerkleTree::new(&db);
+        let old_root_hash = if kvs.is_empty() {
+            db.put_node(NodeKey::new_empty_path(0), Node::new_null()).unwrap();
+            *SPARSE_MERKLE_PLACEHOLDER_HASH
+        } else {
+            let (root_hash, batch) = tree
+                .put_blob_set(kvs.iter().map(|(k, v)| (*k, v.clone())).collect(), 0)
+                .unwrap();
+            db.write_tree_update_batch(batch).unwrap();
+            root_hash
+        };
+
+        // Each write is to an existing key, or to a new key if one is given.
+        let existing_keys: Vec<_> = kvs.keys().cloned().collect();
+        let writes: Vec<_> = writes
+            .into_iter()
+            .map(|(index, new_key, blob)| {
+                let key = match new_key {
+                    Some(key) => key,
+                    None if existing_keys.is_empty() => HashValue::zero(),
+                    None => *index.get(&existing_keys),
+                };
+                (key, blob)
+            })
+            .collect();
+        let keys: Vec<_> = writes.iter().map(|(k, _v)| *k).collect();
+        let proof = tree.get_update_proof(&keys, 0 /* version */).unwrap();
+        for (key, blob) in proof.old_values() {
+            prop_assert_eq!(blob.as_ref(), kvs.get(key));
+        }
+
+        let (new_root_hash, batch) = tree.put_blob_set(writes.clone(), 1 /* version */).unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+        let proof: SparseMerkleUpdateProof =
+            lcs::from_bytes(&lcs::to_bytes(&proof).unwrap()).unwrap();
+        proof.verify(old_root_hash, new_root_hash, &writes).unwrap();
+
+        prop_assert!(proof.verify(new_root_hash, new_root_hash, &writes).is_err());
+        prop_assert!(proof.verify(old_root_hash, old_root_hash, &writes).is_err());
+        let mut other_writes = writes.clone();
+        other_writes.last_mut().unwrap().1 = other_blob;
+        if other_writes != writes {
+            prop_assert!(proof.verify(old_root_hash, new_root_hash, &other_writes).is_err());
+        }
+        prop_assert!(proof
+            .verify(old_root_hash, new_root_hash, &writes[..writes.len() - 1])
+            .is_err());
+    }
+}
+
+#[test]
+fn test_update_proof_tampered() {
+    let key1 = HashValue::new([0x00; HashValue::LENGTH]);
+    let key2 = HashValue::new([0x01; HashValue::LENGTH]);
+    let key3 = HashValue::new([0xff; HashValue::LENGTH]);
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::new(&db);
+    let (old_root_hash, batch) = tree
+        .put_blob_set(
+            vec![
+                (key1, AccountStateBlob::from(vec![1u8])),
+                (key3, AccountStateBlob::from(vec![3u8])),
+            ],
+            0, /* version */
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let writes = vec![
+        (key1, AccountStateBlob::from(vec![4u8])),
+        (key2, AccountStateBlob::from(vec![5u8])),
+    ];
+    let proof = tree.get_update_proof(&[key2, key1], 0).unwrap();
+    assert_eq!(
+        proof.old_values(),
+        &[
+            (key1, Some(AccountStateBlob::from(vec![1u8]))),
+            (key2, None)
+        ][..]
+    );
+    let (new_root_hash, _batch) = tree.put_blob_set(writes.clone(), 1).unwrap();
+    proof.verify(old_root_hash, new_root_hash, &writes).unwrap();
+
+    // Claiming a different old value for a key is rejected.
+    let tampered_proof =
+        SparseMerkleUpdateProof::new(vec![(key1, None), (key2, None)], proof.nodes().to_vec());
+    assert!(tampered_proof
+        .verify(old_root_hash, new_root_hash, &writes)
+        .is_err());
+    let tampered_proof = SparseMerkleUpdateProof::new(
+        vec![
+            (key1, Some(AccountStateBlob::from(vec![2u8]))),
+            (key2, None),
+        ],
+        proof.nodes().to_vec(),
+    );
+    assert!(tampered_proof
+        .verify(old_root_hash, new_root_hash, &writes)
+        .is_err());
+
+    // Dropping or adding nodes is rejected.
+    let mut nodes = proof.nodes().to_vec();
+    nodes.pop();
+    let tampered_proof = SparseMerkleUpdateProof::new(proof.old_values().to_vec(), nodes);
+    assert!(tampered_proof
+        .verify(old_root_hash, new_root_hash, &writes)
+        .is_err());
+    let mut nodes = proof.nodes().to_vec();
+    nodes.push(SparseMerkleUpdateProofNode::Empty);
+    let tampered_proof = SparseMerkleUpdateProof::new(proof.old_values().to_vec(), nodes);
+    assert!(tampered_proof
+        .verify(old_root_hash, new_root_hash, &writes)
+        .is_err());
+}
+
+#[test]
+fn test_update_proof_requires_uncommitted_leaf_count() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::new_with_leaf_count(&db, LeafCountMode::Committed);
+    let key1 = HashValue::new([0x00; HashValue::LENGTH]);
+    let key2 = HashValue::new([0xff; HashValue::LENGTH]);
+    let (_root_hash, batch) = tree
+        .put_blob_set(
+            vec![
+                (key1, AccountStateBlob::from(vec![1u8])),
+                (key2, AccountStateBlob::from(vec![2u8])),
+            ],
+            0, /* version */
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert!(tree.get_update_proof(&[key1], 0).is_err());
+}
+
+/// Records the nodes, stale node indices and values written early by `put_blob_sets_bounded`.
+struct SpillRecorder<'a> {
+    db: &'a MockTreeStore,
+    node_batch: RefCell<NodeBatch>,
+    stale_node_index_batch: RefCell<StaleNodeIndexBatch>,
+    value_batch: RefCell<ValueBatch>,
+}
+
+impl<'a> SpillRecorder<'a> {
+    fn new(db: &'a MockTreeStore) -> Self {
+        Self {
+            db,
+            node_batch: RefCell::new(NodeBatch::new()),
+            stale_node_index_batch: RefCell::new(StaleNodeIndexBatch::new()),
+            value_batch: RefCell::new(ValueBatch::new()),
+        }
+    }
+}
+
+impl<'a> TreeWriter for SpillRecorder<'a> {
+    fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()> {
+        // A hash-only leaf is never written before its blob.
+        for node in node_batch.values() {
+            if let Node::Leaf(leaf_node) = node {
+                if leaf_node.is_hash_only() {
+                    ensure!(
+                        self.db.get_value_option(&leaf_node.blob_hash())?.is_some(),
+                        "Blob of leaf {:x} is not written.",
+                        leaf_node.account_key()
+                    );
+                }
+            }
+        }
+        self.node_batch.borrow_mut().extend(node_batch.clone());
+        self.db.write_node_batch(node_batch)
+    }
+
+    fn write_value_batch(&self, value_batch: &ValueBatch) -> Result<()> {
+        self.value_batch.borrow_mut().extend(value_batch.clone());
+        self.db.write_value_batch(value_batch)
+    }
+}
+
+fn new_tree(db: &MockTreeStore, hash_only_leaves: bool) -> JellyfishMerkleTree<'_, MockTreeStore> {
+    if hash_only_leaves {
+        JellyfishMerkleTree::new(db).with_hash_only_leaves()
+    } else {
+        JellyfishMerkleTree::new(db)
+    }
+}
+
+impl<'a> StaleNodeIndexWriter for SpillRecorder<'a> {
+    fn write_stale_node_index_batch(
+        &self,
+        stale_node_index_batch: &StaleNodeIndexBatch,
+    ) -> Result<()> {
+        self.stale_node_index_batch
+            .borrow_mut()
+            .extend(stale_node_index_batch.iter().cloned());
+        self.db.write_stale_node_index_batch(stale_node_index_batch)
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(10))]
+
+    #[test]
+    fn test_put_blob_sets_bounded(
+        kvs in btree_map(any::<HashValue>(), any::<AccountStateBlob>(), 1..200),
+        updates in vec(
+            vec(
+                (
+                    any::<prop::sample::Index>(),
+                    proptest::option::of(any::<HashValue>()),
+                    proptest::option::of(any::<AccountStateBlob>()),
+                ),
+                1..100,
+            ),
+            1..4,
+        ),
+        sort_keys in any::<bool>(),
+        max_nodes_in_memory in 1..50usize,
+        hash_only_leaves in any::<bool>(),
+    ) {
+        // Each update is to an existing key, or to a new key if one is given.
+        let all_keys: Vec<_> = kvs.keys().cloned().collect();
+        let blob_sets: Vec<Vec<_>> = updates
+            .into_iter()
+            .map(|updates| {
+                let mut blob_set: Vec<_> = updates
+                    .into_iter()
+                    .map(|(index, new_key, blob)| {
+                        (new_key.unwrap_or_else(|| *index.get(&all_keys)), blob)
+                    })
+                    .collect();
+                if sort_keys {
+                    blob_set.sort_by_key(|(key, _blob)| *key);
+                }
+                blob_set
+            })
+            .collect();
+
+        let init_blob_set = vec![kvs.into_iter().collect()];
+        let db = MockTreeStore::default();
+        let (_root_hashes, batch) = new_tree(&db, hash_only_leaves)
+            .put_blob_sets(init_blob_set.clone(), 0 /* first_version */)
+            .unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+        let (expected_root_hashes, expected_batch) = new_tree(&db, hash_only_leaves)
+            .put_blob_sets2(blob_sets.clone(), 1 /* first_version */)
+            .unwrap();
+
+        let bounded_db = MockTreeStore::default();
+        let (_root_hashes, batch) = new_tree(&bounded_db, hash_only_leaves)
+            .put_blob_sets(init_blob_set, 0 /* first_version */)
+            .unwrap();
+        bounded_db.write_tree_update_batch(batch).unwrap();
+        let recorder = SpillRecorder::new(&bounded_db);
+        let (root_hashes, batch) = new_tree(&bounded_db, hash_only_leaves)
+            .put_blob_sets_bounded(
+                blob_sets,
+                1, /* first_version */
+                &recorder,
+                max_nodes_in_memory,
+            )
+            .unwrap();
+        prop_assert_eq!(root_hashes, expected_root_hashes);
+        prop_assert_eq!(batch.num_new_leaves, expected_batch.num_new_leaves);
+        prop_assert_eq!(batch.num_stale_leaves, expected_batch.num_stale_leaves);
+
+        let mut node_batch = recorder.node_batch.into_inner();
+        let num_spilled_nodes = node_batch.len();
+        node_batch.extend(batch.node_batch.clone());
+        prop_assert_eq!(node_batch.len(), num_spilled_nodes + batch.node_batch.len());
+        prop_assert_eq!(node_batch, expected_batch.node_batch);
+        let mut stale_node_index_batch = recorder.stale_node_index_batch.into_inner();
+        let num_spilled_indices = stale_node_index_batch.len();
+        stale_node_index_batch.extend(batch.stale_node_index_batch.clone());
+        prop_assert_eq!(
+            stale_node_index_batch.len(),
+            num_spilled_indices + batch.stale_node_index_batch.len()
+        );
+        prop_assert_eq!(stale_node_index_batch, expected_batch.stale_node_index_batch);
+        let mut value_batch = recorder.value_batch.into_inner();
+        value_batch.extend(batch.value_batch.clone());
+        prop_assert_eq!(value_batch, expected_batch.value_batch);
+    }
+}
+
+#[test]
+fn test_put_blob_sets_bounded_huge_version() {
+    let mut rng: StdRng = StdRng::from_seed([0; 32]);
+    let mut blob_set: Vec<_> = (0..10_000)
+        .map(|_| {
+            (
+                HashValue::random_with_rng(&mut rng),
+                Some(AccountStateBlob::from(vec![rng.gen::<u8>()])),
+            )
+        })
+        .collect();
+    blob_set.sort_by_key(|(key, _blob)| *key);
+
+    let db = MockTreeStore::default();
+    let recorder = SpillRecorder::new(&db);
+    let tree = JellyfishMerkleTree::new(&db);
+    let (root_hashes, batch) = tree
+        .put_blob_sets_bounded(
+            vec![blob_set.clone()],
+            0, /* first_version */
+            &recorder,
+            100,
+        )
+        .unwrap();
+
+    // Most of the single version is written before it is finished.
+    assert!(batch.node_batch.len() < 1000);
+    assert_eq!(batch.num_new_leaves, 10_000);
+    db.write_tree_update_batch(batch).unwrap();
+
+    let expected_db = MockTreeStore::default();
+    let (expected_root_hashes, _batch) = JellyfishMerkleTree::new(&expected_db)
+        .put_blob_sets2(vec![blob_set.clone()], 0 /* first_version */)
+        .unwrap();
+    assert_eq!(root_hashes, expected_root_hashes);
+    for (key, blob) in blob_set.iter().step_by(100) {
+        let (value, proof) = tree.get_with_proof(*key, 0).unwrap();
+        assert_eq!(&value, blob);
+        proof.verify(root_hashes[0], *key, value.as_ref()).unwrap();
+    }
+}
+
+#[test]
+fn test_put_blob_sets_bounded_unsorted_version() {
+    // The first keys of the version are sorted, but the last one goes back to the left, under
+    // the internal node created by the first two.
+    let blob_set: Vec<_> = [0x10u8, 0x11, 0x20, 0x12]
+        .iter()
+        .map(|byte| {
+            (
+                HashValue::new([*byte; HashValue::LENGTH]),
+                Some(AccountStateBlob::from(vec![*byte])),
+            )
+        })
+        .collect();
+
+    let db = MockTreeStore::default();
+    let recorder = SpillRecorder::new(&db);
+    let (root_hashes, batch) = JellyfishMerkleTree::new(&db)
+        .put_blob_sets_bounded(
+            vec![blob_set.clone()],
+            0, /* first_version */
+            &recorder,
+            1,
+        )
+        .unwrap();
+    // Nodes of an unsorted version are only written once it is finished.
+    assert!(recorder.node_batch.into_inner().is_empty());
+
+    let expected_db = MockTreeStore::default();
+    let (expected_root_hashes, expected_batch) = JellyfishMerkleTree::new(&expected_db)
+        .put_blob_sets2(vec![blob_set], 0 /* first_version */)
+        .unwrap();
+    assert_eq!(root_hashes, expected_root_hashes);
+    assert_eq!(batch, expected_batch);
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(10))]
+
+    #[test]
+    fn test_hash_schemes(
+        batches in vec(
+            vec((any::<HashValue>(), any::<AccountStateBlob>()), 1..50),
+            1..3,
+        ),
+        leaf_count_mode in proptest::option::of(any::<LeafCountMode>()),
+        other_key in any::<HashValue>(),
+    ) {
+        let version = batches.len() as Version - 1;
+        let put = |db: &MockTreeStore, root_hashes: Vec<HashValue>, batch| {
+            db.write_tree_update_batch(batch).unwrap();
+            root_hashes
+        };
+
+        // The default scheme gives the same tree as the default tree.
+        let default_db = MockTreeStore::default();
+        let tree = match leaf_count_mode {
+            Some(mode) => JellyfishMerkleTree::new_with_leaf_count(&default_db, mode),
+            None => JellyfishMerkleTree::new(&default_db),
+        };
+        let (root_hashes, batch) = tree.put_blob_sets(batches.clone(), 0).unwrap();
+        let default_root_hashes = put(&default_db, root_hashes, batch);
+        let blake3_db = MockTreeStore::default();
+        let tree = JellyfishMerkleTree::new_with_scheme(&blake3_db, leaf_count_mode, Blake3);
+        let (root_hashes, batch) = tree.put_blob_sets(batches.clone(), 0).unwrap();
+        prop_assert_eq!(put(&blake3_db, root_hashes, batch), default_root_hashes.clone());
+
+        let sha3_db = MockTreeStore::default();
+        let tree = JellyfishMerkleTree::new_with_scheme(&sha3_db, leaf_count_mode, Sha3_256);
+        let (root_hashes, batch) = tree.put_blob_sets(batches.clone(), 0).unwrap();
+        let sha3_root_hashes = put(&sha3_db, root_hashes, batch);
+        prop_assert_ne!(&sha3_root_hashes, &default_root_hashes);
+        prop_assert_eq!(tree.get_root_hash(version).unwrap(), sha3_root_hashes[version as usize]);
+
+        let root_hash = sha3_root_hashes[version as usize];
+        let kvs: BTreeMap<_, _> = batches.into_iter().flatten().collect();
+        for (key, blob) in kvs.iter().chain(std::iter::once((&other_key, &AccountStateBlob::from(vec![])))) {
+            // Proofs of trees with committed leaf counts are checked with
+            // `get_with_counted_proof`.
+            if leaf_count_mode == Some(LeafCountMode::Committed) {
+                let (value, proof) = tree.get_with_counted_proof(*key, version).unwrap();
+                prop_assert_eq!(value.as_ref(), kvs.get(key));
+                let (_rank, num_leaves) = proof
+                    .verify_with_scheme(&Sha3_256, root_hash, *key, value.as_ref())
+                    .unwrap();
+                prop_assert_eq!(num_leaves, kvs.len() as u64);
+                prop_assert!(proof.verify(root_hash, *key, value.as_ref()).is_err());
+                continue;
+            }
+            let (value, proof) = tree.get_with_proof(*key, version).unwrap();
+            prop_assert_eq!(value.as_ref(), kvs.get(key));
+            prop_assert!(proof
+                .verify_with_scheme(&Sha3_256, root_hash, *key, value.as_ref())
+                .is_ok());
+            prop_assert!(proof.verify(root_hash, *key, value.as_ref()).is_err());
+            if value.is_some() {
+                prop_assert!(proof
+                    .verify_with_scheme(&Sha2_256, root_hash, *key, Some(blob))
+                    .is_err());
+            }
+        }
+        if leaf_count_mode != Some(LeafCountMode::Committed) {
+            for (key, blob, proof) in tree.sample_leaves_with_proof(version, 3, [0; 32]).unwrap() {
+                prop_assert!(proof
+                    .verify_with_scheme(&Sha3_256, root_hash, key, Some(&blob))
+                    .is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn test_hash_only_leaves(
+        batches in vec(
+            vec((any::<HashValue>(), any::<AccountStateBlob>()), 1..50),
+            1..3,
+        ),
+        other_key in any::<HashValue>(),
+    ) {
+        let full_db = MockTreeStore::default();
+        let hash_only_db = MockTreeStore::default();
+        // Has the same nodes as `hash_only_db`, but no values.
+        let nodes_only_db = MockTreeStore::default();
+        let full_tree = JellyfishMerkleTree::new(&full_db);
+        let tree = JellyfishMerkleTree::new(&hash_only_db).with_hash_only_leaves();
+        for (version, blob_set) in batches.iter().enumerate() {
+            let version = version as Version;
+            let (expected_root_hash, full_batch) =
+                full_tree.put_blob_set(blob_set.clone(), version).unwrap();
+            full_db.write_tree_update_batch(full_batch).unwrap();
+            let (root_hash, batch) = tree.put_blob_set(blob_set.clone(), version).unwrap();
+            prop_assert_eq!(root_hash, expected_root_hash);
+            // Only the blobs of this version are written, even if other leaves moved.
+            let blob_hashes: BTreeSet<_> = blob_set.iter().map(|(_, blob)| blob.hash()).collect();
+            let value_hashes: BTreeSet<_> = batch.value_batch.keys().cloned().collect();
+            prop_assert_eq!(value_hashes, blob_hashes);
+            for node in batch.node_batch.values() {
+                if let Node::Leaf(leaf_node) = node {
+                    prop_assert!(leaf_node.is_hash_only());
+                }
+            }
+            nodes_only_db.write_node_batch(&batch.node_batch).unwrap();
+            hash_only_db.write_tree_update_batch(batch).unwrap();
+        }
+
+        let version = batches.len() as Version - 1;
+        let blob_hashes: BTreeSet<_> =
+            batches.iter().flatten().map(|(_, blob)| blob.hash()).collect();
+        prop_assert_eq!(hash_only_db.num_values(), blob_hashes.len());
+        let kvs: BTreeMap<_, _> = batches.into_iter().flatten().collect();
+        for key in kvs.keys().chain(std::iter::once(&other_key)) {
+            let (value, proof) = tree.get_with_proof(*key, version).unwrap();
+            prop_assert_eq!(value.as_ref(), kvs.get(key));
+            prop_assert_eq!(&proof, &full_tree.get_with_proof(*key, version).unwrap().1);
+            let (bytes, _) = tree.get_bytes_with_proof(*key, version).unwrap();
+            prop_assert_eq!(bytes.as_deref(), value.as_ref().map(AsRef::as_ref));
+        }
+        let iter = JellyfishMerkleIterator::new_borrowed(&hash_only_db, version, HashValue::zero())
+            .unwrap();
+        let expected_kvs: Vec<_> = kvs.clone().into_iter().collect();
+        prop_assert_eq!(iter.collect::<Result<Vec<_>>>().unwrap(), expected_kvs);
+
+        // Proofs do not read the blobs.
+        let nodes_only_tree = JellyfishMerkleTree::new(&nodes_only_db);
+        let rightmost_key = *kvs.keys().next_back().unwrap();
+        prop_assert_eq!(
+            nodes_only_tree.get_range_proof(rightmost_key, version).unwrap(),
+            full_tree.get_range_proof(rightmost_key, version).unwrap()
+        );
+        prop_assert!(nodes_only_tree.get_with_proof(rightmost_key, version).is_err());
+    }
+
+    #[test]
+    fn test_inline_leaves(
+        batches in vec(
+            vec((any::<HashValue>(), any::<AccountStateBlob>()), 1..50),
+            1..3,
+        ),
+        max_inline_blob_size in 0..200usize,
+    ) {
+        let full_db = MockTreeStore::default();
+        let db = MockTreeStore::default();
+        // Has the same nodes as `db`, except for the leaves that are inlined into their parents.
+        let no_inline_leaves_db = MockTreeStore::default();
+        let full_tree = JellyfishMerkleTree::new(&full_db);
+        let tree = JellyfishMerkleTree::new(&db).with_inline_leaves(max_inline_blob_size);
+        for (version, blob_set) in batches.iter().enumerate() {
+            let version = version as Version;
+            let (expected_root_hash, full_batch) =
+                full_tree.put_blob_set(blob_set.clone(), version).unwrap();
+            full_db.write_tree_update_batch(full_batch).unwrap();
+            let (root_hash, batch) = tree.put_blob_set(blob_set.clone(), version).unwrap();
+            prop_assert_eq!(root_hash, expected_root_hash);
+
+            let mut node_batch = batch.node_batch.clone();
+            for node in batch.node_batch.values() {
+                if let Node::Internal(internal_node) = node {
+                    for i in 0..16u8 {
+                        let n = Nibble::from(i);
+                        if let Some(leaf_node) = internal_node.inline_leaf(n) {
+                            let blob_size = leaf_node.blob().unwrap().as_ref().len();
+                            prop_assert!(blob_size <= max_inline_blob_size);
+                        }
+                    }
+                }
+            }
+            node_batch.retain(|node_key, node| match node {
+                Node::Leaf(leaf_node) => {
+                    node_key.nibble_path().num_nibbles() == 0
+                        || leaf_node.blob().unwrap().as_ref().len() > max_inline_blob_size
+                }
+                _ => true,
+            });
+            no_inline_leaves_db.write_node_batch(&node_batch).unwrap();
+            db.write_tree_update_batch(batch).unwrap();
+        }
+
+        // Reads do not need the inlined leaves, and return the same as without inlining.
+        let version = batches.len() as Version - 1;
+        let kvs: BTreeMap<_, _> = batches.into_iter().flatten().collect();
+        let tree = JellyfishMerkleTree::new(&no_inline_leaves_db);
+        for key in kvs.keys() {
+            let (value, proof) = tree.get_with_proof(*key, version).unwrap();
+            prop_assert_eq!(value.as_ref(), kvs.get(key));
+            prop_assert_eq!(&proof, &full_tree.get_with_proof(*key, version).unwrap().1);
+            let (bytes, bytes_proof) = tree.get_bytes_with_proof(*key, version).unwrap();
+            prop_assert_eq!(bytes.as_deref(), value.as_ref().map(AsRef::as_ref));
+            prop_assert_eq!(bytes_proof, proof);
+        }
+        let iter = JellyfishMerkleIterator::new_borrowed(
+            &no_inline_leaves_db,
+            version,
+            HashValue::zero(),
+        )
+        .unwrap();
+        let expected_kvs: Vec<_> = kvs.into_iter().collect();
+        prop_assert_eq!(iter.collect::<Result<Vec<_>>>().unwrap(), expected_kvs);
+    }
+}
+
+#[test]
+fn test_hash_scheme_single_leaf() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::new_with_scheme(&db, None, Sha2_256);
+    let key = HashValue::random();
+    let blob = AccountStateBlob::from(vec![1u8, 2u8, 3u8]);
+    let (root_hash, batch) = tree.put_blob_set(vec![(key, blob.clone())], 0).unwrap();
+    assert_eq!(
+        root_hash,
+        SparseMerkleLeafNode::new(key, blob.hash_with_scheme(&Sha2_256))
+            .hash_with_scheme(&Sha2_256)
+    );
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(
+        tree.get_subtree_root_with_proof(&NibblePath::new(vec![]), 0)
+            .unwrap()
+            .0,
+        root_hash
+    );
 }
 
 fn test_existent_keys_impl<'a>(
@@ -702,6 +1469,10 @@ fn test_existent_keys_impl<'a>(
         let (account, proof) = tree.get_with_proof(*key, version).unwrap();
         assert!(proof.verify(root_hash, *key, account.as_ref()).is_ok());
         assert_eq!(account.unwrap(), *value);
+
+        let (bytes, bytes_proof) = tree.get_bytes_with_proof(*key, version).unwrap();
+        assert_eq!(bytes.unwrap().as_ref(), value.as_ref());
+        assert_eq!(bytes_proof, proof);
     }
 }
 
@@ -716,6 +1487,10 @@ fn test_nonexistent_keys_impl<'a>(
         let (account, proof) = tree.get_with_proof(*key, version).unwrap();
         assert!(proof.verify(root_hash, *key, account.as_ref()).is_ok());
         assert!(account.is_none());
+
+        let (bytes, bytes_proof) = tree.get_bytes_with_proof(*key, version).unwrap();
+        assert!(bytes.is_none());
+        assert_eq!(bytes_proof, proof);
     }
 }
 
@@ -0,0 +1,262 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    kv_store::{KeyLayout, KvBatch, KvIter, KvTreeStore, OrderedKvStore},
+    mock_tree_store::MockTreeStore,
+    nibble_path::NibblePath,
+    node_type::{LeafCountMode, Node, NodeKey},
+    JellyfishMerkleTree, PathTreeReader, RootVersionReader, TreeReader,
+};
+use anyhow::Result;
+use libra_crypto::{hash::CryptoHash, HashValue};
+use libra_nibble::Nibble;
+use libra_types::{account_state_blob::AccountStateBlob, transaction::Version};
+use proptest::{collection::vec, prelude::*};
+use std::{collections::BTreeMap, sync::RwLock};
+
+/// An ordered key-value store in memory.
+#[derive(Default)]
+struct MemoryKvStore(RwLock<BTreeMap<Vec<u8>, Vec<u8>>>);
+
+impl OrderedKvStore for MemoryKvStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.0.read().unwrap().get(key).cloned())
+    }
+
+    fn write_batch(&self, batch: KvBatch) -> Result<()> {
+        let mut locked = self.0.write().unwrap();
+        for (key, value) in batch.ops {
+            match value {
+                Some(value) => locked.insert(key, value),
+                None => locked.remove(&key),
+            };
+        }
+        Ok(())
+    }
+
+    fn iter_prefix(&self, prefix: &[u8]) -> Result<KvIter<'_>> {
+        let items: Vec<_> = self
+            .0
+            .read()
+            .unwrap()
+            .range(prefix.to_vec()..)
+            .take_while(|(key, _value)| key.starts_with(prefix))
+            .map(|(key, value)| Ok((key.clone(), value.clone())))
+            .collect();
+        Ok(Box::new(items.into_iter()))
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(10))]
+
+    #[test]
+    fn test_kv_tree_store(
+        batches in vec(
+            vec((any::<HashValue>(), any::<AccountStateBlob>()), 1..50),
+            1..5,
+        ),
+        leaf_count_mode in proptest::option::of(any::<LeafCountMode>()),
+        path_major in any::<bool>(),
+        least_readable_version in any::<prop::sample::Index>(),
+    ) {
+        let db = MockTreeStore::default();
+        let layout = if path_major { KeyLayout::PathMajor } else { KeyLayout::VersionMajor };
+        let store = KvTreeStore::new_with_layout(MemoryKvStore::default(), layout);
+        for (version, blob_set) in batches.iter().enumerate() {
+            let tree = match leaf_count_mode {
+                Some(mode) => JellyfishMerkleTree::new_with_leaf_count(&store, mode),
+                None => JellyfishMerkleTree::new(&store),
+            };
+            let (_root_hash, batch) = tree
+                .put_blob_set(blob_set.clone(), version as Version)
+                .unwrap();
+            store.write_tree_update_batch(&batch).unwrap();
+            db.write_tree_update_batch(batch).unwrap();
+        }
+
+        // Every leaf ever written is still there.
+        let max_key = batches.iter().flatten().map(|(k, _v)| *k).max().unwrap();
+        let (_node_key, rightmost_leaf) = store.get_rightmost_leaf().unwrap().unwrap();
+        prop_assert_eq!(rightmost_leaf.account_key(), max_key);
+
+        let least_readable_version = least_readable_version.index(batches.len()) as Version;
+        let num_nodes = db.num_nodes();
+        db.purge_stale_nodes(least_readable_version).unwrap();
+        prop_assert_eq!(
+            store.purge_stale_nodes(least_readable_version).unwrap(),
+            num_nodes - db.num_nodes()
+        );
+        let db_tree = JellyfishMerkleTree::new(&db);
+        let store_tree = JellyfishMerkleTree::new(&store);
+        for version in 0..batches.len() as Version {
+            for (key, _blob) in batches.iter().flatten() {
+                let proof = store_tree.get_with_proof(*key, version);
+                if version < least_readable_version {
+                    prop_assert!(proof.is_err());
+                } else {
+                    let (blob, proof) = proof.unwrap();
+                    let (bytes, bytes_proof) =
+                        store_tree.get_bytes_with_proof(*key, version).unwrap();
+                    prop_assert_eq!(bytes.as_deref(), blob.as_ref().map(AsRef::as_ref));
+                    prop_assert_eq!(&bytes_proof, &proof);
+                    prop_assert_eq!(
+                        (blob, proof),
+                        db_tree.get_with_proof(*key, version).unwrap()
+                    );
+                }
+            }
+        }
+        let root_node_key = NodeKey::new_empty_path(least_readable_version);
+        prop_assert_eq!(
+            store.get_node(&root_node_key).unwrap(),
+            db.get_node(&root_node_key).unwrap()
+        );
+        prop_assert_eq!(store.get_root_versions().unwrap(), db.get_root_versions().unwrap());
+    }
+}
+
+/// Returns the keys of the nodes reachable from the root of the tree at `version`.
+fn reachable_node_keys(db: &MockTreeStore, version: Version) -> Vec<NodeKey> {
+    let mut node_keys = vec![];
+    let mut stack = vec![NodeKey::new_empty_path(version)];
+    while let Some(node_key) = stack.pop() {
+        if let Node::Internal(internal_node) = db.get_node(&node_key).unwrap() {
+            for i in 0..16u8 {
+                let n = Nibble::from(i);
+                if let Some(child) = internal_node.child(n) {
+                    stack.push(node_key.gen_child_node_key(child.version, n));
+                }
+            }
+        }
+        node_keys.push(node_key);
+    }
+    node_keys
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(10))]
+
+    #[test]
+    fn test_kv_tree_store_path_major(
+        batches in vec(
+            vec((any::<HashValue>(), any::<AccountStateBlob>()), 1..50),
+            1..5,
+        ),
+    ) {
+        let db = MockTreeStore::default();
+        let store = KvTreeStore::new_with_layout(MemoryKvStore::default(), KeyLayout::PathMajor);
+        for (version, blob_set) in batches.iter().enumerate() {
+            let (_root_hash, batch) = JellyfishMerkleTree::new(&db)
+                .put_blob_set(blob_set.clone(), version as Version)
+                .unwrap();
+            store.write_tree_update_batch(&batch).unwrap();
+            db.write_tree_update_batch(batch).unwrap();
+        }
+
+        // Every node of every version can be read by its path alone.
+        for version in 0..batches.len() as Version {
+            for node_key in reachable_node_keys(&db, version) {
+                let (latest_node_key, node) = store
+                    .get_latest_node_option(node_key.nibble_path(), version)
+                    .unwrap()
+                    .unwrap();
+                prop_assert_eq!(&latest_node_key, &node_key);
+                prop_assert_eq!(node, db.get_node(&node_key).unwrap());
+            }
+        }
+
+        // Scanning a subtree returns exactly the nodes under it.
+        let latest_version = batches.len() as Version - 1;
+        for node_key in reachable_node_keys(&db, latest_version) {
+            let prefix = NodeKey::path_major_prefix(node_key.nibble_path());
+            let mut expected: Vec<_> = store
+                .iter_subtree(&NibblePath::new(vec![]))
+                .unwrap()
+                .map(|item| item.unwrap().0)
+                .filter(|key| {
+                    NodeKey::path_major_prefix(key.nibble_path()).starts_with(&prefix)
+                })
+                .collect();
+            let mut scanned: Vec<_> = store
+                .iter_subtree(node_key.nibble_path())
+                .unwrap()
+                .map(|item| item.unwrap().0)
+                .collect();
+            expected.sort();
+            scanned.sort();
+            prop_assert!(scanned.contains(&node_key));
+            prop_assert_eq!(scanned, expected);
+        }
+    }
+}
+
+#[test]
+fn test_kv_tree_store_path_major_lookup() {
+    let store = KvTreeStore::new_with_layout(MemoryKvStore::default(), KeyLayout::PathMajor);
+    let key1 = HashValue::new([0x00; HashValue::LENGTH]);
+    let key2 = HashValue::new([0xff; HashValue::LENGTH]);
+    for (version, key) in [key1, key2].iter().enumerate() {
+        let (_root_hash, batch) = JellyfishMerkleTree::new(&store)
+            .put_blob_set(
+                vec![(*key, AccountStateBlob::from(vec![1u8]))],
+                version as Version,
+            )
+            .unwrap();
+        store.write_tree_update_batch(&batch).unwrap();
+    }
+
+    // The root at each version is the newest one at or below it.
+    let root_path = NibblePath::new(vec![]);
+    for version in 0..3 {
+        let (node_key, _node) = store
+            .get_latest_node_option(&root_path, version)
+            .unwrap()
+            .unwrap();
+        assert_eq!(node_key, NodeKey::new_empty_path(version.min(1)));
+    }
+
+    // The leaf of `key2` only exists since version 1.
+    let leaf_path = NibblePath::new_odd(vec![0xf0]);
+    assert!(store
+        .get_latest_node_option(&leaf_path, 0)
+        .unwrap()
+        .is_none());
+    let (node_key, node) = store
+        .get_latest_node_option(&leaf_path, 1)
+        .unwrap()
+        .unwrap();
+    assert_eq!(node_key, NodeKey::new(1, leaf_path));
+    assert!(node.is_leaf());
+
+    // Version-major stores can't be read by path.
+    let store = KvTreeStore::new(MemoryKvStore::default());
+    assert!(store.get_latest_node_option(&root_path, 0).is_err());
+    assert!(store.iter_subtree(&root_path).is_err());
+}
+
+#[test]
+fn test_kv_tree_store_empty() {
+    let store = KvTreeStore::new(MemoryKvStore::default());
+    assert!(store.get_rightmost_leaf().unwrap().is_none());
+    assert_eq!(store.purge_stale_nodes(0).unwrap(), 0);
+    assert!(JellyfishMerkleTree::new(&store).get_root_hash(0).is_err());
+}
+
+#[test]
+fn test_kv_tree_store_hash_only_leaves() {
+    let store = KvTreeStore::new(MemoryKvStore::default());
+    let tree = JellyfishMerkleTree::new(&store).with_hash_only_leaves();
+    let key = HashValue::random();
+    let blob = AccountStateBlob::from(vec![1; 100]);
+    let (_root_hash, batch) = tree.put_blob_set(vec![(key, blob.clone())], 0).unwrap();
+    store.write_tree_update_batch(&batch).unwrap();
+
+    assert_eq!(
+        store.get_value_option(&blob.hash()).unwrap(),
+        Some(blob.clone())
+    );
+    assert_eq!(tree.get_with_proof(key, 0).unwrap().0, Some(blob));
+    assert_eq!(store.get_value_option(&HashValue::random()).unwrap(), None);
+}
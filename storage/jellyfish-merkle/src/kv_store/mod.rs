@@ -0,0 +1,366 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module implements storing the tree in any ordered key-value store. A database only needs
+//! to implement [`OrderedKvStore`], and [`KvTreeStore`] provides [`TreeReader`], [`TreeWriter`],
+//! the storage and purging of stale node indices and the lookup of the rightmost leaf on top of
+//! it.
+//!
+//! Nodes, stale node indices and the blobs of hash-only leaves are kept in three keyspaces, which
+//! are told apart by the first byte of the key:
+//!
+//! ```text
+//! |<-----------------key------------------>|<-----value----->|
+//! | 0 | node_key                           | serialized_node |
+//! | 1 | stale_since_version | node_key     |                 |
+//! | 2 | blob_hash                          | blob            |
+//! ```
+//!
+//! Versions are encoded in big endian, so the stale node indices are ordered by version and
+//! purging only scans the indices it removes.
+//!
+//! Node keys are encoded with [`NodeKey::encode`] by default. With [`KeyLayout::PathMajor`] they
+//! are encoded with [`NodeKey::encode_path_major`] instead, so the store can also look up nodes
+//! by path through [`PathTreeReader`] and the nodes of a subtree are next to each other.
+//!
+//! [`OrderedKvStore`]: trait.OrderedKvStore.html
+//! [`KvTreeStore`]: struct.KvTreeStore.html
+//! [`TreeReader`]: ../trait.TreeReader.html
+//! [`TreeWriter`]: ../trait.TreeWriter.html
+//! [`NodeKey::encode`]: ../node_type/struct.NodeKey.html#method.encode
+//! [`NodeKey::encode_path_major`]: ../node_type/struct.NodeKey.html#method.encode_path_major
+//! [`KeyLayout::PathMajor`]: enum.KeyLayout.html#variant.PathMajor
+//! [`PathTreeReader`]: ../trait.PathTreeReader.html
+
+#[cfg(test)]
+mod kv_store_test;
+
+use crate::{
+    nibble_path::NibblePath,
+    node_type::{LeafNode, Node, NodeKey, PATH_MAJOR_TERMINATOR},
+    NodeBatch, PathTreeReader, RootVersionReader, StaleNodeIndex, StaleNodeIndexBatch,
+    StaleNodeIndexWriter, TreeReader, TreeUpdateBatch, TreeWriter, ValueBatch,
+};
+use anyhow::{ensure, Result};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use bytes::Bytes;
+use libra_crypto::HashValue;
+use libra_types::{account_state_blob::AccountStateBlob, transaction::Version};
+use std::{io::Cursor, mem::size_of};
+
+const NODE_KEYSPACE: u8 = 0;
+const STALE_NODE_INDEX_KEYSPACE: u8 = 1;
+const VALUE_KEYSPACE: u8 = 2;
+
+/// Puts and deletes that an [`OrderedKvStore`](trait.OrderedKvStore.html) applies atomically, in
+/// order.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct KvBatch {
+    /// The keys with their new values, or `None` if they are deleted.
+    pub ops: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+}
+
+impl KvBatch {
+    /// Creates an empty batch.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets `key` to `value`.
+    pub fn put(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.ops.push((key, Some(value)));
+    }
+
+    /// Deletes `key`.
+    pub fn delete(&mut self, key: Vec<u8>) {
+        self.ops.push((key, None));
+    }
+}
+
+/// The iterator over the key-value pairs under a prefix.
+pub type KvIter<'a> = Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>;
+
+/// `OrderedKvStore` is the minimal interface a database needs to store the tree through
+/// [`KvTreeStore`](struct.KvTreeStore.html). Keys are ordered lexicographically as bytes.
+pub trait OrderedKvStore {
+    /// Gets the value of `key`. Returns `None` if the key does not exist.
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    /// Applies the puts and deletes in `batch` atomically.
+    fn write_batch(&self, batch: KvBatch) -> Result<()>;
+
+    /// Iterates over the key-value pairs whose keys start with `prefix`, in increasing order of
+    /// keys.
+    fn iter_prefix(&self, prefix: &[u8]) -> Result<KvIter<'_>>;
+
+    /// Same as [`iter_prefix`](trait.OrderedKvStore.html#method.iter_prefix), but starts at the
+    /// first key at or after `start`. Stores that can seek should override this.
+    fn iter_prefix_from(&self, prefix: &[u8], start: &[u8]) -> Result<KvIter<'_>> {
+        let start = start.to_vec();
+        Ok(Box::new(self.iter_prefix(prefix)?.filter(move |item| {
+            item.as_ref().map_or(true, |(key, _value)| *key >= start)
+        })))
+    }
+}
+
+/// How [`KvTreeStore`](struct.KvTreeStore.html) encodes node keys.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KeyLayout {
+    /// Version first, with [`NodeKey::encode`](../node_type/struct.NodeKey.html#method.encode).
+    VersionMajor,
+    /// Nibble path first, with
+    /// [`NodeKey::encode_path_major`](../node_type/struct.NodeKey.html#method.encode_path_major).
+    PathMajor,
+}
+
+/// Stores the tree in an [`OrderedKvStore`](trait.OrderedKvStore.html).
+pub struct KvTreeStore<K> {
+    kv: K,
+    layout: KeyLayout,
+}
+
+impl<K: OrderedKvStore> KvTreeStore<K> {
+    /// Creates a store of the tree in `kv`, with version-major node keys.
+    pub fn new(kv: K) -> Self {
+        Self::new_with_layout(kv, KeyLayout::VersionMajor)
+    }
+
+    /// Creates a store of the tree in `kv`, with node keys encoded in `layout`.
+    pub fn new_with_layout(kv: K, layout: KeyLayout) -> Self {
+        Self { kv, layout }
+    }
+
+    /// Returns the underlying key-value store.
+    pub fn inner(&self) -> &K {
+        &self.kv
+    }
+
+    /// Writes the nodes, stale node indices and values in `batch` atomically.
+    pub fn write_tree_update_batch(&self, batch: &TreeUpdateBatch) -> Result<()> {
+        let mut kv_batch = KvBatch::new();
+        self.add_node_batch(&mut kv_batch, &batch.node_batch)?;
+        add_stale_node_index_batch(&mut kv_batch, &batch.stale_node_index_batch)?;
+        add_value_batch(&mut kv_batch, &batch.value_batch);
+        self.kv.write_batch(kv_batch)
+    }
+
+    /// Deletes the stale nodes that are no longer needed to read `least_readable_version` or
+    /// later versions, along with their stale node indices. Returns the number of deleted nodes.
+    pub fn purge_stale_nodes(&self, least_readable_version: Version) -> Result<usize> {
+        let mut kv_batch = KvBatch::new();
+        let mut num_purged = 0;
+        for item in self.kv.iter_prefix(&[STALE_NODE_INDEX_KEYSPACE])? {
+            let (key, _value) = item?;
+            let stale_node_index = decode_stale_node_index_key(&key)?;
+            // Only records retired before or at `least_readable_version` can be purged in order
+            // to keep that version still readable.
+            if stale_node_index.stale_since_version > least_readable_version {
+                break;
+            }
+            kv_batch.delete(self.encode_node_key(&stale_node_index.node_key)?);
+            kv_batch.delete(key);
+            num_purged += 1;
+        }
+        self.kv.write_batch(kv_batch)?;
+        Ok(num_purged)
+    }
+
+    /// Returns the leaf with the greatest account key of all the leaves in the store, or `None`
+    /// if there is no leaf. This scans every node.
+    pub fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        let mut rightmost_leaf: Option<(NodeKey, LeafNode)> = None;
+        for item in self.kv.iter_prefix(&[NODE_KEYSPACE])? {
+            let (key, value) = item?;
+            if let Node::Leaf(leaf_node) = Node::decode(&value)? {
+                let is_rightmost = match &rightmost_leaf {
+                    Some((_node_key, rightmost)) => {
+                        leaf_node.account_key() > rightmost.account_key()
+                    }
+                    None => true,
+                };
+                if is_rightmost {
+                    rightmost_leaf = Some((self.decode_node_key(&key)?, leaf_node));
+                }
+            }
+        }
+        Ok(rightmost_leaf)
+    }
+
+    /// Returns the nodes under `nibble_path`, including the ones at it, of every version. The
+    /// store must have path-major node keys.
+    pub fn iter_subtree(
+        &self,
+        nibble_path: &NibblePath,
+    ) -> Result<impl Iterator<Item = Result<(NodeKey, Node)>> + '_> {
+        ensure!(
+            self.layout == KeyLayout::PathMajor,
+            "Subtrees can only be scanned with path-major node keys."
+        );
+        let mut prefix = vec![NODE_KEYSPACE];
+        prefix.extend(NodeKey::path_major_prefix(nibble_path));
+        Ok(self.kv.iter_prefix(&prefix)?.map(move |item| {
+            let (key, value) = item?;
+            Ok((self.decode_node_key(&key)?, Node::decode(&value)?))
+        }))
+    }
+
+    fn add_node_batch(&self, kv_batch: &mut KvBatch, node_batch: &NodeBatch) -> Result<()> {
+        for (node_key, node) in node_batch {
+            kv_batch.put(self.encode_node_key(node_key)?, node.encode()?);
+        }
+        Ok(())
+    }
+
+    fn encode_node_key(&self, node_key: &NodeKey) -> Result<Vec<u8>> {
+        let mut out = vec![NODE_KEYSPACE];
+        out.extend(match self.layout {
+            KeyLayout::VersionMajor => node_key.encode()?,
+            KeyLayout::PathMajor => node_key.encode_path_major()?,
+        });
+        Ok(out)
+    }
+
+    fn decode_node_key(&self, key: &[u8]) -> Result<NodeKey> {
+        match self.layout {
+            KeyLayout::VersionMajor => NodeKey::decode(&key[1..]),
+            KeyLayout::PathMajor => NodeKey::decode_path_major(&key[1..]),
+        }
+    }
+}
+
+impl<K: OrderedKvStore> TreeReader for KvTreeStore<K> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        match self.kv.get(&self.encode_node_key(node_key)?)? {
+            Some(value) => Ok(Some(Node::decode(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn get_encoded_node_option(&self, node_key: &NodeKey) -> Result<Option<Bytes>> {
+        Ok(self
+            .kv
+            .get(&self.encode_node_key(node_key)?)?
+            .map(Bytes::from))
+    }
+
+    fn has_encoded_nodes(&self) -> bool {
+        true
+    }
+
+    fn get_value_option(&self, blob_hash: &HashValue) -> Result<Option<AccountStateBlob>> {
+        Ok(self
+            .kv
+            .get(&encode_value_key(blob_hash))?
+            .map(AccountStateBlob::from))
+    }
+}
+
+impl<K: OrderedKvStore> PathTreeReader for KvTreeStore<K> {
+    fn get_latest_node_option(
+        &self,
+        nibble_path: &NibblePath,
+        version: Version,
+    ) -> Result<Option<(NodeKey, Node)>> {
+        ensure!(
+            self.layout == KeyLayout::PathMajor,
+            "Nodes can only be looked up by path with path-major node keys."
+        );
+        let mut prefix = vec![NODE_KEYSPACE];
+        prefix.extend(NodeKey::path_major_prefix(nibble_path));
+        prefix.push(PATH_MAJOR_TERMINATOR);
+        let start = self.encode_node_key(&NodeKey::new(version, nibble_path.clone()))?;
+        match self.kv.iter_prefix_from(&prefix, &start)?.next() {
+            Some(item) => {
+                let (key, value) = item?;
+                Ok(Some((self.decode_node_key(&key)?, Node::decode(&value)?)))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl<K: OrderedKvStore> RootVersionReader for KvTreeStore<K> {
+    fn get_root_versions(&self) -> Result<Vec<Version>> {
+        // With path-major node keys the roots are next to each other, otherwise every node is
+        // scanned.
+        let prefix = match self.layout {
+            KeyLayout::VersionMajor => vec![NODE_KEYSPACE],
+            KeyLayout::PathMajor => vec![NODE_KEYSPACE, PATH_MAJOR_TERMINATOR],
+        };
+        let mut versions = vec![];
+        for item in self.kv.iter_prefix(&prefix)? {
+            let (key, _value) = item?;
+            let node_key = self.decode_node_key(&key)?;
+            if node_key.nibble_path().num_nibbles() == 0 {
+                versions.push(node_key.version());
+            }
+        }
+        versions.sort();
+        Ok(versions)
+    }
+}
+
+impl<K: OrderedKvStore> TreeWriter for KvTreeStore<K> {
+    fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()> {
+        let mut kv_batch = KvBatch::new();
+        self.add_node_batch(&mut kv_batch, node_batch)?;
+        self.kv.write_batch(kv_batch)
+    }
+
+    fn write_value_batch(&self, value_batch: &ValueBatch) -> Result<()> {
+        let mut kv_batch = KvBatch::new();
+        add_value_batch(&mut kv_batch, value_batch);
+        self.kv.write_batch(kv_batch)
+    }
+}
+
+impl<K: OrderedKvStore> StaleNodeIndexWriter for KvTreeStore<K> {
+    fn write_stale_node_index_batch(
+        &self,
+        stale_node_index_batch: &StaleNodeIndexBatch,
+    ) -> Result<()> {
+        let mut kv_batch = KvBatch::new();
+        add_stale_node_index_batch(&mut kv_batch, stale_node_index_batch)?;
+        self.kv.write_batch(kv_batch)
+    }
+}
+
+fn add_stale_node_index_batch(
+    kv_batch: &mut KvBatch,
+    stale_node_index_batch: &StaleNodeIndexBatch,
+) -> Result<()> {
+    for stale_node_index in stale_node_index_batch {
+        kv_batch.put(encode_stale_node_index_key(stale_node_index)?, vec![]);
+    }
+    Ok(())
+}
+
+fn add_value_batch(kv_batch: &mut KvBatch, value_batch: &ValueBatch) {
+    for (blob_hash, blob) in value_batch {
+        kv_batch.put(encode_value_key(blob_hash), blob.as_ref().to_vec());
+    }
+}
+
+fn encode_value_key(blob_hash: &HashValue) -> Vec<u8> {
+    let mut out = vec![VALUE_KEYSPACE];
+    out.extend_from_slice(blob_hash.as_ref());
+    out
+}
+
+fn encode_stale_node_index_key(stale_node_index: &StaleNodeIndex) -> Result<Vec<u8>> {
+    let mut out = vec![STALE_NODE_INDEX_KEYSPACE];
+    out.write_u64::<BigEndian>(stale_node_index.stale_since_version)?;
+    out.extend(stale_node_index.node_key.encode()?);
+    Ok(out)
+}
+
+fn decode_stale_node_index_key(key: &[u8]) -> Result<StaleNodeIndex> {
+    let mut reader = Cursor::new(key);
+    reader.set_position(1);
+    let stale_since_version = reader.read_u64::<BigEndian>()?;
+    let node_key = NodeKey::decode(&key[1 + size_of::<Version>()..])?;
+    Ok(StaleNodeIndex {
+        stale_since_version,
+        node_key,
+    })
+}
@@ -68,35 +68,94 @@
 //! [`InternalNode`]: node_type/struct.InternalNode.html
 //! [`LeafNode`]: node_type/struct.LeafNode.html
 
+pub mod builder;
+pub mod copy;
+pub mod diff;
+pub mod file_store;
+pub mod frozen;
 pub mod iterator;
 #[cfg(test)]
 mod jellyfish_merkle_test;
+pub mod kv_store;
+pub mod migrate;
 #[cfg(test)]
 mod mock_tree_store;
-mod nibble_path;
+pub mod namespace;
+pub mod nibble_path;
 pub mod node_type;
 pub mod restore;
+pub mod sampler;
+pub mod snapshot;
+pub mod subtree;
 #[cfg(test)]
 mod test_helper;
 mod tree_cache;
+pub mod witness;
 
 use anyhow::{bail, ensure, format_err, Result};
-pub use libra_crypto::{hash::CryptoHash, HashValue};
+use bytes::Bytes;
+use iterator::{path_to_key, JellyfishMerkleIterator};
+pub use libra_crypto::{
+    hash::{Blake3, CryptoHash, HashScheme, Sha2_256, Sha3_256},
+    HashValue,
+};
+use libra_nibble::Nibble;
 pub use libra_types::{
     account_state_blob::AccountStateBlob,
-    proof::{SparseMerkleLeafNode, SparseMerkleProof, SparseMerkleRangeProof},
+    proof::{
+        SparseMerkleCountedProof, SparseMerkleLeafNode, SparseMerkleProof, SparseMerkleRangeProof,
+        SparseMerkleUpdateProof, SparseMerkleUpdateProofNode,
+    },
     transaction::Version,
 };
 use nibble_path::{skip_common_prefix, NibbleIterator, NibblePath};
-use node_type::{Child, Children, InternalNode, LeafNode, Node, NodeKey};
+use node_type::{
+    Child, Children, InternalNode, LeafCountMode, LeafNode, LeafNodeView, Node, NodeKey, NodeView,
+};
 #[cfg(any(test, feature = "fuzzing"))]
 use proptest_derive::Arbitrary;
+use sampler::LeafSampler;
 use std::collections::{BTreeMap, BTreeSet};
+use subtree::{prefix_key_range, SubtreeProof};
 use tree_cache::TreeCache;
+use witness::{Witness, WitnessRecorder};
 
 /// The hardcoded maximum height of a [`JellyfishMerkleTree`] in nibbles.
 pub const ROOT_NIBBLE_HEIGHT: usize = HashValue::LENGTH * 2;
 
+/// Returns the blob of `leaf_node`, reading it from `reader` if the leaf is hash-only.
+pub(crate) fn get_leaf_blob<R: TreeReader + ?Sized>(
+    reader: &R,
+    leaf_node: LeafNode,
+) -> Result<AccountStateBlob> {
+    let blob_hash = leaf_node.blob_hash();
+    match leaf_node.into_blob() {
+        Some(blob) => Ok(blob),
+        None => reader.get_value(&blob_hash),
+    }
+}
+
+/// Returns the index of the child at `node_key` in its parent.
+fn child_index(node_key: &NodeKey) -> Nibble {
+    node_key
+        .nibble_path()
+        .last()
+        .expect("Child must have a nibble.")
+}
+
+/// What is found at a node while walking down the tree to a key, see
+/// [`JellyfishMerkleTree::walk_to_key`](struct.JellyfishMerkleTree.html#method.walk_to_key).
+enum ProofStep<L> {
+    /// The root of an empty tree.
+    Null,
+    /// An internal node, with the siblings of the path in it and the key of the child on the
+    /// path, or `None` if there is no child on the path.
+    Internal(Vec<HashValue>, Option<NodeKey>),
+    /// A leaf, with the siblings of the path in its parent if the leaf is inlined into it, and
+    /// what is returned for the leaf if it is the leaf of the key.
+    Leaf(Vec<HashValue>, SparseMerkleLeafNode, Option<L>),
+}
+
 /// `TreeReader` defines the interface between
 /// [`JellyfishMerkleTree`](struct.JellyfishMerkleTree.html)
 /// and underlying storage holding nodes.
@@ -110,14 +169,92 @@ pub trait TreeReader {
     /// Gets node given a node key. Returns `None` if the node does not exist.
     fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>>;
 
-    /// Gets the rightmost leaf. Note that this assumes we are in the process of restoring the tree
-    /// and all nodes are at the same version.
-    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>>;
+    /// Gets the serialized node given a node key. Returns `None` if the node does not exist.
+    /// Storage that keeps nodes serialized should return its buffer here, so reading a value out
+    /// of the node doesn't copy it, and return `true` from
+    /// [`has_encoded_nodes`](trait.TreeReader.html#method.has_encoded_nodes). The default
+    /// implementation encodes the node read by `get_node_option`.
+    fn get_encoded_node_option(&self, node_key: &NodeKey) -> Result<Option<Bytes>> {
+        match self.get_node_option(node_key)? {
+            Some(node) => Ok(Some(Bytes::from(node.encode()?))),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns `true` if this storage keeps nodes serialized and
+    /// [`get_encoded_node_option`](trait.TreeReader.html#method.get_encoded_node_option) returns
+    /// its buffers. Otherwise nodes are read with `get_node_option` rather than encoded again.
+    fn has_encoded_nodes(&self) -> bool {
+        false
+    }
+
+    /// Gets the blob of a hash-only leaf given its hash. Returns error if the blob does not
+    /// exist.
+    fn get_value(&self, blob_hash: &HashValue) -> Result<AccountStateBlob> {
+        self.get_value_option(blob_hash)?
+            .ok_or_else(|| format_err!("Missing value with hash {:x}.", blob_hash))
+    }
+
+    /// Gets the blob of a hash-only leaf given its hash. Returns `None` if the blob does not
+    /// exist. Storage of trees with hash-only leaves must keep the blobs written in
+    /// [`TreeUpdateBatch::value_batch`](struct.TreeUpdateBatch.html#structfield.value_batch) and
+    /// implement this.
+    fn get_value_option(&self, _blob_hash: &HashValue) -> Result<Option<AccountStateBlob>> {
+        Ok(None)
+    }
+}
+
+/// `PathTreeReader` is implemented by storage that can look up nodes by nibble path without
+/// knowing their versions, e.g. storage keyed by
+/// [`NodeKey::encode_path_major`](node_type/struct.NodeKey.html#method.encode_path_major).
+///
+/// Nodes are never written at a path with a version older than an existing node there, so if the
+/// tree at `version` has a node at some path, it is the newest node at that path whose version is
+/// at or below `version`.
+pub trait PathTreeReader: TreeReader {
+    /// Gets the newest node at `nibble_path` whose version is at or below `version`, along with
+    /// its node key. Returns `None` if there is no such node.
+    fn get_latest_node_option(
+        &self,
+        nibble_path: &NibblePath,
+        version: Version,
+    ) -> Result<Option<(NodeKey, Node)>>;
+}
+
+/// `RootVersionReader` is implemented by storage that can list the versions of the roots it
+/// holds, so that every live version can be visited without probing versions one by one.
+pub trait RootVersionReader: TreeReader {
+    /// Gets the versions of all the roots in storage, in increasing order. This includes
+    /// `PRE_GENESIS_VERSION` if there is a pre-genesis root.
+    fn get_root_versions(&self) -> Result<Vec<Version>>;
 }
 
 pub trait TreeWriter {
     /// Writes a node batch into storage.
     fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()>;
+
+    /// Writes a batch of the blobs of hash-only leaves into storage. Storage of trees with
+    /// hash-only leaves must implement this, along with
+    /// [`TreeReader::get_value_option`](trait.TreeReader.html#method.get_value_option). The
+    /// default implementation returns error unless the batch is empty.
+    fn write_value_batch(&self, value_batch: &ValueBatch) -> Result<()> {
+        ensure!(
+            value_batch.is_empty(),
+            "Storage does not keep the blobs of hash-only leaves."
+        );
+        Ok(())
+    }
+}
+
+/// `StaleNodeIndexWriter` writes the stale node indices that
+/// [`put_blob_sets_bounded`](struct.JellyfishMerkleTree.html#method.put_blob_sets_bounded)
+/// streams out before it returns.
+pub trait StaleNodeIndexWriter {
+    /// Writes a stale node index batch into storage.
+    fn write_stale_node_index_batch(
+        &self,
+        stale_node_index_batch: &StaleNodeIndexBatch,
+    ) -> Result<()>;
 }
 
 /// Node batch that will be written into db atomically with other batches.
@@ -125,6 +262,9 @@ pub type NodeBatch = BTreeMap<NodeKey, Node>;
 /// [`StaleNodeIndex`](struct.StaleNodeIndex.html) batch that will be written into db atomically
 /// with other batches.
 pub type StaleNodeIndexBatch = BTreeSet<StaleNodeIndex>;
+/// Batch of the blobs of hash-only leaves keyed by their hashes, which will be written into db
+/// atomically with other batches.
+pub type ValueBatch = BTreeMap<HashValue, AccountStateBlob>;
 
 /// Indicates a node becomes stale since `stale_since_version`.
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
@@ -147,11 +287,28 @@ pub struct TreeUpdateBatch {
     pub stale_node_index_batch: StaleNodeIndexBatch,
     pub num_new_leaves: usize,
     pub num_stale_leaves: usize,
+    pub value_batch: ValueBatch,
+}
+
+/// A chunk of consecutive account blobs at some version, along with the proof that can be fed
+/// directly into [`JellyfishMerkleRestore::add_chunk`](restore/struct.JellyfishMerkleRestore.html#method.add_chunk).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StateChunkWithProof {
+    /// The account blobs in this chunk, sorted by key.
+    pub blobs: Vec<(HashValue, AccountStateBlob)>,
+    /// The range proof for the last key in `blobs`.
+    pub proof: SparseMerkleRangeProof,
+    /// The key to start the next chunk with, or `None` if this is the last chunk.
+    pub next_start_key: Option<HashValue>,
 }
 
 /// The Jellyfish Merkle tree data structure. See [`crate`] for description.
-pub struct JellyfishMerkleTree<'a, R: 'a + TreeReader> {
+pub struct JellyfishMerkleTree<'a, R: 'a + TreeReader, S: HashScheme = Blake3> {
     reader: &'a R,
+    leaf_count_mode: Option<LeafCountMode>,
+    hash_scheme: S,
+    hash_only_leaves: bool,
+    max_inline_blob_size: Option<usize>,
 }
 
 impl<'a, R> JellyfishMerkleTree<'a, R>
@@ -160,7 +317,76 @@ where
 {
     /// Creates a `JellyfishMerkleTree` backed by the given [`TreeReader`](trait.TreeReader.html).
     pub fn new(reader: &'a R) -> Self {
-        Self { reader }
+        Self::new_with_scheme(reader, None, Blake3)
+    }
+
+    /// Same as [`new`](struct.JellyfishMerkleTree.html#method.new), but the internal nodes written
+    /// by this tree track the number of leaves under each child in `mode`. The same mode must be
+    /// used for all versions of a tree.
+    pub fn new_with_leaf_count(reader: &'a R, mode: LeafCountMode) -> Self {
+        Self::new_with_scheme(reader, Some(mode), Blake3)
+    }
+}
+
+impl<'a, R, S> JellyfishMerkleTree<'a, R, S>
+where
+    R: 'a + TreeReader,
+    S: HashScheme,
+{
+    /// Creates a `JellyfishMerkleTree` whose hashes are computed with `hash_scheme` instead of
+    /// [`Blake3`](struct.Blake3.html), tracking leaf counts in `leaf_count_mode` if it is given.
+    /// The same scheme must be used for all versions of a tree, and its proofs must be verified
+    /// with it, for example with
+    /// [`SparseMerkleProof::verify_with_scheme`](struct.SparseMerkleProof.html#method.verify_with_scheme).
+    ///
+    /// The other modules of this crate that compute hashes, such as
+    /// [`restore`](restore/index.html), take the scheme through their own `_with_scheme`
+    /// constructors and functions.
+    pub fn new_with_scheme(
+        reader: &'a R,
+        leaf_count_mode: Option<LeafCountMode>,
+        hash_scheme: S,
+    ) -> Self {
+        Self {
+            reader,
+            leaf_count_mode,
+            hash_scheme,
+            hash_only_leaves: false,
+            max_inline_blob_size: None,
+        }
+    }
+
+    /// Makes the leaves written by this tree hash-only, with their blobs written into the
+    /// [`value_batch`](struct.TreeUpdateBatch.html#structfield.value_batch) of the update batch
+    /// instead. A blob is then written once, however many times its leaf moves in the tree, and
+    /// only read when it is returned, not for proofs.
+    ///
+    /// Blobs are never purged along with stale nodes, since other leaves may share them.
+    pub fn with_hash_only_leaves(mut self) -> Self {
+        self.hash_only_leaves = true;
+        self
+    }
+
+    /// Makes the leaves written by this tree whose blobs have at most `max_inline_blob_size`
+    /// bytes also inlined into their parent internal nodes. Reading such a leaf then takes one
+    /// node read less, while hashes and proofs stay the same.
+    pub fn with_inline_leaves(mut self, max_inline_blob_size: usize) -> Self {
+        self.max_inline_blob_size = Some(max_inline_blob_size);
+        self
+    }
+
+    /// Creates a tree with the same options as this one, reading from `reader` instead.
+    fn with_reader<'b, R2: 'b + TreeReader>(
+        &self,
+        reader: &'b R2,
+    ) -> JellyfishMerkleTree<'b, R2, S> {
+        JellyfishMerkleTree {
+            reader,
+            leaf_count_mode: self.leaf_count_mode,
+            hash_scheme: self.hash_scheme.clone(),
+            hash_only_leaves: self.hash_only_leaves,
+            max_inline_blob_size: self.max_inline_blob_size,
+        }
     }
 
     /// This is a convenient function that calls
@@ -236,10 +462,10 @@ where
             let version = first_version + idx as u64;
             blob_set
                 .into_iter()
-                .map(|(key, blob)| Self::put(key, Some(blob), version, &mut tree_cache))
+                .map(|(key, blob)| self.put(key, Some(blob), version, &mut tree_cache))
                 .collect::<Result<_>>()?;
             // Freezes the current cache to make all contents in the current cache immutable.
-            tree_cache.freeze();
+            tree_cache.freeze(&self.hash_scheme);
         }
 
         Ok(tree_cache.into())
@@ -260,16 +486,72 @@ where
             let version = first_version + idx as u64;
             blob_set
                 .into_iter()
-                .map(|(key, blob)| Self::put(key, blob, version, &mut tree_cache))
+                .map(|(key, blob)| self.put(key, blob, version, &mut tree_cache))
                 .collect::<Result<_>>()?;
             // Freezes the current cache to make all contents in the current cache immutable.
-            tree_cache.freeze();
+            tree_cache.freeze(&self.hash_scheme);
+        }
+
+        Ok(tree_cache.into())
+    }
+
+    /// Same as [`put_blob_sets`](struct.JellyfishMerkleTree.html#method.put_blob_sets), but also
+    /// returns the [`Witness`](witness/struct.Witness.html) of the updates, which has every node
+    /// read from storage. With the witness, the root hashes can be recomputed without storage
+    /// using a [`WitnessReader`](witness/struct.WitnessReader.html).
+    pub fn put_blob_sets_with_witness(
+        &self,
+        blob_sets: Vec<Vec<(HashValue, AccountStateBlob)>>,
+        first_version: Version,
+    ) -> Result<(Vec<HashValue>, TreeUpdateBatch, Witness)> {
+        let recorder = WitnessRecorder::new(self.reader);
+        let tree = self.with_reader(&recorder);
+        let (root_hashes, tree_update_batch) = tree.put_blob_sets(blob_sets, first_version)?;
+        Ok((root_hashes, tree_update_batch, recorder.into_witness()))
+    }
+
+    /// Same as [`put_blob_sets2`](struct.JellyfishMerkleTree.html#method.put_blob_sets2), but
+    /// keeps at most about `max_nodes_in_memory` nodes in memory, so that a huge number of
+    /// updates, for example in genesis or migrations, can be applied in one go. Whenever there
+    /// are more nodes, the nodes that can no longer change are written to `writer`, along with
+    /// the stale node indices and the blobs of hash-only leaves collected so far, and they are
+    /// left out of the returned batch.
+    ///
+    /// Nodes of earlier versions in `blob_sets` can always be written early, while new nodes of a
+    /// version can only be written early if the keys of that version are sorted, so huge
+    /// versions should be sorted by key. Either way, what is written to `writer` together with
+    /// the returned batch is exactly the batch `put_blob_sets2` returns. Since later updates
+    /// may read the nodes written early, `writer` must write into the storage this tree reads
+    /// from, so unlike `put_blob_sets2` the updates are not written atomically.
+    pub fn put_blob_sets_bounded<W: TreeWriter + StaleNodeIndexWriter>(
+        &self,
+        blob_sets: Vec<Vec<(HashValue, Option<AccountStateBlob>)>>,
+        first_version: Version,
+        writer: &W,
+        max_nodes_in_memory: usize,
+    ) -> Result<(Vec<HashValue>, TreeUpdateBatch)> {
+        let mut tree_cache =
+            TreeCache::new_with_spilling(self.reader, first_version, writer, max_nodes_in_memory)?;
+        for (idx, blob_set) in blob_sets.into_iter().enumerate() {
+            assert!(
+                !blob_set.is_empty(),
+                "Transactions that output empty write set should not be included.",
+            );
+            let version = first_version + idx as u64;
+            tree_cache.set_keys_sorted(blob_set.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+            blob_set
+                .into_iter()
+                .map(|(key, blob)| self.put(key, blob, version, &mut tree_cache))
+                .collect::<Result<_>>()?;
+            // Freezes the current cache to make all contents in the current cache immutable.
+            tree_cache.freeze(&self.hash_scheme);
         }
 
         Ok(tree_cache.into())
     }
 
     fn put(
+        &self,
         key: HashValue,
         blob: Option<AccountStateBlob>,
         version: Version,
@@ -283,7 +565,7 @@ where
         let mut nibble_iter = nibble_path.nibbles();
 
         // Start insertion from the root node.
-        match Self::insert_at(
+        match self.insert_at(
             root_node_key.clone(),
             version,
             &mut nibble_iter,
@@ -303,7 +585,7 @@ where
                 tree_cache.set_root_node_key(genesis_root_key);
             }
         }
-        Ok(())
+        tree_cache.finish_put(key)
     }
 
     /// Helper function for recursive insertion into the subtree that starts from the current
@@ -311,6 +593,7 @@ where
     /// It is safe to use recursion here because the max depth is limited by the key length which
     /// for this tree is the length of the hash of account addresses.
     fn insert_at(
+        &self,
         node_key: NodeKey,
         version: Version,
         nibble_iter: &mut NibbleIterator,
@@ -319,7 +602,7 @@ where
     ) -> Result<PutResult<(NodeKey, Node)>> {
         let node = tree_cache.get_node(&node_key)?;
         match node {
-            Node::Internal(internal_node) => Self::insert_at_internal_node(
+            Node::Internal(internal_node) => self.insert_at_internal_node(
                 node_key,
                 internal_node,
                 version,
@@ -327,7 +610,7 @@ where
                 blob,
                 tree_cache,
             ),
-            Node::Leaf(leaf_node) => Self::insert_at_leaf_node(
+            Node::Leaf(leaf_node) => self.insert_at_leaf_node(
                 node_key,
                 leaf_node,
                 version,
@@ -347,7 +630,7 @@ where
                     tree_cache.delete_node(&node_key, false /* is_leaf */);
                 }
                 if let Some(blob) = blob {
-                    Ok(PutResult::Updated(Self::create_leaf_node(
+                    Ok(PutResult::Updated(self.create_leaf_node(
                         NodeKey::new_empty_path(version),
                         &nibble_iter,
                         blob,
@@ -364,6 +647,7 @@ where
     /// `internal_node`. Returns the newly inserted node with its
     /// [`NodeKey`](node_type/struct.NodeKey.html).
     fn insert_at_internal_node(
+        &self,
         mut node_key: NodeKey,
         internal_node: InternalNode,
         version: Version,
@@ -379,13 +663,13 @@ where
         let result = match internal_node.child(child_index) {
             Some(child) => {
                 let child_node_key = node_key.gen_child_node_key(child.version, child_index);
-                Self::insert_at(child_node_key, version, nibble_iter, blob, tree_cache)?
+                self.insert_at(child_node_key, version, nibble_iter, blob, tree_cache)?
             }
             None => {
                 if let Some(blob) = blob {
                     // insert
                     let new_child_node_key = node_key.gen_child_node_key(version, child_index);
-                    PutResult::Updated(Self::create_leaf_node(
+                    PutResult::Updated(self.create_leaf_node(
                         new_child_node_key,
                         nibble_iter,
                         blob,
@@ -407,10 +691,16 @@ where
             }
             PutResult::Updated((_, new_node)) => {
                 // update child
-                children.insert(
-                    child_index,
-                    Child::new(new_node.hash(), version, new_node.is_leaf()),
-                );
+                let new_child = match &new_node {
+                    Node::Leaf(leaf_node) => self.new_leaf_child(leaf_node, version),
+                    _ => self.new_child(
+                        new_node.hash_with_scheme(&self.hash_scheme),
+                        version,
+                        false, /* is_leaf */
+                        new_node.leaf_count(),
+                    ),
+                };
+                children.insert(child_index, new_child);
             }
             PutResult::Removed => {
                 // remove child
@@ -434,7 +724,7 @@ where
                 tree_cache.put_node(node_key.clone(), child_node.clone())?;
                 Ok(PutResult::Updated((node_key, child_node)))
             } else {
-                let new_internal_node = InternalNode::new(children);
+                let new_internal_node = self.new_internal_node(children)?;
 
                 node_key.set_version(version);
 
@@ -452,6 +742,7 @@ where
     /// `existing_leaf_node`. Returns the newly inserted node with its
     /// [`NodeKey`](node_type/struct.NodeKey.html).
     fn insert_at_leaf_node(
+        &self,
         mut node_key: NodeKey,
         existing_leaf_node: LeafNode,
         version: Version,
@@ -491,7 +782,7 @@ where
                 // The new leaf node will have the same nibble_path with a new version as node_key.
                 node_key.set_version(version);
                 // Create the new leaf node with the same address but new blob content.
-                return Ok(PutResult::Updated(Self::create_leaf_node(
+                return Ok(PutResult::Updated(self.create_leaf_node(
                     node_key,
                     nibble_iter,
                     blob,
@@ -522,7 +813,7 @@ where
             let mut children = Children::new();
             children.insert(
                 existing_leaf_index,
-                Child::new(existing_leaf_node.hash(), version, true /* is_leaf */),
+                self.new_leaf_child(&existing_leaf_node, version),
             );
             node_key = NodeKey::new(version, common_nibble_path.clone());
             tree_cache.put_node(
@@ -530,18 +821,19 @@ where
                 existing_leaf_node.into(),
             )?;
 
-            let (_, new_leaf_node) = Self::create_leaf_node(
+            let (_, new_leaf_node) = self.create_leaf_node(
                 node_key.gen_child_node_key(version, new_leaf_index),
                 nibble_iter,
                 blob,
                 tree_cache,
             )?;
-            children.insert(
-                new_leaf_index,
-                Child::new(new_leaf_node.hash(), version, true /* is_leaf */),
-            );
+            let new_leaf_child = match &new_leaf_node {
+                Node::Leaf(leaf_node) => self.new_leaf_child(leaf_node, version),
+                _ => unreachable!("A leaf node was just created."),
+            };
+            children.insert(new_leaf_index, new_leaf_child);
 
-            let internal_node = InternalNode::new(children);
+            let internal_node = self.new_internal_node(children)?;
             let mut next_internal_node = internal_node.clone();
             tree_cache.put_node(node_key.clone(), internal_node.into())?;
 
@@ -553,9 +845,14 @@ where
                 let mut children = Children::new();
                 children.insert(
                     nibble,
-                    Child::new(next_internal_node.hash(), version, false /* is_leaf */),
+                    self.new_child(
+                        next_internal_node.hash_with_scheme(&self.hash_scheme),
+                        version,
+                        false, /* is_leaf */
+                        next_internal_node.leaf_count(),
+                    ),
                 );
-                let internal_node = InternalNode::new(children);
+                let internal_node = self.new_internal_node(children)?;
                 next_internal_node = internal_node.clone();
                 tree_cache.put_node(node_key.clone(), internal_node.into())?;
             }
@@ -567,8 +864,54 @@ where
         }
     }
 
+    /// Helper function for creating a child, with its leaf count if this tree tracks it.
+    fn new_child(
+        &self,
+        hash: HashValue,
+        version: Version,
+        is_leaf: bool,
+        leaf_count: Option<u64>,
+    ) -> Child {
+        match (self.leaf_count_mode, leaf_count) {
+            (Some(_), Some(leaf_count)) => {
+                Child::new_with_leaf_count(hash, version, is_leaf, leaf_count)
+            }
+            _ => Child::new(hash, version, is_leaf),
+        }
+    }
+
+    /// Helper function for creating a leaf child, with `leaf_node` inlined if this tree inlines
+    /// leaves and its blob is small enough.
+    fn new_leaf_child(&self, leaf_node: &LeafNode, version: Version) -> Child {
+        let hash = leaf_node.hash_with_scheme(&self.hash_scheme);
+        let blob_size = leaf_node.blob().map_or(0, |blob| blob.as_ref().len());
+        match self.max_inline_blob_size {
+            Some(max_inline_blob_size) if blob_size <= max_inline_blob_size => {
+                Child::new_with_inline_leaf(hash, version, leaf_node.clone())
+            }
+            _ => Child::new(hash, version, true /* is_leaf */),
+        }
+    }
+
+    /// Helper function for creating internal nodes in the leaf count mode of this tree.
+    fn new_internal_node(&self, children: Children) -> Result<InternalNode> {
+        Ok(match self.leaf_count_mode {
+            Some(mode) => {
+                ensure!(
+                    children
+                        .values()
+                        .all(|child| child.is_leaf || child.leaf_count.is_some()),
+                    "Cannot track leaf counts in a tree that was created without them.",
+                );
+                InternalNode::new_with_leaf_count(children, mode)
+            }
+            None => InternalNode::new(children),
+        })
+    }
+
     /// Helper function for creating leaf nodes. Returns the newly created leaf node.
     fn create_leaf_node(
+        &self,
         node_key: NodeKey,
         nibble_iter: &NibbleIterator,
         blob: AccountStateBlob,
@@ -576,11 +919,16 @@ where
     ) -> Result<(NodeKey, Node)> {
         // Get the underlying bytes of nibble_iter which must be a key, i.e., hashed account address
         // with `HashValue::LENGTH` bytes.
-        let new_leaf_node = Node::new_leaf(
-            HashValue::from_slice(nibble_iter.get_nibble_path().bytes())
-                .expect("LeafNode must have full nibble path."),
-            blob,
-        );
+        let account_key = HashValue::from_slice(nibble_iter.get_nibble_path().bytes())
+            .expect("LeafNode must have full nibble path.");
+        let new_leaf_node: Node = if self.hash_only_leaves {
+            let blob_hash = blob.hash_with_scheme(&self.hash_scheme);
+            tree_cache.put_value(blob_hash, blob);
+            LeafNode::new_hash_only(account_key, blob_hash)
+        } else {
+            LeafNode::new_with_scheme(account_key, blob, &self.hash_scheme)
+        }
+        .into();
 
         tree_cache.put_node(node_key.clone(), new_leaf_node.clone())?;
         Ok((node_key, new_leaf_node))
@@ -592,6 +940,150 @@ where
         key: HashValue,
         version: Version,
     ) -> Result<(Option<AccountStateBlob>, SparseMerkleProof)> {
+        let (leaf_node, proof) = self.get_leaf_with_proof(key, version)?;
+        let blob = match leaf_node {
+            Some(leaf_node) => Some(get_leaf_blob(self.reader, leaf_node)?),
+            None => None,
+        };
+        Ok((blob, proof))
+    }
+
+    /// Same as [`get_with_proof`](struct.JellyfishMerkleTree.html#method.get_with_proof), but
+    /// returns the leaf of `key` instead of its blob, so the blob of a hash-only leaf is not read.
+    fn get_leaf_with_proof(
+        &self,
+        key: HashValue,
+        version: Version,
+    ) -> Result<(Option<LeafNode>, SparseMerkleProof)> {
+        self.walk_to_key(key, version, |node_key, nibble| {
+            Ok(match self.reader.get_node(node_key)? {
+                Node::Internal(internal_node) => {
+                    let queried_child_index =
+                        nibble.ok_or_else(|| format_err!("ran out of nibbles"))?;
+                    let (child_node_key, siblings) = internal_node.get_child_with_siblings(
+                        node_key,
+                        queried_child_index,
+                        &self.hash_scheme,
+                    );
+                    // A leaf inlined into this node does not have to be read.
+                    match child_node_key.as_ref().and_then(|child_node_key| {
+                        internal_node.into_inline_leaf(child_index(child_node_key))
+                    }) {
+                        Some(leaf_node) => Self::leaf_step(key, siblings, leaf_node),
+                        None => ProofStep::Internal(siblings, child_node_key),
+                    }
+                }
+                Node::Leaf(leaf_node) => Self::leaf_step(key, vec![], leaf_node),
+                Node::Null => ProofStep::Null,
+            })
+        })
+    }
+
+    /// Helper function for the step of
+    /// [`get_leaf_with_proof`](struct.JellyfishMerkleTree.html#method.get_leaf_with_proof) at
+    /// `leaf_node`, which returns the leaf if it is the leaf of `key`.
+    fn leaf_step(
+        key: HashValue,
+        siblings: Vec<HashValue>,
+        leaf_node: LeafNode,
+    ) -> ProofStep<LeafNode> {
+        let proof_leaf = SparseMerkleLeafNode::new(leaf_node.account_key(), leaf_node.blob_hash());
+        let leaf_node = if leaf_node.account_key() == key {
+            Some(leaf_node)
+        } else {
+            None
+        };
+        ProofStep::Leaf(siblings, proof_leaf, leaf_node)
+    }
+
+    /// Same as [`get_with_proof`](struct.JellyfishMerkleTree.html#method.get_with_proof), but
+    /// returns the blob as [`Bytes`]. If the reader keeps nodes serialized (see
+    /// [`TreeReader::has_encoded_nodes`]), the blob is a slice of the buffer of its leaf, so it is
+    /// not copied.
+    ///
+    /// [`TreeReader::has_encoded_nodes`]: trait.TreeReader.html#method.has_encoded_nodes
+    pub fn get_bytes_with_proof(
+        &self,
+        key: HashValue,
+        version: Version,
+    ) -> Result<(Option<Bytes>, SparseMerkleProof)> {
+        if !self.reader.has_encoded_nodes() {
+            let (leaf_node, proof) = self.get_leaf_with_proof(key, version)?;
+            let bytes = match leaf_node {
+                Some(leaf_node) => Some(Bytes::from(Vec::from(get_leaf_blob(
+                    self.reader,
+                    leaf_node,
+                )?))),
+                None => None,
+            };
+            return Ok((bytes, proof));
+        }
+        self.walk_to_key(key, version, |node_key, nibble| {
+            let encoded_node = self
+                .reader
+                .get_encoded_node_option(node_key)?
+                .ok_or_else(|| format_err!("Missing node at {:?}.", node_key))?;
+            Ok(match NodeView::decode(&encoded_node)? {
+                NodeView::Internal(internal_node) => {
+                    let queried_child_index =
+                        nibble.ok_or_else(|| format_err!("ran out of nibbles"))?;
+                    let (child_node_key, siblings) = internal_node.get_child_with_siblings(
+                        node_key,
+                        queried_child_index,
+                        &self.hash_scheme,
+                    );
+                    // A leaf inlined into this node does not have to be read.
+                    match child_node_key.as_ref().and_then(|child_node_key| {
+                        internal_node.inline_leaf(child_index(child_node_key))
+                    }) {
+                        Some(leaf_node) => {
+                            self.bytes_leaf_step(key, siblings, &encoded_node, leaf_node)?
+                        }
+                        None => ProofStep::Internal(siblings, child_node_key),
+                    }
+                }
+                NodeView::Leaf(leaf_node) => {
+                    self.bytes_leaf_step(key, vec![], &encoded_node, leaf_node)?
+                }
+                NodeView::Null => ProofStep::Null,
+            })
+        })
+    }
+
+    /// Helper function for the step of
+    /// [`get_bytes_with_proof`](struct.JellyfishMerkleTree.html#method.get_bytes_with_proof) at
+    /// `leaf_node`, which is viewed in `encoded_node`. Returns the blob if it is the leaf of
+    /// `key`.
+    fn bytes_leaf_step(
+        &self,
+        key: HashValue,
+        siblings: Vec<HashValue>,
+        encoded_node: &Bytes,
+        leaf_node: LeafNodeView,
+    ) -> Result<ProofStep<Bytes>> {
+        let blob = if leaf_node.account_key() != key {
+            None
+        } else if let Some(blob) = leaf_node.blob() {
+            Some(encoded_node.slice_ref(blob))
+        } else {
+            let blob = self.reader.get_value(&leaf_node.blob_hash())?;
+            Some(Bytes::from(Vec::from(blob)))
+        };
+        Ok(ProofStep::Leaf(siblings, leaf_node.into(), blob))
+    }
+
+    /// Walks down the tree at `version` to `key` and returns what is found for `key` along with
+    /// its proof. `read_step` reads the node at each node key on the path, given the nibble of
+    /// `key` at the depth of the node.
+    fn walk_to_key<L, F>(
+        &self,
+        key: HashValue,
+        version: Version,
+        mut read_step: F,
+    ) -> Result<(Option<L>, SparseMerkleProof)>
+    where
+        F: FnMut(&NodeKey, Option<Nibble>) -> Result<ProofStep<L>>,
+    {
         // Empty tree just returns proof with no sibling hash.
         let mut next_node_key = NodeKey::new_empty_path(version);
         let mut siblings = vec![];
@@ -601,44 +1093,24 @@ where
         // We limit the number of loops here deliberately to avoid potential cyclic graph bugs
         // in the tree structure.
         for nibble_depth in 0..=ROOT_NIBBLE_HEIGHT {
-            let next_node = self.reader.get_node(&next_node_key)?;
-            match next_node {
-                Node::Internal(internal_node) => {
-                    let queried_child_index = nibble_iter
-                        .next()
-                        .ok_or_else(|| format_err!("ran out of nibbles"))?;
-                    let (child_node_key, mut siblings_in_internal) =
-                        internal_node.get_child_with_siblings(&next_node_key, queried_child_index);
+            let (proof_leaf, found) = match read_step(&next_node_key, nibble_iter.next())? {
+                ProofStep::Internal(mut siblings_in_internal, child_node_key) => {
                     siblings.append(&mut siblings_in_internal);
-                    next_node_key = match child_node_key {
-                        Some(node_key) => node_key,
-                        None => {
-                            return Ok((
-                                None,
-                                SparseMerkleProof::new(None, {
-                                    siblings.reverse();
-                                    siblings
-                                }),
-                            ))
+                    match child_node_key {
+                        Some(node_key) => {
+                            next_node_key = node_key;
+                            continue;
                         }
-                    };
+                        None => (None, None),
+                    }
                 }
-                Node::Leaf(leaf_node) => {
-                    return Ok((
-                        if leaf_node.account_key() == key {
-                            Some(leaf_node.blob().clone())
-                        } else {
-                            None
-                        },
-                        SparseMerkleProof::new(Some(leaf_node.into()), {
-                            siblings.reverse();
-                            siblings
-                        }),
-                    ));
+                ProofStep::Leaf(mut siblings_in_internal, proof_leaf, found) => {
+                    siblings.append(&mut siblings_in_internal);
+                    (Some(proof_leaf), found)
                 }
-                Node::Null => {
+                ProofStep::Null => {
                     if nibble_depth == 0 {
-                        return Ok((None, SparseMerkleProof::new(None, vec![])));
+                        (None, None)
                     } else {
                         bail!(
                             "Non-root null node exists with node key {:?}",
@@ -646,7 +1118,9 @@ where
                         );
                     }
                 }
-            }
+            };
+            siblings.reverse();
+            return Ok((found, SparseMerkleProof::new(proof_leaf, siblings)));
         }
         bail!("Jellyfish Merkle tree has cyclic graph inside.");
     }
@@ -657,8 +1131,8 @@ where
         rightmost_key_to_prove: HashValue,
         version: Version,
     ) -> Result<SparseMerkleRangeProof> {
-        let (account, proof) = self.get_with_proof(rightmost_key_to_prove, version)?;
-        ensure!(account.is_some(), "rightmost_key_to_prove must exist.");
+        let (leaf_node, proof) = self.get_leaf_with_proof(rightmost_key_to_prove, version)?;
+        ensure!(leaf_node.is_some(), "rightmost_key_to_prove must exist.");
 
         let siblings = proof
             .siblings()
@@ -678,6 +1152,459 @@ where
         Ok(SparseMerkleRangeProof::new(siblings))
     }
 
+    /// Gets the proof that shows how writing `keys` changes the tree at `version`. The proof
+    /// carries the values of `keys` at `version`, so it can be verified against any new values
+    /// written to them. Returns error if the tree commits to leaf counts.
+    pub fn get_update_proof(
+        &self,
+        keys: &[HashValue],
+        version: Version,
+    ) -> Result<SparseMerkleUpdateProof> {
+        let keys: Vec<_> = keys
+            .iter()
+            .cloned()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        ensure!(!keys.is_empty(), "No keys to prove.");
+        let root_node_key = NodeKey::new_empty_path(version);
+        let root_node = self.reader.get_node(&root_node_key)?;
+        let mut old_values = BTreeMap::new();
+        let mut nodes = vec![];
+        self.add_update_proof_nodes(
+            &root_node_key,
+            root_node,
+            0, /* depth */
+            &keys,
+            &mut old_values,
+            &mut nodes,
+        )?;
+        Ok(SparseMerkleUpdateProof::new(
+            keys.iter()
+                .map(|key| (*key, old_values.remove(key)))
+                .collect(),
+            nodes,
+        ))
+    }
+
+    /// Adds the nodes of the update proof for the subtree of `node`, which is at `depth` in bits
+    /// and where `keys` fall, to `nodes`. Found values of `keys` are added to `old_values`.
+    fn add_update_proof_nodes(
+        &self,
+        node_key: &NodeKey,
+        node: Node,
+        depth: usize,
+        keys: &[HashValue],
+        old_values: &mut BTreeMap<HashValue, AccountStateBlob>,
+        nodes: &mut Vec<SparseMerkleUpdateProofNode>,
+    ) -> Result<()> {
+        match node {
+            Node::Null => nodes.push(SparseMerkleUpdateProofNode::Empty),
+            Node::Leaf(leaf_node) => {
+                let proof_leaf =
+                    SparseMerkleLeafNode::new(leaf_node.account_key(), leaf_node.blob_hash());
+                if keys.contains(&leaf_node.account_key()) {
+                    old_values.insert(
+                        leaf_node.account_key(),
+                        get_leaf_blob(self.reader, leaf_node)?,
+                    );
+                }
+                nodes.push(SparseMerkleUpdateProofNode::Leaf(proof_leaf));
+            }
+            Node::Internal(internal_node) => {
+                ensure!(
+                    internal_node.leaf_count_mode() != Some(LeafCountMode::Committed),
+                    "Update proofs are not supported for trees committing to leaf counts.",
+                );
+                self.add_update_proof_nodes_in_range(
+                    node_key,
+                    &internal_node,
+                    0,  /* start */
+                    16, /* width */
+                    depth,
+                    keys,
+                    old_values,
+                    nodes,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Same as `add_update_proof_nodes`, for the subtree of the children of `internal_node` in
+    /// `[start, start + width)`.
+    #[allow(clippy::too_many_arguments)]
+    fn add_update_proof_nodes_in_range(
+        &self,
+        node_key: &NodeKey,
+        internal_node: &InternalNode,
+        start: u8,
+        width: u8,
+        depth: usize,
+        keys: &[HashValue],
+        old_values: &mut BTreeMap<HashValue, AccountStateBlob>,
+        nodes: &mut Vec<SparseMerkleUpdateProofNode>,
+    ) -> Result<()> {
+        if keys.is_empty() {
+            nodes.push(SparseMerkleUpdateProofNode::Subtree(
+                internal_node.range_hash(start, width, &self.hash_scheme),
+            ));
+            return Ok(());
+        }
+
+        let mut children = (start..start + width)
+            .map(Nibble::from)
+            .filter_map(|n| internal_node.child(n).map(|child| (n, child)));
+        match (children.next(), children.next()) {
+            (None, _) => nodes.push(SparseMerkleUpdateProofNode::Empty),
+            (Some((n, child)), None) if child.is_leaf || width == 1 => {
+                let child_node_key = node_key.gen_child_node_key(child.version, n);
+                let child_node = self.reader.get_node(&child_node_key)?;
+                self.add_update_proof_nodes(
+                    &child_node_key,
+                    child_node,
+                    depth,
+                    keys,
+                    old_values,
+                    nodes,
+                )?;
+            }
+            _ => {
+                nodes.push(SparseMerkleUpdateProofNode::Internal);
+                let num_left = keys
+                    .iter()
+                    .position(|key| key.iter_bits().nth(depth).expect("Must exist."))
+                    .unwrap_or(keys.len());
+                for (half_start, half_keys) in [
+                    (start, &keys[..num_left]),
+                    (start + width / 2, &keys[num_left..]),
+                ]
+                .iter()
+                {
+                    self.add_update_proof_nodes_in_range(
+                        node_key,
+                        internal_node,
+                        *half_start,
+                        width / 2,
+                        depth + 1,
+                        half_keys,
+                        old_values,
+                        nodes,
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the root hash of the subtree at `prefix` in the tree at `version`, along with the
+    /// proof that links it to the root of the tree. See [`subtree`](subtree/index.html) for how
+    /// the root hash of a subtree is defined.
+    pub fn get_subtree_root_with_proof(
+        &self,
+        prefix: &NibblePath,
+        version: Version,
+    ) -> Result<(HashValue, SubtreeProof)> {
+        let mut next_node_key = NodeKey::new_empty_path(version);
+        let mut siblings = vec![];
+        let mut nibble_iter = prefix.nibbles();
+
+        // We limit the number of loops here deliberately to avoid potential cyclic graph bugs
+        // in the tree structure.
+        for nibble_depth in 0..=ROOT_NIBBLE_HEIGHT {
+            let next_node = self.reader.get_node(&next_node_key)?;
+            match next_node {
+                Node::Internal(internal_node) => {
+                    let queried_child_index = match nibble_iter.next() {
+                        Some(nibble) => nibble,
+                        // We have reached the prefix.
+                        None => {
+                            siblings.reverse();
+                            return Ok((
+                                internal_node.hash_with_scheme(&self.hash_scheme),
+                                SubtreeProof::new(None, siblings),
+                            ));
+                        }
+                    };
+                    let (child_node_key, mut siblings_in_internal) = internal_node
+                        .get_child_with_siblings(
+                            &next_node_key,
+                            queried_child_index,
+                            &self.hash_scheme,
+                        );
+                    siblings.append(&mut siblings_in_internal);
+                    next_node_key = match child_node_key {
+                        Some(node_key) => node_key,
+                        None => {
+                            siblings.reverse();
+                            return Ok((
+                                self.hash_scheme.placeholder_hash(),
+                                SubtreeProof::new(None, siblings),
+                            ));
+                        }
+                    };
+                }
+                Node::Leaf(leaf_node) => {
+                    // The leaf is the only one under the prefix if it is under the prefix at all.
+                    let start_key = path_to_key(prefix);
+                    let subtree_root_hash =
+                        if leaf_node.account_key().common_prefix_nibbles_len(start_key)
+                            >= prefix.num_nibbles()
+                        {
+                            leaf_node.hash_with_scheme(&self.hash_scheme)
+                        } else {
+                            self.hash_scheme.placeholder_hash()
+                        };
+                    siblings.reverse();
+                    return Ok((
+                        subtree_root_hash,
+                        SubtreeProof::new(Some(leaf_node.into()), siblings),
+                    ));
+                }
+                Node::Null => {
+                    if nibble_depth == 0 {
+                        return Ok((
+                            self.hash_scheme.placeholder_hash(),
+                            SubtreeProof::new(None, vec![]),
+                        ));
+                    } else {
+                        bail!(
+                            "Non-root null node exists with node key {:?}",
+                            next_node_key
+                        );
+                    }
+                }
+            }
+        }
+        bail!("Jellyfish Merkle tree has cyclic graph inside.");
+    }
+
+    /// Returns an iterator over all the key-blob pairs under `prefix` in the tree at `version`, in
+    /// ascending order of keys.
+    pub fn iter_subtree(
+        &self,
+        prefix: &NibblePath,
+        version: Version,
+    ) -> Result<JellyfishMerkleIterator<R, &'a R>> {
+        let (start_key, end_key) = prefix_key_range(prefix);
+        let iter = JellyfishMerkleIterator::new_borrowed(self.reader, version, start_key)?;
+        Ok(match end_key {
+            Some(end_key) => iter.with_end_key(end_key),
+            None => iter,
+        })
+    }
+
+    /// Gets a chunk of account blobs at `version`, starting from the smallest key that is greater
+    /// or equal to `start_key`. The chunk ends as soon as adding another blob would make it contain
+    /// more than `max_items` blobs or more than `max_bytes` bytes of keys and blobs, but it always
+    /// contains at least one blob. Returns error if there is no key at or after `start_key`.
+    pub fn get_chunk_with_proof(
+        &self,
+        version: Version,
+        start_key: HashValue,
+        max_items: usize,
+        max_bytes: usize,
+    ) -> Result<StateChunkWithProof> {
+        ensure!(max_items > 0, "max_items must be positive.");
+
+        let iter = JellyfishMerkleIterator::new_borrowed(self.reader, version, start_key)?;
+        let mut blobs = vec![];
+        let mut num_bytes = 0;
+        let mut next_start_key = None;
+        for res in iter {
+            let (key, blob) = res?;
+            let blob_bytes = HashValue::LENGTH + blob.as_ref().len();
+            if !blobs.is_empty() && (blobs.len() >= max_items || num_bytes + blob_bytes > max_bytes)
+            {
+                next_start_key = Some(key);
+                break;
+            }
+            num_bytes += blob_bytes;
+            blobs.push((key, blob));
+        }
+
+        let last_key = match blobs.last() {
+            Some((key, _blob)) => *key,
+            None => bail!(
+                "No key at or after {:x} exists at version {}.",
+                start_key,
+                version
+            ),
+        };
+        let proof = self.get_range_proof(last_key, version)?;
+        Ok(StateChunkWithProof {
+            blobs,
+            proof,
+            next_start_key,
+        })
+    }
+
+    /// Returns the account state blob (if applicable) and the proof that also reveals the rank of
+    /// `key` and the total number of leaves. The tree must commit its leaf counts, see
+    /// [`LeafCountMode::Committed`](node_type/enum.LeafCountMode.html#variant.Committed).
+    pub fn get_with_counted_proof(
+        &self,
+        key: HashValue,
+        version: Version,
+    ) -> Result<(Option<AccountStateBlob>, SparseMerkleCountedProof)> {
+        let mut next_node_key = NodeKey::new_empty_path(version);
+        let mut siblings = vec![];
+        let nibble_path = NibblePath::new(key.to_vec());
+        let mut nibble_iter = nibble_path.nibbles();
+
+        for nibble_depth in 0..=ROOT_NIBBLE_HEIGHT {
+            let next_node = self.reader.get_node(&next_node_key)?;
+            match next_node {
+                Node::Internal(internal_node) => {
+                    ensure!(
+                        internal_node.leaf_count_mode() == Some(LeafCountMode::Committed),
+                        "Node {:?} does not commit its leaf counts.",
+                        next_node_key,
+                    );
+                    let queried_child_index = nibble_iter
+                        .next()
+                        .ok_or_else(|| format_err!("ran out of nibbles"))?;
+                    let (child_node_key, mut siblings_in_internal) = internal_node
+                        .get_child_with_counted_siblings(
+                            &next_node_key,
+                            queried_child_index,
+                            &self.hash_scheme,
+                        );
+                    siblings.append(&mut siblings_in_internal);
+                    next_node_key = match child_node_key {
+                        Some(node_key) => node_key,
+                        None => {
+                            siblings.reverse();
+                            return Ok((None, SparseMerkleCountedProof::new(None, siblings)));
+                        }
+                    };
+                }
+                Node::Leaf(leaf_node) => {
+                    let proof_leaf =
+                        SparseMerkleLeafNode::new(leaf_node.account_key(), leaf_node.blob_hash());
+                    let blob = if leaf_node.account_key() == key {
+                        Some(get_leaf_blob(self.reader, leaf_node)?)
+                    } else {
+                        None
+                    };
+                    siblings.reverse();
+                    return Ok((
+                        blob,
+                        SparseMerkleCountedProof::new(Some(proof_leaf), siblings),
+                    ));
+                }
+                Node::Null => {
+                    if nibble_depth == 0 {
+                        return Ok((None, SparseMerkleCountedProof::new(None, vec![])));
+                    } else {
+                        bail!(
+                            "Non-root null node exists with node key {:?}",
+                            next_node_key
+                        );
+                    }
+                }
+            }
+        }
+        bail!("Jellyfish Merkle tree has cyclic graph inside.");
+    }
+
+    /// Returns the number of leaves in the tree at `version`. The tree must track leaf counts.
+    pub fn get_leaf_count(&self, version: Version) -> Result<u64> {
+        let root_node_key = NodeKey::new_empty_path(version);
+        self.reader
+            .get_node(&root_node_key)?
+            .leaf_count()
+            .ok_or_else(|| format_err!("Node {:?} does not track leaf counts.", root_node_key))
+    }
+
+    /// Returns the number of keys less than `key` in the tree at `version`. The tree must track
+    /// leaf counts.
+    pub fn get_rank(&self, key: HashValue, version: Version) -> Result<u64> {
+        let mut next_node_key = NodeKey::new_empty_path(version);
+        let mut rank = 0;
+        let nibble_path = NibblePath::new(key.to_vec());
+        let mut nibble_iter = nibble_path.nibbles();
+        for _nibble_depth in 0..=ROOT_NIBBLE_HEIGHT {
+            match self.reader.get_node(&next_node_key)? {
+                Node::Internal(internal_node) => {
+                    let child_index = nibble_iter
+                        .next()
+                        .ok_or_else(|| format_err!("ran out of nibbles"))?;
+                    for i in 0..u8::from(child_index) {
+                        if let Some(child) = internal_node.child(Nibble::from(i)) {
+                            rank += child.leaf_count.ok_or_else(|| {
+                                format_err!("Node {:?} does not track leaf counts.", next_node_key)
+                            })?;
+                        }
+                    }
+                    next_node_key = match internal_node.child(child_index) {
+                        Some(child) => next_node_key.gen_child_node_key(child.version, child_index),
+                        None => return Ok(rank),
+                    };
+                }
+                Node::Leaf(leaf_node) => {
+                    return Ok(rank + (leaf_node.account_key() < key) as u64);
+                }
+                Node::Null => return Ok(rank),
+            }
+        }
+        bail!("Jellyfish Merkle tree has cyclic graph inside.");
+    }
+
+    /// Returns the `index`-th smallest key in the tree at `version`, or `None` if there are not
+    /// that many keys. The tree must track leaf counts.
+    pub fn get_key_at_index(&self, mut index: u64, version: Version) -> Result<Option<HashValue>> {
+        let mut next_node_key = NodeKey::new_empty_path(version);
+        for _nibble_depth in 0..=ROOT_NIBBLE_HEIGHT {
+            match self.reader.get_node(&next_node_key)? {
+                Node::Internal(internal_node) => {
+                    let mut next_child = None;
+                    for i in 0..16u8 {
+                        let child_index = Nibble::from(i);
+                        if let Some(child) = internal_node.child(child_index) {
+                            let leaf_count = child.leaf_count.ok_or_else(|| {
+                                format_err!("Node {:?} does not track leaf counts.", next_node_key)
+                            })?;
+                            if index < leaf_count {
+                                next_child = Some((child_index, child.version));
+                                break;
+                            }
+                            index -= leaf_count;
+                        }
+                    }
+                    next_node_key = match next_child {
+                        Some((child_index, child_version)) => {
+                            next_node_key.gen_child_node_key(child_version, child_index)
+                        }
+                        None => return Ok(None),
+                    };
+                }
+                Node::Leaf(leaf_node) => {
+                    return Ok(if index == 0 {
+                        Some(leaf_node.account_key())
+                    } else {
+                        None
+                    });
+                }
+                Node::Null => return Ok(None),
+            }
+        }
+        bail!("Jellyfish Merkle tree has cyclic graph inside.");
+    }
+
+    /// Draws up to `num_samples` distinct random leaves from the tree at `version`, each along
+    /// with the proof of its key. The same `seed` always yields the same leaves from the same
+    /// tree. See [`sampler`](sampler/index.html) for how the leaves are picked.
+    pub fn sample_leaves_with_proof(
+        &self,
+        version: Version,
+        num_samples: usize,
+        seed: [u8; 32],
+    ) -> Result<Vec<(HashValue, AccountStateBlob, SparseMerkleProof)>> {
+        LeafSampler::new_with_scheme(self.reader, version, seed, self.hash_scheme.clone())
+            .sample_distinct(num_samples)
+    }
+
     #[cfg(test)]
     pub fn get(&self, key: HashValue, version: Version) -> Result<Option<AccountStateBlob>> {
         Ok(self.get_with_proof(key, version)?.0)
@@ -694,7 +1621,7 @@ where
         Ok(self
             .reader
             .get_node_option(&root_node_key)?
-            .map(|root_node| root_node.hash()))
+            .map(|root_node| root_node.hash_with_scheme(&self.hash_scheme)))
     }
 }
 
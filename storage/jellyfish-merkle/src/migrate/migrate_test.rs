@@ -0,0 +1,307 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    migrate::{migrate_tree, migrate_tree_with_batch_size, MigrationScope, RootHashMapping},
+    mock_tree_store::MockTreeStore,
+    node_type::{LeafCountMode, LeafNode, NodeKey},
+    test_helper::init_mock_db_with_options,
+    JellyfishMerkleTree,
+};
+use libra_crypto::{
+    hash::{Blake3, Sha3_256},
+    HashValue,
+};
+use libra_types::{
+    account_state_blob::AccountStateBlob,
+    transaction::{Version, PRE_GENESIS_VERSION},
+};
+use proptest::{collection::vec, prelude::*};
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(10))]
+
+    #[test]
+    fn test_migrate_tree(
+        batches in vec(
+            vec((any::<HashValue>(), any::<AccountStateBlob>()), 1..50),
+            1..5,
+        ),
+        leaf_count_mode in prop_oneof![
+            Just(None),
+            Just(Some(LeafCountMode::Stored)),
+            Just(Some(LeafCountMode::Committed)),
+        ],
+        max_batch_size in 1..20usize,
+        other_keys in vec(any::<HashValue>(), 10),
+    ) {
+        let (db, old_root_hashes) =
+            init_mock_db_with_options(&batches, leaf_count_mode, Sha3_256);
+        let (expected_db, new_root_hashes) =
+            init_mock_db_with_options(&batches, leaf_count_mode, Blake3);
+
+        let latest_version = batches.len() as Version - 1;
+        let target = MockTreeStore::default();
+        let root_hash_mappings = migrate_tree_with_batch_size(
+            &db,
+            &target,
+            latest_version,
+            MigrationScope::AllLiveVersions,
+            &Sha3_256,
+            &Blake3,
+            max_batch_size,
+        )
+        .unwrap();
+        let expected_mappings: Vec<_> = old_root_hashes
+            .into_iter()
+            .zip(new_root_hashes.into_iter())
+            .enumerate()
+            .map(|(version, (old_root_hash, new_root_hash))| RootHashMapping {
+                version: version as Version,
+                old_root_hash,
+                new_root_hash,
+            })
+            .collect();
+        prop_assert_eq!(root_hash_mappings, expected_mappings);
+
+        // The target is the same as a store built with the target scheme in the first place.
+        prop_assert_eq!(target.num_nodes(), expected_db.num_nodes());
+        let target_tree = JellyfishMerkleTree::new(&target);
+        let expected_tree = JellyfishMerkleTree::new(&expected_db);
+        for version in 0..=latest_version {
+            for key in batches.iter().flatten().map(|(k, _v)| k).chain(other_keys.iter()) {
+                prop_assert_eq!(
+                    target_tree.get_with_proof(*key, version).unwrap(),
+                    expected_tree.get_with_proof(*key, version).unwrap()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_migrate_tree_latest_only(
+        batches in vec(
+            vec((any::<HashValue>(), any::<AccountStateBlob>()), 1..50),
+            1..5,
+        ),
+        leaf_count_mode in proptest::option::of(any::<LeafCountMode>()),
+    ) {
+        let (db, old_root_hashes) =
+            init_mock_db_with_options(&batches, leaf_count_mode, Sha3_256);
+        let (expected_db, new_root_hashes) =
+            init_mock_db_with_options(&batches, leaf_count_mode, Blake3);
+
+        let latest_version = batches.len() as Version - 1;
+        let target = MockTreeStore::default();
+        let root_hash_mappings = migrate_tree(
+            &db,
+            &target,
+            latest_version,
+            MigrationScope::LatestOnly,
+            &Sha3_256,
+            &Blake3,
+        )
+        .unwrap();
+        prop_assert_eq!(
+            root_hash_mappings,
+            vec![RootHashMapping {
+                version: latest_version,
+                old_root_hash: *old_root_hashes.last().unwrap(),
+                new_root_hash: *new_root_hashes.last().unwrap(),
+            }]
+        );
+
+        // Only the nodes of the latest version are in the target.
+        expected_db.purge_stale_nodes(latest_version).unwrap();
+        prop_assert_eq!(target.num_nodes(), expected_db.num_nodes());
+    }
+}
+
+#[test]
+fn test_migrate_tree_skips_pruned_versions() {
+    let batches: Vec<Vec<_>> = (0..4)
+        .map(|_| vec![(HashValue::random(), AccountStateBlob::from(vec![1u8]))])
+        .collect();
+    let (db, old_root_hashes) = init_mock_db_with_options(&batches, None, Sha3_256);
+    db.purge_stale_nodes(2).unwrap();
+
+    let target = MockTreeStore::default();
+    let root_hash_mappings = migrate_tree(
+        &db,
+        &target,
+        3, /* latest_version */
+        MigrationScope::AllLiveVersions,
+        &Sha3_256,
+        &Blake3,
+    )
+    .unwrap();
+    let versions: Vec<_> = root_hash_mappings.iter().map(|m| m.version).collect();
+    assert_eq!(versions, vec![2, 3]);
+    assert_eq!(root_hash_mappings[1].old_root_hash, old_root_hashes[3]);
+    assert_eq!(
+        JellyfishMerkleTree::new(&target).get_root_hash(3).unwrap(),
+        root_hash_mappings[1].new_root_hash
+    );
+
+    // The latest version must exist.
+    assert!(migrate_tree(
+        &db,
+        &MockTreeStore::default(),
+        4, /* latest_version */
+        MigrationScope::AllLiveVersions,
+        &Sha3_256,
+        &Blake3,
+    )
+    .is_err());
+}
+
+#[test]
+fn test_migrate_tree_with_pre_genesis() {
+    let key1 = HashValue::new([0x00; HashValue::LENGTH]);
+    let key2 = HashValue::new([0xff; HashValue::LENGTH]);
+    let blob1 = AccountStateBlob::from(vec![1u8]);
+    let blob2 = AccountStateBlob::from(vec![2u8]);
+    let db = MockTreeStore::default();
+    db.put_node(
+        NodeKey::new_empty_path(PRE_GENESIS_VERSION),
+        LeafNode::new_with_scheme(key1, blob1.clone(), &Sha3_256).into(),
+    )
+    .unwrap();
+    let (_root_hash, batch) = JellyfishMerkleTree::new_with_scheme(&db, None, Sha3_256)
+        .put_blob_set(vec![(key2, blob2)], 0 /* version */)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let target = MockTreeStore::default();
+    let root_hash_mappings = migrate_tree(
+        &db,
+        &target,
+        0, /* latest_version */
+        MigrationScope::AllLiveVersions,
+        &Sha3_256,
+        &Blake3,
+    )
+    .unwrap();
+    let versions: Vec<_> = root_hash_mappings.iter().map(|m| m.version).collect();
+    assert_eq!(versions, vec![PRE_GENESIS_VERSION, 0]);
+    assert_eq!(target.num_nodes(), db.num_nodes());
+
+    let target_tree = JellyfishMerkleTree::new(&target);
+    for mapping in &root_hash_mappings {
+        assert_eq!(
+            target_tree.get_root_hash(mapping.version).unwrap(),
+            mapping.new_root_hash
+        );
+    }
+    assert_eq!(
+        root_hash_mappings[0].new_root_hash,
+        LeafNode::new(key1, blob1.clone()).hash()
+    );
+    assert_eq!(target_tree.get(key1, 0).unwrap().unwrap(), blob1);
+}
+
+#[test]
+fn test_migrate_tree_with_inline_leaves() {
+    let blob_sets: Vec<Vec<_>> = (0..3)
+        .map(|i| {
+            (0..20)
+                .map(|_| {
+                    (
+                        HashValue::random(),
+                        AccountStateBlob::from(vec![i as u8; 8]),
+                    )
+                })
+                .collect()
+        })
+        .collect();
+    let db = MockTreeStore::default();
+    let (_root_hashes, batch) = JellyfishMerkleTree::new_with_scheme(&db, None, Sha3_256)
+        .with_inline_leaves(8 /* max_inline_blob_size */)
+        .put_blob_sets(blob_sets.clone(), 0 /* first_version */)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let target = MockTreeStore::default();
+    let root_hash_mappings = migrate_tree(
+        &db,
+        &target,
+        2, /* latest_version */
+        MigrationScope::AllLiveVersions,
+        &Sha3_256,
+        &Blake3,
+    )
+    .unwrap();
+
+    // The leaves inlined into the target have their blob hashes under the target scheme, so
+    // proofs built from them verify.
+    let target_tree = JellyfishMerkleTree::new(&target);
+    for (version, blob_set) in blob_sets.iter().enumerate() {
+        let root_hash = root_hash_mappings[version].new_root_hash;
+        for (key, blob) in blob_set {
+            let (value, proof) = target_tree
+                .get_with_proof(*key, version as Version)
+                .unwrap();
+            assert_eq!(value.as_ref(), Some(blob));
+            assert!(proof.verify(root_hash, *key, Some(blob)).is_ok());
+        }
+    }
+}
+
+#[test]
+fn test_migrate_tree_with_hash_only_leaves() {
+    let blob_sets: Vec<Vec<_>> = (0..3)
+        .map(|i| {
+            (0..20)
+                .map(|_| {
+                    (
+                        HashValue::random(),
+                        AccountStateBlob::from(vec![i as u8; 8]),
+                    )
+                })
+                .collect()
+        })
+        .collect();
+    let db = MockTreeStore::default();
+    let (_root_hashes, batch) = JellyfishMerkleTree::new_with_scheme(&db, None, Sha3_256)
+        .with_hash_only_leaves()
+        .put_blob_sets(blob_sets.clone(), 0 /* first_version */)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let expected_db = MockTreeStore::default();
+    let (new_root_hashes, batch) = JellyfishMerkleTree::new(&expected_db)
+        .with_hash_only_leaves()
+        .put_blob_sets(blob_sets.clone(), 0 /* first_version */)
+        .unwrap();
+    expected_db.write_tree_update_batch(batch).unwrap();
+
+    let target = MockTreeStore::default();
+    let root_hash_mappings = migrate_tree_with_batch_size(
+        &db,
+        &target,
+        2, /* latest_version */
+        MigrationScope::AllLiveVersions,
+        &Sha3_256,
+        &Blake3,
+        5, /* max_batch_size */
+    )
+    .unwrap();
+    let migrated_root_hashes: Vec<_> = root_hash_mappings.iter().map(|m| m.new_root_hash).collect();
+    assert_eq!(migrated_root_hashes, new_root_hashes);
+
+    // The leaves stay hash-only, with their blobs under the hashes of the target scheme.
+    assert_eq!(target.num_nodes(), expected_db.num_nodes());
+    assert_eq!(target.num_values(), expected_db.num_values());
+    let target_tree = JellyfishMerkleTree::new(&target);
+    for (version, blob_set) in blob_sets.iter().enumerate() {
+        for (key, blob) in blob_set {
+            let (leaf_node, _proof) = target_tree
+                .get_leaf_with_proof(*key, version as Version)
+                .unwrap();
+            assert!(leaf_node.unwrap().is_hash_only());
+            assert_eq!(
+                target_tree.get(*key, version as Version).unwrap().as_ref(),
+                Some(blob)
+            );
+        }
+    }
+}
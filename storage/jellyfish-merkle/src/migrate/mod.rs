@@ -0,0 +1,260 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module implements migrating a store to another hash scheme, for example stores built
+//! before the switch from SHA3-256 to BLAKE3. The nodes of the chosen versions are read from the
+//! source store, every leaf and internal hash is recomputed under the target scheme, and the
+//! rebuilt nodes are written into the target store. Node keys are not part of node hashes, so
+//! every node keeps its key and the trees keep their shape.
+//!
+//! Hash-only leaves stay hash-only. Their blobs are rehashed under the target scheme and written
+//! into the target with
+//! [`TreeWriter::write_value_batch`](../trait.TreeWriter.html#method.write_value_batch), so the
+//! target must keep blobs if the source has hash-only leaves.
+//!
+//! Stale node indices are not migrated. With
+//! [`MigrationScope::AllLiveVersions`](enum.MigrationScope.html#variant.AllLiveVersions) every
+//! node the indices of the source refer to is migrated under the same key, so they can be copied
+//! to the target as they are to prune it like the source. With
+//! [`MigrationScope::LatestOnly`](enum.MigrationScope.html#variant.LatestOnly) they must not be
+//! copied, since they refer to nodes of older versions that are never migrated.
+
+#[cfg(test)]
+mod migrate_test;
+
+use crate::{
+    builder::DEFAULT_MAX_BATCH_SIZE,
+    get_leaf_blob,
+    node_type::{Children, InternalNode, LeafNode, Node, NodeKey},
+    NodeBatch, RootVersionReader, TreeReader, TreeWriter, ValueBatch,
+};
+use anyhow::{bail, ensure, Result};
+use libra_crypto::{hash::HashScheme, HashValue};
+use libra_nibble::Nibble;
+use libra_types::transaction::{Version, PRE_GENESIS_VERSION};
+
+/// Which versions of the source store to migrate.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MigrationScope {
+    /// Every version up to the latest one whose root is still in the source store, i.e. every
+    /// version that has not been pruned, including the pre-genesis version.
+    AllLiveVersions,
+    /// Only the latest version, so the target starts from it without the history before it.
+    LatestOnly,
+}
+
+/// The root hash of a migrated version under the source scheme and under the target scheme.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RootHashMapping {
+    pub version: Version,
+    pub old_root_hash: HashValue,
+    pub new_root_hash: HashValue,
+}
+
+/// Migrates the versions in `scope` up to `latest_version` from `reader`, whose hashes are
+/// computed with `source_scheme`, into `target` with hashes computed with `target_scheme`.
+/// Returns the old and new root hashes of every migrated version, in order of versions, with the
+/// pre-genesis version first.
+pub fn migrate_tree<R, T, S, N>(
+    reader: &R,
+    target: &T,
+    latest_version: Version,
+    scope: MigrationScope,
+    source_scheme: &S,
+    target_scheme: &N,
+) -> Result<Vec<RootHashMapping>>
+where
+    R: RootVersionReader,
+    T: TreeReader + TreeWriter,
+    S: HashScheme,
+    N: HashScheme,
+{
+    migrate_tree_with_batch_size(
+        reader,
+        target,
+        latest_version,
+        scope,
+        source_scheme,
+        target_scheme,
+        DEFAULT_MAX_BATCH_SIZE,
+    )
+}
+
+/// Same as [`migrate_tree`](fn.migrate_tree.html), but keeps at most `max_batch_size` nodes in
+/// memory before writing them.
+///
+/// Versions are migrated in increasing order and the nodes of each version are visited depth
+/// first, so children are always written before their parents and the root of a version is
+/// written after the rest of its tree. A node shared with an earlier version is read back from
+/// `target` instead of being migrated again, which also lets an interrupted migration be resumed
+/// with the same `target`.
+pub fn migrate_tree_with_batch_size<R, T, S, N>(
+    reader: &R,
+    target: &T,
+    latest_version: Version,
+    scope: MigrationScope,
+    source_scheme: &S,
+    target_scheme: &N,
+    max_batch_size: usize,
+) -> Result<Vec<RootHashMapping>>
+where
+    R: RootVersionReader,
+    T: TreeReader + TreeWriter,
+    S: HashScheme,
+    N: HashScheme,
+{
+    ensure!(max_batch_size > 0, "Batch size must be positive.");
+    let versions = match scope {
+        MigrationScope::AllLiveVersions => {
+            let mut versions = reader.get_root_versions()?;
+            // The pre-genesis version sorts last but comes before every other version.
+            if versions.last() == Some(&PRE_GENESIS_VERSION) {
+                versions.pop();
+                versions.insert(0, PRE_GENESIS_VERSION);
+            }
+            versions
+                .retain(|version| *version <= latest_version || *version == PRE_GENESIS_VERSION);
+            versions
+        }
+        MigrationScope::LatestOnly => vec![latest_version],
+    };
+    ensure!(
+        versions.last() == Some(&latest_version),
+        "Root of latest version {} is missing.",
+        latest_version
+    );
+    let mut migrator = Migrator {
+        reader,
+        target,
+        target_scheme,
+        max_batch_size,
+        batch: NodeBatch::new(),
+        value_batch: ValueBatch::new(),
+    };
+
+    let mut root_hash_mappings = vec![];
+    for version in versions {
+        let root_node_key = NodeKey::new_empty_path(version);
+        let root_node = reader.get_node(&root_node_key)?;
+        let old_root_hash = root_node.hash_with_scheme(source_scheme);
+        let new_root_hash = migrator.migrate_node(root_node_key, root_node)?;
+        root_hash_mappings.push(RootHashMapping {
+            version,
+            old_root_hash,
+            new_root_hash,
+        });
+    }
+    migrator.flush()?;
+    Ok(root_hash_mappings)
+}
+
+/// Rebuilds nodes under the target scheme and writes them in batches.
+struct Migrator<'a, R, T, N> {
+    reader: &'a R,
+    target: &'a T,
+    target_scheme: &'a N,
+    max_batch_size: usize,
+    /// The rebuilt nodes that have not been written yet.
+    batch: NodeBatch,
+    /// The blobs of the rebuilt hash-only leaves that have not been written yet, keyed by their
+    /// hashes under the target scheme.
+    value_batch: ValueBatch,
+}
+
+impl<'a, R, T, N> Migrator<'a, R, T, N>
+where
+    R: TreeReader,
+    T: TreeReader + TreeWriter,
+    N: HashScheme,
+{
+    /// Returns the new hash of the node at `node_key`, migrating it first unless it already has
+    /// been.
+    fn migrate_child(&mut self, node_key: NodeKey) -> Result<HashValue> {
+        if let Some(node) = self.batch.get(&node_key) {
+            return Ok(node.hash_with_scheme(self.target_scheme));
+        }
+        if let Some(node) = self.target.get_node_option(&node_key)? {
+            return Ok(node.hash_with_scheme(self.target_scheme));
+        }
+        let node = self.reader.get_node(&node_key)?;
+        ensure!(
+            !matches!(node, Node::Null),
+            "Unexpected null node {:?} under a root.",
+            node_key
+        );
+        self.migrate_node(node_key, node)
+    }
+
+    /// Rebuilds `node` and the part of the tree under it that has not been migrated yet, and
+    /// returns its new hash.
+    fn migrate_node(&mut self, node_key: NodeKey, node: Node) -> Result<HashValue> {
+        let new_node = match node {
+            Node::Null => Node::new_null(),
+            Node::Leaf(leaf_node) => {
+                let account_key = leaf_node.account_key();
+                if leaf_node.is_hash_only() {
+                    let blob = self.reader.get_value(&leaf_node.blob_hash())?;
+                    let blob_hash = blob.hash_with_scheme(self.target_scheme);
+                    self.value_batch.insert(blob_hash, blob);
+                    LeafNode::new_hash_only(account_key, blob_hash).into()
+                } else {
+                    let blob = get_leaf_blob(self.reader, leaf_node)?;
+                    LeafNode::new_with_scheme(account_key, blob, self.target_scheme).into()
+                }
+            }
+            Node::Internal(internal_node) => {
+                let mut children = Children::new();
+                for i in 0..16u8 {
+                    let n = Nibble::from(i);
+                    if let Some(child) = internal_node.child(n) {
+                        let mut child = child.clone();
+                        let child_key = node_key.gen_child_node_key(child.version, n);
+                        child.hash = self.migrate_child(child_key.clone())?;
+                        // An inlined leaf has its blob hash under the source scheme, so it is
+                        // replaced with the migrated leaf.
+                        if child.inline_leaf.is_some() {
+                            child.inline_leaf = Some(self.get_migrated_leaf(&child_key)?);
+                        }
+                        children.insert(n, child);
+                    }
+                }
+                match internal_node.leaf_count_mode() {
+                    Some(mode) => InternalNode::new_with_leaf_count(children, mode).into(),
+                    None => InternalNode::new(children).into(),
+                }
+            }
+        };
+        let new_hash = new_node.hash_with_scheme(self.target_scheme);
+        self.batch.insert(node_key, new_node);
+        if self.batch.len() >= self.max_batch_size {
+            self.flush()?;
+        }
+        Ok(new_hash)
+    }
+
+    /// Returns the migrated leaf at `node_key`.
+    fn get_migrated_leaf(&self, node_key: &NodeKey) -> Result<LeafNode> {
+        let node = match self.batch.get(node_key) {
+            Some(node) => node.clone(),
+            None => self.target.get_node(node_key)?,
+        };
+        match node {
+            Node::Leaf(leaf_node) => Ok(leaf_node),
+            _ => bail!("Inlined child {:?} is not a leaf.", node_key),
+        }
+    }
+
+    /// Writes the rebuilt nodes that have not been written yet, after the blobs of their
+    /// hash-only leaves.
+    fn flush(&mut self) -> Result<()> {
+        if !self.value_batch.is_empty() {
+            self.target.write_value_batch(&self.value_batch)?;
+            self.value_batch.clear();
+        }
+        if !self.batch.is_empty() {
+            self.target.write_node_batch(&self.batch)?;
+            self.batch.clear();
+        }
+        Ok(())
+    }
+}
@@ -2,39 +2,56 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
-    node_type::{LeafNode, Node, NodeKey},
-    NodeBatch, StaleNodeIndex, TreeReader, TreeUpdateBatch, TreeWriter,
+    namespace::{
+        Namespace, NamespacedNodeKey, NamespacedStaleNodeIndex, NamespacedTreeReader,
+        NamespacedTreeWriter, NamespacedUpdateBatch,
+    },
+    node_type::{Node, NodeKey},
+    restore::{RestoreProgress, RestoreStore},
+    NodeBatch, RootVersionReader, StaleNodeIndex, StaleNodeIndexBatch, StaleNodeIndexWriter,
+    TreeReader, TreeUpdateBatch, TreeWriter, ValueBatch,
 };
 use anyhow::{bail, ensure, Result};
-use libra_types::transaction::Version;
+use libra_crypto::HashValue;
+use libra_types::{account_state_blob::AccountStateBlob, transaction::Version};
 use std::{
     collections::{hash_map::Entry, BTreeSet, HashMap},
     sync::RwLock,
 };
 
 #[derive(Default)]
-pub struct MockTreeStore(RwLock<(HashMap<NodeKey, Node>, BTreeSet<StaleNodeIndex>)>);
+pub struct MockTreeStore(
+    RwLock<(
+        HashMap<NodeKey, Node>,
+        BTreeSet<StaleNodeIndex>,
+        HashMap<NodeKey, RestoreProgress>,
+        HashMap<HashValue, AccountStateBlob>,
+    )>,
+);
 
 impl TreeReader for MockTreeStore {
     fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
         Ok(self.0.read().unwrap().0.get(node_key).cloned())
     }
 
-    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
-        let locked = self.0.read().unwrap();
-        let mut node_key_and_node: Option<(NodeKey, LeafNode)> = None;
-
-        for (key, value) in locked.0.iter() {
-            if let Node::Leaf(leaf_node) = value {
-                if node_key_and_node.is_none()
-                    || leaf_node.account_key() > node_key_and_node.as_ref().unwrap().1.account_key()
-                {
-                    node_key_and_node.replace((key.clone(), leaf_node.clone()));
-                }
-            }
-        }
+    fn get_value_option(&self, blob_hash: &HashValue) -> Result<Option<AccountStateBlob>> {
+        Ok(self.0.read().unwrap().3.get(blob_hash).cloned())
+    }
+}
 
-        Ok(node_key_and_node)
+impl RootVersionReader for MockTreeStore {
+    fn get_root_versions(&self) -> Result<Vec<Version>> {
+        let mut versions: Vec<_> = self
+            .0
+            .read()
+            .unwrap()
+            .0
+            .keys()
+            .filter(|node_key| node_key.nibble_path().num_nibbles() == 0)
+            .map(|node_key| node_key.version())
+            .collect();
+        versions.sort();
+        Ok(versions)
     }
 }
 
@@ -46,6 +63,56 @@ impl TreeWriter for MockTreeStore {
         }
         Ok(())
     }
+
+    fn write_value_batch(&self, value_batch: &ValueBatch) -> Result<()> {
+        self.0.write().unwrap().3.extend(value_batch.clone());
+        Ok(())
+    }
+}
+
+impl StaleNodeIndexWriter for MockTreeStore {
+    fn write_stale_node_index_batch(
+        &self,
+        stale_node_index_batch: &StaleNodeIndexBatch,
+    ) -> Result<()> {
+        stale_node_index_batch
+            .iter()
+            .map(|i| self.put_stale_node_index(i.clone()))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(())
+    }
+}
+
+impl RestoreStore for MockTreeStore {
+    fn get_restore_progress(&self, root_node_key: &NodeKey) -> Result<Option<RestoreProgress>> {
+        Ok(self.0.read().unwrap().2.get(root_node_key).cloned())
+    }
+
+    fn write_restore_batch(
+        &self,
+        root_node_key: &NodeKey,
+        node_batch: &NodeBatch,
+        progress: Option<&RestoreProgress>,
+    ) -> Result<()> {
+        let mut locked = self.0.write().unwrap();
+        for (node_key, node) in node_batch.clone() {
+            assert_eq!(locked.0.insert(node_key, node), None);
+        }
+        match progress {
+            Some(progress) => locked.2.insert(root_node_key.clone(), progress.clone()),
+            None => locked.2.remove(root_node_key),
+        };
+        Ok(())
+    }
+
+    fn delete_restore_batch(&self, root_node_key: &NodeKey, node_keys: &[NodeKey]) -> Result<()> {
+        let mut locked = self.0.write().unwrap();
+        for node_key in node_keys {
+            locked.0.remove(node_key);
+        }
+        locked.2.remove(root_node_key);
+        Ok(())
+    }
 }
 
 impl MockTreeStore {
@@ -76,6 +143,7 @@ impl MockTreeStore {
             .into_iter()
             .map(|i| self.put_stale_node_index(i))
             .collect::<Result<Vec<_>>>()?;
+        self.0.write().unwrap().3.extend(batch.value_batch);
         Ok(())
     }
 
@@ -103,4 +171,107 @@ impl MockTreeStore {
     pub fn num_nodes(&self) -> usize {
         self.0.read().unwrap().0.len()
     }
+
+    pub fn num_values(&self) -> usize {
+        self.0.read().unwrap().3.len()
+    }
+}
+
+/// An in-memory store shared by the trees of several namespaces.
+#[derive(Default)]
+pub struct MockNamespacedStore(
+    RwLock<(
+        HashMap<NamespacedNodeKey, Node>,
+        BTreeSet<NamespacedStaleNodeIndex>,
+        HashMap<(Namespace, HashValue), AccountStateBlob>,
+    )>,
+);
+
+impl NamespacedTreeReader for MockNamespacedStore {
+    fn get_node_option(&self, namespace: Namespace, node_key: &NodeKey) -> Result<Option<Node>> {
+        let key = NamespacedNodeKey {
+            namespace,
+            node_key: node_key.clone(),
+        };
+        Ok(self.0.read().unwrap().0.get(&key).cloned())
+    }
+
+    fn get_value_option(
+        &self,
+        namespace: Namespace,
+        blob_hash: &HashValue,
+    ) -> Result<Option<AccountStateBlob>> {
+        Ok(self
+            .0
+            .read()
+            .unwrap()
+            .2
+            .get(&(namespace, *blob_hash))
+            .cloned())
+    }
+}
+
+impl NamespacedTreeWriter for MockNamespacedStore {
+    fn write_namespaced_batch(&self, batch: &NamespacedUpdateBatch) -> Result<()> {
+        let mut locked = self.0.write().unwrap();
+        // Nothing is written unless the whole batch can be.
+        for (key, _node) in batch.node_batch() {
+            ensure!(!locked.0.contains_key(&key), "Key {:?} exists.", key);
+        }
+        for index in batch.stale_node_index_batch() {
+            ensure!(!locked.1.contains(&index), "Duplicated retire log.");
+        }
+        for (key, node) in batch.node_batch() {
+            locked.0.insert(key, node.clone());
+        }
+        locked.1.extend(batch.stale_node_index_batch());
+        for (namespace, blob_hash, blob) in batch.value_batch() {
+            locked.2.insert((namespace, blob_hash), blob.clone());
+        }
+        Ok(())
+    }
+}
+
+impl MockNamespacedStore {
+    /// Purges the stale nodes of `namespace` up to `least_readable_version`, leaving the other
+    /// namespaces alone.
+    pub fn purge_stale_nodes(
+        &self,
+        namespace: Namespace,
+        least_readable_version: Version,
+    ) -> Result<()> {
+        let mut wlocked = self.0.write().unwrap();
+        let to_prune = wlocked
+            .1
+            .iter()
+            .skip_while(|log| log.namespace < namespace)
+            .take_while(|log| {
+                log.namespace == namespace
+                    && log.stale_node_index.stale_since_version <= least_readable_version
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for log in to_prune {
+            let key = NamespacedNodeKey {
+                namespace,
+                node_key: log.stale_node_index.node_key.clone(),
+            };
+            let removed = wlocked.0.remove(&key).is_some();
+            ensure!(removed, "Stale node index refers to non-existent node.");
+            wlocked.1.remove(&log);
+        }
+
+        Ok(())
+    }
+
+    pub fn num_nodes(&self, namespace: Namespace) -> usize {
+        self.0
+            .read()
+            .unwrap()
+            .0
+            .keys()
+            .filter(|key| key.namespace == namespace)
+            .count()
+    }
 }
@@ -0,0 +1,326 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module implements running several independent trees, e.g. for accounts, code and the
+//! storage of each app, in one store. Each tree has its own [`Namespace`], which prefixes the
+//! storage keys of its nodes and stale node indices, so the same [`NodeKey`] can be used by every
+//! tree.
+//!
+//! A backend implements [`NamespacedTreeReader`] and [`NamespacedTreeWriter`] once, and each tree
+//! reads and writes through a [`NamespaceView`] of it. The updates of all namespaces at a version
+//! can be collected into one [`NamespacedUpdateBatch`] and written atomically. Stale node indices
+//! are ordered by namespace before version, so each namespace can be pruned up to its own
+//! version.
+//!
+//! Trees in different namespaces must hash with a [`NamespacedScheme`], which derives the seeds of
+//! every domain from the namespace, so a node or a proof of one tree is never valid in another.
+//!
+//! [`Namespace`]: struct.Namespace.html
+//! [`NodeKey`]: ../node_type/struct.NodeKey.html
+//! [`NamespacedTreeReader`]: trait.NamespacedTreeReader.html
+//! [`NamespacedTreeWriter`]: trait.NamespacedTreeWriter.html
+//! [`NamespaceView`]: struct.NamespaceView.html
+//! [`NamespacedUpdateBatch`]: struct.NamespacedUpdateBatch.html
+//! [`NamespacedScheme`]: struct.NamespacedScheme.html
+
+#[cfg(test)]
+mod namespace_test;
+
+use crate::{
+    node_type::{Node, NodeKey},
+    NodeBatch, StaleNodeIndex, StaleNodeIndexBatch, StaleNodeIndexWriter, TreeReader,
+    TreeUpdateBatch, TreeWriter, ValueBatch,
+};
+use anyhow::Result;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use libra_crypto::{
+    hash::{HashDomain, HashScheme},
+    HashValue,
+};
+use libra_types::account_state_blob::AccountStateBlob;
+#[cfg(any(test, feature = "fuzzing"))]
+use proptest_derive::Arbitrary;
+use std::{collections::BTreeMap, io::Cursor, mem::size_of};
+
+/// Identifies one of the trees sharing a store.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
+pub struct Namespace(pub u32);
+
+impl Namespace {
+    /// Serializes to bytes that sort in the same order as namespaces.
+    pub fn encode(self) -> Vec<u8> {
+        self.0.to_be_bytes().to_vec()
+    }
+}
+
+/// A [`HashScheme`] whose seeds are derived from the seeds of another scheme and a namespace, so
+/// the hashes of trees in different namespaces are domain separated.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NamespacedScheme<S> {
+    hash_scheme: S,
+    namespace: Namespace,
+    seeds: [[u8; HashValue::LENGTH]; 4],
+}
+
+impl<S: HashScheme> NamespacedScheme<S> {
+    /// Creates the scheme of `namespace` hashing with `hash_scheme`. The seed of each domain is the
+    /// hash of the seed of `hash_scheme` followed by the namespace.
+    pub fn new(hash_scheme: S, namespace: Namespace) -> Self {
+        let mut seeds = [[0; HashValue::LENGTH]; 4];
+        for (seed, domain) in seeds.iter_mut().zip(HashDomain::ALL.iter()) {
+            let hash = hash_scheme.hash_of(&[hash_scheme.seed(*domain), &namespace.encode()]);
+            seed.copy_from_slice(hash.as_ref());
+        }
+        Self {
+            hash_scheme,
+            namespace,
+            seeds,
+        }
+    }
+
+    /// Returns the namespace of this scheme.
+    pub fn namespace(&self) -> Namespace {
+        self.namespace
+    }
+}
+
+impl<S: HashScheme> HashScheme for NamespacedScheme<S> {
+    fn hash_of(&self, buffers: &[&[u8]]) -> HashValue {
+        self.hash_scheme.hash_of(buffers)
+    }
+
+    fn seed(&self, domain: HashDomain) -> &[u8; HashValue::LENGTH] {
+        &self.seeds[domain as usize]
+    }
+
+    fn placeholder_hash(&self) -> HashValue {
+        self.hash_scheme.placeholder_hash()
+    }
+}
+
+/// The storage key of a node in a namespaced store.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
+pub struct NamespacedNodeKey {
+    pub namespace: Namespace,
+    pub node_key: NodeKey,
+}
+
+impl NamespacedNodeKey {
+    /// Serializes to bytes for physical storage, the namespace followed by the encoded node key.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut out = self.namespace.encode();
+        out.extend(self.node_key.encode()?);
+        Ok(out)
+    }
+
+    /// Recovers from serialized bytes in physical storage.
+    pub fn decode(val: &[u8]) -> Result<Self> {
+        let mut reader = Cursor::new(val);
+        let namespace = Namespace(reader.read_u32::<BigEndian>()?);
+        let node_key = NodeKey::decode(&val[size_of::<u32>()..])?;
+        Ok(Self {
+            namespace,
+            node_key,
+        })
+    }
+}
+
+/// The storage key of a stale node index in a namespaced store. Indices are ordered by namespace
+/// first, so a pruner can scan the indices of one namespace by version.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
+pub struct NamespacedStaleNodeIndex {
+    pub namespace: Namespace,
+    pub stale_node_index: StaleNodeIndex,
+}
+
+impl NamespacedStaleNodeIndex {
+    /// Serializes to bytes for physical storage, the namespace followed by the version since which
+    /// the node is stale and the encoded node key.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut out = self.namespace.encode();
+        out.write_u64::<BigEndian>(self.stale_node_index.stale_since_version)?;
+        out.extend(self.stale_node_index.node_key.encode()?);
+        Ok(out)
+    }
+
+    /// Recovers from serialized bytes in physical storage.
+    pub fn decode(val: &[u8]) -> Result<Self> {
+        let mut reader = Cursor::new(val);
+        let namespace = Namespace(reader.read_u32::<BigEndian>()?);
+        let stale_since_version = reader.read_u64::<BigEndian>()?;
+        let node_key = NodeKey::decode(&val[size_of::<u32>() + size_of::<u64>()..])?;
+        Ok(Self {
+            namespace,
+            stale_node_index: StaleNodeIndex {
+                stale_since_version,
+                node_key,
+            },
+        })
+    }
+}
+
+/// The updates of several namespaces that will be written into a namespaced store atomically.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct NamespacedUpdateBatch {
+    pub batches: BTreeMap<Namespace, TreeUpdateBatch>,
+}
+
+impl NamespacedUpdateBatch {
+    /// Creates an empty batch.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the updates of a tree in `namespace`, merging them with the updates of the namespace
+    /// added before.
+    pub fn add(&mut self, namespace: Namespace, tree_update_batch: TreeUpdateBatch) {
+        let batch = self.batches.entry(namespace).or_default();
+        batch.node_batch.extend(tree_update_batch.node_batch);
+        batch
+            .stale_node_index_batch
+            .extend(tree_update_batch.stale_node_index_batch);
+        batch.num_new_leaves += tree_update_batch.num_new_leaves;
+        batch.num_stale_leaves += tree_update_batch.num_stale_leaves;
+        batch.value_batch.extend(tree_update_batch.value_batch);
+    }
+
+    /// Returns the nodes to write, keyed by their storage keys.
+    pub fn node_batch(&self) -> impl Iterator<Item = (NamespacedNodeKey, &Node)> {
+        self.batches.iter().flat_map(|(namespace, batch)| {
+            batch.node_batch.iter().map(move |(node_key, node)| {
+                (
+                    NamespacedNodeKey {
+                        namespace: *namespace,
+                        node_key: node_key.clone(),
+                    },
+                    node,
+                )
+            })
+        })
+    }
+
+    /// Returns the blobs of hash-only leaves to write, along with their namespaces and hashes.
+    pub fn value_batch(
+        &self,
+    ) -> impl Iterator<Item = (Namespace, HashValue, &AccountStateBlob)> + '_ {
+        self.batches.iter().flat_map(|(namespace, batch)| {
+            batch
+                .value_batch
+                .iter()
+                .map(move |(blob_hash, blob)| (*namespace, *blob_hash, blob))
+        })
+    }
+
+    /// Returns the stale node indices to write.
+    pub fn stale_node_index_batch(&self) -> impl Iterator<Item = NamespacedStaleNodeIndex> + '_ {
+        self.batches.iter().flat_map(|(namespace, batch)| {
+            batch
+                .stale_node_index_batch
+                .iter()
+                .map(move |stale_node_index| NamespacedStaleNodeIndex {
+                    namespace: *namespace,
+                    stale_node_index: stale_node_index.clone(),
+                })
+        })
+    }
+}
+
+/// `NamespacedTreeReader` reads the nodes of every namespace from a store shared by several
+/// trees.
+pub trait NamespacedTreeReader {
+    /// Gets the node with `node_key` in `namespace`. Returns `None` if the node does not exist.
+    fn get_node_option(&self, namespace: Namespace, node_key: &NodeKey) -> Result<Option<Node>>;
+
+    /// Gets the blob of a hash-only leaf in `namespace` given its hash. Returns `None` if the blob
+    /// does not exist.
+    fn get_value_option(
+        &self,
+        _namespace: Namespace,
+        _blob_hash: &HashValue,
+    ) -> Result<Option<AccountStateBlob>> {
+        Ok(None)
+    }
+}
+
+/// `NamespacedTreeWriter` writes the updates of several namespaces into a store shared by several
+/// trees.
+pub trait NamespacedTreeWriter {
+    /// Writes the nodes, stale node indices and values of every namespace in `batch` atomically.
+    fn write_namespaced_batch(&self, batch: &NamespacedUpdateBatch) -> Result<()>;
+}
+
+/// The tree in one namespace of a store shared by several trees, which is read and written
+/// through the [`TreeReader`](../trait.TreeReader.html) and
+/// [`TreeWriter`](../trait.TreeWriter.html) interfaces like a store of its own.
+pub struct NamespaceView<'a, B> {
+    backend: &'a B,
+    namespace: Namespace,
+}
+
+impl<'a, B> NamespaceView<'a, B> {
+    /// Creates a view of the tree in `namespace` of `backend`.
+    pub fn new(backend: &'a B, namespace: Namespace) -> Self {
+        Self { backend, namespace }
+    }
+
+    /// Returns the namespace of this view.
+    pub fn namespace(&self) -> Namespace {
+        self.namespace
+    }
+}
+
+impl<'a, B: NamespacedTreeReader> TreeReader for NamespaceView<'a, B> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        self.backend.get_node_option(self.namespace, node_key)
+    }
+
+    fn get_value_option(&self, blob_hash: &HashValue) -> Result<Option<AccountStateBlob>> {
+        self.backend.get_value_option(self.namespace, blob_hash)
+    }
+}
+
+impl<'a, B: NamespacedTreeWriter> TreeWriter for NamespaceView<'a, B> {
+    fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()> {
+        let mut batch = NamespacedUpdateBatch::new();
+        batch.add(
+            self.namespace,
+            TreeUpdateBatch {
+                node_batch: node_batch.clone(),
+                ..TreeUpdateBatch::default()
+            },
+        );
+        self.backend.write_namespaced_batch(&batch)
+    }
+
+    fn write_value_batch(&self, value_batch: &ValueBatch) -> Result<()> {
+        let mut batch = NamespacedUpdateBatch::new();
+        batch.add(
+            self.namespace,
+            TreeUpdateBatch {
+                value_batch: value_batch.clone(),
+                ..TreeUpdateBatch::default()
+            },
+        );
+        self.backend.write_namespaced_batch(&batch)
+    }
+}
+
+impl<'a, B: NamespacedTreeWriter> StaleNodeIndexWriter for NamespaceView<'a, B> {
+    fn write_stale_node_index_batch(
+        &self,
+        stale_node_index_batch: &StaleNodeIndexBatch,
+    ) -> Result<()> {
+        let mut batch = NamespacedUpdateBatch::new();
+        batch.add(
+            self.namespace,
+            TreeUpdateBatch {
+                stale_node_index_batch: stale_node_index_batch.clone(),
+                ..TreeUpdateBatch::default()
+            },
+        );
+        self.backend.write_namespaced_batch(&batch)
+    }
+}
@@ -0,0 +1,176 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    mock_tree_store::{MockNamespacedStore, MockTreeStore},
+    namespace::{
+        Namespace, NamespaceView, NamespacedNodeKey, NamespacedScheme, NamespacedStaleNodeIndex,
+        NamespacedTreeWriter, NamespacedUpdateBatch,
+    },
+    node_type::{Node, NodeKey},
+    JellyfishMerkleTree, TreeReader, TreeUpdateBatch,
+};
+use libra_crypto::{
+    hash::{Blake3, CryptoHash},
+    HashValue,
+};
+use libra_types::{account_state_blob::AccountStateBlob, transaction::Version};
+use proptest::{collection::vec, prelude::*};
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(10))]
+
+    #[test]
+    fn test_namespaced_trees(
+        batches in vec(
+            vec((any::<HashValue>(), any::<AccountStateBlob>()), 1..50),
+            2..5,
+        ),
+    ) {
+        // Both trees get the same updates, at every version in one batch.
+        let namespaces = [Namespace(0), Namespace(1)];
+        let backend = MockNamespacedStore::default();
+        let mut root_hashes = vec![vec![]; namespaces.len()];
+        for (version, blob_set) in batches.iter().enumerate() {
+            let mut batch = NamespacedUpdateBatch::new();
+            for (i, namespace) in namespaces.iter().enumerate() {
+                let view = NamespaceView::new(&backend, *namespace);
+                let tree = JellyfishMerkleTree::new_with_scheme(
+                    &view,
+                    None, /* leaf_count_mode */
+                    NamespacedScheme::new(Blake3, *namespace),
+                );
+                let (root_hash, tree_update_batch) = tree
+                    .put_blob_set(blob_set.clone(), version as Version)
+                    .unwrap();
+                root_hashes[i].push(root_hash);
+                batch.add(*namespace, tree_update_batch);
+            }
+            backend.write_namespaced_batch(&batch).unwrap();
+        }
+
+        // Each tree is the same as in a store of its own, but the hashes are different.
+        for (i, namespace) in namespaces.iter().enumerate() {
+            let db = MockTreeStore::default();
+            let tree = JellyfishMerkleTree::new_with_scheme(
+                &db,
+                None, /* leaf_count_mode */
+                NamespacedScheme::new(Blake3, *namespace),
+            );
+            let (expected_root_hashes, batch) = tree.put_blob_sets(batches.clone(), 0).unwrap();
+            db.write_tree_update_batch(batch).unwrap();
+            prop_assert_eq!(&root_hashes[i], &expected_root_hashes);
+            prop_assert_eq!(backend.num_nodes(*namespace), db.num_nodes());
+        }
+        prop_assert_ne!(&root_hashes[0], &root_hashes[1]);
+
+        let latest_version = batches.len() as Version - 1;
+        let (key, blob) = &batches[0][0];
+        let view = NamespaceView::new(&backend, namespaces[0]);
+        let scheme = NamespacedScheme::new(Blake3, namespaces[0]);
+        let tree = JellyfishMerkleTree::new_with_scheme(&view, None, scheme.clone());
+        let (value, proof) = tree.get_with_proof(*key, 0).unwrap();
+        prop_assert_eq!(value.as_ref(), Some(blob));
+        prop_assert!(proof
+            .verify_with_scheme(&scheme, root_hashes[0][0], *key, value.as_ref())
+            .is_ok());
+        // A proof of one namespace is not valid in another.
+        prop_assert!(proof
+            .verify_with_scheme(
+                &NamespacedScheme::new(Blake3, namespaces[1]),
+                root_hashes[1][0],
+                *key,
+                value.as_ref(),
+            )
+            .is_err());
+
+        // Pruning one namespace leaves the other one alone.
+        let num_nodes = backend.num_nodes(namespaces[1]);
+        backend.purge_stale_nodes(namespaces[0], latest_version).unwrap();
+        prop_assert_eq!(backend.num_nodes(namespaces[1]), num_nodes);
+        prop_assert!(tree.get_with_proof(*key, 0).is_err());
+        prop_assert!(tree.get_with_proof(*key, latest_version).is_ok());
+        let other_view = NamespaceView::new(&backend, namespaces[1]);
+        let other_tree = JellyfishMerkleTree::new_with_scheme(
+            &other_view,
+            None,
+            NamespacedScheme::new(Blake3, namespaces[1]),
+        );
+        prop_assert!(other_tree.get_with_proof(*key, 0).is_ok());
+    }
+
+    #[test]
+    fn test_namespaced_key_encoding(
+        node_key1 in any::<NamespacedNodeKey>(),
+        node_key2 in any::<NamespacedNodeKey>(),
+        index in any::<NamespacedStaleNodeIndex>(),
+    ) {
+        let encoded1 = node_key1.encode().unwrap();
+        prop_assert_eq!(&NamespacedNodeKey::decode(&encoded1).unwrap(), &node_key1);
+        prop_assert_eq!(
+            NamespacedStaleNodeIndex::decode(&index.encode().unwrap()).unwrap(),
+            index
+        );
+
+        // Keys of different namespaces are ordered by namespace.
+        let encoded2 = node_key2.encode().unwrap();
+        if node_key1.namespace != node_key2.namespace {
+            prop_assert_eq!(
+                encoded1.cmp(&encoded2),
+                node_key1.namespace.cmp(&node_key2.namespace)
+            );
+        }
+    }
+}
+
+#[test]
+fn test_namespaced_hash_only_leaves() {
+    let backend = MockNamespacedStore::default();
+    let key = HashValue::random();
+    let blobs = [
+        AccountStateBlob::from(vec![1u8]),
+        AccountStateBlob::from(vec![2u8]),
+    ];
+    let mut batch = NamespacedUpdateBatch::new();
+    for (i, blob) in blobs.iter().enumerate() {
+        let view = NamespaceView::new(&backend, Namespace(i as u32));
+        let (_root_hash, tree_update_batch) = JellyfishMerkleTree::new(&view)
+            .with_hash_only_leaves()
+            .put_blob_set(vec![(key, blob.clone())], 0 /* version */)
+            .unwrap();
+        batch.add(Namespace(i as u32), tree_update_batch);
+    }
+    backend.write_namespaced_batch(&batch).unwrap();
+
+    // Each namespace reads back its own blob.
+    for (i, blob) in blobs.iter().enumerate() {
+        let view = NamespaceView::new(&backend, Namespace(i as u32));
+        let (value, _proof) = JellyfishMerkleTree::new(&view)
+            .get_with_proof(key, 0)
+            .unwrap();
+        assert_eq!(value.as_ref(), Some(blob));
+    }
+    let view = NamespaceView::new(&backend, Namespace(2));
+    assert!(view.get_value_option(&blobs[0].hash()).unwrap().is_none());
+}
+
+#[test]
+fn test_namespaced_batch_is_atomic() {
+    let backend = MockNamespacedStore::default();
+    let node_key = NodeKey::new_empty_path(0);
+    let mut batch = NamespacedUpdateBatch::new();
+    batch.add(Namespace(1), new_null_batch(&node_key));
+    backend.write_namespaced_batch(&batch).unwrap();
+
+    // The node of namespace 0 is not written, since the one of namespace 1 already exists.
+    batch.add(Namespace(0), new_null_batch(&node_key));
+    assert!(backend.write_namespaced_batch(&batch).is_err());
+    assert_eq!(backend.num_nodes(Namespace(0)), 0);
+    assert_eq!(backend.num_nodes(Namespace(1)), 1);
+}
+
+fn new_null_batch(node_key: &NodeKey) -> TreeUpdateBatch {
+    let mut batch = TreeUpdateBatch::default();
+    batch.node_batch.insert(node_key.clone(), Node::new_null());
+    batch
+}
@@ -8,22 +8,23 @@
 //! [`JellyfishMerkleTree`](crate::JellyfishMerkleTree). [`InternalNode`] represents a 4-level
 //! binary tree to optimize for IOPS: it compresses a tree with 31 nodes into one node with 16
 //! chidren at the lowest level. [`LeafNode`] stores the full key and the account blob data
-//! associated.
+//! associated. Small leaf children can also be inlined into the encoding of their parent
+//! [`InternalNode`], see [`Child::new_with_inline_leaf`].
 
 #[cfg(test)]
 mod node_type_test;
 
 use crate::{nibble_path::NibblePath, ROOT_NIBBLE_HEIGHT};
-use anyhow::{ensure, Context, Result};
+use anyhow::{bail, ensure, format_err, Context, Result};
 use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
 use libra_crypto::{
-    hash::{CryptoHash, SPARSE_MERKLE_PLACEHOLDER_HASH},
+    hash::{Blake3, HashDomain, HashScheme},
     HashValue,
 };
 use libra_nibble::Nibble;
 use libra_types::{
     account_state_blob::AccountStateBlob,
-    proof::{SparseMerkleInternalNode, SparseMerkleLeafNode},
+    proof::{SparseMerkleCountedInternalNode, SparseMerkleInternalNode, SparseMerkleLeafNode},
     transaction::Version,
 };
 use num_derive::{FromPrimitive, ToPrimitive};
@@ -32,9 +33,9 @@ use num_traits::cast::FromPrimitive;
 use proptest::{collection::hash_map, prelude::*};
 #[cfg(any(test, feature = "fuzzing"))]
 use proptest_derive::Arbitrary;
-use serde::{Deserialize, Serialize};
 use std::{
     collections::hash_map::HashMap,
+    convert::TryFrom,
     io::{prelude::*, Cursor, Read, SeekFrom, Write},
     mem::size_of,
 };
@@ -136,8 +137,57 @@ impl NodeKey {
         };
         Ok(NodeKey::new(version, nibble_path))
     }
+
+    /// Serializes to bytes for physical storage ordered by nibble path first, as an alternative to
+    /// [`encode`](NodeKey::encode). Each nibble takes a byte, followed by
+    /// [`PATH_MAJOR_TERMINATOR`] and the bitwise inverted version in big endian. So the keys of
+    /// the nodes under a path all start with [`path_major_prefix`](NodeKey::path_major_prefix) of
+    /// it, and the nodes at the same path are ordered from the newest, so the newest one at or
+    /// below some version is the first key at or after the key of that version.
+    pub fn encode_path_major(&self) -> Result<Vec<u8>> {
+        let mut out = Self::path_major_prefix(self.nibble_path());
+        out.push(PATH_MAJOR_TERMINATOR);
+        out.write_u64::<BigEndian>(!self.version())?;
+        Ok(out)
+    }
+
+    /// Recovers from bytes serialized by [`encode_path_major`](NodeKey::encode_path_major).
+    pub fn decode_path_major(val: &[u8]) -> Result<NodeKey> {
+        let num_nibbles = val
+            .iter()
+            .position(|byte| *byte == PATH_MAJOR_TERMINATOR)
+            .ok_or_else(|| format_err!("Missing terminator in path-major key {:?}.", val))?;
+        ensure!(
+            num_nibbles <= ROOT_NIBBLE_HEIGHT,
+            "Invalid number of nibbles: {}",
+            num_nibbles,
+        );
+        let mut nibble_path = NibblePath::new(vec![]);
+        for byte in &val[..num_nibbles] {
+            ensure!(*byte < 16, "Invalid nibble {} in path-major key.", byte);
+            nibble_path.push(Nibble::from(*byte));
+        }
+        let mut reader = Cursor::new(&val[num_nibbles + 1..]);
+        let version = !reader.read_u64::<BigEndian>()?;
+        ensure!(
+            reader.position() as usize == val.len() - num_nibbles - 1,
+            "Trailing bytes in path-major key {:?}.",
+            val
+        );
+        Ok(NodeKey::new(version, nibble_path))
+    }
+
+    /// Returns the prefix of the path-major keys of the nodes at `nibble_path` and under it.
+    pub fn path_major_prefix(nibble_path: &NibblePath) -> Vec<u8> {
+        nibble_path.nibbles().map(u8::from).collect()
+    }
 }
 
+/// Ends the nibble path in a key serialized by
+/// [`NodeKey::encode_path_major`](NodeKey::encode_path_major). It is greater than every nibble,
+/// so a node is ordered after the nodes under it.
+pub const PATH_MAJOR_TERMINATOR: u8 = 16;
+
 /// Each child of [`InternalNode`] encapsulates a nibble forking at this node.
 #[derive(Clone, Debug, Eq, PartialEq)]
 #[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
@@ -150,6 +200,13 @@ pub struct Child {
     pub version: Version,
     // Whether the child is a leaf node.
     pub is_leaf: bool,
+    // The number of leaves under the child, if the [`InternalNode`] the child belongs to tracks
+    // it. See [`LeafCountMode`].
+    pub leaf_count: Option<u64>,
+    // A copy of the child, if it is a leaf inlined into the encoding of the [`InternalNode`] the
+    // child belongs to, so it can be read without reading the leaf itself.
+    #[cfg_attr(any(test, feature = "fuzzing"), proptest(value = "None"))]
+    pub inline_leaf: Option<LeafNode>,
 }
 
 impl Child {
@@ -158,10 +215,50 @@ impl Child {
             hash,
             version,
             is_leaf,
+            leaf_count: None,
+            inline_leaf: None,
+        }
+    }
+
+    /// Creates a leaf child whose leaf is inlined into the [`InternalNode`] the child belongs to.
+    /// `leaf_node` is still stored under the node key of the child as well.
+    pub fn new_with_inline_leaf(hash: HashValue, version: Version, leaf_node: LeafNode) -> Self {
+        Self {
+            hash,
+            version,
+            is_leaf: true,
+            leaf_count: None,
+            inline_leaf: Some(leaf_node),
+        }
+    }
+
+    pub fn new_with_leaf_count(
+        hash: HashValue,
+        version: Version,
+        is_leaf: bool,
+        leaf_count: u64,
+    ) -> Self {
+        Self {
+            hash,
+            version,
+            is_leaf,
+            leaf_count: Some(leaf_count),
+            inline_leaf: None,
         }
     }
 }
 
+/// Indicates how an [`InternalNode`] tracks the number of leaves under each of its children.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
+pub enum LeafCountMode {
+    /// The leaf counts are stored in the node but do not affect its hash.
+    Stored,
+    /// The leaf counts are also committed into the hash of the node, so they can be proven. See
+    /// `SparseMerkleCountedProof`.
+    Committed,
+}
+
 /// [`Children`] is just a collection of children belonging to a [`InternalNode`], indexed from 0 to
 /// 15, inclusive.
 pub(crate) type Children = HashMap<Nibble, Child>;
@@ -175,6 +272,8 @@ pub(crate) type Children = HashMap<Nibble, Child>;
 pub struct InternalNode {
     // Up to 16 children.
     children: Children,
+    // Whether and how the number of leaves under each child is tracked.
+    leaf_count_mode: Option<LeafCountMode>,
 }
 
 /// Computes the hash of internal node according to [`JellyfishTree`](crate::JellyfishTree)
@@ -223,6 +322,10 @@ pub struct InternalNode {
 /// height
 /// Note: @ denotes placeholder hash.
 /// ```
+///
+/// If the leaf counts are committed (see [`LeafCountMode::Committed`]), each `#` above is instead
+/// the hash of its two direct children along with the number of leaves under each of them, while
+/// the rules above stay the same.
 #[cfg(any(test, feature = "fuzzing"))]
 impl Arbitrary for InternalNode {
     type Parameters = ();
@@ -243,7 +346,38 @@ impl Arbitrary for InternalNode {
 
 impl InternalNode {
     /// Creates a new Internal node.
-    pub fn new(children: Children) -> Self {
+    pub fn new(mut children: Children) -> Self {
+        Self::check_children(&children);
+        for child in children.values_mut() {
+            child.leaf_count = None;
+        }
+        Self {
+            children,
+            leaf_count_mode: None,
+        }
+    }
+
+    /// Creates a new Internal node that tracks the number of leaves under each child in `mode`.
+    /// Every child that is not a leaf must have its leaf count set.
+    pub fn new_with_leaf_count(mut children: Children, mode: LeafCountMode) -> Self {
+        Self::check_children(&children);
+        for child in children.values_mut() {
+            if child.is_leaf {
+                child.leaf_count = Some(1);
+            } else {
+                assert!(
+                    child.leaf_count.is_some(),
+                    "Leaf count of internal child must be known."
+                );
+            }
+        }
+        Self {
+            children,
+            leaf_count_mode: Some(mode),
+        }
+    }
+
+    fn check_children(children: &Children) {
         // Assert the internal node must have >= 1 children. If it only has one child, it cannot be
         // a leaf node. Otherwise, the leaf node should be a child of this internal node's parent.
         assert!(!children.is_empty());
@@ -256,17 +390,45 @@ impl InternalNode {
                     .is_leaf
             )
         }
-        Self { children }
+        assert!(
+            children
+                .values()
+                .all(|child| child.is_leaf || child.inline_leaf.is_none()),
+            "Only leaf children can be inlined."
+        );
+    }
+
+    /// Returns how this node tracks leaf counts, or `None` if it does not.
+    pub fn leaf_count_mode(&self) -> Option<LeafCountMode> {
+        self.leaf_count_mode
+    }
+
+    /// Returns the number of leaves under this node, or `None` if this node does not track it.
+    pub fn leaf_count(&self) -> Option<u64> {
+        self.leaf_count_mode?;
+        Some(self.range_leaf_count(0, 16))
     }
 
     pub fn hash(&self) -> HashValue {
+        self.hash_with_scheme(&Blake3)
+    }
+
+    /// Same as [`hash`](InternalNode::hash), but computed with `hash_scheme`.
+    pub fn hash_with_scheme<S: HashScheme>(&self, hash_scheme: &S) -> HashValue {
         self.merkle_hash(
             0,  /* start index */
             16, /* the number of leaves in the subtree of which we want the hash of root */
             self.generate_bitmaps(),
+            hash_scheme,
         )
     }
 
+    /// Returns the hash of the subtree of the children in `[start, start + width)`, where `width`
+    /// is a power of two and `start` is a multiple of it.
+    pub fn range_hash<S: HashScheme>(&self, start: u8, width: u8, hash_scheme: &S) -> HashValue {
+        self.merkle_hash(start, width, self.generate_bitmaps(), hash_scheme)
+    }
+
     pub fn serialize(&self, binary: &mut Vec<u8>) -> Result<()> {
         let (mut existence_bitmap, leaf_bitmap) = self.generate_bitmaps();
         binary.write_u16::<LittleEndian>(existence_bitmap)?;
@@ -276,57 +438,35 @@ impl InternalNode {
             let child = &self.children[&Nibble::from(next_child)];
             serialize_u64_varint(child.version, binary);
             binary.extend(child.hash.to_vec());
+            // Leaf children always have a count of 1, so only internal children store theirs.
+            if self.leaf_count_mode.is_some() && !child.is_leaf {
+                serialize_u64_varint(
+                    child
+                        .leaf_count
+                        .expect("Counted node must have leaf counts."),
+                    binary,
+                );
+            }
             existence_bitmap &= !(1 << next_child);
         }
         Ok(())
     }
 
     pub fn deserialize(data: &[u8]) -> Result<Self> {
-        let mut reader = Cursor::new(data);
-        let len = data.len();
+        Self::deserialize_impl(data, None)
+    }
 
-        // Read and validate existence and leaf bitmaps
-        let mut existence_bitmap = reader.read_u16::<LittleEndian>()?;
-        let leaf_bitmap = reader.read_u16::<LittleEndian>()?;
-        match existence_bitmap {
-            0 => return Err(NodeDecodeError::NoChildren.into()),
-            _ if (existence_bitmap & leaf_bitmap) != leaf_bitmap => {
-                return Err(NodeDecodeError::ExtraLeaves {
-                    existing: existence_bitmap,
-                    leaves: leaf_bitmap,
-                }
-                .into())
-            }
-            _ => (),
-        }
+    /// Same as [`deserialize`](InternalNode::deserialize), for nodes serialized with leaf counts
+    /// tracked in `mode`.
+    pub fn deserialize_with_leaf_count(data: &[u8], mode: LeafCountMode) -> Result<Self> {
+        Self::deserialize_impl(data, Some(mode))
+    }
 
-        // Reconstruct children
-        let mut children = HashMap::new();
-        for _ in 0..existence_bitmap.count_ones() {
-            let next_child = existence_bitmap.trailing_zeros() as u8;
-            let version = deserialize_u64_varint(&mut reader)?;
-            let pos = reader.position() as usize;
-            let remaining = len - pos;
-            ensure!(
-                remaining >= size_of::<HashValue>(),
-                "not enough bytes left, children: {}, bytes: {}",
-                existence_bitmap.count_ones(),
-                remaining
-            );
-            let child_bit = 1 << next_child;
-            children.insert(
-                Nibble::from(next_child),
-                Child::new(
-                    HashValue::from_slice(&reader.get_ref()[pos..pos + size_of::<HashValue>()])?,
-                    version,
-                    (leaf_bitmap & child_bit) != 0,
-                ),
-            );
-            reader.seek(SeekFrom::Current(size_of::<HashValue>() as i64))?;
-            existence_bitmap &= !child_bit;
-        }
-        assert_eq!(existence_bitmap, 0);
-        Ok(Self { children })
+    fn deserialize_impl(data: &[u8], leaf_count_mode: Option<LeafCountMode>) -> Result<Self> {
+        Ok(
+            InternalNodeView::new(data, leaf_count_mode, false /* has_inline_leaves */)?
+                .to_internal_node(),
+        )
     }
 
     /// Gets the `n`-th child.
@@ -334,6 +474,50 @@ impl InternalNode {
         self.children.get(&n)
     }
 
+    /// Gets the leaf of the `n`-th child if it is inlined into this node.
+    pub fn inline_leaf(&self, n: Nibble) -> Option<&LeafNode> {
+        self.children.get(&n)?.inline_leaf.as_ref()
+    }
+
+    /// Consumes the node and returns the leaf of the `n`-th child if it is inlined into this node,
+    /// without copying it.
+    pub fn into_inline_leaf(mut self, n: Nibble) -> Option<LeafNode> {
+        self.children.remove(&n)?.inline_leaf
+    }
+
+    /// Returns `true` if any child is a leaf inlined into this node.
+    pub fn has_inline_leaves(&self) -> bool {
+        self.children
+            .values()
+            .any(|child| child.inline_leaf.is_some())
+    }
+
+    /// Serializes the leaves inlined into this node, following the bitmap of the children that
+    /// have them. Each leaf is serialized by [`LeafNode::serialize`] in `blob_hash_encoding` with
+    /// a varint length.
+    fn serialize_inline_leaves(
+        &self,
+        binary: &mut Vec<u8>,
+        blob_hash_encoding: BlobHashEncoding,
+    ) -> Result<()> {
+        let mut inline_bitmap = 0u16;
+        for (nibble, child) in &self.children {
+            if child.inline_leaf.is_some() {
+                inline_bitmap |= 1 << u8::from(*nibble);
+            }
+        }
+        binary.write_u16::<LittleEndian>(inline_bitmap)?;
+        for i in 0..16u8 {
+            if let Some(leaf_node) = self.inline_leaf(Nibble::from(i)) {
+                let mut encoded_leaf = vec![];
+                leaf_node.serialize(&mut encoded_leaf, blob_hash_encoding)?;
+                serialize_u64_varint(encoded_leaf.len() as u64, binary);
+                binary.extend(encoded_leaf);
+            }
+        }
+        Ok(())
+    }
+
     /// Return the total number of existing children.
     pub fn num_children(&self) -> usize {
         self.children.len()
@@ -368,23 +552,103 @@ impl InternalNode {
         (bitmaps.0 & mask, bitmaps.1 & mask)
     }
 
-    fn merkle_hash(
+    /// Gets the child and its corresponding siblings that are necessary to generate the proof for
+    /// the `n`-th child. If it is an existence proof, the returned child must be the `n`-th
+    /// child; otherwise, the returned child may be another child. See inline explanation for
+    /// details. When calling this function with n = 11 (node `b` in the following graph), the
+    /// range at each level is illustrated as a pair of square brackets:
+    ///
+    /// ```text
+    ///     4      [f   e   d   c   b   a   9   8   7   6   5   4   3   2   1   0] -> root level
+    ///            ---------------------------------------------------------------
+    ///     3      [f   e   d   c   b   a   9   8] [7   6   5   4   3   2   1   0] width = 8
+    ///                                  chs <--┘                        shs <--┘
+    ///     2      [f   e   d   c] [b   a   9   8] [7   6   5   4] [3   2   1   0] width = 4
+    ///                  shs <--┘               └--> chs
+    ///     1      [f   e] [d   c] [b   a] [9   8] [7   6] [5   4] [3   2] [1   0] width = 2
+    ///                          chs <--┘       └--> shs
+    ///     0      [f] [e] [d] [c] [b] [a] [9] [8] [7] [6] [5] [4] [3] [2] [1] [0] width = 1
+    ///     ^                chs <--┘   └--> shs
+    ///     |   MSB|<---------------------- uint 16 ---------------------------->|LSB
+    ///  height    chs: `child_half_start`         shs: `sibling_half_start`
+    /// ```
+    pub fn get_child_with_siblings<S: HashScheme>(
+        &self,
+        node_key: &NodeKey,
+        n: Nibble,
+        hash_scheme: &S,
+    ) -> (Option<NodeKey>, Vec<HashValue>) {
+        ChildLookup::get_child_with_siblings(self, node_key, n, hash_scheme)
+    }
+
+    /// Same as [`get_child_with_siblings`](InternalNode::get_child_with_siblings), but also
+    /// returns the number of leaves under each sibling. The counts are 0 if this node does not
+    /// track leaf counts.
+    pub fn get_child_with_counted_siblings<S: HashScheme>(
+        &self,
+        node_key: &NodeKey,
+        n: Nibble,
+        hash_scheme: &S,
+    ) -> (Option<NodeKey>, Vec<(HashValue, u64)>) {
+        ChildLookup::get_child_with_counted_siblings(self, node_key, n, hash_scheme)
+    }
+}
+
+impl ChildLookup for InternalNode {
+    fn bitmaps(&self) -> (u16, u16) {
+        self.generate_bitmaps()
+    }
+
+    fn leaf_count_mode(&self) -> Option<LeafCountMode> {
+        self.leaf_count_mode
+    }
+
+    fn child_summary(&self, n: Nibble) -> Option<(HashValue, Version, Option<u64>)> {
+        self.child(n)
+            .map(|child| (child.hash, child.version, child.leaf_count))
+    }
+}
+
+/// Looks up the hashes, versions and leaf counts of the children of an internal node, which is
+/// all that is needed to compute its hashes and proofs. Implemented by both [`InternalNode`] and
+/// [`InternalNodeView`], so a proof can be read out of an encoded node without copying it.
+trait ChildLookup {
+    /// Returns the existence and leaf bitmaps, see [`InternalNode::generate_bitmaps`].
+    fn bitmaps(&self) -> (u16, u16);
+
+    /// Returns how the node tracks leaf counts, or `None` if it does not.
+    fn leaf_count_mode(&self) -> Option<LeafCountMode>;
+
+    /// Gets the hash, the version and the leaf count of the `n`-th child.
+    fn child_summary(&self, n: Nibble) -> Option<(HashValue, Version, Option<u64>)>;
+
+    /// Returns the total number of leaves under the children in [start, start + width), treating
+    /// unknown leaf counts as 0.
+    fn range_leaf_count(&self, start: u8, width: u8) -> u64 {
+        (start..start + width)
+            .filter_map(|i| self.child_summary(Nibble::from(i)))
+            .map(|(_hash, _version, leaf_count)| leaf_count.unwrap_or(0))
+            .sum()
+    }
+
+    fn merkle_hash<S: HashScheme>(
         &self,
         start: u8,
         width: u8,
         (existence_bitmap, leaf_bitmap): (u16, u16),
+        hash_scheme: &S,
     ) -> HashValue {
         // Given a bit [start, 1 << nibble_height], return the value of that range.
         let (range_existence_bitmap, range_leaf_bitmap) =
-            Self::range_bitmaps(start, width, (existence_bitmap, leaf_bitmap));
+            InternalNode::range_bitmaps(start, width, (existence_bitmap, leaf_bitmap));
         if range_existence_bitmap == 0 {
             // No child under this subtree
-            *SPARSE_MERKLE_PLACEHOLDER_HASH
+            hash_scheme.placeholder_hash()
         } else if range_existence_bitmap.count_ones() == 1 && (range_leaf_bitmap != 0 || width == 1)
         {
             // Only 1 leaf child under this subtree or reach the lowest level
             let only_child_index = Nibble::from(range_existence_bitmap.trailing_zeros() as u8);
-            self.child(only_child_index)
+            self.child_summary(only_child_index)
                 .with_context(|| {
                     format!(
                         "Corrupted internal node: existence_bitmap indicates \
@@ -393,45 +657,57 @@ impl InternalNode {
                     )
                 })
                 .unwrap()
-                .hash
+                .0
         } else {
-            let left_child = self.merkle_hash(start, width / 2, (existence_bitmap, leaf_bitmap));
+            let left_child = self.merkle_hash(
+                start,
+                width / 2,
+                (existence_bitmap, leaf_bitmap),
+                hash_scheme,
+            );
             let right_child = self.merkle_hash(
                 start + width / 2,
                 width / 2,
                 (existence_bitmap, leaf_bitmap),
+                hash_scheme,
             );
-            SparseMerkleInternalNode::new(left_child, right_child).hash()
+            if self.leaf_count_mode() == Some(LeafCountMode::Committed) {
+                SparseMerkleCountedInternalNode::new(
+                    left_child,
+                    self.range_leaf_count(start, width / 2),
+                    right_child,
+                    self.range_leaf_count(start + width / 2, width / 2),
+                )
+                .hash_with_scheme(hash_scheme)
+            } else {
+                SparseMerkleInternalNode::new(left_child, right_child).hash_with_scheme(hash_scheme)
+            }
         }
     }
 
-    /// Gets the child and its corresponding siblings that are necessary to generate the proof for
-    /// the `n`-th child. If it is an existence proof, the returned child must be the `n`-th
-    /// child; otherwise, the returned child may be another child. See inline explanation for
-    /// details. When calling this function with n = 11 (node `b` in the following graph), the
-    /// range at each level is illustrated as a pair of square brackets:
-    ///
-    /// ```text
-    ///     4      [f   e   d   c   b   a   9   8   7   6   5   4   3   2   1   0] -> root level
-    ///            ---------------------------------------------------------------
-    ///     3      [f   e   d   c   b   a   9   8] [7   6   5   4   3   2   1   0] width = 8
-    ///                                  chs <--┘                        shs <--┘
-    ///     2      [f   e   d   c] [b   a   9   8] [7   6   5   4] [3   2   1   0] width = 4
-    ///                  shs <--┘               └--> chs
-    ///     1      [f   e] [d   c] [b   a] [9   8] [7   6] [5   4] [3   2] [1   0] width = 2
-    ///                          chs <--┘       └--> shs
-    ///     0      [f] [e] [d] [c] [b] [a] [9] [8] [7] [6] [5] [4] [3] [2] [1] [0] width = 1
-    ///     ^                chs <--┘   └--> shs
-    ///     |   MSB|<---------------------- uint 16 ---------------------------->|LSB
-    ///  height    chs: `child_half_start`         shs: `sibling_half_start`
-    /// ```
-    pub fn get_child_with_siblings(
+    /// See [`InternalNode::get_child_with_siblings`].
+    fn get_child_with_siblings<S: HashScheme>(
         &self,
         node_key: &NodeKey,
         n: Nibble,
+        hash_scheme: &S,
     ) -> (Option<NodeKey>, Vec<HashValue>) {
+        let (child, siblings) = self.get_child_with_counted_siblings(node_key, n, hash_scheme);
+        (
+            child,
+            siblings.into_iter().map(|(hash, _count)| hash).collect(),
+        )
+    }
+
+    /// See [`InternalNode::get_child_with_counted_siblings`].
+    fn get_child_with_counted_siblings<S: HashScheme>(
+        &self,
+        node_key: &NodeKey,
+        n: Nibble,
+        hash_scheme: &S,
+    ) -> (Option<NodeKey>, Vec<(HashValue, u64)>) {
         let mut siblings = vec![];
-        let (existence_bitmap, leaf_bitmap) = self.generate_bitmaps();
+        let (existence_bitmap, leaf_bitmap) = self.bitmaps();
 
         // Nibble height from 3 to 0.
         for h in (0..4).rev() {
@@ -440,14 +716,21 @@ impl InternalNode {
             let width = 1 << h;
             let (child_half_start, sibling_half_start) = get_child_and_sibling_half_start(n, h);
             // Compute the root hash of the subtree rooted at the sibling of `r`.
-            siblings.push(self.merkle_hash(
-                sibling_half_start,
-                width,
-                (existence_bitmap, leaf_bitmap),
+            siblings.push((
+                self.merkle_hash(
+                    sibling_half_start,
+                    width,
+                    (existence_bitmap, leaf_bitmap),
+                    hash_scheme,
+                ),
+                self.range_leaf_count(sibling_half_start, width),
             ));
 
-            let (range_existence_bitmap, range_leaf_bitmap) =
-                Self::range_bitmaps(child_half_start, width, (existence_bitmap, leaf_bitmap));
+            let (range_existence_bitmap, range_leaf_bitmap) = InternalNode::range_bitmaps(
+                child_half_start,
+                width,
+                (existence_bitmap, leaf_bitmap),
+            );
 
             if range_existence_bitmap == 0 {
                 // No child in this range.
@@ -463,7 +746,7 @@ impl InternalNode {
                 return (
                     {
                         let only_child_version = self
-                            .child(only_child_index)
+                            .child_summary(only_child_index)
                             // Should be guaranteed by the self invariants, but these are not easy to express at the moment
                             .with_context(|| {
                                 format!(
@@ -473,7 +756,7 @@ impl InternalNode {
                                 )
                             })
                             .unwrap()
-                            .version;
+                            .1;
                         Some(node_key.gen_child_node_key(only_child_version, only_child_index))
                     },
                     siblings,
@@ -499,43 +782,152 @@ pub(crate) fn get_child_and_sibling_half_start(n: Nibble, height: u8) -> (u8, u8
     (child_half_start, sibling_half_start)
 }
 
-/// Represents an account.
-#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+/// Represents an account. A hash-only leaf doesn't have the blob, which is kept in a value store
+/// instead, see [`TreeReader::get_value_option`](crate::TreeReader::get_value_option).
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct LeafNode {
     // The hashed account address associated with this leaf node.
     account_key: HashValue,
     // The hash of the account state blob.
     blob_hash: HashValue,
-    // The account blob associated with `account_key`.
-    blob: AccountStateBlob,
+    // The account blob associated with `account_key`, or `None` if the leaf is hash-only.
+    blob: Option<AccountStateBlob>,
 }
 
 impl LeafNode {
     /// Creates a new leaf node.
     pub fn new(account_key: HashValue, blob: AccountStateBlob) -> Self {
-        let blob_hash = blob.hash();
+        Self::new_with_scheme(account_key, blob, &Blake3)
+    }
+
+    /// Same as [`new`](LeafNode::new), but the hash of `blob` is computed with `hash_scheme`.
+    pub fn new_with_scheme<S: HashScheme>(
+        account_key: HashValue,
+        blob: AccountStateBlob,
+        hash_scheme: &S,
+    ) -> Self {
+        let blob_hash = blob.hash_with_scheme(hash_scheme);
+        Self {
+            account_key,
+            blob_hash,
+            blob: Some(blob),
+        }
+    }
+
+    /// Creates a hash-only leaf node, whose blob hashes to `blob_hash`.
+    pub fn new_hash_only(account_key: HashValue, blob_hash: HashValue) -> Self {
         Self {
             account_key,
             blob_hash,
-            blob,
+            blob: None,
         }
     }
 
+    /// Returns `true` if the leaf doesn't have the blob.
+    pub fn is_hash_only(&self) -> bool {
+        self.blob.is_none()
+    }
+
     /// Gets the account key, the hashed account address.
     pub fn account_key(&self) -> HashValue {
         self.account_key
     }
 
-    /// Gets the associated blob itself.
-    pub fn blob(&self) -> &AccountStateBlob {
-        &self.blob
+    /// Gets the hash of the blob.
+    pub fn blob_hash(&self) -> HashValue {
+        self.blob_hash
+    }
+
+    /// Gets the associated blob itself, or `None` if the leaf is hash-only.
+    pub fn blob(&self) -> Option<&AccountStateBlob> {
+        self.blob.as_ref()
+    }
+
+    /// Consumes the leaf and returns the blob without copying it, or `None` if the leaf is
+    /// hash-only.
+    pub fn into_blob(self) -> Option<AccountStateBlob> {
+        self.blob
     }
 
     pub fn hash(&self) -> HashValue {
-        SparseMerkleLeafNode::new(self.account_key, self.blob_hash).hash()
+        self.hash_with_scheme(&Blake3)
+    }
+
+    /// Same as [`hash`](LeafNode::hash), but computed with `hash_scheme`.
+    pub fn hash_with_scheme<S: HashScheme>(&self, hash_scheme: &S) -> HashValue {
+        SparseMerkleLeafNode::new(self.account_key, self.blob_hash).hash_with_scheme(hash_scheme)
+    }
+}
+
+impl LeafNode {
+    /// Serializes in the versioned leaf format, the format version followed by the flags, the
+    /// account key, the blob hash if it is stored and the blob with a varint length unless the
+    /// leaf is hash-only. With [`Recomputed`](BlobHashEncoding::Recomputed), the blob hash is
+    /// only stored if it can't be recomputed from the blob with the default hash scheme.
+    pub fn serialize(
+        &self,
+        binary: &mut Vec<u8>,
+        blob_hash_encoding: BlobHashEncoding,
+    ) -> Result<()> {
+        let mut flags = 0;
+        match &self.blob {
+            Some(blob) => {
+                let store_blob_hash = match blob_hash_encoding {
+                    BlobHashEncoding::Recomputed => {
+                        self.blob_hash != blob.hash_with_scheme(&Blake3)
+                    }
+                    BlobHashEncoding::Stored => true,
+                };
+                if store_blob_hash {
+                    flags |= LEAF_FLAG_BLOB_HASH;
+                }
+            }
+            None => flags |= LEAF_FLAG_BLOB_HASH | LEAF_FLAG_HASH_ONLY,
+        }
+        binary.push(LEAF_FORMAT_VERSION);
+        binary.push(flags);
+        binary.extend(self.account_key.to_vec());
+        if flags & LEAF_FLAG_BLOB_HASH != 0 {
+            binary.extend(self.blob_hash.to_vec());
+        }
+        if let Some(blob) = &self.blob {
+            let blob: &[u8] = blob.as_ref();
+            serialize_u64_varint(blob.len() as u64, binary);
+            binary.extend(blob);
+        }
+        Ok(())
+    }
+
+    /// Recovers from bytes serialized by [`serialize`](LeafNode::serialize).
+    pub fn deserialize(data: &[u8]) -> Result<Self> {
+        Ok(LeafNodeView::new(data)?.to_leaf_node())
     }
 }
 
+/// How [`LeafNode::serialize`] encodes the hash of the blob.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
+pub enum BlobHashEncoding {
+    /// The blob hash is recomputed from the blob when decoding, and is only stored if it was
+    /// computed with another hash scheme than the default one. This saves the space of the hash
+    /// at the cost of hashing the blob on every encode and decode.
+    Recomputed,
+    /// The blob hash is always stored, so neither encoding nor decoding hashes the blob. This is
+    /// what [`Node::encode`] uses.
+    Stored,
+}
+
+/// The format version of the leaves serialized by [`LeafNode::serialize`]. New fields are added
+/// behind new flags, and a new format version is only needed to change the existing fields.
+const LEAF_FORMAT_VERSION: u8 = 0;
+
+/// Set in the flags of a serialized leaf if the blob hash is stored.
+const LEAF_FLAG_BLOB_HASH: u8 = 1;
+
+/// Set in the flags of a serialized leaf if the leaf is hash-only, along with
+/// [`LEAF_FLAG_BLOB_HASH`].
+const LEAF_FLAG_HASH_ONLY: u8 = 2;
+
 impl From<LeafNode> for SparseMerkleLeafNode {
     fn from(leaf_node: LeafNode) -> Self {
         Self::new(leaf_node.account_key, leaf_node.blob_hash)
@@ -547,9 +939,20 @@ impl From<LeafNode> for SparseMerkleLeafNode {
 enum NodeTag {
     Null = 0,
     Internal = 1,
-    Leaf = 2,
+    // Leaves serialized with LCS, which are still decoded but no longer written.
+    LegacyLeaf = 2,
+    InternalWithLeafCount = 3,
+    InternalWithCommittedLeafCount = 4,
+    Leaf = 5,
+    // Internal nodes with some leaf children inlined, followed by the leaf count mode.
+    InternalWithInlineLeaves = 6,
 }
 
+// The leaf count modes of internal nodes with inline leaves.
+const INLINE_LEAF_COUNT_MODE_NONE: u8 = 0;
+const INLINE_LEAF_COUNT_MODE_STORED: u8 = 1;
+const INLINE_LEAF_COUNT_MODE_COMMITTED: u8 = 2;
+
 /// The concrete node type of [`JellyfishMerkleTree`](crate::JellyfishMerkleTree).
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Node {
@@ -603,20 +1006,54 @@ impl Node {
         }
     }
 
-    /// Serializes to bytes for physical storage.
+    /// Returns the number of leaves under this node, or `None` if this is an internal node that
+    /// does not track it.
+    pub fn leaf_count(&self) -> Option<u64> {
+        match self {
+            Node::Null => Some(0),
+            Node::Internal(internal_node) => internal_node.leaf_count(),
+            Node::Leaf(_) => Some(1),
+        }
+    }
+
+    /// Serializes to bytes for physical storage. The blob hash of a leaf is always stored, see
+    /// [`BlobHashEncoding::Stored`].
     pub fn encode(&self) -> Result<Vec<u8>> {
+        self.encode_with_blob_hash_encoding(BlobHashEncoding::Stored)
+    }
+
+    /// Same as [`encode`](Node::encode), but the blob hash of a leaf, or of the leaves inlined into
+    /// an internal node, is encoded in `blob_hash_encoding`.
+    pub fn encode_with_blob_hash_encoding(
+        &self,
+        blob_hash_encoding: BlobHashEncoding,
+    ) -> Result<Vec<u8>> {
         let mut out = vec![];
         match self {
             Node::Null => {
                 out.push(NodeTag::Null as u8);
             }
+            Node::Internal(internal_node) if internal_node.has_inline_leaves() => {
+                out.push(NodeTag::InternalWithInlineLeaves as u8);
+                out.push(match internal_node.leaf_count_mode() {
+                    None => INLINE_LEAF_COUNT_MODE_NONE,
+                    Some(LeafCountMode::Stored) => INLINE_LEAF_COUNT_MODE_STORED,
+                    Some(LeafCountMode::Committed) => INLINE_LEAF_COUNT_MODE_COMMITTED,
+                });
+                internal_node.serialize(&mut out)?;
+                internal_node.serialize_inline_leaves(&mut out, blob_hash_encoding)?;
+            }
             Node::Internal(internal_node) => {
-                out.push(NodeTag::Internal as u8);
+                out.push(match internal_node.leaf_count_mode() {
+                    None => NodeTag::Internal,
+                    Some(LeafCountMode::Stored) => NodeTag::InternalWithLeafCount,
+                    Some(LeafCountMode::Committed) => NodeTag::InternalWithCommittedLeafCount,
+                } as u8);
                 internal_node.serialize(&mut out)?
             }
             Node::Leaf(leaf_node) => {
                 out.push(NodeTag::Leaf as u8);
-                out.extend(lcs::to_bytes(&leaf_node)?);
+                leaf_node.serialize(&mut out, blob_hash_encoding)?;
             }
         }
         Ok(out)
@@ -624,27 +1061,413 @@ impl Node {
 
     /// Computes the hash of nodes.
     pub fn hash(&self) -> HashValue {
+        self.hash_with_scheme(&Blake3)
+    }
+
+    /// Same as [`hash`](Node::hash), but computed with `hash_scheme`.
+    pub fn hash_with_scheme<S: HashScheme>(&self, hash_scheme: &S) -> HashValue {
         match self {
-            Node::Null => *SPARSE_MERKLE_PLACEHOLDER_HASH,
-            Node::Internal(internal_node) => internal_node.hash(),
-            Node::Leaf(leaf_node) => leaf_node.hash(),
+            Node::Null => hash_scheme.placeholder_hash(),
+            Node::Internal(internal_node) => internal_node.hash_with_scheme(hash_scheme),
+            Node::Leaf(leaf_node) => leaf_node.hash_with_scheme(hash_scheme),
         }
     }
 
     /// Recovers from serialized bytes in physical storage.
     pub fn decode(val: &[u8]) -> Result<Node> {
+        Ok(NodeView::decode(val)?.to_node())
+    }
+}
+
+/// A borrowed view of an encoded [`Node`], which is decoded without copying the blob of a leaf or
+/// building the map of children of an internal node.
+#[derive(Clone, Debug)]
+pub enum NodeView<'a> {
+    /// Represents `null`.
+    Null,
+    /// A view of an encoded [`InternalNode`].
+    Internal(InternalNodeView<'a>),
+    /// A view of an encoded [`LeafNode`].
+    Leaf(LeafNodeView<'a>),
+}
+
+impl<'a> NodeView<'a> {
+    /// Decodes a view of bytes serialized by [`Node::encode`].
+    pub fn decode(val: &'a [u8]) -> Result<Self> {
         if val.is_empty() {
             return Err(NodeDecodeError::EmptyInput.into());
         }
         let tag = val[0];
         let node_tag = NodeTag::from_u8(tag);
         match node_tag {
-            Some(NodeTag::Null) => Ok(Node::Null),
-            Some(NodeTag::Internal) => Ok(Node::Internal(InternalNode::deserialize(&val[1..])?)),
-            Some(NodeTag::Leaf) => Ok(Node::Leaf(lcs::from_bytes(&val[1..])?)),
+            Some(NodeTag::Null) => Ok(NodeView::Null),
+            Some(NodeTag::Internal) => Ok(NodeView::Internal(InternalNodeView::new(
+                &val[1..],
+                None,
+                false, /* has_inline_leaves */
+            )?)),
+            Some(NodeTag::LegacyLeaf) => Ok(NodeView::Leaf(LeafNodeView::new_legacy(&val[1..])?)),
+            Some(NodeTag::InternalWithLeafCount) => Ok(NodeView::Internal(InternalNodeView::new(
+                &val[1..],
+                Some(LeafCountMode::Stored),
+                false, /* has_inline_leaves */
+            )?)),
+            Some(NodeTag::InternalWithCommittedLeafCount) => {
+                Ok(NodeView::Internal(InternalNodeView::new(
+                    &val[1..],
+                    Some(LeafCountMode::Committed),
+                    false, /* has_inline_leaves */
+                )?))
+            }
+            Some(NodeTag::InternalWithInlineLeaves) => {
+                ensure!(val.len() > 1, "Missing leaf count mode.");
+                let leaf_count_mode = match val[1] {
+                    INLINE_LEAF_COUNT_MODE_NONE => None,
+                    INLINE_LEAF_COUNT_MODE_STORED => Some(LeafCountMode::Stored),
+                    INLINE_LEAF_COUNT_MODE_COMMITTED => Some(LeafCountMode::Committed),
+                    mode => return Err(NodeDecodeError::UnknownLeafCountMode { mode }.into()),
+                };
+                Ok(NodeView::Internal(InternalNodeView::new(
+                    &val[2..],
+                    leaf_count_mode,
+                    true, /* has_inline_leaves */
+                )?))
+            }
+            Some(NodeTag::Leaf) => Ok(NodeView::Leaf(LeafNodeView::new(&val[1..])?)),
             None => Err(NodeDecodeError::UnknownTag { unknown_tag: tag }.into()),
         }
     }
+
+    /// Copies the viewed node into a [`Node`], including the leaves inlined into an internal node.
+    pub fn to_node(&self) -> Node {
+        match self {
+            NodeView::Null => Node::Null,
+            NodeView::Internal(internal_node) => {
+                Node::Internal(internal_node.to_internal_node_with_inline_leaves())
+            }
+            NodeView::Leaf(leaf_node) => Node::Leaf(leaf_node.to_leaf_node()),
+        }
+    }
+}
+
+/// A borrowed view of an encoded [`InternalNode`]. Children are read from the encoded bytes when
+/// they are looked up.
+#[derive(Clone, Debug)]
+pub struct InternalNodeView<'a> {
+    data: &'a [u8],
+    existence_bitmap: u16,
+    leaf_bitmap: u16,
+    leaf_count_mode: Option<LeafCountMode>,
+    // The offset in `data` of each existing child.
+    offsets: [usize; 16],
+    // The children whose leaves are inlined, and the offset in `data` of each inline leaf, which
+    // starts with its length.
+    inline_bitmap: u16,
+    inline_offsets: [u32; 16],
+}
+
+impl<'a> InternalNodeView<'a> {
+    fn new(
+        data: &'a [u8],
+        leaf_count_mode: Option<LeafCountMode>,
+        has_inline_leaves: bool,
+    ) -> Result<Self> {
+        let mut reader = Cursor::new(data);
+        let len = data.len();
+
+        // Read and validate existence and leaf bitmaps
+        let existence_bitmap = reader.read_u16::<LittleEndian>()?;
+        let leaf_bitmap = reader.read_u16::<LittleEndian>()?;
+        match existence_bitmap {
+            0 => return Err(NodeDecodeError::NoChildren.into()),
+            _ if (existence_bitmap & leaf_bitmap) != leaf_bitmap => {
+                return Err(NodeDecodeError::ExtraLeaves {
+                    existing: existence_bitmap,
+                    leaves: leaf_bitmap,
+                }
+                .into())
+            }
+            _ => (),
+        }
+
+        // Find and validate the children
+        let mut offsets = [0; 16];
+        let mut remaining_bitmap = existence_bitmap;
+        while remaining_bitmap != 0 {
+            let next_child = remaining_bitmap.trailing_zeros() as usize;
+            offsets[next_child] = reader.position() as usize;
+            deserialize_u64_varint(&mut reader)?;
+            let remaining = len - reader.position() as usize;
+            ensure!(
+                remaining >= size_of::<HashValue>(),
+                "not enough bytes left, children: {}, bytes: {}",
+                existence_bitmap.count_ones(),
+                remaining
+            );
+            reader.seek(SeekFrom::Current(size_of::<HashValue>() as i64))?;
+            let child_bit = 1 << next_child;
+            if leaf_count_mode.is_some() && (leaf_bitmap & child_bit) == 0 {
+                deserialize_u64_varint(&mut reader)?;
+            }
+            remaining_bitmap &= !child_bit;
+        }
+
+        // Find and validate the inline leaves. Their blobs are not hashed, see
+        // [`LeafNodeView::blob_hash`].
+        let mut inline_bitmap = 0;
+        let mut inline_offsets = [0; 16];
+        if has_inline_leaves {
+            inline_bitmap = reader.read_u16::<LittleEndian>()?;
+            if inline_bitmap == 0 || (leaf_bitmap & inline_bitmap) != inline_bitmap {
+                return Err(NodeDecodeError::ExtraInlineLeaves {
+                    leaves: leaf_bitmap,
+                    inline_leaves: inline_bitmap,
+                }
+                .into());
+            }
+            let mut remaining_bitmap = inline_bitmap;
+            while remaining_bitmap != 0 {
+                let next_child = remaining_bitmap.trailing_zeros() as usize;
+                inline_offsets[next_child] = u32::try_from(reader.position())?;
+                let leaf_len = deserialize_u64_varint(&mut reader)?;
+                let start = reader.position() as usize;
+                ensure!(
+                    leaf_len <= (len - start) as u64,
+                    "not enough bytes left for inline leaf, expected: {}, bytes: {}",
+                    leaf_len,
+                    len - start
+                );
+                let end = start + leaf_len as usize;
+                LeafNodeView::new(&data[start..end])?;
+                reader.set_position(end as u64);
+                remaining_bitmap &= !(1 << next_child);
+            }
+            ensure!(
+                reader.position() as usize == len,
+                "{} trailing bytes after inline leaves",
+                len - reader.position() as usize
+            );
+        }
+        Ok(Self {
+            data,
+            existence_bitmap,
+            leaf_bitmap,
+            leaf_count_mode,
+            offsets,
+            inline_bitmap,
+            inline_offsets,
+        })
+    }
+
+    /// Returns how this node tracks leaf counts, or `None` if it does not.
+    pub fn leaf_count_mode(&self) -> Option<LeafCountMode> {
+        self.leaf_count_mode
+    }
+
+    /// Return the total number of existing children.
+    pub fn num_children(&self) -> usize {
+        self.existence_bitmap.count_ones() as usize
+    }
+
+    /// Gets the `n`-th child. Its leaf is not copied even if it is inlined into this node, see
+    /// [`inline_leaf`](InternalNodeView::inline_leaf).
+    pub fn child(&self, n: Nibble) -> Option<Child> {
+        let (hash, version, leaf_count) = self.child_summary(n)?;
+        let is_leaf = (self.leaf_bitmap & (1 << u8::from(n))) != 0;
+        let mut child = Child::new(hash, version, is_leaf);
+        child.leaf_count = leaf_count;
+        Some(child)
+    }
+
+    /// Gets a view of the leaf of the `n`-th child if it is inlined into this node.
+    pub fn inline_leaf(&self, n: Nibble) -> Option<LeafNodeView<'a>> {
+        if (self.inline_bitmap & (1 << u8::from(n))) == 0 {
+            return None;
+        }
+        // The inline leaves have been validated when the view was created.
+        let mut reader =
+            Cursor::new(&self.data[self.inline_offsets[u8::from(n) as usize] as usize..]);
+        let leaf_len = deserialize_u64_varint(&mut reader).expect("Leaf must be valid.") as usize;
+        let start = reader.position() as usize;
+        Some(
+            LeafNodeView::new(&reader.get_ref()[start..start + leaf_len])
+                .expect("Leaf must be valid."),
+        )
+    }
+
+    /// Same as [`InternalNode::get_child_with_siblings`], computed from the encoded children
+    /// without copying them into an [`InternalNode`].
+    pub fn get_child_with_siblings<S: HashScheme>(
+        &self,
+        node_key: &NodeKey,
+        n: Nibble,
+        hash_scheme: &S,
+    ) -> (Option<NodeKey>, Vec<HashValue>) {
+        ChildLookup::get_child_with_siblings(self, node_key, n, hash_scheme)
+    }
+
+    /// Same as [`InternalNode::get_child_with_counted_siblings`], computed from the encoded
+    /// children without copying them into an [`InternalNode`].
+    pub fn get_child_with_counted_siblings<S: HashScheme>(
+        &self,
+        node_key: &NodeKey,
+        n: Nibble,
+        hash_scheme: &S,
+    ) -> (Option<NodeKey>, Vec<(HashValue, u64)>) {
+        ChildLookup::get_child_with_counted_siblings(self, node_key, n, hash_scheme)
+    }
+
+    /// Copies the viewed node into an [`InternalNode`], without the leaves inlined into it.
+    pub fn to_internal_node(&self) -> InternalNode {
+        let children = (0..16u8)
+            .map(Nibble::from)
+            .filter_map(|n| self.child(n).map(|child| (n, child)))
+            .collect();
+        InternalNode {
+            children,
+            leaf_count_mode: self.leaf_count_mode,
+        }
+    }
+
+    /// Same as [`to_internal_node`](InternalNodeView::to_internal_node), but also copies the
+    /// leaves inlined into the node.
+    pub fn to_internal_node_with_inline_leaves(&self) -> InternalNode {
+        let mut internal_node = self.to_internal_node();
+        for (n, child) in internal_node.children.iter_mut() {
+            child.inline_leaf = self
+                .inline_leaf(*n)
+                .map(|leaf_node| leaf_node.to_leaf_node());
+        }
+        internal_node
+    }
+}
+
+impl<'a> ChildLookup for InternalNodeView<'a> {
+    fn bitmaps(&self) -> (u16, u16) {
+        (self.existence_bitmap, self.leaf_bitmap)
+    }
+
+    fn leaf_count_mode(&self) -> Option<LeafCountMode> {
+        self.leaf_count_mode
+    }
+
+    fn child_summary(&self, n: Nibble) -> Option<(HashValue, Version, Option<u64>)> {
+        let child_bit = 1 << u8::from(n);
+        if (self.existence_bitmap & child_bit) == 0 {
+            return None;
+        }
+        // The child has been validated when the view was created.
+        let mut reader = Cursor::new(&self.data[self.offsets[u8::from(n) as usize]..]);
+        let version = deserialize_u64_varint(&mut reader).expect("Child must be valid.");
+        let pos = reader.position() as usize;
+        let hash = HashValue::from_slice(&reader.get_ref()[pos..pos + size_of::<HashValue>()])
+            .expect("Child must be valid.");
+        let leaf_count = match self.leaf_count_mode {
+            None => None,
+            Some(_) if (self.leaf_bitmap & child_bit) != 0 => Some(1),
+            Some(_) => {
+                reader.set_position((pos + size_of::<HashValue>()) as u64);
+                Some(deserialize_u64_varint(&mut reader).expect("Child must be valid."))
+            }
+        };
+        Some((hash, version, leaf_count))
+    }
+}
+
+/// A borrowed view of an encoded [`LeafNode`], whose blob is not copied.
+#[derive(Clone, Debug)]
+pub struct LeafNodeView<'a> {
+    account_key: HashValue,
+    // The blob hash if it is stored, or `None` if it is recomputed from the blob when needed.
+    blob_hash: Option<HashValue>,
+    blob: Option<&'a [u8]>,
+}
+
+impl<'a> LeafNodeView<'a> {
+    /// Creates a view of bytes serialized by [`LeafNode::serialize`].
+    fn new(data: &'a [u8]) -> Result<Self> {
+        let mut reader = Cursor::new(data);
+        let format_version = reader.read_u8()?;
+        if format_version != LEAF_FORMAT_VERSION {
+            return Err(NodeDecodeError::UnknownLeafFormat { format_version }.into());
+        }
+        let flags = reader.read_u8()?;
+        if flags & !(LEAF_FLAG_BLOB_HASH | LEAF_FLAG_HASH_ONLY) != 0 {
+            return Err(NodeDecodeError::UnknownLeafFlags { flags }.into());
+        }
+        let account_key = read_hash_value(&mut reader)?;
+        let blob_hash = if flags & LEAF_FLAG_BLOB_HASH != 0 {
+            Some(read_hash_value(&mut reader)?)
+        } else {
+            None
+        };
+        if flags & LEAF_FLAG_HASH_ONLY != 0 {
+            let blob_hash =
+                blob_hash.ok_or_else(|| format_err!("Missing blob hash of hash-only leaf."))?;
+            read_blob(&mut reader, 0)?;
+            return Ok(Self {
+                account_key,
+                blob_hash: Some(blob_hash),
+                blob: None,
+            });
+        }
+        let blob_len = deserialize_u64_varint(&mut reader)?;
+        let blob = read_blob(&mut reader, blob_len)?;
+        Ok(Self {
+            account_key,
+            blob_hash,
+            blob: Some(blob),
+        })
+    }
+
+    /// Creates a view of a leaf serialized with LCS, which has the account key, the blob hash and
+    /// the blob with a ULEB128 length.
+    fn new_legacy(data: &'a [u8]) -> Result<Self> {
+        let mut reader = Cursor::new(data);
+        let account_key = read_hash_value(&mut reader)?;
+        let blob_hash = read_hash_value(&mut reader)?;
+        let blob_len = deserialize_uleb128_len(&mut reader)?;
+        let blob = read_blob(&mut reader, blob_len)?;
+        Ok(Self {
+            account_key,
+            blob_hash: Some(blob_hash),
+            blob: Some(blob),
+        })
+    }
+
+    /// Gets the account key, the hashed account address.
+    pub fn account_key(&self) -> HashValue {
+        self.account_key
+    }
+
+    /// Gets the hash of the blob. If the hash is not stored, the blob is hashed on every call.
+    pub fn blob_hash(&self) -> HashValue {
+        match (self.blob_hash, self.blob) {
+            (Some(blob_hash), _) => blob_hash,
+            (None, Some(blob)) => hash_blob(blob),
+            (None, None) => unreachable!("Hash-only leaves always store the blob hash."),
+        }
+    }
+
+    /// Gets the bytes of the associated blob, or `None` if the leaf is hash-only.
+    pub fn blob(&self) -> Option<&'a [u8]> {
+        self.blob
+    }
+
+    /// Copies the viewed leaf into a [`LeafNode`].
+    pub fn to_leaf_node(&self) -> LeafNode {
+        LeafNode {
+            account_key: self.account_key,
+            blob_hash: self.blob_hash(),
+            blob: self.blob.map(|blob| AccountStateBlob::from(blob.to_vec())),
+        }
+    }
+}
+
+impl<'a> From<LeafNodeView<'a>> for SparseMerkleLeafNode {
+    fn from(leaf_node: LeafNodeView<'a>) -> Self {
+        Self::new(leaf_node.account_key, leaf_node.blob_hash())
+    }
 }
 
 /// Error thrown when a [`Node`] fails to be deserialized out of a byte sequence stored in physical
@@ -670,6 +1493,26 @@ pub enum NodeDecodeError {
         leaves
     )]
     ExtraLeaves { existing: u16, leaves: u16 },
+
+    /// The format version of a leaf is unknown.
+    #[error("Unknown leaf format version: {}", format_version)]
+    UnknownLeafFormat { format_version: u8 },
+
+    /// A leaf has flags set that are unknown.
+    #[error("Unknown leaf flags: {:#x}", flags)]
+    UnknownLeafFlags { flags: u8 },
+
+    /// The leaf count mode of an internal node with inline leaves is unknown.
+    #[error("Unknown leaf count mode: {}", mode)]
+    UnknownLeafCountMode { mode: u8 },
+
+    /// Inline leaf bits set for no or non-leaf children
+    #[error(
+        "Inline leaf bits set for no or non-leaf children, leaves: {}, inline leaves: {}",
+        leaves,
+        inline_leaves
+    )]
+    ExtraInlineLeaves { leaves: u16, inline_leaves: u16 },
 }
 
 /// Helper function to serialize version in a more efficient encoding.
@@ -709,3 +1552,55 @@ where
     num |= u64::from(byte) << 56;
     Ok(num)
 }
+
+/// Helper function to deserialize the ULEB128 lengths of sequences serialized with LCS.
+fn deserialize_uleb128_len<T>(reader: &mut T) -> Result<u64>
+where
+    T: Read,
+{
+    let mut len = 0u64;
+    for shift in (0..32).step_by(7) {
+        let byte = reader.read_u8()?;
+        let digit = byte & 0x7f;
+        len |= u64::from(digit) << shift;
+        if digit == byte {
+            ensure!(
+                shift == 0 || digit != 0,
+                "Invalid ULEB128 length: not canonical."
+            );
+            ensure!(
+                len <= u64::from(u32::MAX),
+                "Invalid ULEB128 length: overflow."
+            );
+            return Ok(len);
+        }
+    }
+    bail!("Invalid ULEB128 length: overflow.")
+}
+
+/// Helper function to read a hash value.
+fn read_hash_value(reader: &mut Cursor<&[u8]>) -> Result<HashValue> {
+    let mut bytes = [0u8; HashValue::LENGTH];
+    reader.read_exact(&mut bytes)?;
+    Ok(HashValue::new(bytes))
+}
+
+/// Helper function to read a blob of `len` bytes, which must be the rest of the input.
+fn read_blob<'a>(reader: &mut Cursor<&'a [u8]>, len: u64) -> Result<&'a [u8]> {
+    let data = *reader.get_ref();
+    let pos = reader.position() as usize;
+    ensure!(
+        (data.len() - pos) as u64 == len,
+        "Blob length mismatch, expected: {}, remaining: {}",
+        len,
+        data.len() - pos
+    );
+    Ok(&data[pos..])
+}
+
+/// Helper function to hash a blob like
+/// [`AccountStateBlob::hash_with_scheme`](AccountStateBlob::hash_with_scheme) does with the
+/// default hash scheme, without copying it.
+fn hash_blob(blob: &[u8]) -> HashValue {
+    Blake3.hash_of(&[Blake3.seed(HashDomain::AccountStateBlob), blob])
+}
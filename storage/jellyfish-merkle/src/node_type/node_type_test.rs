@@ -3,7 +3,7 @@
 
 use super::*;
 use libra_crypto::{
-    hash::{CryptoHash, SPARSE_MERKLE_PLACEHOLDER_HASH},
+    hash::{Blake3, CryptoHash, Sha3_256, SPARSE_MERKLE_PLACEHOLDER_HASH},
     HashValue,
 };
 use libra_types::proof::{SparseMerkleInternalNode, SparseMerkleLeafNode};
@@ -82,6 +82,72 @@ fn test_encode_decode() {
     }
 }
 
+proptest! {
+    #[test]
+    fn test_path_major_roundtrip(node_key in any::<NodeKey>()) {
+        let encoded = node_key.encode_path_major().unwrap();
+        prop_assert_eq!(NodeKey::decode_path_major(&encoded).unwrap(), node_key);
+    }
+
+    #[test]
+    fn test_path_major_order(
+        node_key in any::<NodeKey>(),
+        other_version in any::<Version>(),
+        child_version in any::<Version>(),
+        nibble in (0..16u8).prop_map(Nibble::from),
+    ) {
+        let encoded = node_key.encode_path_major().unwrap();
+        prop_assert!(encoded.starts_with(&NodeKey::path_major_prefix(node_key.nibble_path())));
+
+        // Nodes at the same path are ordered from the newest.
+        let other = NodeKey::new(other_version, node_key.nibble_path().clone());
+        prop_assert_eq!(
+            other.encode_path_major().unwrap().cmp(&encoded),
+            node_key.version().cmp(&other_version)
+        );
+
+        // Nodes under a path share its prefix and are ordered before the nodes at it.
+        if node_key.nibble_path().num_nibbles() < ROOT_NIBBLE_HEIGHT {
+            let child = node_key.gen_child_node_key(child_version, nibble);
+            let child_encoded = child.encode_path_major().unwrap();
+            prop_assert!(
+                child_encoded.starts_with(&NodeKey::path_major_prefix(node_key.nibble_path()))
+            );
+            prop_assert!(child_encoded < encoded);
+        }
+    }
+}
+
+#[test]
+fn test_decode_path_major_invalid() {
+    // No terminator.
+    assert!(NodeKey::decode_path_major(&[1, 2, 3]).is_err());
+    // Nibble out of range.
+    assert!(
+        NodeKey::decode_path_major(&[17, PATH_MAJOR_TERMINATOR, 0, 0, 0, 0, 0, 0, 0, 0]).is_err()
+    );
+    // Missing or trailing bytes of the version.
+    assert!(NodeKey::decode_path_major(&[1, PATH_MAJOR_TERMINATOR, 0, 0, 0]).is_err());
+    assert!(
+        NodeKey::decode_path_major(&[1, PATH_MAJOR_TERMINATOR, 0, 0, 0, 0, 0, 0, 0, 0, 0]).is_err()
+    );
+    assert_eq!(
+        NodeKey::decode_path_major(&[
+            PATH_MAJOR_TERMINATOR,
+            0xff,
+            0xff,
+            0xff,
+            0xff,
+            0xff,
+            0xff,
+            0xff,
+            0xfe
+        ])
+        .unwrap(),
+        NodeKey::new_empty_path(1)
+    );
+}
+
 proptest! {
     #[test]
     fn test_u64_varint_roundtrip(input in any::<u64>()) {
@@ -98,6 +164,355 @@ proptest! {
     }
 }
 
+proptest! {
+    #[test]
+    fn test_counted_internal_node_roundtrip(
+        children in hash_map(any::<Nibble>(), (any::<Child>(), 2..1000u64), 2..=16),
+        mode in any::<LeafCountMode>(),
+    ) {
+        let children: Children = children
+            .into_iter()
+            .map(|(nibble, (child, leaf_count))| {
+                (
+                    nibble,
+                    Child::new_with_leaf_count(child.hash, child.version, child.is_leaf, leaf_count),
+                )
+            })
+            .collect();
+        let expected_leaf_count = children
+            .values()
+            .map(|child| if child.is_leaf { 1 } else { child.leaf_count.unwrap() })
+            .sum::<u64>();
+        let node = Node::Internal(InternalNode::new_with_leaf_count(children.clone(), mode));
+        prop_assert_eq!(node.leaf_count(), Some(expected_leaf_count));
+
+        let decoded = Node::decode(&node.encode().unwrap()).unwrap();
+        prop_assert_eq!(&decoded, &node);
+
+        // Only committed leaf counts change the hash.
+        let uncounted_hash = InternalNode::new(children).hash();
+        match mode {
+            LeafCountMode::Stored => prop_assert_eq!(node.hash(), uncounted_hash),
+            LeafCountMode::Committed => prop_assert_ne!(node.hash(), uncounted_hash),
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    fn test_leaf_roundtrip(
+        account_key in any::<HashValue>(),
+        blob in any::<AccountStateBlob>(),
+        use_default_scheme in any::<bool>(),
+        blob_hash_encoding in any::<BlobHashEncoding>(),
+    ) {
+        let leaf_node = if use_default_scheme {
+            LeafNode::new(account_key, blob)
+        } else {
+            LeafNode::new_with_scheme(account_key, blob, &Sha3_256)
+        };
+        let node = Node::Leaf(leaf_node.clone());
+        let encoded = node.encode_with_blob_hash_encoding(blob_hash_encoding).unwrap();
+        prop_assert_eq!(Node::decode(&encoded).unwrap(), node.clone());
+
+        // The legacy encoding is still readable, and is larger unless the blob hash is stored.
+        let legacy = encode_legacy_leaf(&leaf_node);
+        prop_assert_eq!(Node::decode(&legacy).unwrap(), node);
+        if use_default_scheme && blob_hash_encoding == BlobHashEncoding::Recomputed {
+            prop_assert!(encoded.len() < legacy.len());
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    fn test_internal_node_view(
+        children in hash_map(any::<Nibble>(), (any::<Child>(), 2..1000u64), 2..=16),
+        leaf_count_mode in proptest::option::of(any::<LeafCountMode>()),
+    ) {
+        let children: Children = children
+            .into_iter()
+            .map(|(nibble, (child, leaf_count))| {
+                (
+                    nibble,
+                    Child::new_with_leaf_count(child.hash, child.version, child.is_leaf, leaf_count),
+                )
+            })
+            .collect();
+        let internal_node = match leaf_count_mode {
+            Some(mode) => InternalNode::new_with_leaf_count(children, mode),
+            None => InternalNode::new(children),
+        };
+        let encoded = Node::Internal(internal_node.clone()).encode().unwrap();
+        let view = match NodeView::decode(&encoded).unwrap() {
+            NodeView::Internal(view) => view,
+            _ => panic!("Expected an internal node."),
+        };
+        prop_assert_eq!(view.leaf_count_mode(), leaf_count_mode);
+        prop_assert_eq!(view.num_children(), internal_node.num_children());
+        for i in 0..16u8 {
+            let n = Nibble::from(i);
+            prop_assert_eq!(view.child(n), internal_node.child(n).cloned());
+            let node_key = NodeKey::new_empty_path(0);
+            prop_assert_eq!(
+                view.get_child_with_counted_siblings(&node_key, n, &Blake3),
+                internal_node.get_child_with_counted_siblings(&node_key, n, &Blake3)
+            );
+        }
+        prop_assert_eq!(view.to_internal_node(), internal_node);
+    }
+
+    #[test]
+    fn test_leaf_node_view(
+        account_key in any::<HashValue>(),
+        blob in any::<AccountStateBlob>(),
+        blob_hash_encoding in any::<BlobHashEncoding>(),
+    ) {
+        let leaf_node = LeafNode::new(account_key, blob.clone());
+        let encoded = Node::Leaf(leaf_node.clone())
+            .encode_with_blob_hash_encoding(blob_hash_encoding)
+            .unwrap();
+        let legacy = encode_legacy_leaf(&leaf_node);
+        for encoded in &[encoded, legacy] {
+            let view = match NodeView::decode(encoded).unwrap() {
+                NodeView::Leaf(view) => view,
+                _ => panic!("Expected a leaf node."),
+            };
+            prop_assert_eq!(view.account_key(), account_key);
+            prop_assert_eq!(view.blob_hash(), leaf_node.blob_hash());
+            let view_blob = view.blob().unwrap();
+            prop_assert_eq!(view_blob, blob.as_ref());
+            // The blob is borrowed from the encoded leaf.
+            prop_assert!(encoded.ends_with(view_blob));
+            prop_assert_eq!(
+                view_blob.as_ptr() as usize + view_blob.len(),
+                encoded.as_ptr() as usize + encoded.len()
+            );
+            prop_assert_eq!(&view.to_leaf_node(), &leaf_node);
+        }
+    }
+}
+
+#[test]
+fn test_legacy_leaf_encoding() {
+    // Blobs of at least 128 bytes take more than one byte of ULEB128 length.
+    for len in &[0, 1, 127, 128, 300, 20_000] {
+        let leaf_node = LeafNode::new(HashValue::random(), AccountStateBlob::from(vec![3; *len]));
+        let legacy = encode_legacy_leaf(&leaf_node);
+        assert_eq!(Node::decode(&legacy).unwrap(), Node::Leaf(leaf_node));
+
+        let mut trailing = legacy.clone();
+        trailing.push(0);
+        assert!(Node::decode(&trailing).is_err());
+        assert!(Node::decode(&legacy[..legacy.len() - 1]).is_err());
+    }
+    // Lengths must be canonical.
+    let mut non_canonical = vec![2];
+    non_canonical.extend(&[0; 2 * HashValue::LENGTH]);
+    non_canonical.extend(&[0x81, 0x00, 0xaa]);
+    assert!(Node::decode(&non_canonical).is_err());
+}
+
+#[test]
+fn test_inline_leaves_encoding() {
+    let leaf_node = LeafNode::new(HashValue::random(), AccountStateBlob::from(vec![0x02; 10]));
+    let leaf_hash = leaf_node.hash();
+    let internal_hash = HashValue::random();
+    for leaf_count_mode in &[
+        None,
+        Some(LeafCountMode::Stored),
+        Some(LeafCountMode::Committed),
+    ] {
+        let mut children = Children::new();
+        children.insert(
+            Nibble::from(1),
+            Child::new_with_inline_leaf(leaf_hash, 0, leaf_node.clone()),
+        );
+        children.insert(
+            Nibble::from(2),
+            Child::new(leaf_hash, 1, true /* is_leaf */),
+        );
+        children.insert(
+            Nibble::from(3),
+            Child::new_with_leaf_count(internal_hash, 2, false /* is_leaf */, 5),
+        );
+        let new_internal_node = |children: Children| match leaf_count_mode {
+            Some(mode) => InternalNode::new_with_leaf_count(children, *mode),
+            None => InternalNode::new(children),
+        };
+        let internal_node = new_internal_node(children.clone());
+        assert!(internal_node.has_inline_leaves());
+        assert_eq!(internal_node.inline_leaf(Nibble::from(1)), Some(&leaf_node));
+        assert_eq!(internal_node.inline_leaf(Nibble::from(2)), None);
+
+        // Inline leaves do not change the hash.
+        let mut plain_children = children;
+        plain_children
+            .get_mut(&Nibble::from(1))
+            .unwrap()
+            .inline_leaf = None;
+        let plain_internal_node = new_internal_node(plain_children);
+        assert!(!plain_internal_node.has_inline_leaves());
+        assert_eq!(internal_node.hash(), plain_internal_node.hash());
+
+        let encoded = Node::Internal(internal_node.clone()).encode().unwrap();
+        let plain_encoded = Node::Internal(plain_internal_node).encode().unwrap();
+        assert_eq!(encoded[0], 6);
+        assert_eq!(&encoded[2..plain_encoded.len() + 1], &plain_encoded[1..]);
+        assert_eq!(
+            Node::decode(&encoded).unwrap(),
+            Node::Internal(internal_node.clone())
+        );
+        let view = match NodeView::decode(&encoded).unwrap() {
+            NodeView::Internal(view) => view,
+            _ => panic!("Expected an internal node."),
+        };
+        assert_eq!(view.leaf_count_mode(), *leaf_count_mode);
+        assert_eq!(
+            view.inline_leaf(Nibble::from(1)).unwrap().to_leaf_node(),
+            leaf_node
+        );
+        assert!(view.inline_leaf(Nibble::from(2)).is_none());
+        assert!(view.inline_leaf(Nibble::from(3)).is_none());
+        // Inline leaves are only copied when asked for.
+        assert!(view.child(Nibble::from(1)).unwrap().inline_leaf.is_none());
+        assert!(!view.to_internal_node().has_inline_leaves());
+        assert_eq!(view.to_internal_node_with_inline_leaves(), internal_node);
+
+        // Error cases
+        let mut trailing = encoded.clone();
+        trailing.push(0);
+        assert!(Node::decode(&trailing).is_err());
+        assert!(Node::decode(&encoded[..encoded.len() - 1]).is_err());
+        let mut unknown_mode = encoded.clone();
+        unknown_mode[1] = 3;
+        assert_eq!(
+            Node::decode(&unknown_mode)
+                .unwrap_err()
+                .downcast::<NodeDecodeError>()
+                .unwrap(),
+            NodeDecodeError::UnknownLeafCountMode { mode: 3 }
+        );
+        // Only leaf children can be inlined.
+        let mut non_leaf = plain_encoded[1..].to_vec();
+        non_leaf.insert(0, encoded[1]);
+        non_leaf.insert(0, 6);
+        non_leaf.extend(&[0x08, 0x00]);
+        assert_eq!(
+            Node::decode(&non_leaf)
+                .unwrap_err()
+                .downcast::<NodeDecodeError>()
+                .unwrap(),
+            NodeDecodeError::ExtraInlineLeaves {
+                leaves: 0x06,
+                inline_leaves: 0x08,
+            }
+        );
+    }
+}
+
+/// Encodes `leaf_node` the way leaves used to be, with LCS.
+fn encode_legacy_leaf(leaf_node: &LeafNode) -> Vec<u8> {
+    let mut legacy = vec![2];
+    legacy.extend(
+        lcs::to_bytes(&(
+            leaf_node.account_key(),
+            leaf_node.blob_hash(),
+            leaf_node.blob().expect("Legacy leaves have blobs."),
+        ))
+        .unwrap(),
+    );
+    legacy
+}
+
+#[test]
+fn test_hash_only_leaf_encoding() {
+    let account_key = HashValue::random();
+    let blob = AccountStateBlob::from(vec![0x02; 200]);
+    let full_leaf = LeafNode::new(account_key, blob.clone());
+    let leaf_node = LeafNode::new_hash_only(account_key, full_leaf.blob_hash());
+    assert!(leaf_node.is_hash_only());
+    assert!(!full_leaf.is_hash_only());
+    assert_eq!(leaf_node.blob(), None);
+    // A hash-only leaf hashes the same as the full leaf.
+    assert_eq!(leaf_node.hash(), full_leaf.hash());
+
+    // Tag, format version, flags, account key and blob hash, and no blob.
+    let encoded = Node::Leaf(leaf_node.clone()).encode().unwrap();
+    assert_eq!(encoded.len(), 3 + 2 * HashValue::LENGTH);
+    assert_eq!(&encoded[..3], &[5, 0, 3]);
+    assert_eq!(
+        Node::decode(&encoded).unwrap(),
+        Node::Leaf(leaf_node.clone())
+    );
+    let view = match NodeView::decode(&encoded).unwrap() {
+        NodeView::Leaf(view) => view,
+        _ => panic!("Expected a leaf node."),
+    };
+    assert_eq!(view.blob(), None);
+    assert_eq!(view.blob_hash(), full_leaf.blob_hash());
+    assert_eq!(view.to_leaf_node(), leaf_node);
+
+    // Error cases
+    let mut missing_hash = vec![5, 0, 2];
+    missing_hash.extend(account_key.as_ref());
+    assert!(Node::decode(&missing_hash).is_err());
+    let mut trailing = encoded;
+    trailing.push(0);
+    assert!(Node::decode(&trailing).is_err());
+}
+
+#[test]
+fn test_leaf_encoding() {
+    let account_key = HashValue::random();
+    let blob = AccountStateBlob::from(vec![0x02; 200]);
+    let leaf_node = LeafNode::new(account_key, blob.clone());
+    let encoded = Node::Leaf(leaf_node.clone())
+        .encode_with_blob_hash_encoding(BlobHashEncoding::Recomputed)
+        .unwrap();
+    // Tag, format version, flags, account key, two bytes of blob length and the blob.
+    assert_eq!(encoded.len(), 3 + HashValue::LENGTH + 2 + 200);
+    assert_eq!(&encoded[..3], &[5, 0, 0]);
+    // The blob hash is stored by default.
+    let stored = Node::Leaf(leaf_node).encode().unwrap();
+    assert_eq!(stored.len(), encoded.len() + HashValue::LENGTH);
+    assert_eq!(&stored[..3], &[5, 0, 1]);
+    // The blob hash of another hash scheme is always stored.
+    let leaf_node = LeafNode::new_with_scheme(account_key, blob, &Sha3_256);
+    assert_eq!(
+        Node::Leaf(leaf_node)
+            .encode_with_blob_hash_encoding(BlobHashEncoding::Recomputed)
+            .unwrap()
+            .len(),
+        encoded.len() + HashValue::LENGTH
+    );
+
+    // Error cases
+    let mut unknown_format = encoded.clone();
+    unknown_format[1] = 1;
+    assert_eq!(
+        Node::decode(&unknown_format)
+            .unwrap_err()
+            .downcast::<NodeDecodeError>()
+            .unwrap(),
+        NodeDecodeError::UnknownLeafFormat { format_version: 1 }
+    );
+    let mut unknown_flags = encoded.clone();
+    unknown_flags[2] = 0x80;
+    assert_eq!(
+        Node::decode(&unknown_flags)
+            .unwrap_err()
+            .downcast::<NodeDecodeError>()
+            .unwrap(),
+        NodeDecodeError::UnknownLeafFlags { flags: 0x80 }
+    );
+    let mut trailing = encoded.clone();
+    trailing.push(0);
+    assert!(Node::decode(&trailing).is_err());
+    assert!(Node::decode(&encoded[..encoded.len() - 1]).is_err());
+    assert!(Node::decode(&encoded[..20]).is_err());
+}
+
 #[test]
 fn test_internal_validity() {
     let result = panic::catch_unwind(|| {
@@ -160,13 +575,13 @@ proptest! {
 
         for i in 0..8 {
             prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings(&internal_node_key, i.into(), &Blake3),
                 (Some(leaf1_node_key.clone()), vec![hash2])
             );
         }
         for i in 8..16 {
             prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings(&internal_node_key, i.into(), &Blake3),
                 (Some(leaf2_node_key.clone()), vec![hash1])
             );
         }
@@ -207,14 +622,14 @@ proptest! {
 
         for i in 0..4 {
             prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings(&internal_node_key, i.into(), &Blake3),
                 (None, vec![*SPARSE_MERKLE_PLACEHOLDER_HASH, hash_x1])
             );
         }
 
         for i in 4..6 {
             prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings(&internal_node_key, i.into(), &Blake3),
                 (
                     Some(leaf1_node_key.clone()),
                     vec![
@@ -228,7 +643,7 @@ proptest! {
 
         for i in 6..8 {
             prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings(&internal_node_key, i.into(), &Blake3),
                 (
                     Some(leaf2_node_key.clone()),
                     vec![
@@ -242,7 +657,7 @@ proptest! {
 
         for i in 8..16 {
             prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings(&internal_node_key, i.into(), &Blake3),
                 (None, vec![hash_x2])
             );
         }
@@ -281,21 +696,21 @@ proptest! {
 
         for i in 0..4 {
             prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings(&internal_node_key, i.into(), &Blake3),
                 (Some(leaf1_node_key.clone()),vec![hash3, hash2])
             );
         }
 
         for i in 4..8 {
             prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings(&internal_node_key, i.into(), &Blake3),
                 (Some(leaf2_node_key.clone()),vec![hash3, hash1])
             );
         }
 
         for i in 8..16 {
             prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings(&internal_node_key, i.into(), &Blake3),
                 (Some(leaf3_node_key.clone()),vec![hash_x])
             );
         }
@@ -346,7 +761,7 @@ proptest! {
 
         for i in 0..2 {
             prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings(&internal_node_key, i.into(), &Blake3),
                 (
                     Some(leaf1_node_key.clone()),
                     vec![hash4, hash_x4, hash_x1]
@@ -355,7 +770,7 @@ proptest! {
         }
 
         prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, 2.into()),
+                internal_node.get_child_with_siblings(&internal_node_key, 2.into(), &Blake3),
             (
                 Some(internal2_node_key),
                 vec![
@@ -368,7 +783,7 @@ proptest! {
         );
 
         prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, 3.into()),
+                internal_node.get_child_with_siblings(&internal_node_key, 3.into(), &Blake3),
 
             (
                 None,
@@ -378,7 +793,7 @@ proptest! {
 
         for i in 4..6 {
             prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings(&internal_node_key, i.into(), &Blake3),
                 (
                     None,
                     vec![hash4, hash_x2, hash_x3]
@@ -387,7 +802,7 @@ proptest! {
         }
 
         prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, 6.into()),
+                internal_node.get_child_with_siblings(&internal_node_key, 6.into(), &Blake3),
             (
                 None,
                 vec![
@@ -400,7 +815,7 @@ proptest! {
         );
 
         prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, 7.into()),
+                internal_node.get_child_with_siblings(&internal_node_key, 7.into(), &Blake3),
             (
                 Some(internal3_node_key),
                 vec![
@@ -414,7 +829,7 @@ proptest! {
 
         for i in 8..16 {
             prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings(&internal_node_key, i.into(), &Blake3),
                 (Some(leaf4_node_key.clone()), vec![hash_x5])
             );
         }
@@ -474,13 +889,13 @@ fn test_internal_hash_and_proof() {
 
         for i in 0..4 {
             assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings(&internal_node_key, i.into(), &Blake3),
                 (None, vec![hash_x6, hash_x2])
             );
         }
 
         assert_eq!(
-            internal_node.get_child_with_siblings(&internal_node_key, index1),
+            internal_node.get_child_with_siblings(&internal_node_key, index1, &Blake3),
             (
                 Some(child1_node_key),
                 vec![
@@ -493,7 +908,7 @@ fn test_internal_hash_and_proof() {
         );
 
         assert_eq!(
-            internal_node.get_child_with_siblings(&internal_node_key, 5.into()),
+            internal_node.get_child_with_siblings(&internal_node_key, 5.into(), &Blake3),
             (
                 None,
                 vec![
@@ -506,7 +921,7 @@ fn test_internal_hash_and_proof() {
         );
         for i in 6..8 {
             assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings(&internal_node_key, i.into(), &Blake3),
                 (
                     None,
                     vec![hash_x6, *SPARSE_MERKLE_PLACEHOLDER_HASH, hash_x1]
@@ -516,14 +931,14 @@ fn test_internal_hash_and_proof() {
 
         for i in 8..12 {
             assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings(&internal_node_key, i.into(), &Blake3),
                 (None, vec![hash_x3, hash_x5])
             );
         }
 
         for i in 12..14 {
             assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings(&internal_node_key, i.into(), &Blake3),
                 (
                     None,
                     vec![hash_x3, *SPARSE_MERKLE_PLACEHOLDER_HASH, hash_x4]
@@ -531,7 +946,7 @@ fn test_internal_hash_and_proof() {
             );
         }
         assert_eq!(
-            internal_node.get_child_with_siblings(&internal_node_key, 14.into()),
+            internal_node.get_child_with_siblings(&internal_node_key, 14.into(), &Blake3),
             (
                 None,
                 vec![
@@ -543,7 +958,7 @@ fn test_internal_hash_and_proof() {
             )
         );
         assert_eq!(
-            internal_node.get_child_with_siblings(&internal_node_key, index2),
+            internal_node.get_child_with_siblings(&internal_node_key, index2, &Blake3),
             (
                 Some(child2_node_key),
                 vec![
@@ -606,7 +1021,7 @@ fn test_internal_hash_and_proof() {
         assert_eq!(internal_node.hash(), root_hash);
 
         assert_eq!(
-            internal_node.get_child_with_siblings(&internal_node_key, 0.into()),
+            internal_node.get_child_with_siblings(&internal_node_key, 0.into(), &Blake3),
             (
                 Some(child1_node_key),
                 vec![
@@ -619,7 +1034,7 @@ fn test_internal_hash_and_proof() {
         );
 
         assert_eq!(
-            internal_node.get_child_with_siblings(&internal_node_key, 1.into()),
+            internal_node.get_child_with_siblings(&internal_node_key, 1.into(), &Blake3),
             (
                 None,
                 vec![
@@ -633,7 +1048,7 @@ fn test_internal_hash_and_proof() {
 
         for i in 2..4 {
             assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings(&internal_node_key, i.into(), &Blake3),
                 (
                     None,
                     vec![*SPARSE_MERKLE_PLACEHOLDER_HASH, hash_x4, hash_x1]
@@ -643,7 +1058,7 @@ fn test_internal_hash_and_proof() {
 
         for i in 4..6 {
             assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings(&internal_node_key, i.into(), &Blake3),
                 (
                     None,
                     vec![*SPARSE_MERKLE_PLACEHOLDER_HASH, hash_x2, hash_x3]
@@ -652,7 +1067,7 @@ fn test_internal_hash_and_proof() {
         }
 
         assert_eq!(
-            internal_node.get_child_with_siblings(&internal_node_key, 6.into()),
+            internal_node.get_child_with_siblings(&internal_node_key, 6.into(), &Blake3),
             (
                 None,
                 vec![
@@ -665,7 +1080,7 @@ fn test_internal_hash_and_proof() {
         );
 
         assert_eq!(
-            internal_node.get_child_with_siblings(&internal_node_key, 7.into()),
+            internal_node.get_child_with_siblings(&internal_node_key, 7.into(), &Blake3),
             (
                 Some(child2_node_key),
                 vec![
@@ -679,7 +1094,7 @@ fn test_internal_hash_and_proof() {
 
         for i in 8..16 {
             assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings(&internal_node_key, i.into(), &Blake3),
                 (None, vec![hash_x5])
             );
         }
@@ -855,7 +1270,7 @@ proptest! {
     ) {
         for n in 0..16u8 {
             prop_assert_eq!(
-                node.get_child_with_siblings(&node_key, n.into()),
+                node.get_child_with_siblings(&node_key, n.into(), &Blake3),
                 NaiveInternalNode::from_clever_node(&node).get_child_with_siblings(&node_key, n)
             )
         }
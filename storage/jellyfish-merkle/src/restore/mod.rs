@@ -2,21 +2,34 @@
 // SPDX-License-Identifier: Apache-2.0
 
 //! This module implements the functionality to restore a `JellyfishMerkleTree` from small chunks
-//! of accounts.
+//! of accounts. The progress of the restoration is persisted along with the nodes written by each
+//! chunk, so an interrupted restoration can resume from where it stopped.
+//!
+//! Accounts must arrive in increasing order of keys, which serializes the whole restoration onto
+//! one stream. To restore a large tree in parallel, the key space can be split into shards by
+//! the first one or two nibbles of the keys. Each shard is restored independently by
+//! [`JellyfishMerkleRestore::new_shard`] and verified against a [`SubtreeProof`] from the target
+//! root, then [`stitch_shards`] builds the nodes above the shards and writes the root.
+//!
+//! [`JellyfishMerkleRestore::new_shard`]: struct.JellyfishMerkleRestore.html#method.new_shard
+//! [`SubtreeProof`]: ../subtree/struct.SubtreeProof.html
+//! [`stitch_shards`]: fn.stitch_shards.html
 
 #[cfg(test)]
 mod restore_test;
 
 use crate::{
-    nibble_path::{NibbleIterator, NibblePath},
+    builder::{ChildInfo, InternalInfo, PartialTree},
+    nibble_path::NibblePath,
     node_type::{
         get_child_and_sibling_half_start, Child, Children, InternalNode, LeafNode, Node, NodeKey,
     },
-    NodeBatch, TreeReader, TreeWriter, ROOT_NIBBLE_HEIGHT,
+    subtree::SubtreeProof,
+    NodeBatch, TreeReader, ROOT_NIBBLE_HEIGHT,
 };
 use anyhow::{bail, ensure, format_err, Result};
 use libra_crypto::{
-    hash::{CryptoHash, SPARSE_MERKLE_PLACEHOLDER_HASH},
+    hash::{Blake3, HashScheme},
     HashValue,
 };
 use libra_nibble::Nibble;
@@ -25,126 +38,63 @@ use libra_types::{
     proof::{SparseMerkleInternalNode, SparseMerkleRangeProof},
     transaction::Version,
 };
-use mirai_annotations::*;
-
-#[derive(Clone, Debug, Eq, PartialEq)]
-enum ChildInfo {
-    /// This child is an internal node. The hash of the internal node is stored here if it is
-    /// known, otherwise it is `None`. In the process of restoring a tree, we will only know the
-    /// hash of an internal node after we see all the keys that share the same prefix.
-    Internal { hash: Option<HashValue> },
-
-    /// This child is a leaf node.
-    Leaf { node: LeafNode },
-}
-
-impl ChildInfo {
-    /// Converts `self` to a child, assuming the hash is known if it's an internal node.
-    fn into_child(self, version: Version) -> Child {
-        match self {
-            Self::Internal { hash } => {
-                Child::new(
-                    hash.expect("Must have been initialized."),
-                    version,
-                    false, /* is_leaf */
-                )
-            }
-            Self::Leaf { node } => {
-                Child::new(node.hash(), version, true /* is_leaf */)
-            }
-        }
-    }
+#[cfg(any(test, feature = "fuzzing"))]
+use proptest_derive::Arbitrary;
+use serde::{Deserialize, Serialize};
+
+/// The progress of restoring the tree, or a shard of it, at some version. It covers the accounts
+/// whose leaves have been written to storage, which are all the accounts received so far except
+/// the last one, whose position in the tree depends on the next account.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
+pub struct RestoreProgress {
+    /// The version of the tree being restored.
+    pub version: Version,
+    /// The largest key written to storage. The restoration continues with the keys after it.
+    pub last_key: HashValue,
+    /// The number of keys written to storage.
+    pub num_keys: u64,
 }
 
-#[derive(Clone, Debug)]
-struct InternalInfo {
-    /// The node key of this internal node.
-    node_key: NodeKey,
-
-    /// The existing children. Every time a child appears, the corresponding position will be set
-    /// to `Some`.
-    children: [Option<ChildInfo>; 16],
-}
-
-impl InternalInfo {
-    /// Creates an empty internal node with no children.
-    fn new_empty(node_key: NodeKey) -> Self {
-        Self {
-            node_key,
-            children: Default::default(),
-        }
-    }
-
-    fn set_child(&mut self, index: usize, child_info: ChildInfo) {
-        precondition!(index < 16);
-        self.children[index] = Some(child_info);
-    }
-
-    /// Converts `self` to an internal node, assuming all of its children are already known and
-    /// fully initialized.
-    fn into_internal_node(mut self, version: Version) -> (NodeKey, InternalNode) {
-        let mut children = Children::new();
-
-        // Calling `into_iter` on an array is equivalent to calling `iter`:
-        // https://github.com/rust-lang/rust/issues/25725. So we use `iter_mut` and `take`.
-        for (index, child_info_option) in self.children.iter_mut().enumerate() {
-            if let Some(child_info) = child_info_option.take() {
-                children.insert((index as u8).into(), child_info.into_child(version));
-            }
-        }
-
-        (self.node_key, InternalNode::new(children))
-    }
+/// `RestoreStore` defines the interface between
+/// [`JellyfishMerkleRestore`](struct.JellyfishMerkleRestore.html) and the underlying storage,
+/// which keeps the progress of the restoration along with the nodes. The progress is identified
+/// by the node key of the root of what is being restored: the root of the tree, or the root of a
+/// shard when shards are restored in parallel.
+pub trait RestoreStore: TreeReader {
+    /// Gets the progress of restoring the subtree at `root_node_key`, or `None` if nothing has
+    /// been written.
+    fn get_restore_progress(&self, root_node_key: &NodeKey) -> Result<Option<RestoreProgress>>;
+
+    /// Writes a node batch and the progress of restoring the subtree at `root_node_key`
+    /// atomically. If `progress` is `None`, the progress is deleted instead, which happens when
+    /// the restoration finishes.
+    fn write_restore_batch(
+        &self,
+        root_node_key: &NodeKey,
+        node_batch: &NodeBatch,
+        progress: Option<&RestoreProgress>,
+    ) -> Result<()>;
+
+    /// Deletes the nodes and the progress of restoring the subtree at `root_node_key`
+    /// atomically.
+    fn delete_restore_batch(&self, root_node_key: &NodeKey, node_keys: &[NodeKey]) -> Result<()>;
 }
 
-pub struct JellyfishMerkleRestore<'a, S> {
+pub struct JellyfishMerkleRestore<'a, S, H = Blake3> {
     /// The underlying storage.
     store: &'a S,
 
-    /// The version of the tree we are restoring.
-    version: Version,
-
-    /// The nodes we have partially restored. Each `partial_nodes[i-1]` is the parent of
-    /// `partial_nodes[i]`. If a node `partial_nodes[i-1]` has multiple children, only the
-    /// rightmost known child will appear here as `partial_nodes[i]`, because any other children on
-    /// the left would have been frozen.
-    ///
-    /// At any point in time, the structure looks like the following:
-    ///
-    /// ```text
-    /// +----+----+----+----+----+----+----+----+
-    /// |    |    |    |    |    |    |    | C  |  partial_nodes[0]
-    /// +----+----+----+----+----+----+----+----+
-    ///   |         |              |
-    ///   |         |              |
-    ///   |         |              |
-    ///   v         v              v
-    /// Frozen    Frozen     +----+----+----+----+----+----+----+----+
-    ///                      |    |    |    | B  |    |    | A  |    |  partial_nodes[1]
-    ///                      +----+----+----+----+----+----+----+----+
-    ///                             |         |
-    ///                             |         |
-    ///                             |         |
-    ///                             v         v
-    ///                            Frozen    Previously inserted account
-    /// ```
-    ///
-    /// We insert the accounts from left to right. So if the next account appears at position `A`,
-    /// it will cause the leaf at position `B` to be frozen. If it appears at position `B`, it
-    /// might cause a few internal nodes to be created additionally. If it appears at position `C`,
-    /// it will also cause `partial_nodes[1]` to be added to `frozen_nodes` as an internal node and
-    /// be removed from `partial_nodes`.
-    partial_nodes: Vec<InternalInfo>,
-
-    /// The nodes that have been fully restored and are ready to be written to storage.
-    frozen_nodes: NodeBatch,
+    /// The proof that links the root of the shard to `expected_root_hash`, which has no siblings
+    /// if we are restoring the whole tree.
+    subtree_proof: SubtreeProof,
 
-    /// The most recently added leaf. This is used to ensure the keys come in increasing order and
-    /// do proof verification.
-    previous_leaf: Option<LeafNode>,
+    /// The tree we have partially restored. Its frozen nodes are written to storage after each
+    /// chunk is verified.
+    tree: PartialTree<H>,
 
-    /// The number of keys we have received since the most recent restart.
-    num_keys_received: u64,
+    /// The progress as of the frozen nodes of `tree`, which is persisted along with them.
+    progress: Option<RestoreProgress>,
 
     /// When the restoration process finishes, we expect the tree to have this root hash.
     expected_root_hash: HashValue,
@@ -152,48 +102,184 @@ pub struct JellyfishMerkleRestore<'a, S> {
 
 impl<'a, S> JellyfishMerkleRestore<'a, S>
 where
-    S: 'a + TreeReader + TreeWriter,
+    S: 'a + RestoreStore,
 {
+    /// Starts restoring the tree at `version`, or resumes if the store has the progress of an
+    /// interrupted attempt. In the latter case, the restoration continues with the keys after
+    /// [`progress`](struct.JellyfishMerkleRestore.html#method.progress).
     pub fn new(store: &'a S, version: Version, expected_root_hash: HashValue) -> Result<Self> {
-        let (partial_nodes, previous_leaf) = match store.get_rightmost_leaf()? {
-            Some((node_key, leaf_node)) => {
+        Self::new_with_scheme(store, version, expected_root_hash, Blake3)
+    }
+
+    /// Starts or resumes restoring the shard of the tree at `version` with all the keys under
+    /// `prefix`. `subtree_proof` links the root of the shard to `expected_root_hash`, as returned
+    /// by [`get_subtree_root_with_proof`] on the source tree. Every chunk and the shard itself are
+    /// verified against `expected_root_hash`, so shards can be restored in any order and
+    /// concurrently, then put together by [`stitch_shards`].
+    ///
+    /// [`get_subtree_root_with_proof`]:
+    /// ../struct.JellyfishMerkleTree.html#method.get_subtree_root_with_proof
+    /// [`stitch_shards`]: fn.stitch_shards.html
+    pub fn new_shard(
+        store: &'a S,
+        version: Version,
+        expected_root_hash: HashValue,
+        prefix: NibblePath,
+        subtree_proof: SubtreeProof,
+    ) -> Result<Self> {
+        Self::new_shard_with_scheme(
+            store,
+            version,
+            expected_root_hash,
+            prefix,
+            subtree_proof,
+            Blake3,
+        )
+    }
+}
+
+impl<'a, S, H> JellyfishMerkleRestore<'a, S, H>
+where
+    S: 'a + RestoreStore,
+    H: HashScheme,
+{
+    /// Same as [`new`](struct.JellyfishMerkleRestore.html#method.new), for a tree computed with
+    /// `hash_scheme`.
+    pub fn new_with_scheme(
+        store: &'a S,
+        version: Version,
+        expected_root_hash: HashValue,
+        hash_scheme: H,
+    ) -> Result<Self> {
+        Self::new_impl(
+            store,
+            NodeKey::new_empty_path(version),
+            expected_root_hash,
+            SubtreeProof::new(None, vec![]),
+            hash_scheme,
+        )
+    }
+
+    /// Same as [`new_shard`](struct.JellyfishMerkleRestore.html#method.new_shard), for a tree
+    /// computed with `hash_scheme`.
+    pub fn new_shard_with_scheme(
+        store: &'a S,
+        version: Version,
+        expected_root_hash: HashValue,
+        prefix: NibblePath,
+        subtree_proof: SubtreeProof,
+        hash_scheme: H,
+    ) -> Result<Self> {
+        ensure!(
+            prefix.num_nibbles() < ROOT_NIBBLE_HEIGHT,
+            "Shard prefix {:?} is too long.",
+            prefix,
+        );
+        Self::new_impl(
+            store,
+            NodeKey::new(version, prefix),
+            expected_root_hash,
+            subtree_proof,
+            hash_scheme,
+        )
+    }
+
+    fn new_impl(
+        store: &'a S,
+        root_node_key: NodeKey,
+        expected_root_hash: HashValue,
+        subtree_proof: SubtreeProof,
+        hash_scheme: H,
+    ) -> Result<Self> {
+        let version = root_node_key.version();
+        let progress = store.get_restore_progress(&root_node_key)?;
+        let tree = match &progress {
+            Some(progress) => {
+                ensure!(
+                    progress.version == version,
+                    "Restore progress is for version {}, expected {}.",
+                    progress.version,
+                    version,
+                );
                 // If the system crashed in the middle of the previous restoration attempt, we need
                 // to recover the partial nodes to the state right before the crash.
-                (
-                    Self::recover_partial_nodes(store, version, node_key)?,
-                    Some(leaf_node),
+                let leaf = Self::find_leaf(store, &root_node_key, progress.last_key)?;
+                let (node_key, leaf_node) = leaf.ok_or_else(|| {
+                    format_err!(
+                        "Leaf of the last restored key {:x} is missing.",
+                        progress.last_key
+                    )
+                })?;
+                let partial_nodes =
+                    Self::recover_partial_nodes(store, &root_node_key, node_key, &hash_scheme)?;
+                PartialTree::new_resumed(
+                    root_node_key,
+                    partial_nodes,
+                    leaf_node,
+                    progress.num_keys,
+                    hash_scheme,
                 )
             }
             None => {
-                // If no rightmost leaf exists, it means this is the first time we start and
-                // storage is still empty. We use a single root node in this case.
-                (
-                    vec![InternalInfo::new_empty(NodeKey::new_empty_path(version))],
-                    None,
-                )
+                // If there is no progress, it means this is the first time we start and storage
+                // is still empty. We use a single root node in this case.
+                PartialTree::new(root_node_key, hash_scheme)
             }
         };
 
         Ok(Self {
             store,
-            version,
-            partial_nodes,
-            frozen_nodes: NodeBatch::new(),
-            previous_leaf,
-            num_keys_received: 0,
+            subtree_proof,
+            tree,
+            progress,
             expected_root_hash,
         })
     }
 
+    /// Returns the progress of the restoration, or `None` if nothing has been written to storage.
+    /// If the restoration is interrupted, it resumes from here.
+    pub fn progress(&self) -> Option<&RestoreProgress> {
+        self.progress.as_ref()
+    }
+
+    /// Finds the leaf of `key` written in storage. Since the partial nodes are not written until
+    /// they are frozen, we try every prefix of `key` below `root_node_key`.
+    fn find_leaf(
+        store: &'a S,
+        root_node_key: &NodeKey,
+        key: HashValue,
+    ) -> Result<Option<(NodeKey, LeafNode)>> {
+        let nibble_path = NibblePath::new(key.to_vec());
+        let mut nibbles = nibble_path.nibbles();
+        let num_prefix_nibbles = root_node_key.nibble_path().num_nibbles();
+        for depth in 0..ROOT_NIBBLE_HEIGHT {
+            nibbles.next();
+            if depth < num_prefix_nibbles {
+                continue;
+            }
+            let node_key =
+                NodeKey::new(root_node_key.version(), nibbles.visited_nibbles().collect());
+            if let Some(Node::Leaf(leaf_node)) = store.get_node_option(&node_key)? {
+                if leaf_node.account_key() == key {
+                    return Ok(Some((node_key, leaf_node)));
+                }
+            }
+        }
+        Ok(None)
+    }
+
     /// Recovers partial nodes from storage. We do this by looking at all the ancestors of the
     /// rightmost leaf. The ones do not exist in storage are the partial nodes.
     fn recover_partial_nodes(
         store: &'a S,
-        version: Version,
+        root_node_key: &NodeKey,
         rightmost_leaf_node_key: NodeKey,
+        hash_scheme: &H,
     ) -> Result<Vec<InternalInfo>> {
+        let version = root_node_key.version();
+        let num_prefix_nibbles = root_node_key.nibble_path().num_nibbles();
         ensure!(
-            rightmost_leaf_node_key.nibble_path().num_nibbles() > 0,
+            rightmost_leaf_node_key.nibble_path().num_nibbles() > num_prefix_nibbles,
             "Root node would not be written until entire restoration process has completed \
              successfully.",
         );
@@ -223,7 +309,7 @@ where
                 if let Some(node) = store.get_node_option(&child_node_key)? {
                     let child_info = match node {
                         Node::Internal(internal_node) => ChildInfo::Internal {
-                            hash: Some(internal_node.hash()),
+                            hash: Some(internal_node.hash_with_scheme(hash_scheme)),
                         },
                         Node::Leaf(leaf_node) => ChildInfo::Leaf { node: leaf_node },
                         Node::Null => bail!("Null node should not appear in storage."),
@@ -241,7 +327,7 @@ where
             }
 
             partial_nodes.push(internal_info);
-            if node_key.nibble_path().num_nibbles() == 0 {
+            if node_key.nibble_path().num_nibbles() == num_prefix_nibbles {
                 break;
             }
             previous_child_index = node_key.nibble_path().last().map(|x| u8::from(x) as usize);
@@ -263,219 +349,32 @@ where
         ensure!(!chunk.is_empty(), "Should not add empty chunks.");
 
         for (key, value) in chunk {
-            if let Some(ref prev_leaf) = self.previous_leaf {
-                ensure!(
-                    key > prev_leaf.account_key(),
-                    "Account keys must come in increasing order.",
-                )
-            }
-            self.add_one(key, value.clone());
-            self.previous_leaf.replace(LeafNode::new(key, value));
-            self.num_keys_received += 1;
+            self.tree.add(key, value)?;
         }
 
         // Verify what we have added so far is all correct.
         self.verify(proof)?;
 
-        // Write the frozen nodes to storage.
-        self.store.write_node_batch(&self.frozen_nodes)?;
-        self.frozen_nodes.clear();
-
-        Ok(())
-    }
-
-    /// Restores one account.
-    fn add_one(&mut self, new_key: HashValue, new_value: AccountStateBlob) {
-        let nibble_path = NibblePath::new(new_key.to_vec());
-        let mut nibbles = nibble_path.nibbles();
-
-        for i in 0..ROOT_NIBBLE_HEIGHT {
-            let child_index = u8::from(nibbles.next().expect("This nibble must exist.")) as usize;
-
-            match self.partial_nodes[i].children[child_index] {
-                Some(ref child_info) => {
-                    // If there exists an internal node at this position, we just continue the loop
-                    // with the next nibble. Here we deal with the leaf case.
-                    if let ChildInfo::Leaf { node } = child_info {
-                        assert_eq!(
-                            i,
-                            self.partial_nodes.len() - 1,
-                            "If we see a leaf, there will be no more partial internal nodes on \
-                             lower level, since they would have been frozen.",
-                        );
-
-                        let existing_leaf = node.clone();
-                        self.insert_at_leaf(
-                            child_index,
-                            existing_leaf,
-                            new_key,
-                            new_value,
-                            nibbles,
-                        );
-                        break;
-                    }
-                }
-                None => {
-                    // This means that we are going to put a leaf in this position. For all the
-                    // descendants on the left, they are now frozen.
-                    self.freeze(i + 1);
-
-                    // Mark this position as a leaf child.
-                    self.partial_nodes[i].set_child(
-                        child_index,
-                        ChildInfo::Leaf {
-                            node: LeafNode::new(new_key, new_value),
-                        },
-                    );
-
-                    // We do not add this leaf node to self.frozen_nodes because we don't know its
-                    // node key yet. We will know its node key when the next account comes.
-                    break;
-                }
-            }
+        // Write the frozen nodes to storage, along with the progress they make.
+        if !self.tree.frozen_nodes.is_empty() {
+            let last_key = self
+                .tree
+                .last_frozen_key()
+                .expect("A leaf must have been frozen.");
+            self.progress = Some(RestoreProgress {
+                version: self.tree.version(),
+                last_key,
+                num_keys: self.tree.num_frozen_leaves(),
+            });
+            self.store.write_restore_batch(
+                self.tree.root_node_key(),
+                &self.tree.frozen_nodes,
+                self.progress.as_ref(),
+            )?;
+            self.tree.frozen_nodes.clear();
         }
-    }
 
-    /// Inserts a new account at the position of the existing leaf node. We may need to create
-    /// multiple internal nodes depending on the length of the common prefix of the existing key
-    /// and the new key.
-    fn insert_at_leaf<'b>(
-        &mut self,
-        child_index: usize,
-        existing_leaf: LeafNode,
-        new_key: HashValue,
-        new_value: AccountStateBlob,
-        mut remaining_nibbles: NibbleIterator<'b>,
-    ) {
-        let num_existing_partial_nodes = self.partial_nodes.len();
-
-        // The node at this position becomes an internal node. Since we may insert more nodes at
-        // this position in the future, we do not know its hash yet.
-        self.partial_nodes[num_existing_partial_nodes - 1]
-            .set_child(child_index, ChildInfo::Internal { hash: None });
-
-        // Next we build the new internal nodes from top to bottom. All these internal node except
-        // the bottom one will now have a single internal node child.
-        let common_prefix_len = existing_leaf
-            .account_key()
-            .common_prefix_nibbles_len(new_key);
-        for _ in num_existing_partial_nodes..common_prefix_len {
-            let visited_nibbles = remaining_nibbles.visited_nibbles().collect();
-            let next_nibble = remaining_nibbles.next().expect("This nibble must exist.");
-            let new_node_key = NodeKey::new(self.version, visited_nibbles);
-
-            let mut internal_info = InternalInfo::new_empty(new_node_key);
-            internal_info.set_child(
-                u8::from(next_nibble) as usize,
-                ChildInfo::Internal { hash: None },
-            );
-            self.partial_nodes.push(internal_info);
-        }
-
-        // The last internal node will have two leaf node children.
-        let visited_nibbles = remaining_nibbles.visited_nibbles().collect();
-        let new_node_key = NodeKey::new(self.version, visited_nibbles);
-        let mut internal_info = InternalInfo::new_empty(new_node_key);
-
-        // Next we put the existing leaf as a child of this internal node.
-        let existing_child_index = existing_leaf.account_key().get_nibble(common_prefix_len);
-        internal_info.set_child(
-            u8::from(existing_child_index) as usize,
-            ChildInfo::Leaf {
-                node: existing_leaf,
-            },
-        );
-
-        // Do not set the new child for now. We always call `freeze` first, then set the new child
-        // later, because this way it's easier in `freeze` to find the correct leaf to freeze --
-        // it's always the rightmost leaf on the lowest level.
-        self.partial_nodes.push(internal_info);
-        self.freeze(self.partial_nodes.len());
-
-        // Now we set the new child.
-        let new_child_index = new_key.get_nibble(common_prefix_len);
-        assert!(
-            new_child_index > existing_child_index,
-            "New leaf must be on the right.",
-        );
-        self.partial_nodes
-            .last_mut()
-            .expect("This node must exist.")
-            .set_child(
-                u8::from(new_child_index) as usize,
-                ChildInfo::Leaf {
-                    node: LeafNode::new(new_key, new_value),
-                },
-            );
-    }
-
-    /// Puts the nodes that will not be changed later in `self.frozen_nodes`.
-    fn freeze(&mut self, num_remaining_partial_nodes: usize) {
-        self.freeze_previous_leaf();
-        self.freeze_internal_nodes(num_remaining_partial_nodes);
-    }
-
-    /// Freezes the previously added leaf node. It should always be the rightmost leaf node on the
-    /// lowest level, inserted in the previous `add_one` call.
-    fn freeze_previous_leaf(&mut self) {
-        // If this is the very first key, there is no previous leaf to freeze.
-        if self.num_keys_received == 0 {
-            return;
-        }
-
-        let last_node = self
-            .partial_nodes
-            .last()
-            .expect("Must have at least one partial node.");
-        let rightmost_child_index = last_node
-            .children
-            .iter()
-            .rposition(|x| x.is_some())
-            .expect("Must have at least one child.");
-
-        match last_node.children[rightmost_child_index] {
-            Some(ChildInfo::Leaf { ref node }) => {
-                let child_node_key = last_node
-                    .node_key
-                    .gen_child_node_key(self.version, (rightmost_child_index as u8).into());
-                self.frozen_nodes
-                    .insert(child_node_key, node.clone().into());
-            }
-            _ => panic!("Must have at least one child and must not have further internal nodes."),
-        }
-    }
-
-    /// Freeze extra internal nodes. Only `num_remaining_nodes` partial internal nodes will be kept
-    /// and the ones on the lower level will be frozen.
-    fn freeze_internal_nodes(&mut self, num_remaining_nodes: usize) {
-        while self.partial_nodes.len() > num_remaining_nodes {
-            let last_node = self.partial_nodes.pop().expect("This node must exist.");
-            let (node_key, internal_node) = last_node.into_internal_node(self.version);
-            // Keep the hash of this node before moving it into `frozen_nodes`, so we can update
-            // its parent later.
-            let node_hash = internal_node.hash();
-            self.frozen_nodes.insert(node_key, internal_node.into());
-
-            // Now that we have computed the hash of the internal node above, we will also update
-            // its parent unless it is root node.
-            if let Some(parent_node) = self.partial_nodes.last_mut() {
-                // This internal node must be the rightmost child of its parent at the moment.
-                let rightmost_child_index = parent_node
-                    .children
-                    .iter()
-                    .rposition(|x| x.is_some())
-                    .expect("Must have at least one child.");
-
-                match parent_node.children[rightmost_child_index] {
-                    Some(ChildInfo::Internal { ref mut hash }) => {
-                        assert_eq!(hash.replace(node_hash), None);
-                    }
-                    _ => panic!(
-                        "Must have at least one child and the rightmost child must not be a leaf."
-                    ),
-                }
-            }
-        }
+        Ok(())
     }
 
     /// Verifies that all accounts that have been added so far (from the leftmost one to
@@ -483,7 +382,10 @@ where
     /// by combining all existing accounts and `proof`.
     #[allow(clippy::collapsible_if)]
     fn verify(&self, proof: SparseMerkleRangeProof) -> Result<()> {
+        let hash_scheme = self.tree.hash_scheme();
+        let placeholder_hash = hash_scheme.placeholder_hash();
         let previous_leaf = self
+            .tree
             .previous_leaf
             .as_ref()
             .expect("The previous leaf must exist.");
@@ -497,18 +399,30 @@ where
 
         // The following process might add some extra placeholder siblings on the left, but it is
         // nontrivial to determine when the loop should stop. So instead we just add these
-        // siblings for now and get rid of them in the next step.
+        // siblings for now and get rid of them in the next step. The siblings above the root of a
+        // shard come from the subtree proof, where the ones close to the root are at the end.
+        let num_prefix_nibbles = self.tree.num_prefix_nibbles();
+        let partial_nodes = &self.tree.partial_nodes;
         let mut num_visited_right_siblings = 0;
         for (i, bit) in previous_key.iter_bits().enumerate() {
             if bit {
                 // This node is a right child and there should be a sibling on the left.
-                let sibling = if i >= self.partial_nodes.len() * 4 {
-                    *SPARSE_MERKLE_PLACEHOLDER_HASH
+                let sibling = if i < num_prefix_nibbles * 4 {
+                    self.subtree_proof
+                        .siblings()
+                        .iter()
+                        .rev()
+                        .nth(i)
+                        .cloned()
+                        .unwrap_or(placeholder_hash)
+                } else if i >= (num_prefix_nibbles + partial_nodes.len()) * 4 {
+                    placeholder_hash
                 } else {
                     Self::compute_left_sibling(
-                        &self.partial_nodes[i / 4],
+                        &partial_nodes[i / 4 - num_prefix_nibbles],
                         previous_key.get_nibble(i / 4),
                         (3 - i % 4) as u8,
+                        hash_scheme,
                     )
                 };
                 left_siblings.push(sibling);
@@ -526,9 +440,7 @@ where
         // sibling if 1) it's a placeholder 2) it's a sibling on the left.
         for bit in previous_key.iter_bits().rev() {
             if bit {
-                if *left_siblings.last().expect("This sibling must exist.")
-                    == *SPARSE_MERKLE_PLACEHOLDER_HASH
-                {
+                if *left_siblings.last().expect("This sibling must exist.") == placeholder_hash {
                     left_siblings.pop();
                 } else {
                     break;
@@ -546,7 +458,7 @@ where
         let num_siblings = left_siblings.len() + proof.right_siblings().len();
         let mut left_sibling_iter = left_siblings.iter().rev();
         let mut right_sibling_iter = proof.right_siblings().iter();
-        let mut current_hash = previous_leaf.hash();
+        let mut current_hash = previous_leaf.hash_with_scheme(hash_scheme);
         for bit in previous_key
             .iter_bits()
             .rev()
@@ -567,7 +479,8 @@ where
                         .ok_or_else(|| format_err!("Missing right sibling."))?,
                 )
             };
-            current_hash = SparseMerkleInternalNode::new(left_hash, right_hash).hash();
+            current_hash =
+                SparseMerkleInternalNode::new(left_hash, right_hash).hash_with_scheme(hash_scheme);
         }
 
         ensure!(
@@ -581,15 +494,23 @@ where
     }
 
     /// Computes the sibling on the left for the `n`-th child.
-    fn compute_left_sibling(partial_node: &InternalInfo, n: Nibble, height: u8) -> HashValue {
+    fn compute_left_sibling(
+        partial_node: &InternalInfo,
+        n: Nibble,
+        height: u8,
+        hash_scheme: &H,
+    ) -> HashValue {
         assert!(height < 4);
         let width = 1usize << height;
         let start = get_child_and_sibling_half_start(n, height).1 as usize;
-        Self::compute_left_sibling_impl(&partial_node.children[start..start + width]).0
+        Self::compute_left_sibling_impl(&partial_node.children[start..start + width], hash_scheme).0
     }
 
     /// Returns the hash for given portion of the subtree and whether this part is a leaf node.
-    fn compute_left_sibling_impl(children: &[Option<ChildInfo>]) -> (HashValue, bool) {
+    fn compute_left_sibling_impl(
+        children: &[Option<ChildInfo>],
+        hash_scheme: &H,
+    ) -> (HashValue, bool) {
         assert!(!children.is_empty());
 
         let num_children = children.len();
@@ -600,22 +521,23 @@ where
                 Some(ChildInfo::Internal { hash }) => {
                     (*hash.as_ref().expect("The hash must be known."), false)
                 }
-                Some(ChildInfo::Leaf { node }) => (node.hash(), true),
-                None => (*SPARSE_MERKLE_PLACEHOLDER_HASH, true),
+                Some(ChildInfo::Leaf { node }) => (node.hash_with_scheme(hash_scheme), true),
+                None => (hash_scheme.placeholder_hash(), true),
             }
         } else {
             let (left_hash, left_is_leaf) =
-                Self::compute_left_sibling_impl(&children[..num_children / 2]);
+                Self::compute_left_sibling_impl(&children[..num_children / 2], hash_scheme);
             let (right_hash, right_is_leaf) =
-                Self::compute_left_sibling_impl(&children[num_children / 2..]);
+                Self::compute_left_sibling_impl(&children[num_children / 2..], hash_scheme);
 
-            if left_hash == *SPARSE_MERKLE_PLACEHOLDER_HASH && right_is_leaf {
+            if left_hash == hash_scheme.placeholder_hash() && right_is_leaf {
                 (right_hash, true)
-            } else if left_is_leaf && right_hash == *SPARSE_MERKLE_PLACEHOLDER_HASH {
+            } else if left_is_leaf && right_hash == hash_scheme.placeholder_hash() {
                 (left_hash, true)
             } else {
                 (
-                    SparseMerkleInternalNode::new(left_hash, right_hash).hash(),
+                    SparseMerkleInternalNode::new(left_hash, right_hash)
+                        .hash_with_scheme(hash_scheme),
                     false,
                 )
             }
@@ -623,33 +545,224 @@ where
     }
 
     /// Finishes the restoration process. This tells the code that there is no more account,
-    /// otherwise we can not freeze the rightmost leaf and its ancestors.
-    pub fn finish(mut self) -> Result<()> {
-        // Deal with the special case when the entire tree has a single leaf.
-        if self.partial_nodes.len() == 1 {
-            let mut num_children = 0;
-            let mut leaf = None;
-            for i in 0..16 {
-                if let Some(ref child_info) = self.partial_nodes[0].children[i] {
-                    num_children += 1;
-                    if let ChildInfo::Leaf { node } = child_info {
-                        leaf = Some(node.clone());
-                    }
+    /// otherwise we can not freeze the rightmost leaf and its ancestors. The rest of the nodes are
+    /// written only if the root hash matches the expected one, in which case the root hash is
+    /// returned and the progress is deleted. For a shard, the root hash of the shard is verified
+    /// against the subtree proof and returned.
+    pub fn finish(mut self) -> Result<HashValue> {
+        // If there is a single leaf, it sits at the highest position where it is the only leaf,
+        // which is at the root of the tree, or possibly above the root of a shard. The siblings
+        // at the bottom of the subtree proof that are placeholders mean the leaf is the only one
+        // in a larger subtree, so it moves up to the nibble that tells it apart from the first
+        // sibling that is not a placeholder.
+        let placeholder_hash = self.tree.hash_scheme().placeholder_hash();
+        let num_siblings = self
+            .subtree_proof
+            .siblings()
+            .iter()
+            .skip_while(|sibling| **sibling == placeholder_hash)
+            .count();
+        let root_hash = self.tree.finish((num_siblings + 3) / 4)?;
+
+        self.subtree_proof.verify_with_scheme(
+            self.tree.hash_scheme(),
+            self.expected_root_hash,
+            self.tree.root_node_key().nibble_path(),
+            root_hash,
+        )?;
+        self.store
+            .write_restore_batch(self.tree.root_node_key(), &self.tree.frozen_nodes, None)?;
+        Ok(root_hash)
+    }
+
+    /// Aborts the restoration process, deleting all the nodes written to storage and the
+    /// progress, so the restoration can start over.
+    pub fn abort(self) -> Result<()> {
+        // Every node written to storage is under one of the children of the partial nodes, except
+        // the rightmost child of each partial node which is either a partial node itself or the
+        // leaf that has not been written.
+        let mut stack = vec![];
+        let version = self.tree.version();
+        for partial_node in &self.tree.partial_nodes {
+            let rightmost_child_index = partial_node.children.iter().rposition(|x| x.is_some());
+            for (i, child_info) in partial_node.children.iter().enumerate() {
+                if child_info.is_some() && Some(i) != rightmost_child_index {
+                    stack.push(
+                        partial_node
+                            .node_key
+                            .gen_child_node_key(version, (i as u8).into()),
+                    );
                 }
             }
+        }
+        // The rightmost leaf of the lowest partial node has been written if we have just resumed.
+        if let (Some(progress), Some(previous_leaf)) = (&self.progress, &self.tree.previous_leaf) {
+            if progress.last_key == previous_leaf.account_key() {
+                let last_node = self
+                    .tree
+                    .partial_nodes
+                    .last()
+                    .expect("Must have a partial node.");
+                if let Some(i) = last_node.children.iter().rposition(|x| x.is_some()) {
+                    stack.push(
+                        last_node
+                            .node_key
+                            .gen_child_node_key(version, (i as u8).into()),
+                    );
+                }
+            }
+        }
 
-            if num_children == 1 {
-                if let Some(node) = leaf {
-                    let node_key = NodeKey::new_empty_path(self.version);
-                    assert!(self.frozen_nodes.is_empty());
-                    self.frozen_nodes.insert(node_key, node.into());
-                    self.store.write_node_batch(&self.frozen_nodes)?;
-                    return Ok(());
+        let mut node_keys = vec![];
+        while let Some(node_key) = stack.pop() {
+            if let Some(node) = self.store.get_node_option(&node_key)? {
+                if let Node::Internal(internal_node) = node {
+                    for i in 0..16u8 {
+                        if let Some(child) = internal_node.child(i.into()) {
+                            stack.push(node_key.gen_child_node_key(child.version, i.into()));
+                        }
+                    }
                 }
+                node_keys.push(node_key);
             }
         }
+        self.store
+            .delete_restore_batch(self.tree.root_node_key(), &node_keys)
+    }
+}
 
-        self.freeze(0);
-        self.store.write_node_batch(&self.frozen_nodes)
+/// Returns the prefixes of all the shards when the key space is split by the first
+/// `num_shard_nibbles` nibbles, in increasing order. There are 16 shards for one nibble and 256
+/// shards for two.
+pub fn shard_prefixes(num_shard_nibbles: usize) -> Vec<NibblePath> {
+    (0..1usize << (num_shard_nibbles * 4))
+        .map(|shard| {
+            (0..num_shard_nibbles)
+                .rev()
+                .map(|i| Nibble::from(((shard >> (i * 4)) & 0xf) as u8))
+                .collect()
+        })
+        .collect()
+}
+
+/// Puts together the shards of the tree at `version` that have been restored by
+/// [`JellyfishMerkleRestore::new_shard`] with prefixes of `num_shard_nibbles` nibbles. The
+/// internal nodes above the shards and the root are written only if the root hash matches
+/// `expected_root_hash`, which fails if any shard is missing. Returns the root hash.
+///
+/// [`JellyfishMerkleRestore::new_shard`]: struct.JellyfishMerkleRestore.html#method.new_shard
+pub fn stitch_shards<S: RestoreStore>(
+    store: &S,
+    version: Version,
+    expected_root_hash: HashValue,
+    num_shard_nibbles: usize,
+) -> Result<HashValue> {
+    stitch_shards_with_scheme(
+        store,
+        version,
+        expected_root_hash,
+        num_shard_nibbles,
+        &Blake3,
+    )
+}
+
+/// Same as [`stitch_shards`](fn.stitch_shards.html), for a tree computed with `hash_scheme`.
+pub fn stitch_shards_with_scheme<S: RestoreStore, H: HashScheme>(
+    store: &S,
+    version: Version,
+    expected_root_hash: HashValue,
+    num_shard_nibbles: usize,
+    hash_scheme: &H,
+) -> Result<HashValue> {
+    ensure!(
+        num_shard_nibbles > 0 && num_shard_nibbles < ROOT_NIBBLE_HEIGHT,
+        "Invalid number of shard nibbles: {}.",
+        num_shard_nibbles,
+    );
+
+    let root_node_key = NodeKey::new_empty_path(version);
+    let mut node_batch = NodeBatch::new();
+    let root_child = stitch_subtree(
+        store,
+        &root_node_key,
+        num_shard_nibbles,
+        &mut node_batch,
+        hash_scheme,
+    )?;
+    let root_hash = match root_child {
+        Some(child) => child.hash,
+        None => {
+            node_batch.insert(root_node_key.clone(), Node::new_null());
+            hash_scheme.placeholder_hash()
+        }
+    };
+    ensure!(
+        root_hash == expected_root_hash,
+        "Root hashes do not match. Actual root hash: {:x}. Expected root hash: {:x}.",
+        root_hash,
+        expected_root_hash,
+    );
+    store.write_restore_batch(&root_node_key, &node_batch, None)?;
+    Ok(root_hash)
+}
+
+/// Builds the internal node at `node_key` from the restored shards below it and adds it to
+/// `node_batch`. Returns the node as a child of its parent, or `None` if there is nothing below.
+fn stitch_subtree<S: RestoreStore, H: HashScheme>(
+    store: &S,
+    node_key: &NodeKey,
+    num_shard_nibbles: usize,
+    node_batch: &mut NodeBatch,
+    hash_scheme: &H,
+) -> Result<Option<Child>> {
+    let version = node_key.version();
+    let at_shard_root = node_key.nibble_path().num_nibbles() == num_shard_nibbles;
+    // Above the roots of the shards, there can only be the single leaf of a shard that is the
+    // only leaf in a larger subtree.
+    match store.get_node_option(node_key)? {
+        Some(Node::Leaf(leaf_node)) => {
+            return Ok(Some(Child::new(
+                leaf_node.hash_with_scheme(hash_scheme),
+                version,
+                true, /* is_leaf */
+            )));
+        }
+        Some(Node::Internal(internal_node)) if at_shard_root => {
+            return Ok(Some(Child::new(
+                internal_node.hash_with_scheme(hash_scheme),
+                version,
+                false, /* is_leaf */
+            )));
+        }
+        Some(_) => bail!("Unexpected node above the shards: {:?}.", node_key),
+        None if at_shard_root => return Ok(None),
+        None => (),
+    }
+
+    let mut children = Children::new();
+    for i in 0..16u8 {
+        let child_node_key = node_key.gen_child_node_key(version, i.into());
+        let child = stitch_subtree(
+            store,
+            &child_node_key,
+            num_shard_nibbles,
+            node_batch,
+            hash_scheme,
+        )?;
+        if let Some(child) = child {
+            children.insert(i.into(), child);
+        }
+    }
+    if children.is_empty() {
+        return Ok(None);
     }
+    ensure!(
+        children.len() > 1 || children.values().all(|child| !child.is_leaf),
+        "The only leaf under {:?} should have been placed at it.",
+        node_key,
+    );
+    let internal_node = InternalNode::new(children);
+    let hash = internal_node.hash_with_scheme(hash_scheme);
+    node_batch.insert(node_key.clone(), internal_node.into());
+    Ok(Some(Child::new(hash, version, false /* is_leaf */)))
 }
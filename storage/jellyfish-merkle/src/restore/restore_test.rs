@@ -2,13 +2,22 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
-    mock_tree_store::MockTreeStore, restore::JellyfishMerkleRestore, test_helper::init_mock_db,
+    mock_tree_store::MockTreeStore,
+    nibble_path::NibblePath,
+    node_type::NodeKey,
+    restore::{
+        shard_prefixes, stitch_shards, stitch_shards_with_scheme, JellyfishMerkleRestore,
+        RestoreStore,
+    },
+    test_helper::{init_mock_db, init_mock_db_with_options},
     JellyfishMerkleTree, TreeReader,
 };
-use libra_crypto::HashValue;
+use anyhow::Result;
+use libra_crypto::{hash::Sha3_256, HashValue};
 use libra_types::{account_state_blob::AccountStateBlob, transaction::Version};
 use proptest::{collection::btree_map, prelude::*};
-use std::collections::BTreeMap;
+use rand::{rngs::StdRng, SeedableRng};
+use std::{collections::BTreeMap, sync::Arc, thread};
 
 proptest! {
     #![proptest_config(ProptestConfig::with_cases(10))]
@@ -31,7 +40,8 @@ proptest! {
                 .add_chunk(vec![(*key, value.clone())], proof)
                 .unwrap();
         }
-        restore.finish().unwrap();
+        prop_assert_eq!(restore.finish().unwrap(), expected_root_hash);
+        prop_assert!(restore_db.get_restore_progress(&NodeKey::new_empty_path(version)).unwrap().is_none());
 
         assert_success(&restore_db, expected_root_hash, &btree, version);
     }
@@ -61,21 +71,28 @@ proptest! {
         }
 
         {
-            let rightmost_key = match restore_db.get_rightmost_leaf().unwrap() {
+            let mut restore =
+                JellyfishMerkleRestore::new(&restore_db, version, expected_root_hash).unwrap();
+            // All keys but the last one in the first batch have been written.
+            let remaining_accounts: Vec<_> = match restore.progress() {
                 None => {
-                    // Sometimes the batch is too small so nothing is written to DB.
-                    return Ok(());
+                    // The batch is too small so nothing is written to DB.
+                    prop_assert_eq!(batch1_size, 1);
+                    all.clone().into_iter().collect()
+                }
+                Some(progress) => {
+                    prop_assert_eq!(progress.num_keys, batch1_size as u64 - 1);
+                    prop_assert_eq!(
+                        Some(&progress.last_key),
+                        all.keys().nth(batch1_size - 2)
+                    );
+                    all.clone()
+                        .into_iter()
+                        .filter(|(k, _v)| *k > progress.last_key)
+                        .collect()
                 }
-                Some((_, node)) => node.account_key(),
             };
-            let remaining_accounts: Vec<_> = all
-                .clone()
-                .into_iter()
-                .filter(|(k, _v)| *k > rightmost_key)
-                .collect();
 
-            let mut restore =
-                JellyfishMerkleRestore::new(&restore_db, version, expected_root_hash).unwrap();
             let proof = tree
                 .get_range_proof(
                     remaining_accounts.last().map(|(key, _value)| *key).unwrap(),
@@ -88,6 +105,354 @@ proptest! {
 
         assert_success(&restore_db, expected_root_hash, &all, version);
     }
+
+    #[test]
+    fn test_restore_abort(
+        (all, batch1_size, resume) in btree_map(any::<HashValue>(), any::<AccountStateBlob>(), 2..1000)
+            .prop_flat_map(|btree| {
+                let len = btree.len();
+                (Just(btree), 1..len, any::<bool>())
+            })
+    ) {
+        let (db, version) = init_mock_db(&all.clone().into_iter().collect());
+        let tree = JellyfishMerkleTree::new(&db);
+        let expected_root_hash = tree.get_root_hash(version).unwrap();
+        let batch1: Vec<_> = all.clone().into_iter().take(batch1_size).collect();
+
+        let restore_db = MockTreeStore::default();
+        let mut restore =
+            JellyfishMerkleRestore::new(&restore_db, version, expected_root_hash).unwrap();
+        let proof = tree
+            .get_range_proof(batch1.last().map(|(key, _value)| *key).unwrap(), version)
+            .unwrap();
+        restore.add_chunk(batch1, proof).unwrap();
+        if resume {
+            // Abort after resuming from the persisted progress.
+            restore = JellyfishMerkleRestore::new(&restore_db, version, expected_root_hash).unwrap();
+        }
+        restore.abort().unwrap();
+        prop_assert_eq!(restore_db.num_nodes(), 0);
+        prop_assert!(restore_db.get_restore_progress(&NodeKey::new_empty_path(version)).unwrap().is_none());
+
+        // Start over.
+        let mut restore =
+            JellyfishMerkleRestore::new(&restore_db, version, expected_root_hash).unwrap();
+        let proof = tree
+            .get_range_proof(*all.keys().last().unwrap(), version)
+            .unwrap();
+        restore.add_chunk(all.clone().into_iter().collect(), proof).unwrap();
+        restore.finish().unwrap();
+        assert_success(&restore_db, expected_root_hash, &all, version);
+    }
+
+    #[test]
+    fn test_restore_by_shards(
+        btree in btree_map(any::<HashValue>(), any::<AccountStateBlob>(), 1..1000),
+        num_shard_nibbles in 1..=2usize,
+    ) {
+        let (db, version) = init_mock_db(&btree.clone().into_iter().collect());
+        let tree = JellyfishMerkleTree::new(&db);
+        let expected_root_hash = tree.get_root_hash(version).unwrap();
+
+        // Restore the shards in reverse order, since they do not depend on each other.
+        let restore_db = MockTreeStore::default();
+        for prefix in shard_prefixes(num_shard_nibbles).into_iter().rev() {
+            restore_shard(&db, &restore_db, version, prefix);
+        }
+        prop_assert_eq!(
+            stitch_shards(&restore_db, version, expected_root_hash, num_shard_nibbles).unwrap(),
+            expected_root_hash
+        );
+
+        assert_success(&restore_db, expected_root_hash, &btree, version);
+    }
+}
+
+/// Restores the shard at `prefix` from `db` into `restore_db`, three accounts per chunk.
+fn restore_shard(
+    db: &MockTreeStore,
+    restore_db: &MockTreeStore,
+    version: Version,
+    prefix: NibblePath,
+) {
+    let tree = JellyfishMerkleTree::new(db);
+    let expected_root_hash = tree.get_root_hash(version).unwrap();
+    let (subtree_root_hash, subtree_proof) =
+        tree.get_subtree_root_with_proof(&prefix, version).unwrap();
+    let kvs = tree
+        .iter_subtree(&prefix, version)
+        .unwrap()
+        .collect::<Result<Vec<_>>>()
+        .unwrap();
+
+    let mut restore = JellyfishMerkleRestore::new_shard(
+        restore_db,
+        version,
+        expected_root_hash,
+        prefix,
+        subtree_proof,
+    )
+    .unwrap();
+    for chunk in kvs.chunks(3) {
+        let proof = tree
+            .get_range_proof(chunk.last().unwrap().0, version)
+            .unwrap();
+        restore.add_chunk(chunk.to_vec(), proof).unwrap();
+    }
+    assert_eq!(restore.finish().unwrap(), subtree_root_hash);
+}
+
+fn gen_btree(num_keys: usize) -> BTreeMap<HashValue, AccountStateBlob> {
+    let mut rng: StdRng = StdRng::from_seed([0u8; 32]);
+    (0..num_keys)
+        .map(|i| {
+            (
+                HashValue::random_with_rng(&mut rng),
+                AccountStateBlob::from(vec![i as u8]),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn test_restore_shards_concurrently() {
+    let btree = gen_btree(1000);
+    let (db, version) = init_mock_db(&btree.clone().into_iter().collect());
+    let expected_root_hash = JellyfishMerkleTree::new(&db)
+        .get_root_hash(version)
+        .unwrap();
+
+    let db = Arc::new(db);
+    let restore_db = Arc::new(MockTreeStore::default());
+    let handles: Vec<_> = shard_prefixes(1)
+        .into_iter()
+        .map(|prefix| {
+            let db = Arc::clone(&db);
+            let restore_db = Arc::clone(&restore_db);
+            thread::spawn(move || restore_shard(&db, &restore_db, version, prefix))
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    assert_eq!(
+        stitch_shards(&*restore_db, version, expected_root_hash, 1).unwrap(),
+        expected_root_hash
+    );
+    assert_success(&restore_db, expected_root_hash, &btree, version);
+}
+
+#[test]
+fn test_restore_shards_with_single_leaves() {
+    // With a single key, its leaf is the root. With two keys that differ in the first nibble,
+    // their leaves are children of the root, above their shards.
+    let key1 = HashValue::new([0x00; HashValue::LENGTH]);
+    let key2 = HashValue::new([0x10; HashValue::LENGTH]);
+    let blob = AccountStateBlob::from(vec![1u8]);
+    for keys in &[vec![key1], vec![key1, key2]] {
+        let btree: BTreeMap<_, _> = keys.iter().map(|key| (*key, blob.clone())).collect();
+        let (db, version) = init_mock_db(&btree.clone().into_iter().collect());
+        let expected_root_hash = JellyfishMerkleTree::new(&db)
+            .get_root_hash(version)
+            .unwrap();
+
+        let restore_db = MockTreeStore::default();
+        for prefix in shard_prefixes(2) {
+            restore_shard(&db, &restore_db, version, prefix);
+        }
+        assert_eq!(
+            stitch_shards(&restore_db, version, expected_root_hash, 2).unwrap(),
+            expected_root_hash
+        );
+        assert_success(&restore_db, expected_root_hash, &btree, version);
+        // The leaves are placed where proofs expect them.
+        for key in keys {
+            assert_eq!(
+                JellyfishMerkleTree::new(&restore_db)
+                    .get_with_proof(*key, version)
+                    .unwrap(),
+                JellyfishMerkleTree::new(&db)
+                    .get_with_proof(*key, version)
+                    .unwrap()
+            );
+        }
+    }
+}
+
+#[test]
+fn test_restore_shard_with_interruption() {
+    let btree = gen_btree(500);
+    let (db, version) = init_mock_db(&btree.clone().into_iter().collect());
+    let tree = JellyfishMerkleTree::new(&db);
+    let expected_root_hash = tree.get_root_hash(version).unwrap();
+    let restore_db = MockTreeStore::default();
+
+    let prefix: NibblePath = vec![0u8.into()].into_iter().collect();
+    let (subtree_root_hash, subtree_proof) =
+        tree.get_subtree_root_with_proof(&prefix, version).unwrap();
+    let kvs = tree
+        .iter_subtree(&prefix, version)
+        .unwrap()
+        .collect::<Result<Vec<_>>>()
+        .unwrap();
+    let batch1 = kvs[..kvs.len() / 2].to_vec();
+    {
+        let mut restore = JellyfishMerkleRestore::new_shard(
+            &restore_db,
+            version,
+            expected_root_hash,
+            prefix.clone(),
+            subtree_proof.clone(),
+        )
+        .unwrap();
+        let proof = tree
+            .get_range_proof(batch1.last().unwrap().0, version)
+            .unwrap();
+        restore.add_chunk(batch1.clone(), proof).unwrap();
+        // Do not call `finish`.
+    }
+
+    // The progress is kept for the shard, not for the whole tree.
+    assert!(restore_db
+        .get_restore_progress(&NodeKey::new_empty_path(version))
+        .unwrap()
+        .is_none());
+    let mut restore = JellyfishMerkleRestore::new_shard(
+        &restore_db,
+        version,
+        expected_root_hash,
+        prefix.clone(),
+        subtree_proof,
+    )
+    .unwrap();
+    let progress = restore.progress().unwrap().clone();
+    assert_eq!(progress.num_keys, batch1.len() as u64 - 1);
+    let remaining: Vec<_> = kvs
+        .into_iter()
+        .filter(|(key, _blob)| *key > progress.last_key)
+        .collect();
+    let proof = tree
+        .get_range_proof(remaining.last().unwrap().0, version)
+        .unwrap();
+    restore.add_chunk(remaining, proof).unwrap();
+    assert_eq!(restore.finish().unwrap(), subtree_root_hash);
+    assert!(restore_db
+        .get_restore_progress(&NodeKey::new(version, prefix))
+        .unwrap()
+        .is_none());
+
+    for prefix in shard_prefixes(1).into_iter().skip(1) {
+        restore_shard(&db, &restore_db, version, prefix);
+    }
+    stitch_shards(&restore_db, version, expected_root_hash, 1).unwrap();
+    assert_success(&restore_db, expected_root_hash, &btree, version);
+}
+
+#[test]
+fn test_restore_shard_invalid() {
+    let btree = gen_btree(100);
+    let (db, version) = init_mock_db(&btree.clone().into_iter().collect());
+    let tree = JellyfishMerkleTree::new(&db);
+    let expected_root_hash = tree.get_root_hash(version).unwrap();
+    let restore_db = MockTreeStore::default();
+
+    // Keys outside the shard are rejected.
+    let prefix: NibblePath = vec![0u8.into()].into_iter().collect();
+    let (_subtree_root_hash, subtree_proof) =
+        tree.get_subtree_root_with_proof(&prefix, version).unwrap();
+    let mut restore = JellyfishMerkleRestore::new_shard(
+        &restore_db,
+        version,
+        expected_root_hash,
+        prefix,
+        subtree_proof,
+    )
+    .unwrap();
+    let (key, blob) = btree.iter().find(|(key, _blob)| key[0] >= 0x10).unwrap();
+    let proof = tree.get_range_proof(*key, version).unwrap();
+    assert!(restore
+        .add_chunk(vec![(*key, blob.clone())], proof)
+        .is_err());
+
+    // Stitching fails if a shard is missing.
+    for prefix in shard_prefixes(1).into_iter().skip(1) {
+        restore_shard(&db, &restore_db, version, prefix);
+    }
+    assert!(stitch_shards(&restore_db, version, expected_root_hash, 1).is_err());
+    assert!(restore_db
+        .get_node_option(&NodeKey::new_empty_path(version))
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn test_restore_with_scheme() {
+    let btree = gen_btree(200);
+    let blob_sets = vec![btree.clone().into_iter().collect::<Vec<_>>()];
+    let (db, root_hashes) = init_mock_db_with_options(&blob_sets, None, Sha3_256);
+    let expected_root_hash = root_hashes[0];
+    let tree = JellyfishMerkleTree::new_with_scheme(&db, None, Sha3_256);
+
+    // The whole tree, interrupted after the first chunk.
+    let restore_db = MockTreeStore::default();
+    let first_chunk = &blob_sets[0][..50];
+    let mut restore =
+        JellyfishMerkleRestore::new_with_scheme(&restore_db, 0, expected_root_hash, Sha3_256)
+            .unwrap();
+    let proof = tree.get_range_proof(first_chunk[49].0, 0).unwrap();
+    restore.add_chunk(first_chunk.to_vec(), proof).unwrap();
+    let mut restore =
+        JellyfishMerkleRestore::new_with_scheme(&restore_db, 0, expected_root_hash, Sha3_256)
+            .unwrap();
+    let last_key = restore.progress().unwrap().last_key;
+    let rest: Vec<_> = blob_sets[0]
+        .iter()
+        .filter(|(key, _blob)| *key > last_key)
+        .cloned()
+        .collect();
+    for chunk in rest.chunks(7) {
+        let proof = tree.get_range_proof(chunk.last().unwrap().0, 0).unwrap();
+        restore.add_chunk(chunk.to_vec(), proof).unwrap();
+    }
+    assert_eq!(restore.finish().unwrap(), expected_root_hash);
+    let restored_tree = JellyfishMerkleTree::new_with_scheme(&restore_db, None, Sha3_256);
+    assert_eq!(restored_tree.get_root_hash(0).unwrap(), expected_root_hash);
+
+    // The same tree by shards.
+    let restore_db = MockTreeStore::default();
+    for prefix in shard_prefixes(1) {
+        let (subtree_root_hash, subtree_proof) =
+            tree.get_subtree_root_with_proof(&prefix, 0).unwrap();
+        let shard_kvs = tree
+            .iter_subtree(&prefix, 0)
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        let mut restore = JellyfishMerkleRestore::new_shard_with_scheme(
+            &restore_db,
+            0,
+            expected_root_hash,
+            prefix,
+            subtree_proof,
+            Sha3_256,
+        )
+        .unwrap();
+        for chunk in shard_kvs.chunks(3) {
+            let proof = tree.get_range_proof(chunk.last().unwrap().0, 0).unwrap();
+            restore.add_chunk(chunk.to_vec(), proof).unwrap();
+        }
+        assert_eq!(restore.finish().unwrap(), subtree_root_hash);
+    }
+    assert!(stitch_shards(&restore_db, 0, expected_root_hash, 1).is_err());
+    assert_eq!(
+        stitch_shards_with_scheme(&restore_db, 0, expected_root_hash, 1, &Sha3_256).unwrap(),
+        expected_root_hash
+    );
+    let restored_tree = JellyfishMerkleTree::new_with_scheme(&restore_db, None, Sha3_256);
+    for (key, value) in &btree {
+        assert_eq!(restored_tree.get(*key, 0).unwrap(), Some(value.clone()));
+    }
 }
 
 fn assert_success(
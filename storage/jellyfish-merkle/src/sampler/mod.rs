@@ -0,0 +1,223 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module implements `LeafSampler`, which draws random leaves from the tree at some version,
+//! each along with the proof of the leaf, for audits and data availability checks. The sampler
+//! is driven by a caller-supplied seed, so the same seed always yields the same leaves from the
+//! same tree. The seed drives a `ChaCha20Rng`, whose output is fixed by its specification rather
+//! than by the version of the `rand` crate, so samples stay reproducible across upgrades.
+//!
+//! Each sample descends from the root by a random key. At an internal node, the child at the
+//! next nibble of the key is taken, or the nearest existing child if there is none at that
+//! nibble. Left alone, this favors children next to large gaps, so a draw that lands on a child
+//! is accepted with probability inversely proportional to the number of nibbles it owns, which
+//! makes all the existing children of a node equally likely. Leaves are therefore sampled with
+//! probability `1 / (c_1 * c_2 * ... * c_d)`, where `c_i` is the number of children of the `i`-th
+//! internal node on their path. Since keys are hashes, sibling subtrees hold roughly the same
+//! number of leaves and this is close to uniform. Internal nodes that track leaf counts (see
+//! [`LeafCountMode`](../node_type/enum.LeafCountMode.html)) pick children weighted by their exact
+//! leaf counts instead, which makes the sampling exactly uniform.
+
+#[cfg(test)]
+mod sampler_test;
+
+use crate::{
+    get_leaf_blob,
+    node_type::{InternalNode, Node, NodeKey},
+    TreeReader, ROOT_NIBBLE_HEIGHT,
+};
+use anyhow::{bail, format_err, Result};
+use libra_crypto::{
+    hash::{Blake3, HashScheme},
+    HashValue,
+};
+use libra_nibble::Nibble;
+use libra_types::{
+    account_state_blob::AccountStateBlob,
+    proof::{SparseMerkleLeafNode, SparseMerkleProof},
+    transaction::Version,
+};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use std::collections::BTreeSet;
+
+/// The maximum number of draws per requested sample when sampling distinct leaves. Draws that
+/// hit a leaf that has already been sampled are wasted, so this bounds the work on trees with
+/// fewer leaves than requested.
+const MAX_DRAWS_PER_SAMPLE: usize = 32;
+
+/// The `LeafSampler` implementation.
+pub struct LeafSampler<'a, R, S = Blake3> {
+    /// The storage engine from which we can read nodes using node keys.
+    reader: &'a R,
+
+    /// The version of the tree to sample from.
+    version: Version,
+
+    /// The random number generator seeded by the caller.
+    rng: ChaCha20Rng,
+
+    /// The hash scheme the tree is computed with.
+    hash_scheme: S,
+}
+
+impl<'a, R> LeafSampler<'a, R>
+where
+    R: TreeReader,
+{
+    /// Constructs a new sampler over the tree at `version`, seeded with `seed`.
+    pub fn new(reader: &'a R, version: Version, seed: [u8; 32]) -> Self {
+        Self::new_with_scheme(reader, version, seed, Blake3)
+    }
+}
+
+impl<'a, R, S> LeafSampler<'a, R, S>
+where
+    R: TreeReader,
+    S: HashScheme,
+{
+    /// Same as [`new`](struct.LeafSampler.html#method.new), for a tree computed with
+    /// `hash_scheme`.
+    pub fn new_with_scheme(
+        reader: &'a R,
+        version: Version,
+        seed: [u8; 32],
+        hash_scheme: S,
+    ) -> Self {
+        Self {
+            reader,
+            version,
+            rng: ChaCha20Rng::from_seed(seed),
+            hash_scheme,
+        }
+    }
+
+    /// Draws a random leaf, along with the proof of its key. Returns `None` if the tree is empty.
+    pub fn sample(&mut self) -> Result<Option<(HashValue, AccountStateBlob, SparseMerkleProof)>> {
+        let mut next_node_key = NodeKey::new_empty_path(self.version);
+        let mut siblings = vec![];
+
+        // We limit the number of loops here deliberately to avoid potential cyclic graph bugs
+        // in the tree structure.
+        for nibble_depth in 0..=ROOT_NIBBLE_HEIGHT {
+            match self.reader.get_node(&next_node_key)? {
+                Node::Internal(internal_node) => {
+                    let child_index = match internal_node.leaf_count_mode() {
+                        Some(_) => self.choose_child_by_leaf_count(&internal_node)?,
+                        None => self.choose_nearest_child(&internal_node),
+                    };
+                    let (child_node_key, mut siblings_in_internal) = internal_node
+                        .get_child_with_siblings(&next_node_key, child_index, &self.hash_scheme);
+                    siblings.append(&mut siblings_in_internal);
+                    next_node_key = child_node_key.ok_or_else(|| {
+                        format_err!(
+                            "Child {:?} of node {:?} does not exist.",
+                            child_index,
+                            next_node_key,
+                        )
+                    })?;
+                }
+                Node::Leaf(leaf_node) => {
+                    let key = leaf_node.account_key();
+                    siblings.reverse();
+                    let leaf = SparseMerkleLeafNode::new(key, leaf_node.blob_hash());
+                    let proof = SparseMerkleProof::new(Some(leaf), siblings);
+                    let blob = get_leaf_blob(self.reader, leaf_node)?;
+                    return Ok(Some((key, blob, proof)));
+                }
+                Node::Null => {
+                    if nibble_depth == 0 {
+                        return Ok(None);
+                    } else {
+                        bail!(
+                            "Non-root null node exists with node key {:?}",
+                            next_node_key
+                        );
+                    }
+                }
+            }
+        }
+        bail!("Jellyfish Merkle tree has cyclic graph inside.");
+    }
+
+    /// Draws up to `num_samples` distinct leaves. Fewer leaves are returned only if the tree does
+    /// not have enough of them to be found within a bounded number of draws.
+    pub fn sample_distinct(
+        &mut self,
+        num_samples: usize,
+    ) -> Result<Vec<(HashValue, AccountStateBlob, SparseMerkleProof)>> {
+        let mut seen = BTreeSet::new();
+        let mut samples = Vec::with_capacity(num_samples);
+        for _ in 0..num_samples.saturating_mul(MAX_DRAWS_PER_SAMPLE) {
+            if samples.len() == num_samples {
+                break;
+            }
+            match self.sample()? {
+                Some(sample) => {
+                    if seen.insert(sample.0) {
+                        samples.push(sample);
+                    }
+                }
+                None => break,
+            }
+        }
+        Ok(samples)
+    }
+
+    /// Picks the child at a random nibble, or the nearest existing child if there is none at
+    /// that nibble, rejecting the draw with a probability that makes all children equally likely.
+    ///
+    /// The sizes of the subtrees under the children are not known here, so each child is picked
+    /// with the same probability regardless of how many leaves it has. Sampling is only exactly
+    /// uniform over leaves for trees that track leaf counts, which use
+    /// [`choose_child_by_leaf_count`](#method.choose_child_by_leaf_count) instead.
+    fn choose_nearest_child(&mut self, internal_node: &InternalNode) -> Nibble {
+        // The nearest existing child of each nibble. Ties go to the smaller nibble.
+        let mut owners = [0u8; 16];
+        // The number of nibbles each existing child owns.
+        let mut num_owned = [0usize; 16];
+        for (n, owner) in owners.iter_mut().enumerate() {
+            *owner = (0..16u8)
+                .filter(|i| internal_node.child(Nibble::from(*i)).is_some())
+                .min_by_key(|i| (*i as isize - n as isize).abs())
+                .expect("Internal node must have at least one child.");
+            num_owned[*owner as usize] += 1;
+        }
+        let min_owned = num_owned
+            .iter()
+            .filter(|num| **num > 0)
+            .min()
+            .cloned()
+            .expect("Internal node must have at least one child.");
+
+        loop {
+            let owner = owners[self.rng.gen_range(0, 16)];
+            if self
+                .rng
+                .gen_bool(min_owned as f64 / num_owned[owner as usize] as f64)
+            {
+                return Nibble::from(owner);
+            }
+        }
+    }
+
+    /// Picks a child with probability proportional to the number of leaves under it.
+    fn choose_child_by_leaf_count(&mut self, internal_node: &InternalNode) -> Result<Nibble> {
+        let leaf_count = internal_node
+            .leaf_count()
+            .ok_or_else(|| format_err!("Internal node does not track leaf counts."))?;
+        let mut index = self.rng.gen_range(0, leaf_count);
+        for i in 0..16u8 {
+            if let Some(child) = internal_node.child(Nibble::from(i)) {
+                let child_leaf_count = child
+                    .leaf_count
+                    .ok_or_else(|| format_err!("Child {} does not track leaf counts.", i))?;
+                if index < child_leaf_count {
+                    return Ok(Nibble::from(i));
+                }
+                index -= child_leaf_count;
+            }
+        }
+        bail!("Leaf counts of children do not add up to {}.", leaf_count);
+    }
+}
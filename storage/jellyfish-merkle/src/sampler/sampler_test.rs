@@ -0,0 +1,141 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    mock_tree_store::MockTreeStore,
+    node_type::{LeafCountMode, Node, NodeKey},
+    sampler::LeafSampler,
+    test_helper::init_mock_db_with_options,
+    JellyfishMerkleTree,
+};
+use libra_crypto::{hash::Blake3, HashValue};
+use libra_types::account_state_blob::AccountStateBlob;
+use rand::{rngs::StdRng, SeedableRng};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Initializes a DB with a leaf for each of `keys`, all at version 0.
+fn init_db(
+    keys: &[HashValue],
+    leaf_count_mode: Option<LeafCountMode>,
+) -> (MockTreeStore, HashMap<HashValue, AccountStateBlob>) {
+    let kvs: HashMap<_, _> = keys
+        .iter()
+        .map(|key| (*key, AccountStateBlob::from(key.to_vec())))
+        .collect();
+    let (db, _root_hashes) = init_mock_db_with_options(
+        &[kvs.clone().into_iter().collect()],
+        leaf_count_mode,
+        Blake3,
+    );
+    (db, kvs)
+}
+
+/// Generates a random key whose first byte is `first_byte`.
+fn key_with_first_byte(first_byte: u8, rng: &mut StdRng) -> HashValue {
+    let mut buf = HashValue::random_with_rng(rng).to_vec();
+    buf[0] = first_byte;
+    HashValue::from_slice(&buf).unwrap()
+}
+
+/// Draws `num_draws` leaves, possibly repeated, and counts how many times each key is drawn.
+fn count_draws(db: &MockTreeStore, num_draws: usize) -> BTreeMap<HashValue, usize> {
+    let mut sampler = LeafSampler::new(db, 0 /* version */, [0u8; 32]);
+    let mut counts = BTreeMap::new();
+    for _ in 0..num_draws {
+        let (key, _blob, _proof) = sampler.sample().unwrap().unwrap();
+        *counts.entry(key).or_insert(0) += 1;
+    }
+    counts
+}
+
+#[test]
+fn test_sample_leaves_with_proof() {
+    let mut rng: StdRng = StdRng::from_seed([0u8; 32]);
+    let keys: Vec<_> = (0..200)
+        .map(|_| HashValue::random_with_rng(&mut rng))
+        .collect();
+    let (db, kvs) = init_db(&keys, None);
+    let tree = JellyfishMerkleTree::new(&db);
+    let root_hash = tree.get_root_hash(0).unwrap();
+
+    let samples = tree.sample_leaves_with_proof(0, 20, [1u8; 32]).unwrap();
+    assert_eq!(samples.len(), 20);
+    let sampled_keys: BTreeSet<_> = samples.iter().map(|(key, _, _)| *key).collect();
+    assert_eq!(sampled_keys.len(), 20);
+    for (key, blob, proof) in &samples {
+        assert_eq!(kvs.get(key), Some(blob));
+        proof.verify(root_hash, *key, Some(blob)).unwrap();
+    }
+
+    // The same seed yields the same samples, while a different seed does not.
+    assert_eq!(
+        tree.sample_leaves_with_proof(0, 20, [1u8; 32]).unwrap(),
+        samples
+    );
+    assert_ne!(
+        tree.sample_leaves_with_proof(0, 20, [2u8; 32]).unwrap(),
+        samples
+    );
+}
+
+#[test]
+fn test_sample_more_than_num_leaves() {
+    let mut rng: StdRng = StdRng::from_seed([0u8; 32]);
+    let keys: Vec<_> = (0..3)
+        .map(|_| HashValue::random_with_rng(&mut rng))
+        .collect();
+    let (db, _kvs) = init_db(&keys, None);
+    let tree = JellyfishMerkleTree::new(&db);
+
+    let samples = tree.sample_leaves_with_proof(0, 10, [0u8; 32]).unwrap();
+    let sampled_keys: BTreeSet<_> = samples.iter().map(|(key, _, _)| *key).collect();
+    assert_eq!(sampled_keys, keys.into_iter().collect());
+}
+
+#[test]
+fn test_sample_empty_tree() {
+    let db = MockTreeStore::default();
+    db.put_node(NodeKey::new_empty_path(0), Node::new_null())
+        .unwrap();
+    let tree = JellyfishMerkleTree::new(&db);
+    assert!(tree
+        .sample_leaves_with_proof(0, 10, [0u8; 32])
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn test_sample_corrects_nearest_child_bias() {
+    // The root has children 0, 1 and f. Nibble 0 is nearest to child 0, nibbles 1 to 8 are
+    // nearest to child 1 and nibbles 9 to f are nearest to child f, but all three leaves should be
+    // drawn equally often.
+    let mut rng: StdRng = StdRng::from_seed([0u8; 32]);
+    let keys = vec![
+        key_with_first_byte(0x00, &mut rng),
+        key_with_first_byte(0x10, &mut rng),
+        key_with_first_byte(0xf0, &mut rng),
+    ];
+    let (db, _kvs) = init_db(&keys, None);
+
+    let counts = count_draws(&db, 3000);
+    assert_eq!(counts.len(), 3);
+    for count in counts.values() {
+        assert!(*count > 800 && *count < 1200, "{:?}", counts);
+    }
+}
+
+#[test]
+fn test_sample_uniform_with_leaf_count() {
+    // Ten leaves under child 0 of the root and one leaf under child f. Without leaf counts the
+    // leaf under child f would be drawn half of the time.
+    let mut rng: StdRng = StdRng::from_seed([0u8; 32]);
+    let mut keys: Vec<_> = (0..10).map(|i| key_with_first_byte(i, &mut rng)).collect();
+    keys.push(key_with_first_byte(0xf0, &mut rng));
+    let (db, _kvs) = init_db(&keys, Some(LeafCountMode::Stored));
+
+    let counts = count_draws(&db, 11000);
+    assert_eq!(counts.len(), 11);
+    for count in counts.values() {
+        assert!(*count > 800 && *count < 1200, "{:?}", counts);
+    }
+}
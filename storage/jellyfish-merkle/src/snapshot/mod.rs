@@ -0,0 +1,325 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module implements a portable file format for state snapshots, which can be exported from
+//! a tree at some version and imported into another storage without trusting the files. All
+//! files of a snapshot live in one directory on the local filesystem:
+//!   - `state.manifest`: the LCS serialized [`StateSnapshotManifest`], which names the version,
+//!     the root hash and the chunks of the snapshot.
+//!   - `<first_idx>-.chunk`: repeated `len(record) + record`, where `len` is a big endian `u32`
+//!     and `record` is the LCS serialized tuple `(key, account_state_blob)`.
+//!   - `<first_idx>-<last_idx>.proof`: the LCS serialized `SparseMerkleRangeProof` of the last
+//!     key in the chunk.
+//!
+//! Importing a snapshot verifies every chunk against a root hash the caller trusts, which the
+//! manifest has to agree with, through
+//! [`JellyfishMerkleRestore`](../restore/struct.JellyfishMerkleRestore.html). Chunk and proof
+//! files have to be plain file names inside the snapshot directory.
+//!
+//! [`StateSnapshotManifest`]: struct.StateSnapshotManifest.html
+
+#[cfg(test)]
+mod snapshot_test;
+
+use crate::{
+    iterator::JellyfishMerkleIterator,
+    restore::{JellyfishMerkleRestore, RestoreStore},
+    JellyfishMerkleTree, TreeReader,
+};
+use anyhow::{bail, ensure, format_err, Context, Result};
+use libra_crypto::{
+    hash::{Blake3, HashScheme},
+    HashValue,
+};
+use libra_types::{
+    account_state_blob::AccountStateBlob, proof::SparseMerkleRangeProof, transaction::Version,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    convert::TryInto,
+    fs,
+    mem::size_of,
+    path::{Component, Path, PathBuf},
+};
+
+/// The name of the manifest file in a snapshot directory.
+pub const MANIFEST_NAME: &str = "state.manifest";
+
+/// A chunk of consecutive account blobs in a snapshot.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct StateSnapshotChunk {
+    /// Index of the first account in this chunk over all accounts.
+    pub first_idx: u64,
+    /// Index of the last account in this chunk over all accounts.
+    pub last_idx: u64,
+    /// Key of the first account in this chunk.
+    pub first_key: HashValue,
+    /// Key of the last account in this chunk.
+    pub last_key: HashValue,
+    /// Name of the file holding the account blobs, relative to the snapshot directory.
+    pub blobs: String,
+    /// Name of the file holding the range proof of `last_key`, relative to the snapshot
+    /// directory.
+    pub proof: String,
+}
+
+/// The manifest of a snapshot.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct StateSnapshotManifest {
+    /// Version at which this snapshot is taken.
+    pub version: Version,
+    /// Hash of the state tree root.
+    pub root_hash: HashValue,
+    /// All account blobs in chunks, sorted by key.
+    pub chunks: Vec<StateSnapshotChunk>,
+}
+
+fn chunk_name(first_idx: u64) -> String {
+    format!("{}-.chunk", first_idx)
+}
+
+fn chunk_proof_name(first_idx: u64, last_idx: u64) -> String {
+    format!("{}-{}.proof", first_idx, last_idx)
+}
+
+/// Exports the tree at `version` into `dir`, which is created if it does not exist. Each chunk
+/// file holds as many records as fit in `max_chunk_size` bytes, but at least one. Returns the
+/// manifest, which is also written to `dir`. Returns error if the tree is empty.
+pub fn export_state_snapshot<R: TreeReader>(
+    reader: &R,
+    version: Version,
+    dir: &Path,
+    max_chunk_size: usize,
+) -> Result<StateSnapshotManifest> {
+    export_state_snapshot_with_scheme(reader, version, dir, max_chunk_size, &Blake3)
+}
+
+/// Same as [`export_state_snapshot`](fn.export_state_snapshot.html), for a tree computed with
+/// `hash_scheme`. The snapshot must be imported with the same scheme.
+pub fn export_state_snapshot_with_scheme<R: TreeReader, S: HashScheme>(
+    reader: &R,
+    version: Version,
+    dir: &Path,
+    max_chunk_size: usize,
+    hash_scheme: &S,
+) -> Result<StateSnapshotManifest> {
+    let tree = JellyfishMerkleTree::new_with_scheme(
+        reader,
+        None, /* leaf_count_mode */
+        hash_scheme.clone(),
+    );
+    let root_hash = tree
+        .get_root_hash_option(version)?
+        .ok_or_else(|| format_err!("Root node not found for version {}.", version))?;
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}.", dir))?;
+
+    let mut writer = ChunkWriter {
+        tree,
+        version,
+        dir,
+        chunks: vec![],
+    };
+    let mut chunk_bytes = vec![];
+    let mut chunk_first = None;
+    let mut last = None;
+    let iter = JellyfishMerkleIterator::new_borrowed(reader, version, HashValue::zero())?;
+    for (idx, res) in iter.enumerate() {
+        let (key, blob) = res?;
+        let idx = idx as u64;
+        let record_bytes = lcs::to_bytes(&(key, blob))?;
+        if let (Some(first), Some(last)) = (chunk_first, last) {
+            if chunk_bytes.len() + size_of::<u32>() + record_bytes.len() > max_chunk_size {
+                writer.write_chunk(&chunk_bytes, first, last)?;
+                chunk_bytes.clear();
+                chunk_first = None;
+            }
+        }
+        chunk_bytes.extend_from_slice(&(record_bytes.len() as u32).to_be_bytes());
+        chunk_bytes.extend_from_slice(&record_bytes);
+        chunk_first.get_or_insert((idx, key));
+        last = Some((idx, key));
+    }
+    match (chunk_first, last) {
+        (Some(first), Some(last)) => writer.write_chunk(&chunk_bytes, first, last)?,
+        _ => bail!("State is empty at version {}.", version),
+    }
+
+    let manifest = StateSnapshotManifest {
+        version,
+        root_hash,
+        chunks: writer.chunks,
+    };
+    let manifest_path = dir.join(MANIFEST_NAME);
+    fs::write(&manifest_path, lcs::to_bytes(&manifest)?)
+        .with_context(|| format!("Failed to write {:?}.", manifest_path))?;
+    Ok(manifest)
+}
+
+/// Reads the manifest of the snapshot in `dir`.
+pub fn read_manifest(dir: &Path) -> Result<StateSnapshotManifest> {
+    let manifest_path = dir.join(MANIFEST_NAME);
+    let bytes =
+        fs::read(&manifest_path).with_context(|| format!("Failed to read {:?}.", manifest_path))?;
+    Ok(lcs::from_bytes(&bytes)?)
+}
+
+/// Imports the snapshot in `dir` into `store` at `version`, verifying every chunk against
+/// `expected_root_hash`, which must come from a trusted source. The root hash in the manifest
+/// must match it. Returns the manifest.
+///
+/// If an earlier import into `store` at `version` was interrupted, this one resumes it, skipping
+/// the accounts it already wrote.
+pub fn import_state_snapshot<S: RestoreStore>(
+    store: &S,
+    dir: &Path,
+    version: Version,
+    expected_root_hash: HashValue,
+) -> Result<StateSnapshotManifest> {
+    import_state_snapshot_with_scheme(store, dir, version, expected_root_hash, &Blake3)
+}
+
+/// Same as [`import_state_snapshot`](fn.import_state_snapshot.html), but the hashes of the
+/// restored tree are computed with `hash_scheme`, which the snapshot must have been exported
+/// with.
+pub fn import_state_snapshot_with_scheme<S: RestoreStore, H: HashScheme>(
+    store: &S,
+    dir: &Path,
+    version: Version,
+    expected_root_hash: HashValue,
+    hash_scheme: &H,
+) -> Result<StateSnapshotManifest> {
+    let manifest = read_manifest(dir)?;
+    ensure!(
+        manifest.root_hash == expected_root_hash,
+        "Snapshot root hash {:x} does not match the expected root hash {:x}.",
+        manifest.root_hash,
+        expected_root_hash,
+    );
+    ensure!(!manifest.chunks.is_empty(), "Snapshot has no chunks.");
+
+    let mut restore = JellyfishMerkleRestore::new_with_scheme(
+        store,
+        version,
+        expected_root_hash,
+        hash_scheme.clone(),
+    )?;
+    // An interrupted import resumes after the keys already written to storage.
+    let progress = restore.progress().cloned();
+    let num_keys_done = progress.as_ref().map_or(0, |progress| progress.num_keys);
+    let mut next_idx = 0;
+    for chunk in &manifest.chunks {
+        ensure!(
+            chunk.first_idx == next_idx && chunk.last_idx >= chunk.first_idx,
+            "Chunk {} has unexpected indices {}-{}.",
+            chunk.blobs,
+            chunk.first_idx,
+            chunk.last_idx,
+        );
+        next_idx = chunk.last_idx + 1;
+        if chunk.last_idx < num_keys_done {
+            continue;
+        }
+        let mut blobs = read_account_state_chunk(&file_in_dir(dir, &chunk.blobs)?)?;
+        ensure!(
+            blobs.len() as u64 == chunk.last_idx - chunk.first_idx + 1,
+            "Chunk {} has {} accounts, expected {}.",
+            chunk.blobs,
+            blobs.len(),
+            chunk.last_idx - chunk.first_idx + 1,
+        );
+        ensure!(
+            blobs.first().map(|(key, _)| *key) == Some(chunk.first_key)
+                && blobs.last().map(|(key, _)| *key) == Some(chunk.last_key),
+            "Keys in chunk {} do not match the manifest.",
+            chunk.blobs,
+        );
+        if let Some(progress) = &progress {
+            if chunk.first_idx < num_keys_done {
+                let num_skipped = (num_keys_done - chunk.first_idx) as usize;
+                ensure!(
+                    blobs[num_skipped - 1].0 == progress.last_key,
+                    "Chunk {} does not match the progress of the interrupted import.",
+                    chunk.blobs,
+                );
+                blobs.drain(..num_skipped);
+            }
+        }
+        let proof = read_proof(&file_in_dir(dir, &chunk.proof)?)?;
+        restore.add_chunk(blobs, proof)?;
+    }
+    restore.finish()?;
+    Ok(manifest)
+}
+
+/// Returns the path of the file named `name` in `dir`. Returns error unless `name` is a single
+/// plain file name, so that a manifest cannot point outside the snapshot directory.
+fn file_in_dir(dir: &Path, name: &str) -> Result<PathBuf> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(file_name)), None) if file_name == name => Ok(dir.join(name)),
+        _ => bail!("{:?} is not a plain file name.", name),
+    }
+}
+
+/// Writes chunk files and the proofs for them.
+struct ChunkWriter<'a, R: TreeReader, S: HashScheme> {
+    tree: JellyfishMerkleTree<'a, R, S>,
+    version: Version,
+    dir: &'a Path,
+    chunks: Vec<StateSnapshotChunk>,
+}
+
+impl<'a, R: TreeReader, S: HashScheme> ChunkWriter<'a, R, S> {
+    fn write_chunk(
+        &mut self,
+        chunk_bytes: &[u8],
+        (first_idx, first_key): (u64, HashValue),
+        (last_idx, last_key): (u64, HashValue),
+    ) -> Result<()> {
+        let blobs = chunk_name(first_idx);
+        let blobs_path = self.dir.join(&blobs);
+        fs::write(&blobs_path, chunk_bytes)
+            .with_context(|| format!("Failed to write {:?}.", blobs_path))?;
+
+        let proof = chunk_proof_name(first_idx, last_idx);
+        let proof_path = self.dir.join(&proof);
+        let range_proof = self.tree.get_range_proof(last_key, self.version)?;
+        fs::write(&proof_path, lcs::to_bytes(&range_proof)?)
+            .with_context(|| format!("Failed to write {:?}.", proof_path))?;
+
+        self.chunks.push(StateSnapshotChunk {
+            first_idx,
+            last_idx,
+            first_key,
+            last_key,
+            blobs,
+            proof,
+        });
+        Ok(())
+    }
+}
+
+fn read_account_state_chunk(path: &Path) -> Result<Vec<(HashValue, AccountStateBlob)>> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {:?}.", path))?;
+    let mut chunk = vec![];
+    let mut remaining = &bytes[..];
+    while !remaining.is_empty() {
+        ensure!(
+            remaining.len() >= size_of::<u32>(),
+            "Truncated record length in {:?}.",
+            path,
+        );
+        let (len_bytes, rest) = remaining.split_at(size_of::<u32>());
+        let len = u32::from_be_bytes(len_bytes.try_into().expect("Slice has 4 bytes.")) as usize;
+        ensure!(rest.len() >= len, "Truncated record in {:?}.", path);
+        let (record_bytes, rest) = rest.split_at(len);
+        chunk.push(lcs::from_bytes(record_bytes)?);
+        remaining = rest;
+    }
+    Ok(chunk)
+}
+
+fn read_proof(path: &Path) -> Result<SparseMerkleRangeProof> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {:?}.", path))?;
+    Ok(lcs::from_bytes(&bytes)?)
+}
@@ -0,0 +1,265 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    mock_tree_store::MockTreeStore,
+    node_type::{Node, NodeKey},
+    restore::RestoreStore,
+    snapshot::{
+        export_state_snapshot, export_state_snapshot_with_scheme, import_state_snapshot,
+        import_state_snapshot_with_scheme, read_manifest, MANIFEST_NAME,
+    },
+    test_helper::init_mock_db_with_options,
+    JellyfishMerkleTree,
+};
+use libra_crypto::{
+    hash::{Blake3, Sha3_256},
+    HashValue,
+};
+use libra_temppath::TempPath;
+use libra_types::account_state_blob::AccountStateBlob;
+use proptest::{collection::btree_map, prelude::*};
+use std::{collections::BTreeMap, fs};
+
+fn init_db(kvs: &BTreeMap<HashValue, AccountStateBlob>) -> MockTreeStore {
+    let (db, _root_hashes) =
+        init_mock_db_with_options(&[kvs.clone().into_iter().collect()], None, Blake3);
+    db
+}
+
+fn new_temp_dir() -> TempPath {
+    let dir = TempPath::new();
+    dir.create_as_dir().unwrap();
+    dir
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(10))]
+
+    #[test]
+    fn test_export_import(
+        kvs in btree_map(any::<HashValue>(), any::<AccountStateBlob>(), 1..200),
+        max_chunk_size in 1..2000usize,
+    ) {
+        let db = init_db(&kvs);
+        let root_hash = JellyfishMerkleTree::new(&db).get_root_hash(0).unwrap();
+        let dir = new_temp_dir();
+
+        let manifest = export_state_snapshot(&db, 0, dir.path(), max_chunk_size).unwrap();
+        prop_assert_eq!(manifest.version, 0);
+        prop_assert_eq!(manifest.root_hash, root_hash);
+        prop_assert_eq!(manifest.chunks.last().unwrap().last_idx, kvs.len() as u64 - 1);
+        prop_assert_eq!(&read_manifest(dir.path()).unwrap(), &manifest);
+
+        let restore_db = MockTreeStore::default();
+        import_state_snapshot(&restore_db, dir.path(), 1 /* version */, root_hash).unwrap();
+        let tree = JellyfishMerkleTree::new(&restore_db);
+        prop_assert_eq!(tree.get_root_hash(1).unwrap(), root_hash);
+        for (key, value) in &kvs {
+            prop_assert_eq!(&tree.get(*key, 1).unwrap().unwrap(), value);
+        }
+    }
+}
+
+#[test]
+fn test_export_chunks() {
+    let kvs: BTreeMap<_, _> = (0..10u8)
+        .map(|i| {
+            (
+                HashValue::new([i; HashValue::LENGTH]),
+                AccountStateBlob::from(vec![i; 10]),
+            )
+        })
+        .collect();
+    let db = init_db(&kvs);
+    let dir = new_temp_dir();
+
+    // Each record is 4 bytes of length, 32 bytes of key and 11 bytes of blob, so three records fit
+    // in a chunk.
+    let manifest = export_state_snapshot(&db, 0, dir.path(), 150).unwrap();
+    let indices: Vec<_> = manifest
+        .chunks
+        .iter()
+        .map(|chunk| (chunk.first_idx, chunk.last_idx))
+        .collect();
+    assert_eq!(indices, vec![(0, 2), (3, 5), (6, 8), (9, 9)]);
+    assert_eq!(manifest.chunks[1].first_key, HashValue::new([3; 32]));
+    assert_eq!(manifest.chunks[1].last_key, HashValue::new([5; 32]));
+    for chunk in &manifest.chunks {
+        assert!(dir.path().join(&chunk.blobs).is_file());
+        assert!(dir.path().join(&chunk.proof).is_file());
+    }
+}
+
+#[test]
+fn test_export_empty_tree() {
+    let db = MockTreeStore::default();
+    db.put_node(NodeKey::new_empty_path(0), Node::new_null())
+        .unwrap();
+    let dir = new_temp_dir();
+    assert!(export_state_snapshot(&db, 0, dir.path(), 1000).is_err());
+}
+
+#[test]
+fn test_import_tampered_snapshot() {
+    let kvs: BTreeMap<_, _> = (0..10u8)
+        .map(|i| {
+            (
+                HashValue::new([i; HashValue::LENGTH]),
+                AccountStateBlob::from(vec![i; 10]),
+            )
+        })
+        .collect();
+    let db = init_db(&kvs);
+    let dir = new_temp_dir();
+    let manifest = export_state_snapshot(&db, 0, dir.path(), 150).unwrap();
+
+    let root_hash = manifest.root_hash;
+
+    // Flip the last byte of a blob in the second chunk.
+    let chunk_path = dir.path().join(&manifest.chunks[1].blobs);
+    let mut chunk_bytes = fs::read(&chunk_path).unwrap();
+    *chunk_bytes.last_mut().unwrap() ^= 1;
+    fs::write(&chunk_path, chunk_bytes).unwrap();
+    assert!(import_state_snapshot(&MockTreeStore::default(), dir.path(), 0, root_hash).is_err());
+
+    // A manifest naming another root hash is rejected too.
+    let dir = new_temp_dir();
+    let mut manifest = export_state_snapshot(&db, 0, dir.path(), 150).unwrap();
+    manifest.root_hash = HashValue::zero();
+    fs::write(
+        dir.path().join(MANIFEST_NAME),
+        lcs::to_bytes(&manifest).unwrap(),
+    )
+    .unwrap();
+    assert!(import_state_snapshot(&MockTreeStore::default(), dir.path(), 0, root_hash).is_err());
+
+    // So is an intact snapshot of a root other than the trusted one.
+    let dir = new_temp_dir();
+    export_state_snapshot(&db, 0, dir.path(), 150).unwrap();
+    assert!(
+        import_state_snapshot(&MockTreeStore::default(), dir.path(), 0, HashValue::zero()).is_err()
+    );
+}
+
+#[test]
+fn test_import_interrupted() {
+    let kvs: BTreeMap<_, _> = (0..10u8)
+        .map(|i| {
+            (
+                HashValue::new([i; HashValue::LENGTH]),
+                AccountStateBlob::from(vec![i; 10]),
+            )
+        })
+        .collect();
+    let db = init_db(&kvs);
+    let dir = new_temp_dir();
+    let manifest = export_state_snapshot(&db, 0, dir.path(), 150).unwrap();
+    let root_hash = manifest.root_hash;
+
+    // Interrupt the import at the third chunk, after some keys of the second one are written.
+    let chunk_path = dir.path().join(&manifest.chunks[2].blobs);
+    let chunk_bytes = fs::read(&chunk_path).unwrap();
+    let mut tampered_bytes = chunk_bytes.clone();
+    *tampered_bytes.last_mut().unwrap() ^= 1;
+    fs::write(&chunk_path, tampered_bytes).unwrap();
+    let restore_db = MockTreeStore::default();
+    assert!(import_state_snapshot(&restore_db, dir.path(), 0, root_hash).is_err());
+    assert!(restore_db
+        .get_restore_progress(&NodeKey::new_empty_path(0))
+        .unwrap()
+        .is_some());
+
+    // Running the import again resumes it.
+    fs::write(&chunk_path, chunk_bytes).unwrap();
+    import_state_snapshot(&restore_db, dir.path(), 0, root_hash).unwrap();
+    let tree = JellyfishMerkleTree::new(&restore_db);
+    assert_eq!(tree.get_root_hash(0).unwrap(), root_hash);
+    for (key, value) in &kvs {
+        assert_eq!(&tree.get(*key, 0).unwrap().unwrap(), value);
+    }
+}
+
+#[test]
+fn test_import_chunk_outside_snapshot_dir() {
+    let kvs: BTreeMap<_, _> = (0..10u8)
+        .map(|i| {
+            (
+                HashValue::new([i; HashValue::LENGTH]),
+                AccountStateBlob::from(vec![i; 10]),
+            )
+        })
+        .collect();
+    let db = init_db(&kvs);
+    let dir = new_temp_dir();
+    let manifest = export_state_snapshot(&db, 0, dir.path(), 1000).unwrap();
+    let root_hash = manifest.root_hash;
+
+    // The files are intact, but the manifest does not name them by plain file names.
+    fs::create_dir(dir.path().join("sub")).unwrap();
+    for name in &[
+        format!("sub/../{}", manifest.chunks[0].blobs),
+        format!("./{}", manifest.chunks[0].blobs),
+        dir.path()
+            .join(&manifest.chunks[0].blobs)
+            .to_str()
+            .unwrap()
+            .to_string(),
+    ] {
+        let mut bad_manifest = manifest.clone();
+        bad_manifest.chunks[0].blobs = name.clone();
+        fs::write(
+            dir.path().join(MANIFEST_NAME),
+            lcs::to_bytes(&bad_manifest).unwrap(),
+        )
+        .unwrap();
+        assert!(
+            import_state_snapshot(&MockTreeStore::default(), dir.path(), 0, root_hash).is_err()
+        );
+    }
+
+    let mut bad_manifest = manifest.clone();
+    bad_manifest.chunks[0].proof = "../proof".to_string();
+    fs::write(
+        dir.path().join(MANIFEST_NAME),
+        lcs::to_bytes(&bad_manifest).unwrap(),
+    )
+    .unwrap();
+    assert!(import_state_snapshot(&MockTreeStore::default(), dir.path(), 0, root_hash).is_err());
+
+    fs::write(
+        dir.path().join(MANIFEST_NAME),
+        lcs::to_bytes(&manifest).unwrap(),
+    )
+    .unwrap();
+    import_state_snapshot(&MockTreeStore::default(), dir.path(), 0, root_hash).unwrap();
+}
+
+#[test]
+fn test_export_import_with_scheme() {
+    let blob_sets = vec![(0..10u8)
+        .map(|i| {
+            (
+                HashValue::new([i; HashValue::LENGTH]),
+                AccountStateBlob::from(vec![i; 10]),
+            )
+        })
+        .collect::<Vec<_>>()];
+    let (db, root_hashes) = init_mock_db_with_options(&blob_sets, None, Sha3_256);
+    let dir = new_temp_dir();
+    let manifest = export_state_snapshot_with_scheme(&db, 0, dir.path(), 150, &Sha3_256).unwrap();
+    assert_eq!(manifest.root_hash, root_hashes[0]);
+
+    // The chunks only verify against the root hash with the scheme of the tree.
+    assert!(
+        import_state_snapshot(&MockTreeStore::default(), dir.path(), 0, root_hashes[0]).is_err()
+    );
+    let restore_db = MockTreeStore::default();
+    import_state_snapshot_with_scheme(&restore_db, dir.path(), 0, root_hashes[0], &Sha3_256)
+        .unwrap();
+    let tree = JellyfishMerkleTree::new_with_scheme(&restore_db, None, Sha3_256);
+    assert_eq!(tree.get_root_hash(0).unwrap(), root_hashes[0]);
+    for (key, value) in &blob_sets[0] {
+        assert_eq!(&tree.get(*key, 0).unwrap().unwrap(), value);
+    }
+}
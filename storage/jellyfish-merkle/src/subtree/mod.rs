@@ -0,0 +1,224 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module implements the proof that links the root of a subtree, addressed by a nibble path
+//! prefix, to the root of the whole tree. A subtree here is the part of the sparse Merkle tree
+//! covering all keys that start with the prefix, so its root hash is:
+//!   - `SPARSE_MERKLE_PLACEHOLDER_HASH` if there is no key under the prefix,
+//!   - the hash of the leaf if there is exactly one key under the prefix (in which case the leaf
+//!     may sit above the prefix in the Jellyfish Merkle tree), or
+//!   - the hash of the internal node at the prefix otherwise.
+//!
+//! [`compute_subtree_root_hash`] computes the same hash from all the leaves under a prefix, so a
+//! client holding a bucket of keys can verify all of them against the state root at once.
+
+#[cfg(test)]
+mod subtree_test;
+
+use crate::{iterator::path_to_key, nibble_path::NibblePath};
+use anyhow::{ensure, Result};
+use libra_crypto::{
+    hash::{Blake3, HashScheme},
+    HashValue,
+};
+use libra_nibble::Nibble;
+use libra_types::{
+    account_state_blob::AccountStateBlob,
+    proof::{SparseMerkleInternalNode, SparseMerkleLeafNode},
+};
+
+/// The proof that the subtree at some prefix has a certain root hash. Similar to
+/// `SparseMerkleProof`, the proof may end at a leaf or an empty subtree above the prefix.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SubtreeProof {
+    /// If the path from the root to the prefix ends at a leaf above or exactly at the prefix,
+    /// this is that leaf.
+    leaf: Option<SparseMerkleLeafNode>,
+
+    /// All siblings on the path from the root down to the prefix, or to where the path ends. The
+    /// siblings close to the root are at the end of the list.
+    siblings: Vec<HashValue>,
+}
+
+impl SubtreeProof {
+    /// Constructs a new `SubtreeProof` using leaf and a list of siblings.
+    pub fn new(leaf: Option<SparseMerkleLeafNode>, siblings: Vec<HashValue>) -> Self {
+        Self { leaf, siblings }
+    }
+
+    /// Returns the leaf node in this proof.
+    pub fn leaf(&self) -> Option<SparseMerkleLeafNode> {
+        self.leaf
+    }
+
+    /// Returns the list of siblings in this proof.
+    pub fn siblings(&self) -> &[HashValue] {
+        &self.siblings
+    }
+
+    /// Verifies that the subtree at `prefix` has root hash `subtree_root_hash` in the tree with
+    /// root hash `expected_root_hash`.
+    pub fn verify(
+        &self,
+        expected_root_hash: HashValue,
+        prefix: &NibblePath,
+        subtree_root_hash: HashValue,
+    ) -> Result<()> {
+        self.verify_with_scheme(&Blake3, expected_root_hash, prefix, subtree_root_hash)
+    }
+
+    /// Same as [`verify`](struct.SubtreeProof.html#method.verify), for a tree computed with
+    /// `hash_scheme`.
+    pub fn verify_with_scheme<S: HashScheme>(
+        &self,
+        hash_scheme: &S,
+        expected_root_hash: HashValue,
+        prefix: &NibblePath,
+        subtree_root_hash: HashValue,
+    ) -> Result<()> {
+        let prefix_bits: Vec<bool> = prefix.bits().collect();
+        ensure!(
+            self.siblings.len() <= prefix_bits.len(),
+            "Subtree proof has more than {} siblings: {}.",
+            prefix_bits.len(),
+            self.siblings.len(),
+        );
+
+        let current_hash = match self.leaf {
+            Some(leaf) => {
+                // The leaf must sit on the path to the prefix.
+                let leaf_bits: Vec<bool> = leaf.key().iter_bits().collect();
+                ensure!(
+                    leaf_bits[..self.siblings.len()] == prefix_bits[..self.siblings.len()],
+                    "Leaf with key {:x} is not on the path to prefix {:?}.",
+                    leaf.key(),
+                    prefix,
+                );
+                // If the leaf is under the prefix, it is the only one there. Otherwise there is
+                // nothing under the prefix.
+                let leaf_hash = leaf.hash_with_scheme(hash_scheme);
+                let expected_subtree_root_hash =
+                    if leaf_bits[..prefix_bits.len()] == prefix_bits[..] {
+                        leaf_hash
+                    } else {
+                        hash_scheme.placeholder_hash()
+                    };
+                ensure!(
+                    subtree_root_hash == expected_subtree_root_hash,
+                    "Subtree root hashes do not match. Actual: {:x} Expected: {:x}",
+                    subtree_root_hash,
+                    expected_subtree_root_hash,
+                );
+                leaf_hash
+            }
+            None => {
+                // If the proof ends above the prefix, the path ends at an empty subtree.
+                ensure!(
+                    self.siblings.len() == prefix_bits.len()
+                        || subtree_root_hash == hash_scheme.placeholder_hash(),
+                    "Expected empty subtree at prefix {:?}, got root hash {:x}.",
+                    prefix,
+                    subtree_root_hash,
+                );
+                subtree_root_hash
+            }
+        };
+
+        let actual_root_hash = self
+            .siblings
+            .iter()
+            .zip(prefix_bits[..self.siblings.len()].iter().rev())
+            .fold(current_hash, |hash, (sibling_hash, bit)| {
+                if *bit {
+                    SparseMerkleInternalNode::new(*sibling_hash, hash).hash_with_scheme(hash_scheme)
+                } else {
+                    SparseMerkleInternalNode::new(hash, *sibling_hash).hash_with_scheme(hash_scheme)
+                }
+            });
+        ensure!(
+            actual_root_hash == expected_root_hash,
+            "Root hashes do not match. Actual root hash: {:x}. Expected root hash: {:x}.",
+            actual_root_hash,
+            expected_root_hash,
+        );
+
+        Ok(())
+    }
+}
+
+/// Computes the root hash of the subtree at `prefix` from all the key-blob pairs under it. The
+/// pairs must be sorted by key.
+pub fn compute_subtree_root_hash(
+    prefix: &NibblePath,
+    kvs: &[(HashValue, AccountStateBlob)],
+) -> Result<HashValue> {
+    compute_subtree_root_hash_with_scheme(prefix, kvs, &Blake3)
+}
+
+/// Same as [`compute_subtree_root_hash`](fn.compute_subtree_root_hash.html), for a tree computed
+/// with `hash_scheme`.
+pub fn compute_subtree_root_hash_with_scheme<S: HashScheme>(
+    prefix: &NibblePath,
+    kvs: &[(HashValue, AccountStateBlob)],
+    hash_scheme: &S,
+) -> Result<HashValue> {
+    let prefix_bits: Vec<bool> = prefix.bits().collect();
+    let mut leaves = Vec::with_capacity(kvs.len());
+    for (key, blob) in kvs {
+        if let Some((prev_key, _)) = leaves.last() {
+            ensure!(key > prev_key, "Keys must be sorted and unique.");
+        }
+        ensure!(
+            key.iter_bits()
+                .take(prefix_bits.len())
+                .eq(prefix_bits.iter().cloned()),
+            "Key {:x} is not under prefix {:?}.",
+            key,
+            prefix,
+        );
+        let leaf = SparseMerkleLeafNode::new(*key, blob.hash_with_scheme(hash_scheme));
+        leaves.push((*key, leaf.hash_with_scheme(hash_scheme)));
+    }
+    Ok(compute_root_hash_impl(
+        &leaves,
+        prefix_bits.len(),
+        hash_scheme,
+    ))
+}
+
+/// Computes the root hash of the subtree at `depth` bits containing exactly `leaves`.
+fn compute_root_hash_impl<S: HashScheme>(
+    leaves: &[(HashValue, HashValue)],
+    depth: usize,
+    hash_scheme: &S,
+) -> HashValue {
+    match leaves.len() {
+        0 => hash_scheme.placeholder_hash(),
+        1 => leaves[0].1,
+        _ => {
+            let split = leaves
+                .iter()
+                .position(|(key, _)| key.iter_bits().nth(depth).expect("Keys are distinct."))
+                .unwrap_or(leaves.len());
+            let left = compute_root_hash_impl(&leaves[..split], depth + 1, hash_scheme);
+            let right = compute_root_hash_impl(&leaves[split..], depth + 1, hash_scheme);
+            SparseMerkleInternalNode::new(left, right).hash_with_scheme(hash_scheme)
+        }
+    }
+}
+
+/// Returns the smallest key under `prefix`, and the smallest key after all keys under `prefix`
+/// if there is one.
+pub(crate) fn prefix_key_range(prefix: &NibblePath) -> (HashValue, Option<HashValue>) {
+    let start_key = path_to_key(prefix);
+
+    let mut nibbles: Vec<u8> = prefix.nibbles().map(u8::from).collect();
+    while let Some(nibble) = nibbles.pop() {
+        if nibble < 0xf {
+            nibbles.push(nibble + 1);
+            let next_prefix: NibblePath = nibbles.into_iter().map(Nibble::from).collect();
+            return (start_key, Some(path_to_key(&next_prefix)));
+        }
+    }
+    (start_key, None)
+}
@@ -0,0 +1,152 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    mock_tree_store::MockTreeStore,
+    nibble_path::NibblePath,
+    node_type::{Node, NodeKey},
+    subtree::{compute_subtree_root_hash, compute_subtree_root_hash_with_scheme},
+    test_helper::{init_mock_db, init_mock_db_with_options},
+    JellyfishMerkleTree,
+};
+use anyhow::Result;
+use libra_crypto::{
+    hash::{Sha3_256, SPARSE_MERKLE_PLACEHOLDER_HASH},
+    HashValue,
+};
+use libra_nibble::Nibble;
+use libra_types::{account_state_blob::AccountStateBlob, transaction::Version};
+use proptest::{collection::btree_map, prelude::*};
+use std::collections::BTreeMap;
+
+fn key_prefix(key: HashValue, num_nibbles: usize) -> NibblePath {
+    (0..num_nibbles).map(|i| key.get_nibble(i)).collect()
+}
+
+fn test_prefix(
+    tree: &JellyfishMerkleTree<MockTreeStore>,
+    btree: &BTreeMap<HashValue, AccountStateBlob>,
+    version: Version,
+    prefix: &NibblePath,
+) {
+    let root_hash = tree.get_root_hash(version).unwrap();
+    let expected_kvs: Vec<_> = btree
+        .iter()
+        .filter(|(key, _)| key_prefix(**key, prefix.num_nibbles()) == *prefix)
+        .map(|(key, blob)| (*key, blob.clone()))
+        .collect();
+
+    let kvs = tree
+        .iter_subtree(prefix, version)
+        .unwrap()
+        .collect::<Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(kvs, expected_kvs);
+
+    let (subtree_root_hash, proof) = tree.get_subtree_root_with_proof(prefix, version).unwrap();
+    assert_eq!(
+        compute_subtree_root_hash(prefix, &kvs).unwrap(),
+        subtree_root_hash
+    );
+    proof.verify(root_hash, prefix, subtree_root_hash).unwrap();
+
+    // A wrong subtree root hash must not pass.
+    for wrong_hash in &[HashValue::zero(), *SPARSE_MERKLE_PLACEHOLDER_HASH] {
+        if *wrong_hash != subtree_root_hash {
+            assert!(proof.verify(root_hash, prefix, *wrong_hash).is_err());
+        }
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(10))]
+
+    #[test]
+    fn test_subtree_proof(
+        btree in btree_map(any::<HashValue>(), any::<AccountStateBlob>(), 1..1000),
+        short_prefixes in proptest::collection::vec(any::<NibblePath>(), 10),
+    ) {
+        let (db, version) = init_mock_db(&btree.clone().into_iter().collect());
+        let tree = JellyfishMerkleTree::new(&db);
+
+        test_prefix(&tree, &btree, version, &NibblePath::new(vec![]));
+        for prefix in &short_prefixes {
+            test_prefix(&tree, &btree, version, prefix);
+        }
+        // Prefixes of existing keys cover internal nodes, leaves above the prefix and leaves at
+        // full depth.
+        for key in btree.keys().take(10) {
+            for num_nibbles in &[1, 2, 3, 4, 8, 64] {
+                test_prefix(&tree, &btree, version, &key_prefix(*key, *num_nibbles));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_subtree_of_empty_tree() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::new(&db);
+    db.put_node(NodeKey::new_empty_path(0), Node::new_null())
+        .unwrap();
+
+    let prefix: NibblePath = vec![Nibble::from(1)].into_iter().collect();
+    let (subtree_root_hash, proof) = tree.get_subtree_root_with_proof(&prefix, 0).unwrap();
+    assert_eq!(subtree_root_hash, *SPARSE_MERKLE_PLACEHOLDER_HASH);
+    proof
+        .verify(*SPARSE_MERKLE_PLACEHOLDER_HASH, &prefix, subtree_root_hash)
+        .unwrap();
+    assert_eq!(tree.iter_subtree(&prefix, 0).unwrap().count(), 0);
+}
+
+#[test]
+fn test_subtree_at_max_prefix() {
+    let key1 = HashValue::new([0xff; HashValue::LENGTH]);
+    let mut key2_bytes = [0xff; HashValue::LENGTH];
+    key2_bytes[0] = 0xfe;
+    let key2 = HashValue::new(key2_bytes);
+    let btree: BTreeMap<_, _> = vec![
+        (key1, AccountStateBlob::from(vec![1u8])),
+        (key2, AccountStateBlob::from(vec![2u8])),
+    ]
+    .into_iter()
+    .collect();
+    let (db, version) = init_mock_db(&btree.clone().into_iter().collect());
+    let tree = JellyfishMerkleTree::new(&db);
+
+    test_prefix(&tree, &btree, version, &key_prefix(key1, 1));
+    test_prefix(&tree, &btree, version, &key_prefix(key1, 2));
+    test_prefix(&tree, &btree, version, &key_prefix(key2, 2));
+}
+
+#[test]
+fn test_subtree_with_scheme() {
+    let kvs: Vec<_> = (0..50u8)
+        .map(|i| (HashValue::random(), AccountStateBlob::from(vec![i])))
+        .collect();
+    let (db, root_hashes) = init_mock_db_with_options(&[kvs], None, Sha3_256);
+    let tree = JellyfishMerkleTree::new_with_scheme(&db, None, Sha3_256);
+
+    for num_nibbles in 0..3 {
+        let prefix: NibblePath = (0..num_nibbles).map(Nibble::from).collect();
+        let (subtree_root_hash, proof) = tree.get_subtree_root_with_proof(&prefix, 0).unwrap();
+        let subtree_kvs = tree
+            .iter_subtree(&prefix, 0)
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            compute_subtree_root_hash_with_scheme(&prefix, &subtree_kvs, &Sha3_256).unwrap(),
+            subtree_root_hash
+        );
+        proof
+            .verify_with_scheme(&Sha3_256, root_hashes[0], &prefix, subtree_root_hash)
+            .unwrap();
+        // The empty prefix is the whole tree, which the proof has nothing to check for.
+        if num_nibbles > 0 {
+            assert!(proof
+                .verify(root_hashes[0], &prefix, subtree_root_hash)
+                .is_err());
+        }
+    }
+}
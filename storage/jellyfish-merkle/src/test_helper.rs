@@ -1,8 +1,11 @@
 // Copyright (c) The Libra Core Contributors
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::{mock_tree_store::MockTreeStore, JellyfishMerkleTree};
-use libra_crypto::HashValue;
+use crate::{mock_tree_store::MockTreeStore, node_type::LeafCountMode, JellyfishMerkleTree};
+use libra_crypto::{
+    hash::{Blake3, HashScheme},
+    HashValue,
+};
 use libra_types::{account_state_blob::AccountStateBlob, transaction::Version};
 use std::collections::HashMap;
 
@@ -26,15 +29,42 @@ pub fn plus_one(key: HashValue) -> HashValue {
 pub fn init_mock_db(kvs: &HashMap<HashValue, AccountStateBlob>) -> (MockTreeStore, Version) {
     assert!(!kvs.is_empty());
 
-    let db = MockTreeStore::default();
-    let tree = JellyfishMerkleTree::new(&db);
-
-    for (i, (key, value)) in kvs.iter().enumerate() {
-        let (_root_hash, write_batch) = tree
-            .put_blob_set(vec![(*key, value.clone())], i as Version)
-            .unwrap();
-        db.write_tree_update_batch(write_batch).unwrap();
-    }
+    let blob_sets: Vec<_> = kvs
+        .iter()
+        .map(|(key, value)| vec![(*key, value.clone())])
+        .collect();
+    let (db, _root_hashes) = init_mock_db_with_options(&blob_sets, None, Blake3);
 
     (db, (kvs.len() - 1) as Version)
 }
+
+/// Initializes a DB by putting each blob set at its own version, starting from version 0, into a
+/// tree with the given leaf count mode and hash scheme. A blob of `None` deletes the key. Returns
+/// the DB and the root hash of each version.
+pub fn init_mock_db_with_options<B, S>(
+    blob_sets: &[Vec<(HashValue, B)>],
+    leaf_count_mode: Option<LeafCountMode>,
+    hash_scheme: S,
+) -> (MockTreeStore, Vec<HashValue>)
+where
+    B: Clone + Into<Option<AccountStateBlob>>,
+    S: HashScheme,
+{
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::new_with_scheme(&db, leaf_count_mode, hash_scheme);
+    let blob_sets = blob_sets
+        .iter()
+        .map(|blob_set| {
+            blob_set
+                .iter()
+                .map(|(key, blob)| (*key, blob.clone().into()))
+                .collect()
+        })
+        .collect();
+    let (root_hashes, batch) = tree
+        .put_blob_sets2(blob_sets, 0 /* first_version */)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    (db, root_hashes)
+}
@@ -65,17 +65,37 @@
 //!      anything. Otherwise we delete it from the tree cache.
 //! Updating node could be operated as deletion of the node followed by insertion of the updated
 //! node.
+//!
+//! By default everything is kept in memory until the whole batch is returned. In the bounded mode
+//! created by [`TreeCache::new_with_spilling`], nodes that can no longer change are written to a
+//! [`TreeWriter`] whenever there are too many nodes in memory, along with the stale node indices
+//! collected so far:
+//!   1) All the frozen nodes, since they are immutable.
+//!   2) The nodes of the current version that are to the left of the last key put, as long as the
+//!      keys of the current version have been put in increasing order. Later keys of the version
+//!      never visit these nodes. A leaf whose parent is on the path of the last key is kept though,
+//!      since deleting a later key may move it up to its parent.
+//! Whatever is written this way is left out of the final batch, so the written parts and the
+//! final batch together are exactly the batch of the in-memory mode. The written nodes must be
+//! readable from the `TreeReader` afterwards, since the later versions in the batch may need them.
+//!
+//! [`TreeCache::new_with_spilling`]: struct.TreeCache.html#method.new_with_spilling
+//! [`TreeWriter`]: ../trait.TreeWriter.html
 
 #[cfg(test)]
 mod tree_cache_test;
 
 use crate::{
+    nibble_path::{skip_common_prefix, NibblePath},
     node_type::{Node, NodeKey},
-    StaleNodeIndex, TreeReader, TreeUpdateBatch,
+    StaleNodeIndex, StaleNodeIndexWriter, TreeReader, TreeUpdateBatch, TreeWriter,
 };
 use anyhow::{bail, Result};
-use libra_crypto::HashValue;
-use libra_types::transaction::{Version, PRE_GENESIS_VERSION};
+use libra_crypto::{hash::HashScheme, HashValue};
+use libra_types::{
+    account_state_blob::AccountStateBlob,
+    transaction::{Version, PRE_GENESIS_VERSION},
+};
 use std::{
     collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap, HashSet},
     convert::Into,
@@ -101,6 +121,26 @@ struct FrozenTreeCache {
 
     /// Frozen root hashes after each earlier transaction.
     root_hashes: Vec<HashValue>,
+
+    /// Blobs of the hash-only leaves.
+    value_cache: BTreeMap<HashValue, AccountStateBlob>,
+}
+
+/// Where the bounded mode of `TreeCache` writes the nodes, stale node indices and blobs that no
+/// longer need to be kept in memory.
+struct SpillTarget<'a> {
+    /// Writes the nodes and the blobs of hash-only leaves.
+    node_writer: &'a dyn TreeWriter,
+
+    /// Writes the stale node indices.
+    stale_node_index_writer: &'a dyn StaleNodeIndexWriter,
+
+    /// Nodes are written once there are this many of them in memory.
+    max_nodes_in_memory: usize,
+
+    /// Whether all the keys of the current version are put in increasing order, so that new nodes
+    /// to the left of a key that has been put can no longer change.
+    keys_sorted: bool,
 }
 
 /// `TreeCache` is a in-memory cache for per-transaction updates of sparse Merkle nodes and value
@@ -129,6 +169,9 @@ pub struct TreeCache<'a, R: 'a + TreeReader> {
 
     /// The underlying persistent storage.
     reader: &'a R,
+
+    /// Where to write nodes that can no longer change, in the bounded mode.
+    spill_target: Option<SpillTarget<'a>>,
 }
 
 impl<'a, R> TreeCache<'a, R>
@@ -169,9 +212,30 @@ where
             reader,
             num_stale_leaves: 0,
             num_new_leaves: 0,
+            spill_target: None,
         })
     }
 
+    /// Constructs a new `TreeCache` instance in the bounded mode, which writes the nodes that can
+    /// no longer change and the stale node indices to `writer` once there are
+    /// `max_nodes_in_memory` nodes in memory. `writer` must write into the storage that `reader`
+    /// reads from.
+    pub fn new_with_spilling<W: TreeWriter + StaleNodeIndexWriter>(
+        reader: &'a R,
+        next_version: Version,
+        writer: &'a W,
+        max_nodes_in_memory: usize,
+    ) -> Result<Self> {
+        let mut tree_cache = Self::new(reader, next_version)?;
+        tree_cache.spill_target = Some(SpillTarget {
+            node_writer: writer,
+            stale_node_index_writer: writer,
+            max_nodes_in_memory,
+            keys_sorted: false,
+        });
+        Ok(tree_cache)
+    }
+
     /// Gets a node with given node key. If it doesn't exist in node cache, read from `reader`.
     pub fn get_node(&self, node_key: &NodeKey) -> Result<Node> {
         Ok(if let Some(node) = self.node_cache.get(node_key) {
@@ -207,6 +271,13 @@ where
         Ok(())
     }
 
+    /// Puts the blob of a hash-only leaf into value_cache. Blobs are keyed by their hashes, so
+    /// they are never stale and don't need to be frozen. In the bounded mode, they are written
+    /// whenever nodes are, so a leaf is never written before its blob.
+    pub fn put_value(&mut self, blob_hash: HashValue, blob: AccountStateBlob) {
+        self.frozen_cache.value_cache.insert(blob_hash, blob);
+    }
+
     /// Deletes a node with given hash.
     pub fn delete_node(&mut self, old_node_key: &NodeKey, is_leaf: bool) {
         // If node cache doesn't have this node, it means the node is in the previous version of
@@ -222,13 +293,14 @@ where
         }
     }
 
-    /// Freezes all the contents in cache to be immutable and clear `node_cache`.
-    pub fn freeze(&mut self) {
+    /// Freezes all the contents in cache to be immutable and clear `node_cache`. The root hash is
+    /// computed with `hash_scheme`.
+    pub fn freeze<S: HashScheme>(&mut self, hash_scheme: &S) {
         let root_node_key = self.get_root_node_key();
         let root_hash = self
             .get_node(root_node_key)
             .unwrap_or_else(|_| unreachable!("Root node with key {:?} must exist", root_node_key))
-            .hash();
+            .hash_with_scheme(hash_scheme);
         self.frozen_cache.root_hashes.push(root_hash);
         self.frozen_cache.node_cache.extend(self.node_cache.drain());
 
@@ -249,6 +321,92 @@ where
         self.num_new_leaves = 0;
 
         self.next_version += 1;
+        if let Some(spill_target) = &mut self.spill_target {
+            spill_target.keys_sorted = false;
+        }
+    }
+
+    /// Tells the cache whether all the keys of the current version are put in increasing order.
+    /// In the bounded mode, new nodes of the current version are only written early if they are,
+    /// since an out-of-order key may still change any of them.
+    pub fn set_keys_sorted(&mut self, keys_sorted: bool) {
+        if let Some(spill_target) = &mut self.spill_target {
+            spill_target.keys_sorted = keys_sorted;
+        }
+    }
+
+    /// Notifies the cache that `key` has been put in the current version. In the bounded mode,
+    /// writes out what no longer needs to be kept in memory if there are too many nodes.
+    pub fn finish_put(&mut self, key: HashValue) -> Result<()> {
+        let spill_target = match &mut self.spill_target {
+            Some(spill_target) => spill_target,
+            None => return Ok(()),
+        };
+        if self.node_cache.len() + self.frozen_cache.node_cache.len()
+            < spill_target.max_nodes_in_memory
+        {
+            return Ok(());
+        }
+
+        let mut node_batch = std::mem::take(&mut self.frozen_cache.node_cache);
+        if spill_target.keys_sorted {
+            let key_path = NibblePath::new(key.to_vec());
+            let finished_node_keys: Vec<_> = self
+                .node_cache
+                .iter()
+                .filter(|(node_key, node)| {
+                    is_left_of(node_key.nibble_path(), node.is_leaf(), &key_path)
+                })
+                .map(|(node_key, _node)| node_key.clone())
+                .collect();
+            for node_key in finished_node_keys {
+                let node = self.node_cache.remove(&node_key).expect("Must exist.");
+                node_batch.insert(node_key, node);
+            }
+        }
+        let value_batch = std::mem::take(&mut self.frozen_cache.value_cache);
+        if !value_batch.is_empty() {
+            spill_target.node_writer.write_value_batch(&value_batch)?;
+        }
+        if !node_batch.is_empty() {
+            spill_target.node_writer.write_node_batch(&node_batch)?;
+        }
+
+        let mut stale_node_index_batch =
+            std::mem::take(&mut self.frozen_cache.stale_node_index_cache);
+        let stale_since_version = self.next_version;
+        stale_node_index_batch.extend(self.stale_node_index_cache.drain().map(|node_key| {
+            StaleNodeIndex {
+                stale_since_version,
+                node_key,
+            }
+        }));
+        if !stale_node_index_batch.is_empty() {
+            spill_target
+                .stale_node_index_writer
+                .write_stale_node_index_batch(&stale_node_index_batch)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns whether the node at `nibble_path` is to the left of the path of a key that has been put,
+/// so putting greater keys never changes it. A leaf must also not be a child of a node on the path,
+/// otherwise it may move up.
+fn is_left_of(nibble_path: &NibblePath, is_leaf: bool, key_path: &NibblePath) -> bool {
+    let mut node_nibbles = nibble_path.nibbles();
+    let mut key_nibbles = key_path.nibbles();
+    let num_common_nibbles = skip_common_prefix(&mut node_nibbles, &mut key_nibbles);
+    let min_num_nibbles = if is_leaf {
+        num_common_nibbles + 2
+    } else {
+        num_common_nibbles + 1
+    };
+    match (node_nibbles.next(), key_nibbles.next()) {
+        (Some(node_nibble), Some(key_nibble)) => {
+            node_nibble < key_nibble && nibble_path.num_nibbles() >= min_num_nibbles
+        }
+        _ => false,
     }
 }
 
@@ -264,6 +422,7 @@ where
                 stale_node_index_batch: self.frozen_cache.stale_node_index_cache,
                 num_new_leaves: self.frozen_cache.num_new_leaves,
                 num_stale_leaves: self.frozen_cache.num_stale_leaves,
+                value_batch: self.frozen_cache.value_cache,
             },
         )
     }
@@ -3,7 +3,7 @@
 
 use super::*;
 use crate::{mock_tree_store::MockTreeStore, nibble_path::NibblePath, node_type::Node, NodeKey};
-use libra_crypto::HashValue;
+use libra_crypto::{hash::Blake3, HashValue};
 use libra_types::account_state_blob::AccountStateBlob;
 
 fn random_leaf_with_key(next_version: Version) -> (Node, NodeKey) {
@@ -69,12 +69,12 @@ fn test_freeze_with_delete() {
     cache.put_node(node2_key.clone(), node2.clone()).unwrap();
     assert_eq!(cache.get_node(&node1_key).unwrap(), node1);
     assert_eq!(cache.get_node(&node2_key).unwrap(), node2);
-    cache.freeze();
+    cache.freeze(&Blake3);
     assert_eq!(cache.get_node(&node1_key).unwrap(), node1);
     assert_eq!(cache.get_node(&node2_key).unwrap(), node2);
 
     cache.delete_node(&node1_key, true /* is_leaf */);
-    cache.freeze();
+    cache.freeze(&Blake3);
     let (_, update_batch) = cache.into();
     assert_eq!(update_batch.node_batch.len(), 3);
     assert_eq!(update_batch.stale_node_index_batch.len(), 1);
@@ -0,0 +1,216 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module implements witnesses for stateless validation. A [`Witness`] is the set of
+//! existing nodes that applying some updates to the tree reads from storage, so it can be shipped
+//! with the updates and anyone holding only the root hash before the updates can re-apply them
+//! and recompute the new root hash.
+//!
+//! [`WitnessRecorder`] wraps a [`TreeReader`] and records every node read through it, see also
+//! [`put_blob_sets_with_witness`]. [`WitnessReader`] is a [`TreeReader`] that serves only from a
+//! witness. It checks that every node in the witness is linked by hashes to the old root, so a
+//! tampered node is rejected when it is created and a missing node fails the update that needs
+//! it.
+//!
+//! [`Witness`]: struct.Witness.html
+//! [`WitnessRecorder`]: struct.WitnessRecorder.html
+//! [`WitnessReader`]: struct.WitnessReader.html
+//! [`TreeReader`]: ../trait.TreeReader.html
+//! [`put_blob_sets_with_witness`]: ../struct.JellyfishMerkleTree.html#method.put_blob_sets_with_witness
+
+#[cfg(test)]
+mod witness_test;
+
+use crate::{
+    node_type::{Node, NodeKey},
+    TreeReader,
+};
+use anyhow::{ensure, Result};
+use libra_crypto::{
+    hash::{Blake3, HashScheme},
+    HashValue,
+};
+use libra_nibble::Nibble;
+use libra_types::transaction::{Version, PRE_GENESIS_VERSION};
+use std::{cell::RefCell, collections::BTreeMap};
+
+/// The existing nodes needed to apply some updates to the tree.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Witness {
+    nodes: BTreeMap<NodeKey, Node>,
+}
+
+impl Witness {
+    /// Creates a witness consisting of `nodes`.
+    pub fn new(nodes: BTreeMap<NodeKey, Node>) -> Self {
+        Self { nodes }
+    }
+
+    /// Returns the nodes in this witness.
+    pub fn nodes(&self) -> &BTreeMap<NodeKey, Node> {
+        &self.nodes
+    }
+
+    /// Serializes to bytes, so the witness can be shipped.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let encoded_nodes = self
+            .nodes
+            .iter()
+            .map(|(node_key, node)| Ok((node_key.encode()?, node.encode()?)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(lcs::to_bytes(&encoded_nodes)?)
+    }
+
+    /// Recovers from serialized bytes.
+    pub fn decode(val: &[u8]) -> Result<Self> {
+        let encoded_nodes: Vec<(Vec<u8>, Vec<u8>)> = lcs::from_bytes(val)?;
+        let mut nodes = BTreeMap::new();
+        for (node_key_bytes, node_bytes) in encoded_nodes {
+            let node_key = NodeKey::decode(&node_key_bytes)?;
+            ensure!(
+                nodes
+                    .insert(node_key.clone(), Node::decode(&node_bytes)?)
+                    .is_none(),
+                "Duplicate node {:?} in witness.",
+                node_key,
+            );
+        }
+        Ok(Self { nodes })
+    }
+}
+
+/// A [`TreeReader`](../trait.TreeReader.html) that records every node read from the underlying
+/// reader.
+pub struct WitnessRecorder<'a, R> {
+    /// The underlying reader.
+    reader: &'a R,
+
+    /// The nodes read so far.
+    nodes: RefCell<BTreeMap<NodeKey, Node>>,
+}
+
+impl<'a, R> WitnessRecorder<'a, R>
+where
+    R: 'a + TreeReader,
+{
+    /// Starts recording the nodes read from `reader`.
+    pub fn new(reader: &'a R) -> Self {
+        Self {
+            reader,
+            nodes: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    /// Returns the witness consisting of all the nodes read so far.
+    pub fn into_witness(self) -> Witness {
+        Witness::new(self.nodes.into_inner())
+    }
+}
+
+impl<'a, R> TreeReader for WitnessRecorder<'a, R>
+where
+    R: 'a + TreeReader,
+{
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        let node_option = self.reader.get_node_option(node_key)?;
+        if let Some(node) = &node_option {
+            self.nodes
+                .borrow_mut()
+                .insert(node_key.clone(), node.clone());
+        }
+        Ok(node_option)
+    }
+}
+
+/// A [`TreeReader`](../trait.TreeReader.html) that serves only the nodes in a witness, after
+/// checking them against the root hash before the updates.
+pub struct WitnessReader {
+    nodes: BTreeMap<NodeKey, Node>,
+}
+
+impl WitnessReader {
+    /// Creates a reader over `witness` for applying updates starting at `first_version` to the
+    /// tree with root hash `root_hash`. Returns error if any node in the witness is not linked by
+    /// hashes to that root.
+    pub fn new(witness: Witness, first_version: Version, root_hash: HashValue) -> Result<Self> {
+        Self::new_with_scheme(witness, first_version, root_hash, &Blake3)
+    }
+
+    /// Same as [`new`](struct.WitnessReader.html#method.new), for a tree computed with
+    /// `hash_scheme`.
+    pub fn new_with_scheme<S: HashScheme>(
+        witness: Witness,
+        first_version: Version,
+        root_hash: HashValue,
+        hash_scheme: &S,
+    ) -> Result<Self> {
+        let nodes = witness.nodes;
+        // The updates start from the root of the previous version, or the pre-genesis root for
+        // the first version if there is one.
+        let root_node_key = NodeKey::new_empty_path(if first_version == 0 {
+            PRE_GENESIS_VERSION
+        } else {
+            first_version - 1
+        });
+        let root_node = match nodes.get(&root_node_key) {
+            Some(root_node) => root_node,
+            None => {
+                ensure!(
+                    first_version == 0 && root_hash == hash_scheme.placeholder_hash(),
+                    "Witness is missing the root node {:?}.",
+                    root_node_key,
+                );
+                ensure!(nodes.is_empty(), "Witness has nodes but no root node.");
+                return Ok(Self { nodes });
+            }
+        };
+        let actual_root_hash = root_node.hash_with_scheme(hash_scheme);
+        ensure!(
+            actual_root_hash == root_hash,
+            "Root hash mismatch. Expected: {:x}, actual: {:x}.",
+            root_hash,
+            actual_root_hash,
+        );
+
+        // Every other node must be a child of a node in the witness, with the hash the parent has
+        // for it.
+        let mut num_linked_nodes = 1;
+        let mut stack = vec![(root_node_key, root_node)];
+        while let Some((node_key, node)) = stack.pop() {
+            if let Node::Internal(internal_node) = node {
+                for i in 0..16u8 {
+                    let n = Nibble::from(i);
+                    let child = match internal_node.child(n) {
+                        Some(child) => child,
+                        None => continue,
+                    };
+                    let child_node_key = node_key.gen_child_node_key(child.version, n);
+                    if let Some(child_node) = nodes.get(&child_node_key) {
+                        ensure!(
+                            child_node.hash_with_scheme(hash_scheme) == child.hash
+                                && child_node.is_leaf() == child.is_leaf
+                                && (child.leaf_count.is_none()
+                                    || child.leaf_count == child_node.leaf_count()),
+                            "Node {:?} in witness does not match its parent.",
+                            child_node_key,
+                        );
+                        num_linked_nodes += 1;
+                        stack.push((child_node_key, child_node));
+                    }
+                }
+            }
+        }
+        ensure!(
+            num_linked_nodes == nodes.len(),
+            "Witness has {} nodes not linked to the root.",
+            nodes.len() - num_linked_nodes,
+        );
+        Ok(Self { nodes })
+    }
+}
+
+impl TreeReader for WitnessReader {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        Ok(self.nodes.get(node_key).cloned())
+    }
+}
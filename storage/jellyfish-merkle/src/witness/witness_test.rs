@@ -0,0 +1,234 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    mock_tree_store::MockTreeStore,
+    node_type::{LeafCountMode, Node, NodeKey},
+    test_helper::init_mock_db_with_options,
+    witness::{Witness, WitnessReader},
+    JellyfishMerkleTree, TreeReader,
+};
+use libra_crypto::{
+    hash::{Blake3, Sha3_256, SPARSE_MERKLE_PLACEHOLDER_HASH},
+    HashValue,
+};
+use libra_types::{account_state_blob::AccountStateBlob, transaction::Version};
+use proptest::{collection::vec, prelude::*};
+
+fn new_tree<R: TreeReader>(
+    reader: &R,
+    leaf_count_mode: Option<LeafCountMode>,
+) -> JellyfishMerkleTree<'_, R> {
+    match leaf_count_mode {
+        Some(mode) => JellyfishMerkleTree::new_with_leaf_count(reader, mode),
+        None => JellyfishMerkleTree::new(reader),
+    }
+}
+
+/// Returns a tree with two leaves under an internal node, and the witness of updating one of
+/// them at version 1.
+fn witness_of_update() -> (MockTreeStore, Witness, Vec<(HashValue, AccountStateBlob)>) {
+    let key1 = HashValue::new([0x00; HashValue::LENGTH]);
+    let key2 = HashValue::new([0x01; HashValue::LENGTH]);
+    let key3 = HashValue::new([0xff; HashValue::LENGTH]);
+    let (db, _root_hashes) = init_mock_db_with_options(
+        &[vec![
+            (key1, AccountStateBlob::from(vec![1u8])),
+            (key2, AccountStateBlob::from(vec![2u8])),
+            (key3, AccountStateBlob::from(vec![3u8])),
+        ]],
+        None, /* leaf_count_mode */
+        Blake3,
+    );
+    let blob_set = vec![(key1, AccountStateBlob::from(vec![4u8]))];
+    let (_root_hashes, _batch, witness) = JellyfishMerkleTree::new(&db)
+        .put_blob_sets_with_witness(vec![blob_set.clone()], 1 /* first_version */)
+        .unwrap();
+    (db, witness, blob_set)
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(10))]
+
+    #[test]
+    fn test_witness(
+        batches in vec(
+            vec((any::<HashValue>(), any::<AccountStateBlob>()), 1..50),
+            1..3,
+        ),
+        updates in vec(
+            vec(
+                (
+                    any::<prop::sample::Index>(),
+                    proptest::option::of(any::<HashValue>()),
+                    any::<AccountStateBlob>(),
+                ),
+                1..20,
+            ),
+            1..3,
+        ),
+        leaf_count_mode in proptest::option::of(any::<LeafCountMode>()),
+    ) {
+        let (db, _root_hashes) = init_mock_db_with_options(&batches, leaf_count_mode, Blake3);
+        let first_version = batches.len() as Version;
+        let old_root_hash = JellyfishMerkleTree::new(&db)
+            .get_root_hash(first_version - 1)
+            .unwrap();
+
+        // Each update is to an existing key, or to a new key if one is given.
+        let existing_keys: Vec<_> = batches.iter().flatten().map(|(k, _v)| *k).collect();
+        let blob_sets: Vec<Vec<_>> = updates
+            .into_iter()
+            .map(|updates| {
+                updates
+                    .into_iter()
+                    .map(|(index, new_key, blob)| {
+                        (new_key.unwrap_or_else(|| *index.get(&existing_keys)), blob)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let (root_hashes, batch, witness) = new_tree(&db, leaf_count_mode)
+            .put_blob_sets_with_witness(blob_sets.clone(), first_version)
+            .unwrap();
+        for (node_key, node) in witness.nodes() {
+            prop_assert_eq!(&db.get_node(node_key).unwrap(), node);
+        }
+
+        let witness = Witness::decode(&witness.encode().unwrap()).unwrap();
+        let reader = WitnessReader::new(witness, first_version, old_root_hash).unwrap();
+        let (verified_root_hashes, verified_batch) = new_tree(&reader, leaf_count_mode)
+            .put_blob_sets(blob_sets, first_version)
+            .unwrap();
+        prop_assert_eq!(verified_root_hashes, root_hashes);
+        prop_assert_eq!(verified_batch, batch);
+    }
+}
+
+#[test]
+fn test_witness_genesis() {
+    let db = MockTreeStore::default();
+    let blob_set = vec![(HashValue::random(), AccountStateBlob::from(vec![1u8]))];
+    let (root_hashes, _batch, witness) = JellyfishMerkleTree::new(&db)
+        .put_blob_sets_with_witness(vec![blob_set.clone()], 0 /* first_version */)
+        .unwrap();
+    assert!(witness.nodes().is_empty());
+
+    let reader = WitnessReader::new(witness.clone(), 0, *SPARSE_MERKLE_PLACEHOLDER_HASH).unwrap();
+    let (verified_root_hashes, _batch) = JellyfishMerkleTree::new(&reader)
+        .put_blob_sets(vec![blob_set], 0 /* first_version */)
+        .unwrap();
+    assert_eq!(verified_root_hashes, root_hashes);
+
+    // An empty witness does not prove a non-empty tree.
+    assert!(WitnessReader::new(witness.clone(), 0, HashValue::random()).is_err());
+    assert!(WitnessReader::new(witness, 1, *SPARSE_MERKLE_PLACEHOLDER_HASH).is_err());
+}
+
+#[test]
+fn test_witness_wrong_root_hash() {
+    let (_db, witness, _blob_set) = witness_of_update();
+    assert!(WitnessReader::new(witness, 1, HashValue::random()).is_err());
+}
+
+#[test]
+fn test_witness_tampered_node() {
+    let (db, witness, _blob_set) = witness_of_update();
+    let root_hash = JellyfishMerkleTree::new(&db).get_root_hash(0).unwrap();
+    assert!(WitnessReader::new(witness.clone(), 1, root_hash).is_ok());
+
+    let mut nodes = witness.nodes().clone();
+    let (leaf_node_key, leaf_node) = nodes
+        .iter_mut()
+        .find(|(_node_key, node)| node.is_leaf())
+        .unwrap();
+    let account_key = match leaf_node {
+        Node::Leaf(leaf_node) => leaf_node.account_key(),
+        _ => unreachable!(),
+    };
+    let leaf_node_key = leaf_node_key.clone();
+    nodes.insert(
+        leaf_node_key,
+        Node::new_leaf(account_key, AccountStateBlob::from(vec![5u8])),
+    );
+    assert!(WitnessReader::new(Witness::new(nodes), 1, root_hash).is_err());
+}
+
+#[test]
+fn test_witness_missing_node() {
+    let (db, witness, blob_set) = witness_of_update();
+    let root_hash = JellyfishMerkleTree::new(&db).get_root_hash(0).unwrap();
+
+    // Every node is needed, so the update fails without any of them.
+    for node_key in witness.nodes().keys() {
+        let mut nodes = witness.nodes().clone();
+        nodes.remove(node_key);
+        let reader = match WitnessReader::new(Witness::new(nodes), 1, root_hash) {
+            Ok(reader) => reader,
+            Err(_) => continue,
+        };
+        assert!(JellyfishMerkleTree::new(&reader)
+            .put_blob_sets(vec![blob_set.clone()], 1 /* first_version */)
+            .is_err());
+    }
+}
+
+#[test]
+fn test_witness_unlinked_node() {
+    let (db, witness, _blob_set) = witness_of_update();
+    let root_hash = JellyfishMerkleTree::new(&db).get_root_hash(0).unwrap();
+    let mut nodes = witness.nodes().clone();
+    nodes.insert(
+        NodeKey::new_empty_path(0).gen_child_node_key(0, 8.into()),
+        Node::new_leaf(HashValue::random(), AccountStateBlob::from(vec![6u8])),
+    );
+    assert!(WitnessReader::new(Witness::new(nodes), 1, root_hash).is_err());
+}
+
+#[test]
+fn test_witness_with_scheme() {
+    let key1 = HashValue::new([0x00; HashValue::LENGTH]);
+    let key2 = HashValue::new([0xff; HashValue::LENGTH]);
+    let (db, root_hashes) = init_mock_db_with_options(
+        &[vec![
+            (key1, AccountStateBlob::from(vec![1u8])),
+            (key2, AccountStateBlob::from(vec![2u8])),
+        ]],
+        None, /* leaf_count_mode */
+        Sha3_256,
+    );
+    let blob_set = vec![(key1, AccountStateBlob::from(vec![3u8]))];
+    let (new_root_hashes, _batch, witness) =
+        JellyfishMerkleTree::new_with_scheme(&db, None, Sha3_256)
+            .put_blob_sets_with_witness(vec![blob_set.clone()], 1 /* first_version */)
+            .unwrap();
+
+    // The witness is only linked to the root hash with the scheme of the tree.
+    assert!(WitnessReader::new(witness.clone(), 1, root_hashes[0]).is_err());
+    let reader = WitnessReader::new_with_scheme(witness, 1, root_hashes[0], &Sha3_256).unwrap();
+    let (verified_root_hashes, _batch) =
+        JellyfishMerkleTree::new_with_scheme(&reader, None, Sha3_256)
+            .put_blob_sets(vec![blob_set], 1 /* first_version */)
+            .unwrap();
+    assert_eq!(verified_root_hashes, new_root_hashes);
+}
+
+#[test]
+fn test_witness_keeps_tree_options() {
+    let (db, _witness, blob_set) = witness_of_update();
+    let blob_sets = vec![blob_set];
+    for tree in &[
+        JellyfishMerkleTree::new(&db).with_hash_only_leaves(),
+        JellyfishMerkleTree::new(&db).with_inline_leaves(8 /* max_inline_blob_size */),
+    ] {
+        let (root_hashes, batch) = tree
+            .put_blob_sets(blob_sets.clone(), 1 /* first_version */)
+            .unwrap();
+        let (witness_root_hashes, witness_batch, _witness) = tree
+            .put_blob_sets_with_witness(blob_sets.clone(), 1 /* first_version */)
+            .unwrap();
+        assert_eq!(witness_root_hashes, root_hashes);
+        assert_eq!(witness_batch, batch);
+    }
+}
@@ -48,7 +48,11 @@ use crate::{
 };
 use anyhow::{ensure, format_err, Result};
 use itertools::{izip, zip_eq};
-use jellyfish_merkle::{restore::JellyfishMerkleRestore, TreeReader, TreeWriter};
+use jellyfish_merkle::{
+    nibble_path::NibblePath,
+    restore::{stitch_shards, JellyfishMerkleRestore, RestoreStore},
+    subtree::SubtreeProof,
+};
 use libra_crypto::hash::{CryptoHash, HashValue, SPARSE_MERKLE_PLACEHOLDER_HASH};
 use libra_logger::prelude::*;
 use libra_metrics::{
@@ -148,6 +152,7 @@ impl LibraDB {
             EVENT_BY_KEY_CF_NAME,
             EVENT_CF_NAME,
             JELLYFISH_MERKLE_NODE_CF_NAME,
+            JELLYFISH_MERKLE_RESTORE_PROGRESS_CF_NAME,
             LEDGER_COUNTERS_CF_NAME,
             STALE_NODE_INDEX_CF_NAME,
             TRANSACTION_CF_NAME,
@@ -405,10 +410,44 @@ impl LibraDB {
         &self,
         version: Version,
         expected_root_hash: HashValue,
-    ) -> Result<JellyfishMerkleRestore<impl TreeReader + TreeWriter>> {
+    ) -> Result<JellyfishMerkleRestore<impl RestoreStore>> {
         JellyfishMerkleRestore::new(&*self.state_store, version, expected_root_hash)
     }
 
+    /// Gets the receiver of the shard of account states under `prefix`, which can be restored
+    /// concurrently with other shards. See `stitch_state_shards`.
+    pub fn get_state_shard_restore_receiver(
+        &self,
+        version: Version,
+        expected_root_hash: HashValue,
+        prefix: NibblePath,
+        subtree_proof: SubtreeProof,
+    ) -> Result<JellyfishMerkleRestore<impl RestoreStore>> {
+        JellyfishMerkleRestore::new_shard(
+            &*self.state_store,
+            version,
+            expected_root_hash,
+            prefix,
+            subtree_proof,
+        )
+    }
+
+    /// Puts together the shards of account states restored by receivers from
+    /// `get_state_shard_restore_receiver`, once all of them have finished.
+    pub fn stitch_state_shards(
+        &self,
+        version: Version,
+        expected_root_hash: HashValue,
+        num_shard_nibbles: usize,
+    ) -> Result<HashValue> {
+        stitch_shards(
+            &*self.state_store,
+            version,
+            expected_root_hash,
+            num_shard_nibbles,
+        )
+    }
+
     // ================================== Private APIs ==================================
     /// Returns events specified by `query_path` with sequence number in range designated by
     /// `start_seq_num`, `ascending` and `limit`. If ascending is true this query will return up to
@@ -0,0 +1,49 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module defines physical storage schema for the progress of an ongoing state restore.
+//! Progress is identified by the [NodeKey](jellyfish-merkle::node_type::NodeKey) of the root of
+//! the tree, or of the shard, being restored and records the last account key that has been
+//! persisted.
+//! ```text
+//! |<------key----->|<-------value------->|
+//! | root_node_key  | serialized_progress |
+//! ```
+
+use crate::schema::JELLYFISH_MERKLE_RESTORE_PROGRESS_CF_NAME;
+use anyhow::Result;
+use jellyfish_merkle::{node_type::NodeKey, restore::RestoreProgress};
+use schemadb::{
+    define_schema,
+    schema::{KeyCodec, ValueCodec},
+};
+
+define_schema!(
+    JellyfishMerkleRestoreProgressSchema,
+    NodeKey,
+    RestoreProgress,
+    JELLYFISH_MERKLE_RESTORE_PROGRESS_CF_NAME
+);
+
+impl KeyCodec<JellyfishMerkleRestoreProgressSchema> for NodeKey {
+    fn encode_key(&self) -> Result<Vec<u8>> {
+        self.encode()
+    }
+
+    fn decode_key(data: &[u8]) -> Result<Self> {
+        Self::decode(data)
+    }
+}
+
+impl ValueCodec<JellyfishMerkleRestoreProgressSchema> for RestoreProgress {
+    fn encode_value(&self) -> Result<Vec<u8>> {
+        lcs::to_bytes(self).map_err(Into::into)
+    }
+
+    fn decode_value(data: &[u8]) -> Result<Self> {
+        lcs::from_bytes(data).map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod test;
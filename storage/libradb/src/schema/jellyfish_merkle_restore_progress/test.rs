@@ -0,0 +1,16 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+use super::*;
+use proptest::prelude::*;
+use schemadb::schema::assert_encode_decode;
+
+proptest! {
+    #[test]
+    fn test_encode_decode(
+        root_node_key in any::<NodeKey>(),
+        progress in any::<RestoreProgress>(),
+    ) {
+        assert_encode_decode::<JellyfishMerkleRestoreProgressSchema>(&root_node_key, &progress);
+    }
+}
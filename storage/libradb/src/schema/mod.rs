@@ -11,6 +11,7 @@ pub(crate) mod event;
 pub(crate) mod event_accumulator;
 pub(crate) mod event_by_key;
 pub(crate) mod jellyfish_merkle_node;
+pub(crate) mod jellyfish_merkle_restore_progress;
 pub(crate) mod ledger_counters;
 pub(crate) mod ledger_info;
 pub(crate) mod stale_node_index;
@@ -27,6 +28,8 @@ pub(super) const EVENT_ACCUMULATOR_CF_NAME: ColumnFamilyName = "event_accumulato
 pub(super) const EVENT_BY_KEY_CF_NAME: ColumnFamilyName = "event_by_key";
 pub(super) const EVENT_CF_NAME: ColumnFamilyName = "event";
 pub(super) const JELLYFISH_MERKLE_NODE_CF_NAME: ColumnFamilyName = "jellyfish_merkle_node";
+pub(super) const JELLYFISH_MERKLE_RESTORE_PROGRESS_CF_NAME: ColumnFamilyName =
+    "jellyfish_merkle_restore_progress";
 pub(super) const LEDGER_COUNTERS_CF_NAME: ColumnFamilyName = "ledger_counters";
 pub(super) const STALE_NODE_INDEX_CF_NAME: ColumnFamilyName = "stale_node_index";
 pub(super) const TRANSACTION_CF_NAME: ColumnFamilyName = "transaction";
@@ -76,6 +79,10 @@ pub mod fuzzing {
                 super::jellyfish_merkle_node::JellyfishMerkleNodeSchema,
                 data
             );
+            decode_key_value!(
+                super::jellyfish_merkle_restore_progress::JellyfishMerkleRestoreProgressSchema,
+                data
+            );
             decode_key_value!(super::ledger_counters::LedgerCountersSchema, data);
             decode_key_value!(super::ledger_info::LedgerInfoSchema, data);
             decode_key_value!(super::stale_node_index::StaleNodeIndexSchema, data);
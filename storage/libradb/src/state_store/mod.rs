@@ -10,13 +10,17 @@ use crate::{
     change_set::ChangeSet,
     ledger_counters::LedgerCounter,
     schema::{
-        jellyfish_merkle_node::JellyfishMerkleNodeSchema, stale_node_index::StaleNodeIndexSchema,
+        jellyfish_merkle_node::JellyfishMerkleNodeSchema,
+        jellyfish_merkle_restore_progress::JellyfishMerkleRestoreProgressSchema,
+        stale_node_index::StaleNodeIndexSchema,
     },
 };
 use anyhow::Result;
 use jellyfish_merkle::{
-    node_type::{LeafNode, Node, NodeKey},
-    JellyfishMerkleTree, NodeBatch, TreeReader, TreeWriter, ROOT_NIBBLE_HEIGHT,
+    node_type::{Node, NodeKey},
+    restore::{RestoreProgress, RestoreStore},
+    JellyfishMerkleTree, NodeBatch, StaleNodeIndexBatch, StaleNodeIndexWriter, TreeReader,
+    TreeWriter,
 };
 use libra_crypto::{hash::CryptoHash, HashValue};
 use libra_types::{
@@ -113,105 +117,66 @@ impl StateStore {
     pub fn get_root_hash_option(&self, version: Version) -> Result<Option<HashValue>> {
         JellyfishMerkleTree::new(self).get_root_hash_option(version)
     }
-
-    /// Finds the rightmost leaf by scanning the entire DB.
-    #[cfg(test)]
-    pub fn get_rightmost_leaf_naive(&self) -> Result<Option<(NodeKey, LeafNode)>> {
-        let mut ret = None;
-
-        let mut iter = self
-            .db
-            .iter::<JellyfishMerkleNodeSchema>(Default::default())?;
-        iter.seek_to_first();
-
-        while let Some((node_key, node)) = iter.next().transpose()? {
-            if let Node::Leaf(leaf_node) = node {
-                match ret {
-                    None => ret = Some((node_key, leaf_node)),
-                    Some(ref other) => {
-                        if leaf_node.account_key() > other.1.account_key() {
-                            ret = Some((node_key, leaf_node));
-                        }
-                    }
-                }
-            }
-        }
-
-        Ok(ret)
-    }
 }
 
 impl TreeReader for StateStore {
     fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
         Ok(self.db.get::<JellyfishMerkleNodeSchema>(node_key)?)
     }
+}
 
-    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
-        // Since everything has the same version during restore, we seek to the first node and get
-        // its version.
-        let mut iter = self
-            .db
-            .iter::<JellyfishMerkleNodeSchema>(Default::default())?;
-        iter.seek_to_first();
-        let version = match iter.next().transpose()? {
-            Some((node_key, _node)) => node_key.version(),
-            None => return Ok(None),
-        };
-
-        // The encoding of key and value in DB looks like:
-        //
-        // | <-------------- key --------------> | <- value -> |
-        // | version | num_nibbles | nibble_path |    node     |
-        //
-        // Here version is fixed. For each num_nibbles, there could be a range of nibble paths
-        // of the same length. If one of them is the rightmost leaf R, it must be at the end of this
-        // range. Otherwise let's assume the R is in the middle of the range, so we
-        // call the node at the end of this range X:
-        //   1. If X is leaf, then X.account_key() > R.account_key(), because the nibble path is a
-        //      prefix of the account key. So R is not the rightmost leaf.
-        //   2. If X is internal node, then X must be on the right side of R, so all its children's
-        //      account keys are larger than R.account_key(). So R is not the rightmost leaf.
-        //
-        // Given that num_nibbles ranges from 0 to ROOT_NIBBLE_HEIGHT, there are only
-        // ROOT_NIBBLE_HEIGHT+1 ranges, so we can just find the node at the end of each range and
-        // then pick the one with the largest account key.
-        let mut ret = None;
-
-        for num_nibbles in 1..=ROOT_NIBBLE_HEIGHT + 1 {
-            let mut iter = self
-                .db
-                .iter::<JellyfishMerkleNodeSchema>(Default::default())?;
-            // nibble_path is always non-empty except for the root, so if we use an empty nibble
-            // path as the seek key, the iterator will end up pointing to the end of the previous
-            // range.
-            let seek_key = (version, num_nibbles as u8);
-            iter.seek_for_prev(&seek_key)?;
-
-            if let Some((node_key, node)) = iter.next().transpose()? {
-                debug_assert_eq!(node_key.version(), version);
-                debug_assert!(node_key.nibble_path().num_nibbles() < num_nibbles);
-
-                if let Node::Leaf(leaf_node) = node {
-                    match ret {
-                        None => ret = Some((node_key, leaf_node)),
-                        Some(ref other) => {
-                            if leaf_node.account_key() > other.1.account_key() {
-                                ret = Some((node_key, leaf_node));
-                            }
-                        }
-                    }
-                }
-            }
-        }
+impl TreeWriter for StateStore {
+    fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()> {
+        let mut batch = SchemaBatch::new();
+        add_node_batch(&mut batch, node_batch)?;
+        self.db.write_schemas(batch)
+    }
+}
 
-        Ok(ret)
+impl StaleNodeIndexWriter for StateStore {
+    fn write_stale_node_index_batch(
+        &self,
+        stale_node_index_batch: &StaleNodeIndexBatch,
+    ) -> Result<()> {
+        let mut batch = SchemaBatch::new();
+        stale_node_index_batch
+            .iter()
+            .map(|row| batch.put::<StaleNodeIndexSchema>(row, &()))
+            .collect::<Result<Vec<()>>>()?;
+        self.db.write_schemas(batch)
     }
 }
 
-impl TreeWriter for StateStore {
-    fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()> {
+impl RestoreStore for StateStore {
+    fn get_restore_progress(&self, root_node_key: &NodeKey) -> Result<Option<RestoreProgress>> {
+        self.db
+            .get::<JellyfishMerkleRestoreProgressSchema>(root_node_key)
+    }
+
+    fn write_restore_batch(
+        &self,
+        root_node_key: &NodeKey,
+        node_batch: &NodeBatch,
+        progress: Option<&RestoreProgress>,
+    ) -> Result<()> {
         let mut batch = SchemaBatch::new();
         add_node_batch(&mut batch, node_batch)?;
+        match progress {
+            Some(progress) => {
+                batch.put::<JellyfishMerkleRestoreProgressSchema>(root_node_key, progress)?
+            }
+            None => batch.delete::<JellyfishMerkleRestoreProgressSchema>(root_node_key)?,
+        }
+        self.db.write_schemas(batch)
+    }
+
+    fn delete_restore_batch(&self, root_node_key: &NodeKey, node_keys: &[NodeKey]) -> Result<()> {
+        let mut batch = SchemaBatch::new();
+        node_keys
+            .iter()
+            .map(|node_key| batch.delete::<JellyfishMerkleNodeSchema>(node_key))
+            .collect::<Result<Vec<_>>>()?;
+        batch.delete::<JellyfishMerkleRestoreProgressSchema>(root_node_key)?;
         self.db.write_schemas(batch)
     }
 }
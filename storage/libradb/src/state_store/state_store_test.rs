@@ -317,7 +317,7 @@ proptest! {
     }
 
     #[test]
-    fn test_get_rightmost_leaf(
+    fn test_restore_resume_from_progress(
         (input, batch1_size) in hash_map(any::<AccountAddress>(), any::<AccountStateBlob>(), 2..1000)
             .prop_flat_map(|input| {
                 let len = input.len();
@@ -336,29 +336,49 @@ proptest! {
         let db2 = LibraDB::new_for_test(&tmp_dir2);
         let store2 = &db2.state_store;
 
-        let mut restore =
-            JellyfishMerkleRestore::new(&**store2, version, expected_root_hash).unwrap();
-
         let mut ordered_input: Vec<_> = input
             .into_iter()
             .map(|(addr, value)| (addr.hash(), value))
             .collect();
         ordered_input.sort_unstable_by_key(|(key, _value)| *key);
 
-        let batch1: Vec<_> = ordered_input
-            .into_iter()
-            .take(batch1_size)
-            .collect();
+        let batch1: Vec<_> = ordered_input.iter().take(batch1_size).cloned().collect();
         let rightmost_of_batch1 = batch1.last().map(|(key, _value)| *key).unwrap();
         let proof_of_batch1 = store1
             .get_account_state_range_proof(rightmost_of_batch1, version)
             .unwrap();
+        {
+            let mut restore =
+                JellyfishMerkleRestore::new(&**store2, version, expected_root_hash).unwrap();
+            restore.add_chunk(batch1, proof_of_batch1).unwrap();
+        }
 
-        restore.add_chunk(batch1, proof_of_batch1).unwrap();
+        // The last key of the chunk is only persisted along with the next one, so the
+        // restoration resumes right after the second to last key of the first chunk.
+        let root_node_key = NodeKey::new_empty_path(version);
+        let progress = store2.get_restore_progress(&root_node_key).unwrap();
+        if batch1_size == 1 {
+            prop_assert_eq!(progress, None);
+        } else {
+            let progress = progress.unwrap();
+            prop_assert_eq!(progress.version, version);
+            prop_assert_eq!(progress.num_keys, batch1_size as u64 - 1);
+            prop_assert_eq!(progress.last_key, ordered_input[batch1_size - 2].0);
+        }
+
+        let mut restore =
+            JellyfishMerkleRestore::new(&**store2, version, expected_root_hash).unwrap();
+        let num_keys = restore.progress().map_or(0, |progress| progress.num_keys as usize);
+        let batch2: Vec<_> = ordered_input.into_iter().skip(num_keys).collect();
+        let rightmost_of_batch2 = batch2.last().map(|(key, _value)| *key).unwrap();
+        let proof_of_batch2 = store1
+            .get_account_state_range_proof(rightmost_of_batch2, version)
+            .unwrap();
+        restore.add_chunk(batch2, proof_of_batch2).unwrap();
+        prop_assert_eq!(restore.finish().unwrap(), expected_root_hash);
 
-        let expected = store2.get_rightmost_leaf_naive().unwrap();
-        let actual = store2.get_rightmost_leaf().unwrap();
-        prop_assert_eq!(actual, expected);
+        prop_assert_eq!(store2.get_restore_progress(&root_node_key).unwrap(), None);
+        prop_assert_eq!(store2.get_root_hash(version).unwrap(), expected_root_hash);
     }
 }
 
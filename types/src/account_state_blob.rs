@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use libra_crypto::{
-    hash::{CryptoHash, CryptoHasher},
+    hash::{CryptoHash, CryptoHasher, HashDomain, HashScheme},
     HashValue,
 };
 use libra_crypto_derive::CryptoHasher;
@@ -41,6 +41,13 @@ impl From<Vec<u8>> for AccountStateBlob {
     }
 }
 
+impl AccountStateBlob {
+    /// Same as `hash`, but computed with `hash_scheme`.
+    pub fn hash_with_scheme<S: HashScheme>(&self, hash_scheme: &S) -> HashValue {
+        hash_scheme.hash_of(&[hash_scheme.seed(HashDomain::AccountStateBlob), &self.blob])
+    }
+}
+
 impl CryptoHash for AccountStateBlob {
     type Hasher = AccountStateBlobHasher;
 
@@ -72,6 +79,7 @@ impl Arbitrary for AccountStateBlob {
 mod tests {
     use super::*;
     use lcs::test_helpers::assert_canonical_encode_decode;
+    use libra_crypto::hash::{Blake3, Sha3_256};
     use proptest::collection::vec;
 
     fn hash_blob(blob: &[u8]) -> HashValue {
@@ -86,6 +94,13 @@ mod tests {
             prop_assert_eq!(hash_blob(&blob), AccountStateBlob::from(blob).hash());
         }
 
+        #[test]
+        fn account_state_blob_hash_with_scheme(blob in vec(any::<u8>(), 1..100)) {
+            let blob = AccountStateBlob::from(blob);
+            prop_assert_eq!(blob.hash_with_scheme(&Blake3), blob.hash());
+            prop_assert_ne!(blob.hash_with_scheme(&Sha3_256), blob.hash());
+        }
+
         #[test]
         fn account_state_blob_lcs_roundtrip(account_state_blob in any::<AccountStateBlob>()) {
             assert_canonical_encode_decode(account_state_blob);
@@ -7,14 +7,15 @@
 #[path = "unit_tests/proof_conversion_test.rs"]
 mod proof_conversion_test;
 
-use super::{SparseMerkleInternalNode, SparseMerkleLeafNode};
+use super::{SparseMerkleCountedInternalNode, SparseMerkleInternalNode, SparseMerkleLeafNode};
 use crate::account_state_blob::AccountStateBlob;
-use anyhow::{bail, ensure, Result};
+use anyhow::{bail, ensure, format_err, Result};
 use libra_crypto::{
-    hash::{CryptoHash, SPARSE_MERKLE_PLACEHOLDER_HASH},
+    hash::{Blake3, CryptoHash, HashScheme, SPARSE_MERKLE_PLACEHOLDER_HASH},
     HashValue,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 /// A proof that can be used to authenticate an element in a Sparse Merkle Tree given trusted root
 /// hash. For example, `TransactionInfoToAccountProof` can be constructed on top of this structure.
@@ -61,6 +62,17 @@ impl SparseMerkleProof {
         expected_root_hash: HashValue,
         element_key: HashValue,
         element_blob: Option<&AccountStateBlob>,
+    ) -> Result<()> {
+        self.verify_with_scheme(&Blake3, expected_root_hash, element_key, element_blob)
+    }
+
+    /// Same as `verify`, for a Sparse Merkle Tree computed with `hash_scheme`.
+    pub fn verify_with_scheme<S: HashScheme>(
+        &self,
+        hash_scheme: &S,
+        expected_root_hash: HashValue,
+        element_key: HashValue,
+        element_blob: Option<&AccountStateBlob>,
     ) -> Result<()> {
         ensure!(
             self.siblings.len() <= HashValue::LENGTH_IN_BITS,
@@ -69,53 +81,17 @@ impl SparseMerkleProof {
             self.siblings.len(),
         );
 
-        match (element_blob, self.leaf) {
-            (Some(blob), Some(leaf)) => {
-                // This is an inclusion proof, so the key and value hash provided in the proof
-                // should match element_key and element_value_hash. `siblings` should prove the
-                // route from the leaf node to the root.
-                ensure!(
-                    element_key == leaf.key,
-                    "Keys do not match. Key in proof: {:x}. Expected key: {:x}.",
-                    leaf.key,
-                    element_key
-                );
-                let hash = blob.hash();
-                ensure!(
-                    hash == leaf.value_hash,
-                    "Value hashes do not match. Value hash in proof: {:x}. \
-                     Expected value hash: {:x}",
-                    leaf.value_hash,
-                    hash,
-                );
-            }
-            (Some(_blob), None) => bail!("Expected inclusion proof. Found non-inclusion proof."),
-            (None, Some(leaf)) => {
-                // This is a non-inclusion proof. The proof intends to show that if a leaf node
-                // representing `element_key` is inserted, it will break a currently existing leaf
-                // node represented by `proof_key` into a branch. `siblings` should prove the
-                // route from that leaf node to the root.
-                ensure!(
-                    element_key != leaf.key,
-                    "Expected non-inclusion proof, but key exists in proof.",
-                );
-                ensure!(
-                    element_key.common_prefix_bits_len(leaf.key) >= self.siblings.len(),
-                    "Key would not have ended up in the subtree where the provided key in proof \
-                     is the only existing key, if it existed. So this is not a valid \
-                     non-inclusion proof.",
-                );
-            }
-            (None, None) => {
-                // This is a non-inclusion proof. The proof intends to show that if a leaf node
-                // representing `element_key` is inserted, it will show up at a currently empty
-                // position. `sibling` should prove the route from this empty position to the root.
-            }
-        }
+        verify_leaf(
+            hash_scheme,
+            element_key,
+            element_blob,
+            self.leaf,
+            self.siblings.len(),
+        )?;
 
-        let current_hash = self
-            .leaf
-            .map_or(*SPARSE_MERKLE_PLACEHOLDER_HASH, |leaf| leaf.hash());
+        let current_hash = self.leaf.map_or(hash_scheme.placeholder_hash(), |leaf| {
+            leaf.hash_with_scheme(hash_scheme)
+        });
         let actual_root_hash = self
             .siblings
             .iter()
@@ -127,9 +103,9 @@ impl SparseMerkleProof {
             )
             .fold(current_hash, |hash, (sibling_hash, bit)| {
                 if bit {
-                    SparseMerkleInternalNode::new(*sibling_hash, hash).hash()
+                    SparseMerkleInternalNode::new(*sibling_hash, hash).hash_with_scheme(hash_scheme)
                 } else {
-                    SparseMerkleInternalNode::new(hash, *sibling_hash).hash()
+                    SparseMerkleInternalNode::new(hash, *sibling_hash).hash_with_scheme(hash_scheme)
                 }
             });
         ensure!(
@@ -143,6 +119,394 @@ impl SparseMerkleProof {
     }
 }
 
+/// Similar to `SparseMerkleProof`, but for a Sparse Merkle Tree whose internal nodes commit to the
+/// number of leaves under each child. Besides authenticating an element, verifying this proof
+/// reveals how many leaves are in the tree and how many of them have keys less than the element.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SparseMerkleCountedProof {
+    /// Same as the `leaf` in `SparseMerkleProof`.
+    leaf: Option<SparseMerkleLeafNode>,
+
+    /// All siblings in this proof along with the number of leaves under each of them. Siblings are
+    /// ordered from the bottom level to the root level.
+    siblings: Vec<(HashValue, u64)>,
+}
+
+impl SparseMerkleCountedProof {
+    /// Constructs a new `SparseMerkleCountedProof` using leaf and a list of siblings.
+    pub fn new(leaf: Option<SparseMerkleLeafNode>, siblings: Vec<(HashValue, u64)>) -> Self {
+        SparseMerkleCountedProof { leaf, siblings }
+    }
+
+    /// Returns the leaf node in this proof.
+    pub fn leaf(&self) -> Option<SparseMerkleLeafNode> {
+        self.leaf
+    }
+
+    /// Returns the list of siblings and their leaf counts in this proof.
+    pub fn siblings(&self) -> &[(HashValue, u64)] {
+        &self.siblings
+    }
+
+    /// Same as `SparseMerkleProof::verify`. On success, returns the number of leaves whose keys
+    /// are less than `element_key` and the total number of leaves in the tree.
+    pub fn verify(
+        &self,
+        expected_root_hash: HashValue,
+        element_key: HashValue,
+        element_blob: Option<&AccountStateBlob>,
+    ) -> Result<(u64, u64)> {
+        self.verify_with_scheme(&Blake3, expected_root_hash, element_key, element_blob)
+    }
+
+    /// Same as `verify`, for a Sparse Merkle Tree computed with `hash_scheme`.
+    pub fn verify_with_scheme<S: HashScheme>(
+        &self,
+        hash_scheme: &S,
+        expected_root_hash: HashValue,
+        element_key: HashValue,
+        element_blob: Option<&AccountStateBlob>,
+    ) -> Result<(u64, u64)> {
+        ensure!(
+            self.siblings.len() <= HashValue::LENGTH_IN_BITS,
+            "Sparse Merkle Tree proof has more than {} ({}) siblings.",
+            HashValue::LENGTH_IN_BITS,
+            self.siblings.len(),
+        );
+        verify_leaf(
+            hash_scheme,
+            element_key,
+            element_blob,
+            self.leaf,
+            self.siblings.len(),
+        )?;
+
+        let (current_hash, current_count, mut rank) = match self.leaf {
+            Some(leaf) => (
+                leaf.hash_with_scheme(hash_scheme),
+                1,
+                (leaf.key < element_key) as u64,
+            ),
+            None => (hash_scheme.placeholder_hash(), 0, 0),
+        };
+        let (actual_root_hash, total_count) = self
+            .siblings
+            .iter()
+            .zip(
+                element_key
+                    .iter_bits()
+                    .rev()
+                    .skip(HashValue::LENGTH_IN_BITS - self.siblings.len()),
+            )
+            .fold(
+                (current_hash, current_count),
+                |(hash, count), (&(sibling_hash, sibling_count), bit)| {
+                    let hash = if bit {
+                        rank += sibling_count;
+                        SparseMerkleCountedInternalNode::new(
+                            sibling_hash,
+                            sibling_count,
+                            hash,
+                            count,
+                        )
+                        .hash_with_scheme(hash_scheme)
+                    } else {
+                        SparseMerkleCountedInternalNode::new(
+                            hash,
+                            count,
+                            sibling_hash,
+                            sibling_count,
+                        )
+                        .hash_with_scheme(hash_scheme)
+                    };
+                    (hash, count + sibling_count)
+                },
+            );
+        ensure!(
+            actual_root_hash == expected_root_hash,
+            "Root hashes do not match. Actual root hash: {:x}. Expected root hash: {:x}.",
+            actual_root_hash,
+            expected_root_hash,
+        );
+
+        Ok((rank, total_count))
+    }
+}
+
+/// Checks that `leaf`, which the proof of `element_key` ends at after `num_siblings` levels, is
+/// consistent with `element_blob`: the leaf must be the element itself for an inclusion proof,
+/// and must be in the way of `element_key` for a non-inclusion proof.
+fn verify_leaf<S: HashScheme>(
+    hash_scheme: &S,
+    element_key: HashValue,
+    element_blob: Option<&AccountStateBlob>,
+    leaf: Option<SparseMerkleLeafNode>,
+    num_siblings: usize,
+) -> Result<()> {
+    match (element_blob, leaf) {
+        (Some(blob), Some(leaf)) => {
+            // This is an inclusion proof, so the key and value hash provided in the proof
+            // should match element_key and element_value_hash. `siblings` should prove the
+            // route from the leaf node to the root.
+            ensure!(
+                element_key == leaf.key,
+                "Keys do not match. Key in proof: {:x}. Expected key: {:x}.",
+                leaf.key,
+                element_key
+            );
+            let hash = blob.hash_with_scheme(hash_scheme);
+            ensure!(
+                hash == leaf.value_hash,
+                "Value hashes do not match. Value hash in proof: {:x}. \
+                 Expected value hash: {:x}",
+                leaf.value_hash,
+                hash,
+            );
+        }
+        (Some(_blob), None) => bail!("Expected inclusion proof. Found non-inclusion proof."),
+        (None, Some(leaf)) => {
+            // This is a non-inclusion proof. The proof intends to show that if a leaf node
+            // representing `element_key` is inserted, it will break a currently existing leaf
+            // node represented by `proof_key` into a branch. `siblings` should prove the
+            // route from that leaf node to the root.
+            ensure!(
+                element_key != leaf.key,
+                "Expected non-inclusion proof, but key exists in proof.",
+            );
+            ensure!(
+                element_key.common_prefix_bits_len(leaf.key) >= num_siblings,
+                "Key would not have ended up in the subtree where the provided key in proof \
+                 is the only existing key, if it existed. So this is not a valid \
+                 non-inclusion proof.",
+            );
+        }
+        (None, None) => {
+            // This is a non-inclusion proof. The proof intends to show that if a leaf node
+            // representing `element_key` is inserted, it will show up at a currently empty
+            // position. `sibling` should prove the route from this empty position to the root.
+        }
+    }
+    Ok(())
+}
+
+/// A node of the old tree in a `SparseMerkleUpdateProof`.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum SparseMerkleUpdateProofNode {
+    /// An internal node on the paths of the written keys. It is followed by the nodes of its left
+    /// subtree and then the nodes of its right subtree.
+    Internal,
+    /// A subtree that none of the written keys falls in, given by its root hash.
+    Subtree(HashValue),
+    /// A leaf on the paths of the written keys. It is either one of the written keys, or the only
+    /// other key in the subtree where some written keys fall.
+    Leaf(SparseMerkleLeafNode),
+    /// An empty subtree on the paths of the written keys.
+    Empty,
+}
+
+/// A proof that writing some account blobs into a Sparse Merkle Tree with a trusted root hash
+/// results in a tree with a certain root hash. It consists of the old values of the written keys
+/// and the part of the old tree on the paths of those keys, with every subtree off the paths
+/// replaced by its root hash. The same subtree hashes are siblings in both the old and the new
+/// tree, so both root hashes can be recomputed without the tree.
+///
+/// Only writes are covered. Deleting a key can move a leaf up past its siblings, which can not be
+/// seen from their hashes.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SparseMerkleUpdateProof {
+    /// The written keys and their account blobs in the old tree, sorted by key.
+    old_values: Vec<(HashValue, Option<AccountStateBlob>)>,
+
+    /// The nodes of the old tree on the paths of the written keys, in pre-order starting from the
+    /// root.
+    nodes: Vec<SparseMerkleUpdateProofNode>,
+}
+
+impl SparseMerkleUpdateProof {
+    /// Constructs a new `SparseMerkleUpdateProof` using the old values and nodes of the old tree.
+    pub fn new(
+        old_values: Vec<(HashValue, Option<AccountStateBlob>)>,
+        nodes: Vec<SparseMerkleUpdateProofNode>,
+    ) -> Self {
+        Self { old_values, nodes }
+    }
+
+    /// Returns the written keys and their old account blobs.
+    pub fn old_values(&self) -> &[(HashValue, Option<AccountStateBlob>)] {
+        &self.old_values
+    }
+
+    /// Returns the nodes of the old tree in this proof.
+    pub fn nodes(&self) -> &[SparseMerkleUpdateProofNode] {
+        &self.nodes
+    }
+
+    /// Verifies that writing `writes` into the tree with root hash `old_root_hash` results in the
+    /// tree with root hash `new_root_hash`. If a key is written more than once, the last write
+    /// wins. On success, the old values in this proof are those of the written keys in the old
+    /// tree.
+    pub fn verify(
+        &self,
+        old_root_hash: HashValue,
+        new_root_hash: HashValue,
+        writes: &[(HashValue, AccountStateBlob)],
+    ) -> Result<()> {
+        let new_leaves: Vec<_> = writes
+            .iter()
+            .map(|(key, blob)| (*key, blob.hash()))
+            .collect::<BTreeMap<_, _>>()
+            .into_iter()
+            .map(|(key, value_hash)| SparseMerkleLeafNode::new(key, value_hash))
+            .collect();
+        ensure!(!new_leaves.is_empty(), "Nothing is written.");
+        ensure!(
+            self.old_values.len() == new_leaves.len()
+                && self
+                    .old_values
+                    .iter()
+                    .zip(new_leaves.iter())
+                    .all(|((key, _blob), leaf)| *key == leaf.key),
+            "Keys of old values do not match the written keys.",
+        );
+        let old_value_hashes: Vec<_> = self
+            .old_values
+            .iter()
+            .map(|(_key, blob)| blob.as_ref().map(CryptoHash::hash))
+            .collect();
+
+        let mut nodes = self.nodes.iter();
+        let (actual_old_root_hash, actual_new_root_hash) =
+            verify_update_subtree(&mut nodes, 0, &new_leaves, &old_value_hashes)?;
+        ensure!(nodes.next().is_none(), "Update proof has extra nodes.");
+        ensure!(
+            actual_old_root_hash == old_root_hash,
+            "Old root hashes do not match. Actual root hash: {:x}. Expected root hash: {:x}.",
+            actual_old_root_hash,
+            old_root_hash,
+        );
+        ensure!(
+            actual_new_root_hash == new_root_hash,
+            "New root hashes do not match. Actual root hash: {:x}. Expected root hash: {:x}.",
+            actual_new_root_hash,
+            new_root_hash,
+        );
+        Ok(())
+    }
+}
+
+/// Returns the `i`-th bit of `key`, counting from the most significant bit.
+fn bit(key: HashValue, i: usize) -> bool {
+    key.as_ref()[i / 8] & (1 << (7 - i % 8)) != 0
+}
+
+/// Reads the subtree at `depth` where `new_leaves` fall from `nodes`, checks the old values of
+/// their keys and returns the old and new root hashes of the subtree.
+fn verify_update_subtree<'a>(
+    nodes: &mut impl Iterator<Item = &'a SparseMerkleUpdateProofNode>,
+    depth: usize,
+    new_leaves: &[SparseMerkleLeafNode],
+    old_value_hashes: &[Option<HashValue>],
+) -> Result<(HashValue, HashValue)> {
+    let node = nodes
+        .next()
+        .ok_or_else(|| format_err!("Update proof has too few nodes."))?;
+    if let SparseMerkleUpdateProofNode::Subtree(hash) = node {
+        ensure!(
+            new_leaves.is_empty(),
+            "Written keys fall in a subtree of the update proof.",
+        );
+        return Ok((*hash, *hash));
+    }
+    ensure!(
+        !new_leaves.is_empty(),
+        "Update proof has a node off the paths of the written keys.",
+    );
+
+    match node {
+        SparseMerkleUpdateProofNode::Internal => {
+            ensure!(
+                depth < HashValue::LENGTH_IN_BITS,
+                "Update proof is deeper than {} levels.",
+                HashValue::LENGTH_IN_BITS,
+            );
+            let num_left = new_leaves
+                .iter()
+                .position(|leaf| bit(leaf.key, depth))
+                .unwrap_or(new_leaves.len());
+            let (left_old_hash, left_new_hash) = verify_update_subtree(
+                nodes,
+                depth + 1,
+                &new_leaves[..num_left],
+                &old_value_hashes[..num_left],
+            )?;
+            let (right_old_hash, right_new_hash) = verify_update_subtree(
+                nodes,
+                depth + 1,
+                &new_leaves[num_left..],
+                &old_value_hashes[num_left..],
+            )?;
+            Ok((
+                SparseMerkleInternalNode::new(left_old_hash, right_old_hash).hash(),
+                SparseMerkleInternalNode::new(left_new_hash, right_new_hash).hash(),
+            ))
+        }
+        SparseMerkleUpdateProofNode::Leaf(leaf) => {
+            ensure!(
+                leaf.key.common_prefix_bits_len(new_leaves[0].key) >= depth,
+                "Leaf {:x} in update proof is off the paths of the written keys.",
+                leaf.key,
+            );
+            let mut leaves = new_leaves.to_vec();
+            for (new_leaf, old_value_hash) in new_leaves.iter().zip(old_value_hashes) {
+                let expected_old_value_hash = if new_leaf.key == leaf.key {
+                    Some(leaf.value_hash)
+                } else {
+                    None
+                };
+                ensure!(
+                    *old_value_hash == expected_old_value_hash,
+                    "Old value of key {:x} does not match the update proof.",
+                    new_leaf.key,
+                );
+            }
+            if let Err(i) = new_leaves.binary_search_by_key(&leaf.key, |new_leaf| new_leaf.key) {
+                leaves.insert(i, *leaf);
+            }
+            Ok((leaf.hash(), subtree_root_hash(depth, &leaves)))
+        }
+        SparseMerkleUpdateProofNode::Empty => {
+            ensure!(
+                old_value_hashes.iter().all(Option::is_none),
+                "Old value of a key in an empty subtree is not empty.",
+            );
+            Ok((
+                *SPARSE_MERKLE_PLACEHOLDER_HASH,
+                subtree_root_hash(depth, new_leaves),
+            ))
+        }
+        SparseMerkleUpdateProofNode::Subtree(_) => unreachable!(),
+    }
+}
+
+/// Computes the root hash of the subtree at `depth` that has exactly `leaves`, sorted by key.
+fn subtree_root_hash(depth: usize, leaves: &[SparseMerkleLeafNode]) -> HashValue {
+    match leaves.len() {
+        0 => *SPARSE_MERKLE_PLACEHOLDER_HASH,
+        1 => leaves[0].hash(),
+        _ => {
+            let num_left = leaves
+                .iter()
+                .position(|leaf| bit(leaf.key, depth))
+                .unwrap_or(leaves.len());
+            SparseMerkleInternalNode::new(
+                subtree_root_hash(depth + 1, &leaves[..num_left]),
+                subtree_root_hash(depth + 1, &leaves[num_left..]),
+            )
+            .hash()
+        }
+    }
+}
+
 /// A proof that can be used to show that two Merkle accumulators are consistent -- the big one can
 /// be obtained by appending certain leaves to the small one. For example, at some point in time a
 /// client knows that the root hash of the ledger at version 10 is `old_root` (it could be a
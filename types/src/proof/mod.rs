@@ -9,7 +9,10 @@ pub mod proptest_proof;
 mod unit_tests;
 
 use libra_crypto::{
-    hash::{CryptoHash, CryptoHasher, SparseMerkleInternalHasher},
+    hash::{
+        CryptoHash, CryptoHasher, HashDomain, HashScheme, SparseMerkleCountedInternalHasher,
+        SparseMerkleInternalHasher,
+    },
     HashValue,
 };
 use libra_crypto_derive::CryptoHasher;
@@ -18,7 +21,10 @@ use proptest_derive::Arbitrary;
 use serde::{Deserialize, Serialize};
 use std::marker::PhantomData;
 
-pub use self::definition::{SparseMerkleProof, SparseMerkleRangeProof};
+pub use self::definition::{
+    SparseMerkleCountedProof, SparseMerkleProof, SparseMerkleRangeProof, SparseMerkleUpdateProof,
+    SparseMerkleUpdateProofNode,
+};
 
 #[cfg(any(test, feature = "fuzzing"))]
 pub use self::definition::{TestAccumulatorProof, TestAccumulatorRangeProof};
@@ -52,6 +58,66 @@ impl<H: CryptoHasher> CryptoHash for MerkleTreeInternalNode<H> {
 
 pub type SparseMerkleInternalNode = MerkleTreeInternalNode<SparseMerkleInternalHasher>;
 
+impl SparseMerkleInternalNode {
+    /// Same as `hash`, but computed with `hash_scheme`.
+    pub fn hash_with_scheme<S: HashScheme>(&self, hash_scheme: &S) -> HashValue {
+        hash_scheme.hash_of(&[
+            hash_scheme.seed(HashDomain::SparseMerkleInternal),
+            self.left_child.as_ref(),
+            self.right_child.as_ref(),
+        ])
+    }
+}
+
+/// An internal node of the Sparse Merkle Tree whose hash also commits to the number of leaves
+/// under each of its children.
+pub struct SparseMerkleCountedInternalNode {
+    left_child: HashValue,
+    left_count: u64,
+    right_child: HashValue,
+    right_count: u64,
+}
+
+impl SparseMerkleCountedInternalNode {
+    pub fn new(
+        left_child: HashValue,
+        left_count: u64,
+        right_child: HashValue,
+        right_count: u64,
+    ) -> Self {
+        Self {
+            left_child,
+            left_count,
+            right_child,
+            right_count,
+        }
+    }
+
+    /// Same as `hash`, but computed with `hash_scheme`.
+    pub fn hash_with_scheme<S: HashScheme>(&self, hash_scheme: &S) -> HashValue {
+        hash_scheme.hash_of(&[
+            hash_scheme.seed(HashDomain::SparseMerkleCountedInternal),
+            self.left_child.as_ref(),
+            &self.left_count.to_le_bytes(),
+            self.right_child.as_ref(),
+            &self.right_count.to_le_bytes(),
+        ])
+    }
+}
+
+impl CryptoHash for SparseMerkleCountedInternalNode {
+    type Hasher = SparseMerkleCountedInternalHasher;
+
+    fn hash(&self) -> HashValue {
+        let mut state = Self::Hasher::default();
+        state.update(self.left_child.as_ref());
+        state.update(&self.left_count.to_le_bytes());
+        state.update(self.right_child.as_ref());
+        state.update(&self.right_count.to_le_bytes());
+        state.finish()
+    }
+}
+
 #[derive(Clone, Copy, CryptoHasher, Debug, Eq, PartialEq, Serialize, Deserialize)]
 #[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
 pub struct SparseMerkleLeafNode {
@@ -71,6 +137,15 @@ impl SparseMerkleLeafNode {
     pub fn value_hash(&self) -> HashValue {
         self.value_hash
     }
+
+    /// Same as `hash`, but computed with `hash_scheme`.
+    pub fn hash_with_scheme<S: HashScheme>(&self, hash_scheme: &S) -> HashValue {
+        hash_scheme.hash_of(&[
+            hash_scheme.seed(HashDomain::SparseMerkleLeafNode),
+            self.key.as_ref(),
+            self.value_hash.as_ref(),
+        ])
+    }
 }
 
 impl CryptoHash for SparseMerkleLeafNode {
@@ -9,7 +9,8 @@ use crate::{
     proof::{
         definition::MAX_ACCUMULATOR_PROOF_DEPTH, AccountStateProof, AccumulatorExtensionProof,
         EventAccumulatorInternalNode, EventAccumulatorProof, EventProof, SparseMerkleInternalNode,
-        SparseMerkleLeafNode, SparseMerkleProof, TestAccumulatorInternalNode, TestAccumulatorProof,
+        SparseMerkleLeafNode, SparseMerkleProof, SparseMerkleUpdateProof,
+        SparseMerkleUpdateProofNode, TestAccumulatorInternalNode, TestAccumulatorProof,
         TransactionAccumulatorInternalNode, TransactionAccumulatorProof, TransactionInfoWithProof,
     },
     transaction::{RawTransaction, Script, Transaction, TransactionInfo},
@@ -243,6 +244,84 @@ fn test_verify_three_element_sparse_merkle() {
     }
 }
 
+#[test]
+fn test_verify_sparse_merkle_update() {
+    // Same tree as in `test_verify_three_element_sparse_merkle`. Writing key2 and a key starting
+    // with 1 gives:
+    //            root
+    //           /    \
+    //          a      key4
+    //         / \
+    //     key1   b
+    //           / \
+    //       key2   key3
+    let key1 = b"hello".test_only_hash();
+    let key2 = b"world".test_only_hash();
+    let key3 = b"!".test_only_hash();
+    let key4 = b"def".test_only_hash();
+    assert_eq!(key4[0], 0b1000_1110);
+
+    let blob1 = AccountStateBlob::from(b"1".to_vec());
+    let blob2 = AccountStateBlob::from(b"2".to_vec());
+    let blob3 = AccountStateBlob::from(b"3".to_vec());
+    let new_blob2 = AccountStateBlob::from(b"22".to_vec());
+    let blob4 = AccountStateBlob::from(b"4".to_vec());
+
+    let leaf1_hash = SparseMerkleLeafNode::new(key1, blob1.hash()).hash();
+    let leaf2 = SparseMerkleLeafNode::new(key2, blob2.hash());
+    let leaf3_hash = SparseMerkleLeafNode::new(key3, blob3.hash()).hash();
+    let internal_b_hash = SparseMerkleInternalNode::new(leaf2.hash(), leaf3_hash).hash();
+    let internal_a_hash = SparseMerkleInternalNode::new(leaf1_hash, internal_b_hash).hash();
+    let old_root_hash =
+        SparseMerkleInternalNode::new(internal_a_hash, *SPARSE_MERKLE_PLACEHOLDER_HASH).hash();
+
+    let new_leaf2_hash = SparseMerkleLeafNode::new(key2, new_blob2.hash()).hash();
+    let leaf4_hash = SparseMerkleLeafNode::new(key4, blob4.hash()).hash();
+    let new_internal_b_hash = SparseMerkleInternalNode::new(new_leaf2_hash, leaf3_hash).hash();
+    let new_internal_a_hash = SparseMerkleInternalNode::new(leaf1_hash, new_internal_b_hash).hash();
+    let new_root_hash = SparseMerkleInternalNode::new(new_internal_a_hash, leaf4_hash).hash();
+
+    let nodes = vec![
+        SparseMerkleUpdateProofNode::Internal,
+        SparseMerkleUpdateProofNode::Internal,
+        SparseMerkleUpdateProofNode::Subtree(leaf1_hash),
+        SparseMerkleUpdateProofNode::Internal,
+        SparseMerkleUpdateProofNode::Leaf(leaf2),
+        SparseMerkleUpdateProofNode::Subtree(leaf3_hash),
+        SparseMerkleUpdateProofNode::Empty,
+    ];
+    let proof = SparseMerkleUpdateProof::new(
+        vec![(key2, Some(blob2.clone())), (key4, None)],
+        nodes.clone(),
+    );
+    let writes = vec![(key4, blob4.clone()), (key2, new_blob2.clone())];
+    assert!(proof.verify(old_root_hash, new_root_hash, &writes).is_ok());
+
+    // The new root hash must match the writes.
+    assert!(proof.verify(old_root_hash, old_root_hash, &writes).is_err());
+    assert!(proof
+        .verify(old_root_hash, new_root_hash, &[(key2, new_blob2.clone())])
+        .is_err());
+    // The old values must match the old tree.
+    let proof = SparseMerkleUpdateProof::new(vec![(key2, None), (key4, None)], nodes.clone());
+    assert!(proof.verify(old_root_hash, new_root_hash, &writes).is_err());
+    let proof = SparseMerkleUpdateProof::new(
+        vec![(key2, Some(blob2.clone())), (key4, Some(blob4))],
+        nodes,
+    );
+    assert!(proof.verify(old_root_hash, new_root_hash, &writes).is_err());
+    // Written keys can not be hidden in a subtree.
+    let proof = SparseMerkleUpdateProof::new(
+        vec![(key2, Some(blob2)), (key4, None)],
+        vec![
+            SparseMerkleUpdateProofNode::Internal,
+            SparseMerkleUpdateProofNode::Subtree(internal_a_hash),
+            SparseMerkleUpdateProofNode::Empty,
+        ],
+    );
+    assert!(proof.verify(old_root_hash, new_root_hash, &writes).is_err());
+}
+
 #[test]
 fn test_verify_transaction() {
     //            root